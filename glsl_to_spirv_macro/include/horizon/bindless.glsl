@@ -0,0 +1,54 @@
+// Bindless resources, see `horizon::bindless`. The bindings match
+// `horizon::bindless::set_layout_description`.
+#ifndef HORIZON_BINDLESS_GLSL
+#define HORIZON_BINDLESS_GLSL
+
+#extension GL_EXT_nonuniform_qualifier : require
+// Storage images of any format.
+#extension GL_EXT_shader_image_load_formatted : require
+
+#ifndef HORIZON_BINDLESS_SET
+#define HORIZON_BINDLESS_SET 0
+#endif
+
+// Generated as `horizon::bindless::TextureHandle` and the like.
+struct TextureHandle {
+    uint index;
+};
+struct StorageImageHandle {
+    uint index;
+};
+struct BufferHandle {
+    uint index;
+};
+struct SamplerHandle {
+    uint index;
+};
+
+layout(set = HORIZON_BINDLESS_SET, binding = 0) uniform texture2D horizon_textures[];
+layout(set = HORIZON_BINDLESS_SET, binding = 1) uniform image2D horizon_storage_images[];
+layout(set = HORIZON_BINDLESS_SET, binding = 3) uniform sampler horizon_samplers[];
+
+// Views the storage buffers as a block, e.g.
+// `HORIZON_BUFFER_LAYOUT readonly buffer Particles { Particle particles[]; } particle_buffers[];`
+#define HORIZON_BUFFER_LAYOUT layout(set = HORIZON_BINDLESS_SET, binding = 2)
+
+HORIZON_BUFFER_LAYOUT buffer HorizonWords {
+    uint words[];
+} horizon_buffers[];
+
+vec4 horizon_sample(TextureHandle texture_, SamplerHandle sampler_, vec2 uv) {
+    return texture(
+        sampler2D(
+            horizon_textures[nonuniformEXT(texture_.index)],
+            horizon_samplers[nonuniformEXT(sampler_.index)]
+        ),
+        uv
+    );
+}
+
+uint horizon_load_word(BufferHandle buffer_, uint index) {
+    return horizon_buffers[nonuniformEXT(buffer_.index)].words[index];
+}
+
+#endif
@@ -3,6 +3,12 @@ use std::{borrow::Cow, cell::RefCell, os::unix::fs::MetadataExt, rc::Rc};
 
 use crate::utils::{call_site_err, SpanMessages};
 
+/// Includes shipped with the macro, for `#include <name>`.
+const BUILTIN_INCLUDES: &[(&str, &str)] = &[(
+    "horizon/bindless.glsl",
+    include_str!("../include/horizon/bindless.glsl"),
+)];
+
 pub struct Compiler {
     shader_compiler: shaderc::Compiler,
 }
@@ -77,6 +83,14 @@ impl Compiler {
         let includes: Rc<RefCell<Vec<std::path::PathBuf>>> = Rc::new(RefCell::new(vec![]));
         let included_paths = includes.clone();
         additional_options.set_include_callback(move |inc, ty, name_of_file, _| {
+            if matches!(ty, shaderc::IncludeType::Standard) {
+                if let Some((_, content)) = BUILTIN_INCLUDES.iter().find(|(name, _)| *name == inc) {
+                    return Ok(shaderc::ResolvedInclude {
+                        resolved_name: inc.into(),
+                        content: (*content).into(),
+                    });
+                }
+            }
             let mut path = match ty {
                 shaderc::IncludeType::Relative => {
                     let path = std::path::PathBuf::from(&name_of_file);
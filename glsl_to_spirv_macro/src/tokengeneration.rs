@@ -55,8 +55,10 @@ fn create_data_rep(
     let debug_names = reflec.get_debug_names();
     let input_variables =
         reflec.get_all_variables_with_storage_class(spirv_reflect::spirv::StorageClass::Input)?;
-    let _uniform_variables =
+    let uniform_variables =
         reflec.get_all_variables_with_storage_class(spirv_reflect::spirv::StorageClass::Uniform)?;
+    let storage_variables = reflec
+        .get_all_variables_with_storage_class(spirv_reflect::spirv::StorageClass::StorageBuffer)?;
     let _constant_variables = reflec.get_all_variables_with_storage_class(
         spirv_reflect::spirv::StorageClass::UniformConstant,
     )?;
@@ -82,6 +84,22 @@ fn create_data_rep(
         })
         .collect::<Vec<_>>();
     let input_struct_docs = "The input structure for the shader through the pipeline";
+    // Fields of 32 bit components are never padded.
+    let input_pod = input_variables
+        .iter()
+        .map(|x| {
+            reflec
+                .get_type_of_variable(*x)
+                .map(|ty| types.get(&ty).cloned())
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .all(|ty| ty.as_deref().is_some_and(has_32_bit_components))
+        .then(|| {
+            quote::quote!(
+                unsafe impl horizon::types::Pod for Input {}
+            )
+        });
     let input_struct = quote::quote! {
         #[repr(C)]
         #[derive(Debug, Clone, Copy)]
@@ -92,13 +110,328 @@ fn create_data_rep(
                 pub #inputs: #inputstype,
             )*
         }
+        #input_pod
     };
 
+    let blocks = uniform_variables
+        .iter()
+        .chain(&storage_variables)
+        .map(|x| reflec.get_type_of_variable(*x))
+        .collect::<Result<Vec<_>, _>>()?;
+    let structs = create_struct_reps(reflec, &types, &blocks)?;
+
     Ok(quote::quote! {
         #input_struct
+        #(#structs)*
+    })
+}
+
+type TypeMap = std::collections::BTreeMap<u32, std::rc::Rc<spirv_reflect::types::Type>>;
+
+/// Generates the push constant block, the uniform and storage buffer `blocks`, and every struct
+/// reachable through a buffer device address.
+/// Members are placed at their reflected offsets, padding is inserted where needed and after the
+/// last member up to the stride of the struct, and array elements and matrix columns are padded
+/// to their `ArrayStride` and `MatrixStride`, so the structs can be written to a
+/// `horizon::buffer::Buffer` as they are. The layout is asserted at compile time.
+fn create_struct_reps(
+    reflec: &spirv_reflect::Reflection,
+    types: &TypeMap,
+    blocks: &[u32],
+) -> Result<Vec<proc_macro2::TokenStream>, spirv_reflect::ReflectError> {
+    use spirv_reflect::types::Type;
+    let debug_names = reflec.get_debug_names();
+    let push_constant = reflec.get_push_constant_type()?;
+    let pointees = reflec.get_physical_storage_buffer_layouts()?;
+
+    // Collect every struct that is needed, including nested ones.
+    let mut pending = push_constant
+        .into_iter()
+        .chain(blocks.iter().copied())
+        .chain(pointees.values().map(|x| x.pointee))
+        .collect::<Vec<_>>();
+    let mut struct_ids = std::collections::BTreeSet::new();
+    while let Some(id) = pending.pop() {
+        match types.get(&id).map(std::rc::Rc::as_ref) {
+            Some(Type::Struct(_))
+                if debug_names
+                    .get_name(id)
+                    .and_then(|x| bindless_handle(x))
+                    .is_some() => {}
+            Some(Type::Struct(_)) if struct_ids.insert(id) => {
+                pending.extend(reflec.get_struct_layout(id)?.iter().map(|x| x.type_id));
+            }
+            Some(Type::Pointer(ptr)) => pending.push(ptr.pointee),
+            Some(Type::Array(_) | Type::RunTimeArray(_)) => {
+                pending.push(element_type_id(reflec, id)?);
+            }
+            _ => {}
+        }
+    }
+
+    let struct_names = struct_ids
+        .iter()
+        .map(|id| {
+            let name = debug_names.get_name(*id).map_or_else(
+                || {
+                    if Some(*id) == push_constant {
+                        "PushConstants".to_string()
+                    } else {
+                        format!("Struct{id}")
+                    }
+                },
+                |x| (*x).to_string(),
+            );
+            (*id, quote::format_ident!("{}", name))
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    struct_ids
+        .iter()
+        .map(|id| {
+            let name = &struct_names[id];
+            let mut cursor = 0;
+            let mut fields = Vec::new();
+            let mut paddings = Vec::new();
+            let mut args = Vec::new();
+            let mut checks = Vec::new();
+            for (index, member) in reflec.get_struct_layout(*id)?.into_iter().enumerate() {
+                let field = member.name.as_ref().map_or_else(
+                    || quote::format_ident!("field{}", index),
+                    |x| quote::format_ident!("{}", x),
+                );
+                if member.offset < cursor {
+                    let message = format!("`{name}::{field}` overlaps the member before it");
+                    return Ok(quote::quote!(compile_error!(#message);));
+                }
+                if member.offset > cursor {
+                    let padding = quote::format_ident!("_padding{}", index);
+                    let len = (member.offset - cursor) as usize;
+                    fields.push(quote::quote! { #padding: [u8; #len] });
+                    paddings.push(quote::quote! { #padding: [0; #len] });
+                }
+                let layout = MemberStrides {
+                    matrix: member.matrix_stride,
+                    row_major: member.row_major,
+                };
+                let size = rust_size(reflec, types, member.type_id, layout)?;
+                cursor = member.offset + size;
+                let ty = member_type_tokens(reflec, types, &struct_names, member.type_id, layout)?;
+                let (offset, size) = (member.offset as usize, size as usize);
+                checks.push(quote::quote! {
+                    assert!(std::mem::offset_of!(#name, #field) == #offset);
+                    assert!(std::mem::size_of::<#ty>() == #size);
+                });
+                fields.push(quote::quote! { pub #field: #ty });
+                args.push((field, ty));
+            }
+            let size = reflec.get_struct_size(*id)?;
+            if size > cursor {
+                let padding = quote::format_ident!("_padding_end");
+                let len = (size - cursor) as usize;
+                fields.push(quote::quote! { #padding: [u8; #len] });
+                paddings.push(quote::quote! { #padding: [0; #len] });
+            }
+            let size = size.max(cursor) as usize;
+            let (arg_names, arg_types): (Vec<_>, Vec<_>) = args.into_iter().unzip();
+            let doc = format!("`{name}` as laid out by the shader.");
+            Ok(quote::quote! {
+                #[repr(C)]
+                #[derive(Debug, Clone, Copy)]
+                #[doc = #doc]
+                pub struct #name {
+                    #(#fields,)*
+                }
+                #[automatically_derived]
+                impl #name {
+                    #[must_use]
+                    #[allow(clippy::too_many_arguments)]
+                    pub const fn new(#(#arg_names: #arg_types),*) -> Self {
+                        Self {
+                            #(#arg_names,)*
+                            #(#paddings,)*
+                        }
+                    }
+                }
+                // Every byte belongs to a field of the asserted size, so there is no padding.
+                const _: () = {
+                    #(#checks)*
+                    assert!(std::mem::size_of::<#name>() == #size);
+                };
+                unsafe impl horizon::types::Pod for #name {}
+            })
+        })
+        .collect()
+}
+
+/// The `MatrixStride` and `RowMajor` decorations of a member, which apply to the matrices in it.
+#[derive(Debug, Clone, Copy)]
+struct MemberStrides {
+    matrix: Option<u32>,
+    row_major: bool,
+}
+
+/// Like `ToTokens`, but resolves structs and pointees to the generated struct names, and pads
+/// array elements and matrix columns out to their strides.
+fn member_type_tokens(
+    reflec: &spirv_reflect::Reflection,
+    types: &TypeMap,
+    struct_names: &std::collections::BTreeMap<u32, syn::Ident>,
+    type_id: u32,
+    strides: MemberStrides,
+) -> Result<proc_macro2::TokenStream, spirv_reflect::ReflectError> {
+    use spirv_reflect::types::Type;
+    let ty = types
+        .get(&type_id)
+        .ok_or(spirv_reflect::ReflectError::UnresolvedTypeId(type_id))?;
+    let element = || -> Result<_, spirv_reflect::ReflectError> {
+        let element = element_type_id(reflec, type_id)?;
+        let inner = member_type_tokens(reflec, types, struct_names, element, strides)?;
+        let size = rust_size(reflec, types, element, strides)?;
+        Ok(match array_stride(reflec, type_id) {
+            Some(stride) if stride > size => {
+                let padding = (stride - size) as usize;
+                quote::quote!(horizon::types::Padded<#inner, #padding>)
+            }
+            _ => inner,
+        })
+    };
+    Ok(match ty.as_ref() {
+        Type::Struct(_) => reflec
+            .get_debug_names()
+            .get_name(type_id)
+            .and_then(|x| bindless_handle(x))
+            .unwrap_or_else(|| {
+                let name = &struct_names[&type_id];
+                quote::quote!(#name)
+            }),
+        Type::Pointer(ptr) => {
+            let pointee = struct_names.get(&ptr.pointee).map_or_else(
+                || member_type_tokens(reflec, types, struct_names, ptr.pointee, strides),
+                |name| Ok(quote::quote!(#name)),
+            )?;
+            quote::quote!(horizon::types::DeviceAddress<#pointee>)
+        }
+        Type::Array(array) => {
+            let inner = element()?;
+            let len = usize::from(array.len);
+            quote::quote!([#inner; #len])
+        }
+        // Only valid as the last member, it does not take up any space.
+        Type::RunTimeArray(_) => {
+            let inner = element()?;
+            quote::quote!([#inner; 0])
+        }
+        Type::Mat(_) if strides.row_major => {
+            quote::quote!(compile_error!(
+                "row_major matrices are not supported, use column_major"
+            ))
+        }
+        Type::Mat(mat) => match (strides.matrix, mat.inner_type.as_ref()) {
+            (Some(stride), Type::Vector(column)) if stride > vector_size(column) => {
+                let padding = (stride - vector_size(column)) as usize;
+                let rows = usize::from(u16::from(column.size));
+                let columns = usize::from(u16::from(mat.size));
+                let scalar = column.inner_type.to_tokens();
+                quote::quote!(horizon::types::StridedMatrix<#scalar, #rows, #columns, #padding>)
+            }
+            _ => ty.to_tokens(),
+        },
+        _ => ty.to_tokens(),
+    })
+}
+
+/// Size of the Rust type [`member_type_tokens`] generates for `type_id`.
+fn rust_size(
+    reflec: &spirv_reflect::Reflection,
+    types: &TypeMap,
+    type_id: u32,
+    strides: MemberStrides,
+) -> Result<u32, spirv_reflect::ReflectError> {
+    use spirv_reflect::types::Type;
+    let ty = types
+        .get(&type_id)
+        .ok_or(spirv_reflect::ReflectError::UnresolvedTypeId(type_id))?;
+    Ok(match ty.as_ref() {
+        Type::Int(x) => u32::from(x.bits / 8),
+        Type::Float(x) => u32::from(x.bits / 8),
+        Type::Vector(x) => vector_size(x),
+        Type::Mat(mat) => {
+            let column = match mat.inner_type.as_ref() {
+                Type::Vector(column) => vector_size(column),
+                _ => 0,
+            };
+            strides.matrix.unwrap_or(column).max(column) * u32::from(u16::from(mat.size))
+        }
+        Type::Struct(_)
+            if reflec
+                .get_debug_names()
+                .get_name(type_id)
+                .and_then(|x| bindless_handle(x))
+                .is_some() =>
+        {
+            4
+        }
+        Type::Struct(_) => reflec.get_struct_size(type_id)?,
+        Type::Pointer(_) => 8,
+        Type::Array(array) => {
+            let element = rust_size(reflec, types, element_type_id(reflec, type_id)?, strides)?;
+            array_stride(reflec, type_id).map_or(element, |x| x.max(element)) * u32::from(array.len)
+        }
+        Type::RunTimeArray(_) => 0,
     })
 }
 
+fn vector_size(vector: &spirv_reflect::types::Vector) -> u32 {
+    let component = match vector.inner_type.as_ref() {
+        spirv_reflect::types::Type::Int(x) => x.bits,
+        spirv_reflect::types::Type::Float(x) => x.bits,
+        _ => 0,
+    };
+    u32::from(component / 8) * u32::from(u16::from(vector.size))
+}
+
+fn array_stride(reflec: &spirv_reflect::Reflection, type_id: u32) -> Option<u32> {
+    reflec.get_decorations().get(&type_id)?.array_stride
+}
+
+/// Whether `ty` is made of 32 bit scalars, which `#[repr(C)]` never pads between.
+fn has_32_bit_components(ty: &spirv_reflect::types::Type) -> bool {
+    use spirv_reflect::types::Type;
+    match ty {
+        Type::Int(x) => x.bits == 32,
+        Type::Float(x) => x.bits == 32,
+        Type::Vector(x) => has_32_bit_components(&x.inner_type),
+        Type::Mat(x) => has_32_bit_components(&x.inner_type),
+        Type::Array(x) => has_32_bit_components(&x.inner_type),
+        _ => false,
+    }
+}
+
+/// The Rust handle of a handle struct from `horizon/bindless.glsl`, which are not generated.
+fn bindless_handle(name: &str) -> Option<proc_macro2::TokenStream> {
+    match name {
+        "TextureHandle" | "StorageImageHandle" | "BufferHandle" | "SamplerHandle" => {
+            let name = quote::format_ident!("{}", name);
+            Some(quote::quote!(horizon::bindless::#name))
+        }
+        _ => None,
+    }
+}
+
+/// The element type of an `OpTypeArray` or `OpTypeRuntimeArray`.
+fn element_type_id(
+    reflec: &spirv_reflect::Reflection,
+    type_id: u32,
+) -> Result<u32, spirv_reflect::ReflectError> {
+    let inst =
+        spirv_reflect::find_instructions_assigning_to_id(&reflec.0.types_global_values, type_id)?;
+    match inst.operands.first() {
+        Some(spirv_reflect::rspirv::dr::Operand::IdRef(id)) => Ok(*id),
+        _ => Err(spirv_reflect::ReflectError::InvalidInnerType(inst.clone())),
+    }
+}
+
 trait ToTokens {
     fn to_tokens(&self) -> proc_macro2::TokenStream;
 }
@@ -141,7 +474,11 @@ impl ToTokens for spirv_reflect::types::Mat {
         let len: u16 = self.size.into();
         let full = format!("Matrix{len}");
         let full: syn::Type = syn::parse_str(&full).unwrap();
-        let inner = self.inner_type.to_tokens();
+        // The inner type is the column vector, the matrix is generic over its component.
+        let inner = match self.inner_type.as_ref() {
+            spirv_reflect::types::Type::Vector(column) => column.inner_type.to_tokens(),
+            x => x.to_tokens(),
+        };
         quote::quote! (nalgebra::#full<#inner>)
     }
 }
@@ -170,6 +507,8 @@ impl ToTokens for spirv_reflect::types::Type {
             Self::Struct(_) => todo!(),
             Self::Array(x) => x.to_tokens(),
             Self::RunTimeArray(x) => x.to_tokens(),
+            // Without the type map the pointee is unknown, see `member_type_tokens`.
+            Self::Pointer(_) => quote::quote!(u64),
         }
     }
 }
@@ -8,6 +8,26 @@ glsl_to_spirv_macro::shader! {
             ty: "frag",
             path: "tests/shaders/vert.vert"
         },
+        {
+            name: device_address,
+            ty: "vert",
+            path: "tests/shaders/device_address.vert"
+        },
+        {
+            name: padding,
+            ty: "frag",
+            path: "tests/shaders/padding.frag"
+        },
+        {
+            name: bindless,
+            ty: "frag",
+            path: "tests/shaders/bindless.frag"
+        },
+        {
+            name: std140,
+            ty: "frag",
+            path: "tests/shaders/std140.frag"
+        },
     ],
 }
 #[cfg(test)]
@@ -24,4 +44,49 @@ mod test {
             texcoord: [1.0, 2.0].into(),
         };
     }
+    #[test]
+    fn device_address() {
+        let pc = device_address::PushConstants::new(horizon::types::DeviceAddress::NULL, 1.0);
+        let _: horizon::types::DeviceAddress<device_address::Node> = pc.head;
+        assert_eq!(std::mem::offset_of!(device_address::Node, next), 16);
+        assert_eq!(
+            std::mem::offset_of!(device_address::PushConstants, scale),
+            8
+        );
+    }
+    #[test]
+    fn trailing_padding() {
+        let light = padding::Light::new([0.0; 3].into(), 1.0, [1.0; 3].into());
+        assert_eq!(light.intensity, 1.0);
+        assert_eq!(std::mem::offset_of!(padding::Light, color), 16);
+        assert_eq!(std::mem::size_of::<padding::Light>(), 32);
+    }
+    #[test]
+    fn bindless_handles() {
+        use horizon::bindless::{BufferHandle, SamplerHandle, TextureHandle};
+        let material = bindless::Material::new(
+            TextureHandle::from_index(3),
+            SamplerHandle::from_index(0),
+            BufferHandle::from_index(1),
+        );
+        assert_eq!(material.albedo.index(), 3);
+        assert_eq!(std::mem::size_of::<bindless::Material>(), 12);
+    }
+    #[test]
+    fn std140_strides() {
+        use horizon::types::Padded;
+        let rotation = nalgebra::Matrix3::new(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0);
+        let globals = std140::Globals::new(
+            [0.0, 0.25, 0.5, 1.0].map(Padded::new),
+            rotation.into(),
+            [1.0; 4].into(),
+        );
+        assert_eq!(std::mem::size_of_val(&globals.weights), 64);
+        assert_eq!(globals.weights[1].value, 0.25);
+        assert_eq!(std::mem::offset_of!(std140::Globals, rotation), 64);
+        assert_eq!(std::mem::size_of_val(&globals.rotation), 48);
+        assert_eq!(globals.rotation.matrix(), rotation);
+        assert_eq!(std::mem::offset_of!(std140::Globals, tint), 112);
+        assert_eq!(std::mem::size_of::<std140::Globals>(), 128);
+    }
 }
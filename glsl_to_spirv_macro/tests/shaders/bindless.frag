@@ -0,0 +1,16 @@
+#version 460
+#include <horizon/bindless.glsl>
+
+layout(push_constant) uniform Material {
+    TextureHandle albedo;
+    SamplerHandle linear;
+    BufferHandle weights;
+} material;
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 color;
+
+void main() {
+    float weight = uintBitsToFloat(horizon_load_word(material.weights, 0));
+    color = horizon_sample(material.albedo, material.linear, uv) * weight;
+}
@@ -0,0 +1,16 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+
+layout(buffer_reference, std430) buffer Node {
+    vec4 color;
+    Node next;
+};
+
+layout(push_constant) uniform PushConstants {
+    Node head;
+    float scale;
+} pc;
+
+void main() {
+    gl_Position = pc.head.next.color * pc.scale;
+}
@@ -0,0 +1,18 @@
+#version 460
+
+// 28 bytes of members, with a stride of 32 in std430.
+struct Light {
+    vec3 position;
+    float intensity;
+    vec3 color;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Lights {
+    Light lights[];
+};
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = vec4(lights[1].color * lights[1].intensity, 1.0);
+}
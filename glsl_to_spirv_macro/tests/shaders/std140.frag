@@ -0,0 +1,14 @@
+#version 460
+
+// The array elements and matrix columns are 16 bytes apart in std140.
+layout(std140, set = 0, binding = 0) uniform Globals {
+    float weights[4];
+    mat3 rotation;
+    vec4 tint;
+} globals;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = globals.tint * globals.weights[2] + vec4(globals.rotation[1], 0.0);
+}
@@ -0,0 +1,37 @@
+//! Creates a device and an offscreen render target without a window.
+use horizon::offscreen::{OffscreenTarget, OffscreenTargetDescription, RenderTarget};
+use horizon::QueueDescription;
+
+fn main() {
+    tracing_subscriber::fmt::init();
+    let instance = horizon::Instance::new_dynamic(
+        horizon::instance::InstanceCreateInfo::compute_usage("Headless"),
+    )
+    .unwrap();
+
+    let mut info = horizon::DeviceCreationInfo::default();
+    info.request_queue(QueueDescription {
+        flags: horizon::QueueFlags::COMPUTE,
+        ..QueueDescription::from_count(1)
+    });
+    info.request_queue(QueueDescription {
+        flags: horizon::QueueFlags::TRANSFER,
+        ..QueueDescription::from_count(1)
+    });
+    let device = horizon::Device::new(&instance, info).unwrap().device;
+    tracing::info!("Using {}", device.physical_device().name());
+
+    let allocator = horizon::memory::Allocator::new(&device, Default::default());
+    let target = OffscreenTarget::new(
+        &allocator,
+        &OffscreenTargetDescription::new(
+            ash::vk::Extent2D {
+                width: 256,
+                height: 256,
+            },
+            ash::vk::Format::R8G8B8A8_UNORM,
+        ),
+    )
+    .unwrap();
+    tracing::info!("Created {} offscreen images", target.images().len());
+}
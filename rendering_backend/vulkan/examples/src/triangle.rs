@@ -1,6 +1,5 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
-use std::rc::Rc;
 use std::sync::Arc;
 
 use horizon::QueueDescription;
@@ -9,43 +8,51 @@ mod winit_app;
 
 pub struct VulkanData {
     instance: Arc<horizon::Instance>,
-    surface: Rc<horizon::Surface>,
+    surface: Arc<horizon::Surface>,
+    device: Arc<horizon::Device>,
+    queue: Arc<horizon::Queue>,
+    swapchain: horizon::SwapChain,
 }
 
 impl VulkanData {
     fn new(window: &winit::window::Window) -> Self {
         // Default info
-        let instance = horizon::Instance::new_dynamic(horizon::instance::InstanceCreateInfo {
-            application_name: "Test".to_string(),
-            ..Default::default()
-        })
+        let instance = horizon::Instance::new_dynamic(
+            horizon::instance::InstanceCreateInfo::graphics_usage("Test", window).unwrap(),
+        )
         .unwrap();
 
         let surface = instance.create_surface(&window).unwrap();
 
-        let swapchain = horizon::SwapChain::new_promise(horizon::SwapChainDescription {
-            surface: surface.clone(),
-        });
-
-        let queue = horizon::Queue::new_promise(QueueDescription {
+        let size = window.inner_size();
+        let mut info = horizon::DeviceCreationInfo::default();
+        let swapchain = info.request_swapchain(
+            horizon::SwapChainDescription {
+                surface: surface.clone(),
+                policy: Default::default(),
+            },
+            ash::vk::Extent2D {
+                width: size.width,
+                height: size.height,
+            },
+        );
+        let queue = info.request_queue(QueueDescription {
             flags: horizon::QueueFlags::GRAPHICS,
-            supports: Some(swapchain.clone()),
+            supports: Some(swapchain),
             ..QueueDescription::from_count(1)
         });
 
-        let device = horizon::Device::new(
-            &instance,
-            horizon::DeviceCreationInfo {
-                swapchain: vec![swapchain.clone()],
-                queues: vec![queue.clone()],
-                physical_device_creation_info: Default::default(),
-                extensions: vec![],
-                layers: vec![],
-                order: Default::default(),
-            },
-        );
+        let mut created = horizon::Device::new(&instance, info).unwrap();
+        let queue = created.queue(queue).clone();
+        let swapchain = created.take_swapchain(swapchain);
 
-        Self { instance, surface }
+        Self {
+            instance,
+            surface,
+            device: created.device,
+            queue,
+            swapchain,
+        }
     }
 }
 
@@ -0,0 +1,534 @@
+//! Bindless resources.
+//!
+//! A [`BindlessTable`] is a single descriptor set of large update after bind arrays: sampled
+//! images, storage images, storage buffers and samplers. Adding a resource returns a [`Handle`],
+//! its stable index in the array, which shaders read from push constants or buffers. Freed
+//! handles are reused once the frame that freed them is done on the device.
+//!
+//! Shaders declare the set with `#include <horizon/bindless.glsl>` in `shader!`, which also
+//! defines `TextureHandle`, `StorageImageHandle`, `BufferHandle` and `SamplerHandle`. The
+//! generated structs use the matching Rust handles. The set is 0 unless `HORIZON_BINDLESS_SET`
+//! is defined before the include; pipeline layouts recognize it by its unbounded arrays.
+use super::buffer::Buffer;
+use super::descriptor::{BindableSet, SetLayoutBinding, SetLayoutDescription};
+use super::device::Device;
+use super::error::{DescriptorError, InitError};
+use super::handle::{own, Owned};
+use super::image::{Image, ImageView};
+use super::sync::Frame;
+use super::types::Pod;
+use ash::vk;
+use spirv_reflect::requirements::VulkanFeature;
+use std::any::Any;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+/// What a [`Handle`] indexes.
+pub trait HandleKind {
+    const BINDING: u32;
+    const TYPE: vk::DescriptorType;
+    /// Length of the array.
+    const CAPACITY: u32;
+}
+
+/// A `texture2D`, sampled with a [`SamplerHandle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Texture {}
+/// An `image2D` in the `GENERAL` layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageImage {}
+/// A storage buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageBuffer {}
+/// A `sampler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sampler {}
+
+impl HandleKind for Texture {
+    const BINDING: u32 = 0;
+    const TYPE: vk::DescriptorType = vk::DescriptorType::SAMPLED_IMAGE;
+    const CAPACITY: u32 = 1 << 16;
+}
+impl HandleKind for StorageImage {
+    const BINDING: u32 = 1;
+    const TYPE: vk::DescriptorType = vk::DescriptorType::STORAGE_IMAGE;
+    const CAPACITY: u32 = 1 << 14;
+}
+impl HandleKind for StorageBuffer {
+    const BINDING: u32 = 2;
+    const TYPE: vk::DescriptorType = vk::DescriptorType::STORAGE_BUFFER;
+    const CAPACITY: u32 = 1 << 16;
+}
+impl HandleKind for Sampler {
+    const BINDING: u32 = 3;
+    const TYPE: vk::DescriptorType = vk::DescriptorType::SAMPLER;
+    const CAPACITY: u32 = 1 << 11;
+}
+
+/// An index into the array of `K`s, laid out like the GLSL handle structs.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle<K> {
+    index: u32,
+    kind: PhantomData<fn() -> K>,
+}
+
+unsafe impl<K: Copy + 'static> Pod for Handle<K> {}
+
+pub type TextureHandle = Handle<Texture>;
+pub type StorageImageHandle = Handle<StorageImage>;
+pub type BufferHandle = Handle<StorageBuffer>;
+pub type SamplerHandle = Handle<Sampler>;
+
+impl<K> Handle<K> {
+    /// A handle to `index`, which has to be allocated from a table to be used.
+    pub const fn from_index(index: u32) -> Self {
+        Self {
+            index,
+            kind: PhantomData,
+        }
+    }
+    pub const fn index(self) -> u32 {
+        self.index
+    }
+}
+
+/// Bindings of every kind, the same as the GLSL include.
+pub fn set_layout_description() -> SetLayoutDescription {
+    fn binding<K: HandleKind>() -> SetLayoutBinding {
+        SetLayoutBinding {
+            binding: K::BINDING,
+            ty: K::TYPE,
+            count: K::CAPACITY,
+            stages: vk::ShaderStageFlags::ALL,
+            flags: vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+        }
+    }
+    SetLayoutDescription::new(vec![
+        binding::<Texture>(),
+        binding::<StorageImage>(),
+        binding::<StorageBuffer>(),
+        binding::<Sampler>(),
+    ])
+}
+
+/// The elements of one array.
+#[derive(Default)]
+struct Slots {
+    /// Resources kept alive by the occupied elements.
+    resources: Vec<Option<Box<dyn Any + Send + Sync>>>,
+    free: Vec<u32>,
+}
+
+impl Slots {
+    fn allocate(&mut self, binding: u32, capacity: u32) -> Result<u32, DescriptorError> {
+        if let Some(index) = self.free.pop() {
+            return Ok(index);
+        }
+        if self.resources.len() as u32 == capacity {
+            return Err(DescriptorError::TableFull { binding, capacity });
+        }
+        self.resources.push(None);
+        Ok(self.resources.len() as u32 - 1)
+    }
+}
+
+/// The descriptor set of every bindless resource, see the [module](self) documentation.
+pub struct BindlessTable {
+    device: Arc<Device>,
+    pool: Owned<vk::DescriptorPool>,
+    /// Owned by the device.
+    layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    /// By binding. Also synchronizes the updates of the set.
+    slots: Mutex<[Slots; 4]>,
+}
+
+impl std::fmt::Debug for BindlessTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BindlessTable")
+            .field("set", &self.set)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The table is kept alive by the command buffers it is bound in.
+impl BindableSet for Arc<BindlessTable> {
+    fn raw(&self) -> vk::DescriptorSet {
+        self.set
+    }
+    fn owner(&self) -> Arc<dyn Any + Send + Sync> {
+        self.clone()
+    }
+}
+
+impl BindlessTable {
+    /// Descriptor indexing features the table needs. Shaders indexing the arrays with
+    /// non-uniform values need the matching `shader*ArrayNonUniformIndexing` features too.
+    pub const FEATURES: [VulkanFeature; 6] = [
+        VulkanFeature::Vulkan12("runtimeDescriptorArray"),
+        VulkanFeature::Vulkan12("descriptorBindingPartiallyBound"),
+        VulkanFeature::Vulkan12("descriptorBindingUpdateUnusedWhilePending"),
+        VulkanFeature::Vulkan12("descriptorBindingSampledImageUpdateAfterBind"),
+        VulkanFeature::Vulkan12("descriptorBindingStorageImageUpdateAfterBind"),
+        VulkanFeature::Vulkan12("descriptorBindingStorageBufferUpdateAfterBind"),
+    ];
+
+    /// # Errors
+    /// If one of [`Self::FEATURES`] is not enabled, the arrays exceed the limits of the device,
+    /// or the set cannot be created.
+    pub fn new(device: &Arc<Device>) -> Result<Arc<Self>, InitError> {
+        if let Some(feature) = Self::FEATURES
+            .into_iter()
+            .find(|x| !device.enabled_features().get(x).unwrap_or(false))
+        {
+            return Err(InitError::FeatureNotEnabled(feature));
+        }
+        let limits = device.physical_device().descriptor_indexing_properties();
+        let check = |limit, required, supported| {
+            if required > supported {
+                Err(InitError::LimitExceeded {
+                    limit,
+                    required,
+                    supported,
+                })
+            } else {
+                Ok(())
+            }
+        };
+        check(
+            "maxDescriptorSetUpdateAfterBindSampledImages",
+            Texture::CAPACITY,
+            limits.max_descriptor_set_update_after_bind_sampled_images,
+        )?;
+        check(
+            "maxDescriptorSetUpdateAfterBindStorageImages",
+            StorageImage::CAPACITY,
+            limits.max_descriptor_set_update_after_bind_storage_images,
+        )?;
+        check(
+            "maxDescriptorSetUpdateAfterBindStorageBuffers",
+            StorageBuffer::CAPACITY,
+            limits.max_descriptor_set_update_after_bind_storage_buffers,
+        )?;
+        check(
+            "maxDescriptorSetUpdateAfterBindSamplers",
+            Sampler::CAPACITY,
+            limits.max_descriptor_set_update_after_bind_samplers,
+        )?;
+        check(
+            "maxPerStageUpdateAfterBindResources",
+            Texture::CAPACITY
+                + StorageImage::CAPACITY
+                + StorageBuffer::CAPACITY
+                + Sampler::CAPACITY,
+            limits.max_per_stage_update_after_bind_resources,
+        )?;
+
+        let description = set_layout_description();
+        let layout = device.descriptor_set_layout(&description)?;
+        let pool_sizes = description.pool_sizes(1);
+        let pool = unsafe {
+            device.raw().create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                    .pool_sizes(&pool_sizes)
+                    .max_sets(1),
+                device.allocation_callbacks(),
+            )
+        }?;
+        let mut table = Self {
+            device: device.clone(),
+            pool: own(device, pool),
+            layout,
+            set: vk::DescriptorSet::null(),
+            slots: Mutex::default(),
+        };
+        let set_layouts = [layout];
+        table.set = unsafe {
+            device.raw().allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&set_layouts),
+            )
+        }?[0];
+        device.set_debug_name(table.set, "bindless table");
+        Ok(Arc::new(table))
+    }
+
+    /// To be bound at the bindless set of pipelines, see [`BindableSet`].
+    pub fn raw(&self) -> vk::DescriptorSet {
+        self.set
+    }
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Writes `info` to a free element of `K`s, which keeps `resource` alive until it is freed.
+    fn add<K: HandleKind>(
+        &self,
+        resource: Box<dyn Any + Send + Sync>,
+        buffer: Option<vk::DescriptorBufferInfo>,
+        image: Option<vk::DescriptorImageInfo>,
+    ) -> Result<Handle<K>, DescriptorError> {
+        let mut slots = self.slots.lock().unwrap();
+        let slots = &mut slots[K::BINDING as usize];
+        let index = slots.allocate(K::BINDING, K::CAPACITY)?;
+        let buffer_info = buffer.as_slice();
+        let image_info = image.as_slice();
+        let mut write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(K::BINDING)
+            .dst_array_element(index)
+            .descriptor_type(K::TYPE);
+        write = if buffer.is_some() {
+            write.buffer_info(buffer_info)
+        } else {
+            write.image_info(image_info)
+        };
+        unsafe { self.device.raw().update_descriptor_sets(&[write], &[]) };
+        slots.resources[index as usize] = Some(resource);
+        Ok(Handle::from_index(index))
+    }
+
+    fn add_image<K: HandleKind>(
+        &self,
+        resource: Box<dyn Any + Send + Sync>,
+        view: vk::ImageView,
+        usage: vk::ImageUsageFlags,
+        needed: vk::ImageUsageFlags,
+        layout: vk::ImageLayout,
+    ) -> Result<Handle<K>, DescriptorError> {
+        if !usage.contains(needed) {
+            return Err(DescriptorError::MissingImageUsage {
+                binding: K::BINDING,
+                usage,
+            });
+        }
+        let info = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view,
+            image_layout: layout,
+        };
+        self.add(resource, None, Some(info))
+    }
+
+    /// The default view of `image`, sampled in `SHADER_READ_ONLY_OPTIMAL`.
+    /// # Errors
+    /// If the image is not `SAMPLED` or the table is full.
+    pub fn add_texture(&self, image: &Arc<Image>) -> Result<TextureHandle, DescriptorError> {
+        self.add_image(
+            Box::new(image.clone()),
+            image.view(),
+            image.description().usage,
+            vk::ImageUsageFlags::SAMPLED,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+    /// # Errors
+    /// See [`Self::add_texture`].
+    pub fn add_texture_view(
+        &self,
+        view: &Arc<ImageView>,
+    ) -> Result<TextureHandle, DescriptorError> {
+        self.add_image(
+            Box::new(view.clone()),
+            view.raw(),
+            view.image().description().usage,
+            vk::ImageUsageFlags::SAMPLED,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+    /// The default view of `image`, in `GENERAL`.
+    /// # Errors
+    /// If the image is not `STORAGE` or the table is full.
+    pub fn add_storage_image(
+        &self,
+        image: &Arc<Image>,
+    ) -> Result<StorageImageHandle, DescriptorError> {
+        self.add_image(
+            Box::new(image.clone()),
+            image.view(),
+            image.description().usage,
+            vk::ImageUsageFlags::STORAGE,
+            vk::ImageLayout::GENERAL,
+        )
+    }
+    /// # Errors
+    /// See [`Self::add_storage_image`].
+    pub fn add_storage_image_view(
+        &self,
+        view: &Arc<ImageView>,
+    ) -> Result<StorageImageHandle, DescriptorError> {
+        self.add_image(
+            Box::new(view.clone()),
+            view.raw(),
+            view.image().description().usage,
+            vk::ImageUsageFlags::STORAGE,
+            vk::ImageLayout::GENERAL,
+        )
+    }
+    /// # Errors
+    /// If the buffer is not a storage buffer or the table is full.
+    pub fn add_buffer<T: Pod>(
+        &self,
+        buffer: &Arc<Buffer<T>>,
+    ) -> Result<BufferHandle, DescriptorError> {
+        if !buffer
+            .usage()
+            .contains(vk::BufferUsageFlags::STORAGE_BUFFER)
+        {
+            return Err(DescriptorError::MissingBufferUsage {
+                binding: StorageBuffer::BINDING,
+                usage: buffer.usage(),
+            });
+        }
+        let info = vk::DescriptorBufferInfo {
+            buffer: buffer.raw(),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        self.add(Box::new(buffer.clone()), Some(info), None)
+    }
+    /// A sampler from [`SamplerCache::get_shared`](crate::sampler::SamplerCache::get_shared),
+    /// kept alive until it is freed.
+    /// # Errors
+    /// If the table is full.
+    pub fn add_sampler(
+        &self,
+        sampler: &Arc<Owned<vk::Sampler>>,
+    ) -> Result<SamplerHandle, DescriptorError> {
+        let info = vk::DescriptorImageInfo {
+            sampler: ***sampler,
+            ..Default::default()
+        };
+        self.add(Box::new(sampler.clone()), None, Some(info))
+    }
+
+    /// Frees `handle` when dropped, see [`Self::free`]. Its resource is taken out of the table
+    /// right away, so freeing a handle twice leaves the element alone the second time.
+    /// # Panics
+    /// In debug builds, if `handle` is not in use.
+    pub fn release<K: HandleKind>(self: &Arc<Self>, handle: Handle<K>) -> Release {
+        let resource = self.slots.lock().unwrap()[K::BINDING as usize]
+            .resources
+            .get_mut(handle.index as usize)
+            .and_then(Option::take);
+        debug_assert!(
+            resource.is_some(),
+            "Element {} of binding {} is not in use",
+            handle.index,
+            K::BINDING
+        );
+        Release {
+            table: self.clone(),
+            binding: K::BINDING,
+            index: handle.index,
+            resource,
+        }
+    }
+    /// Frees `handle` and drops its resource once `frame` is done on the device. The handle
+    /// must not be used by later frames.
+    pub fn free<K: HandleKind>(self: &Arc<Self>, frame: &mut Frame<'_>, handle: Handle<K>) {
+        frame.defer_destroy(self.release(handle));
+    }
+
+    /// Number of elements in use by kind.
+    pub fn in_use<K: HandleKind>(&self) -> usize {
+        let slots = &self.slots.lock().unwrap()[K::BINDING as usize];
+        slots.resources.len() - slots.free.len()
+    }
+}
+
+/// Returns an element of a [`BindlessTable`] when dropped.
+pub struct Release {
+    table: Arc<BindlessTable>,
+    binding: u32,
+    index: u32,
+    /// `None` if the element was not in use, it is not returned then.
+    resource: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl std::fmt::Debug for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Release")
+            .field("binding", &self.binding)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Release {
+    fn drop(&mut self) {
+        if self.resource.take().is_some() {
+            self.table.slots.lock().unwrap()[self.binding as usize]
+                .free
+                .push(self.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::selection::DeviceRequirements;
+
+    #[test]
+    fn handles() {
+        assert_eq!(std::mem::size_of::<TextureHandle>(), 4);
+        assert_eq!(TextureHandle::from_index(7).index(), 7);
+        let description = set_layout_description();
+        assert_eq!(description.bindings().len(), 4);
+        assert!(description.update_after_bind());
+        for (binding, x) in description.bindings().iter().enumerate() {
+            assert_eq!(x.binding, binding as u32);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn allocate_and_release() {
+        let requirements = BindlessTable::FEATURES
+            .into_iter()
+            .fold(DeviceRequirements::default(), |x, feature| {
+                x.require_feature(feature)
+            });
+        let (device, queue) =
+            crate::test_support::device("bindless test", requirements, vk::QueueFlags::COMPUTE);
+        let allocator = crate::memory::Allocator::new(&device, Default::default());
+        let table = BindlessTable::new(&device).unwrap();
+
+        let buffer = Arc::new(
+            Buffer::from_slice(
+                &allocator,
+                &queue,
+                &[1u32, 2, 3],
+                crate::buffer::BufferDescription::new(crate::memory::MemoryUsage::Dynamic)
+                    .storage(),
+            )
+            .unwrap(),
+        );
+        let first = table.add_buffer(&buffer).unwrap();
+        let second = table.add_buffer(&buffer).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(table.in_use::<StorageBuffer>(), 2);
+        // The table keeps the buffer alive.
+        assert_eq!(Arc::strong_count(&buffer), 3);
+
+        drop(table.release(first));
+        assert_eq!(table.in_use::<StorageBuffer>(), 1);
+        assert_eq!(Arc::strong_count(&buffer), 2);
+        assert_eq!(table.add_buffer(&buffer).unwrap(), first);
+
+        let samplers = crate::sampler::SamplerCache::new(&device);
+        let sampler = samplers
+            .get_shared(&crate::sampler::SamplerDescription::linear())
+            .unwrap();
+        let handle = table.add_sampler(&sampler).unwrap();
+        assert_eq!(Arc::strong_count(&sampler), 3);
+        drop(table.release(handle));
+        assert_eq!(Arc::strong_count(&sampler), 2);
+    }
+}
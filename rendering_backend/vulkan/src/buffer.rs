@@ -0,0 +1,497 @@
+//! Typed buffers.
+//!
+//! A [`Buffer<T>`] is an array of `T` in device memory. Host visible buffers are written and read
+//! through their mapping, device local ones through a staging buffer and a copy on a transfer
+//! capable queue.
+//!
+//! Elements are copied byte for byte, so `T` is [`Pod`] and must be laid out the way the shader
+//! expects it. The structs generated by the shader macro are `#[repr(C)]` with the reflected
+//! offsets and strides, so a uniform block can be written as a Rust value:
+//! ```ignore
+//! let mut ubo = Buffer::new(&allocator, 1, BufferDescription::new(MemoryUsage::Dynamic).uniform())?;
+//! ubo.write_value(0, &shader::vertex::UniformBufferObject::new(model, view, projection))?;
+//! ```
+use crate::command::{CommandPool, Submission};
+use crate::device::Queue;
+use crate::error::MemoryError;
+use crate::memory::{AllocatedBuffer, Allocator, MemoryUsage};
+use crate::types::Pod;
+use ash::vk;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// How a [`Buffer`] is created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferDescription {
+    pub usage: vk::BufferUsageFlags,
+    pub memory: MemoryUsage,
+    /// Shown by validation messages and graphics debuggers.
+    pub name: Option<Cow<'static, str>>,
+}
+
+impl Default for BufferDescription {
+    fn default() -> Self {
+        Self::new(MemoryUsage::GpuOnly)
+    }
+}
+
+impl BufferDescription {
+    /// A buffer without any usage, add them with the builder methods.
+    pub const fn new(memory: MemoryUsage) -> Self {
+        Self {
+            usage: vk::BufferUsageFlags::empty(),
+            memory,
+            name: None,
+        }
+    }
+    #[must_use]
+    pub fn name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    #[must_use]
+    pub fn with_usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+    #[must_use]
+    pub fn vertex(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+    #[must_use]
+    pub fn index(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::INDEX_BUFFER)
+    }
+    #[must_use]
+    pub fn uniform(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+    }
+    #[must_use]
+    pub fn storage(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+    }
+    #[must_use]
+    pub fn indirect(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::INDIRECT_BUFFER)
+    }
+    #[must_use]
+    pub fn transfer_src(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::TRANSFER_SRC)
+    }
+    #[must_use]
+    pub fn transfer_dst(self) -> Self {
+        self.with_usage(vk::BufferUsageFlags::TRANSFER_DST)
+    }
+}
+
+/// An array of `T` in device memory, destroyed when dropped.
+#[derive(Debug)]
+pub struct Buffer<T: Pod> {
+    inner: AllocatedBuffer,
+    len: usize,
+    usage: vk::BufferUsageFlags,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> Buffer<T> {
+    /// Creates a buffer of `len` uninitialized elements.
+    ///
+    /// Buffers in [`MemoryUsage::GpuOnly`] memory can always be copied from and to, so that
+    /// [`Self::upload`] and [`Self::readback`] work on them.
+    /// # Errors
+    /// If the buffer can not be created or no memory fits.
+    /// # Panics
+    /// If the buffer would be empty.
+    pub fn new(
+        allocator: &Arc<Allocator>,
+        len: usize,
+        desc: BufferDescription,
+    ) -> Result<Self, MemoryError> {
+        let size = (len * std::mem::size_of::<T>()) as u64;
+        assert!(size > 0, "Buffers can not be empty");
+        let usage = if desc.memory.is_host_visible() {
+            desc.usage
+        } else {
+            desc.usage | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST
+        };
+        let create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = Self {
+            inner: allocator.create_buffer(&create_info, desc.memory)?,
+            len,
+            usage,
+            _marker: PhantomData,
+        };
+        if let Some(name) = &desc.name {
+            buffer.set_debug_name(name);
+        }
+        Ok(buffer)
+    }
+
+    /// Creates a buffer holding `data`, see [`Self::upload`].
+    /// # Errors
+    /// If the buffer can not be created or the upload fails.
+    /// # Panics
+    /// If `data` is empty.
+    pub fn from_slice(
+        allocator: &Arc<Allocator>,
+        queue: &Queue,
+        data: &[T],
+        desc: BufferDescription,
+    ) -> Result<Self, MemoryError> {
+        let mut buffer = Self::new(allocator, data.len(), desc)?;
+        buffer.upload(queue, 0, data)?;
+        Ok(buffer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Size of the elements in bytes.
+    pub fn size(&self) -> u64 {
+        self.inner.size()
+    }
+    pub fn raw(&self) -> vk::Buffer {
+        self.inner.raw()
+    }
+    /// Names the buffer, see [`Device::set_debug_name`](crate::device::Device::set_debug_name).
+    pub fn set_debug_name(&self, name: &str) {
+        self.allocator().device().set_debug_name(self.raw(), name);
+    }
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+    pub fn inner(&self) -> &AllocatedBuffer {
+        &self.inner
+    }
+    pub fn allocator(&self) -> &Arc<Allocator> {
+        self.inner.allocator()
+    }
+    /// Whether [`Self::write`] and [`Self::read`] can be used.
+    pub fn is_host_visible(&self) -> bool {
+        self.inner.allocation().mapped_ptr().is_some()
+    }
+
+    /// Writes `data` starting at element `offset` through the mapping.
+    /// # Errors
+    /// If the buffer is not host visible.
+    /// # Panics
+    /// If the range is out of bounds.
+    pub fn write(&mut self, offset: usize, data: &[T]) -> Result<(), MemoryError> {
+        self.check_range(offset..offset + data.len());
+        self.inner
+            .allocation_mut()
+            .write(Self::byte_offset(offset), data)
+    }
+
+    /// Writes a single element, e.g. a whole uniform block.
+    /// # Errors
+    /// If the buffer is not host visible.
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn write_value(&mut self, index: usize, value: &T) -> Result<(), MemoryError> {
+        self.write(index, std::slice::from_ref(value))
+    }
+
+    /// Reads the elements in `range` through the mapping.
+    /// The caller has to make sure the device is done writing them, [`Self::readback`] waits for
+    /// that.
+    /// # Errors
+    /// If the buffer is not host visible.
+    /// # Panics
+    /// If the range is out of bounds.
+    pub fn read(&self, range: Range<usize>) -> Result<Vec<T>, MemoryError> {
+        self.check_range(range.clone());
+        let mut data = vec![T::zeroed(); range.len()];
+        self.inner
+            .allocation()
+            .read(Self::byte_offset(range.start), &mut data)?;
+        Ok(data)
+    }
+
+    /// Writes `data` starting at element `offset`, through the mapping if the buffer is host
+    /// visible, otherwise through a staging buffer copied on `queue`. Blocks until the copy is
+    /// done.
+    ///
+    /// The copy waits for earlier work on `queue`, and later work on `queue` waits for the copy.
+    /// Use the queue the buffer is used on, or synchronize with it. Use [`Self::write`] to skip
+    /// the wait on a host visible buffer the device is known not to use.
+    /// # Errors
+    /// If the staging buffer can not be created or the submission fails.
+    /// # Panics
+    /// If the range is out of bounds.
+    pub fn upload(&mut self, queue: &Queue, offset: usize, data: &[T]) -> Result<(), MemoryError> {
+        self.check_range(offset..offset + data.len());
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.is_host_visible() {
+            self.wait_for_queue(queue)?.wait()?;
+            return self.write(offset, data);
+        }
+        let mut staging = Buffer::<T>::new(
+            self.allocator(),
+            data.len(),
+            BufferDescription::new(MemoryUsage::Upload).transfer_src(),
+        )?;
+        staging.write(0, data)?;
+
+        let pool = CommandPool::new(self.allocator().device(), queue.family_index())?;
+        let mut command_buffer = pool.primary()?;
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+        command_buffer.copy_buffer_untracked(
+            staging.raw(),
+            self.raw(),
+            &[vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: Self::byte_offset(offset),
+                size: staging.size(),
+            }],
+        );
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        );
+        command_buffer.submit(queue)?.wait()?;
+        Ok(())
+    }
+
+    /// Starts reading the elements in `range` back to the host, after the work already submitted
+    /// to `queue`. Device local buffers are copied to a staging buffer on `queue`, host visible
+    /// ones are read through the mapping once that work is done.
+    ///
+    /// The buffer stays borrowed until the returned [`Readback`] is done with it.
+    /// # Errors
+    /// If the staging buffer can not be created or the submission fails.
+    /// # Panics
+    /// If the range is out of bounds.
+    pub fn readback(
+        &self,
+        queue: &Queue,
+        range: Range<usize>,
+    ) -> Result<Readback<'_, T>, MemoryError> {
+        self.check_range(range.clone());
+        if range.is_empty() {
+            return Ok(Readback {
+                source: self,
+                state: ReadbackState::Ready(Vec::new()),
+            });
+        }
+        if self.is_host_visible() {
+            return Ok(Readback {
+                source: self,
+                state: ReadbackState::Mapped {
+                    submission: self.wait_for_queue(queue)?,
+                    range,
+                },
+            });
+        }
+        let staging = Buffer::<T>::new(
+            self.allocator(),
+            range.len(),
+            BufferDescription::new(MemoryUsage::Readback).transfer_dst(),
+        )?;
+
+        let pool = CommandPool::new(self.allocator().device(), queue.family_index())?;
+        let mut command_buffer = pool.primary()?;
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        command_buffer.copy_buffer_untracked(
+            self.raw(),
+            staging.raw(),
+            &[vk::BufferCopy {
+                src_offset: Self::byte_offset(range.start),
+                dst_offset: 0,
+                size: staging.size(),
+            }],
+        );
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_READ,
+        );
+        let submission = command_buffer.submit(queue)?;
+        Ok(Readback {
+            source: self,
+            state: ReadbackState::Pending {
+                submission,
+                staging,
+            },
+        })
+    }
+
+    /// Submits a barrier on `queue` that makes the writes of earlier work on it visible to the
+    /// host, and leaves the buffer to the host once it is done.
+    fn wait_for_queue(&self, queue: &Queue) -> Result<Submission, MemoryError> {
+        let pool = CommandPool::new(self.allocator().device(), queue.family_index())?;
+        let mut command_buffer = pool.primary()?;
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_READ | vk::AccessFlags::HOST_WRITE,
+        );
+        Ok(command_buffer.submit(queue)?)
+    }
+
+    fn byte_offset(index: usize) -> u64 {
+        (index * std::mem::size_of::<T>()) as u64
+    }
+    fn check_range(&self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "Range {range:?} is out of bounds for a buffer of {} elements",
+            self.len
+        );
+    }
+}
+
+/// Elements being read back from a [`Buffer`].
+#[derive(Debug)]
+pub struct Readback<'a, T: Pod> {
+    source: &'a Buffer<T>,
+    state: ReadbackState<T>,
+}
+
+#[derive(Debug)]
+enum ReadbackState<T: Pod> {
+    Ready(Vec<T>),
+    /// Read from the source once the device is done with it.
+    Mapped {
+        submission: Submission,
+        range: Range<usize>,
+    },
+    Pending {
+        /// Dropped first, it waits for the copy.
+        submission: Submission,
+        staging: Buffer<T>,
+    },
+}
+
+impl<T: Pod> Readback<'_, T> {
+    /// Whether [`Self::wait`] would return without blocking.
+    /// # Errors
+    /// If the device is lost.
+    pub fn is_ready(&self) -> Result<bool, MemoryError> {
+        match &self.state {
+            ReadbackState::Ready(_) => Ok(true),
+            ReadbackState::Mapped { submission, .. }
+            | ReadbackState::Pending { submission, .. } => Ok(submission.is_complete()?),
+        }
+    }
+    /// Blocks until the copy is done and returns the elements.
+    /// # Errors
+    /// If the device is lost.
+    pub fn wait(self) -> Result<Vec<T>, MemoryError> {
+        match self.state {
+            ReadbackState::Ready(data) => Ok(data),
+            ReadbackState::Mapped { submission, range } => {
+                submission.wait()?;
+                self.source.read(range)
+            }
+            ReadbackState::Pending {
+                submission,
+                staging,
+            } => {
+                submission.wait()?;
+                staging.read(0..staging.len())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn description_builder() {
+        let desc = BufferDescription::new(MemoryUsage::Dynamic)
+            .uniform()
+            .transfer_dst();
+        assert_eq!(desc.memory, MemoryUsage::Dynamic);
+        assert_eq!(
+            desc.usage,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST
+        );
+        assert_eq!(BufferDescription::default().memory, MemoryUsage::GpuOnly);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn upload_and_readback() {
+        let (device, queue) = crate::test_support::device(
+            "buffer test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::TRANSFER,
+        );
+        let allocator = Allocator::new(&device, Default::default());
+
+        #[repr(C)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Element {
+            position: [f32; 3],
+            id: u32,
+        }
+        unsafe impl Pod for Element {}
+        let data = (0..64)
+            .map(|x| Element {
+                position: [x as f32, 0.5, -1.0],
+                id: x,
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffer = Buffer::from_slice(
+            &allocator,
+            &queue,
+            &data,
+            BufferDescription::default().storage(),
+        )
+        .unwrap();
+        assert_eq!(buffer.len(), 64);
+        assert_eq!(buffer.size(), std::mem::size_of_val(data.as_slice()) as u64);
+        let readback = buffer.readback(&queue, 0..64).unwrap();
+        assert_eq!(readback.wait().unwrap(), data);
+
+        let patch = [Element {
+            position: [0.0; 3],
+            id: 1000,
+        }; 4];
+        buffer.upload(&queue, 10, &patch).unwrap();
+        let read = buffer.readback(&queue, 8..16).unwrap().wait().unwrap();
+        assert_eq!(read[..2], data[8..10]);
+        assert_eq!(read[2..6], patch);
+        assert_eq!(read[6..], data[14..16]);
+
+        let mut uniform = Buffer::new(
+            &allocator,
+            1,
+            BufferDescription::new(MemoryUsage::Dynamic).uniform(),
+        )
+        .unwrap();
+        assert!(uniform.is_host_visible());
+        uniform.upload(&queue, 0, &data[3..4]).unwrap();
+        assert_eq!(uniform.read(0..1).unwrap(), [data[3]]);
+        let read = uniform.readback(&queue, 0..1).unwrap().wait().unwrap();
+        assert_eq!(read, [data[3]]);
+    }
+}
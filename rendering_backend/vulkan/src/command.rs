@@ -0,0 +1,1290 @@
+//! Command pools and command buffers.
+//!
+//! Command pools may only be used by one thread at a time, so a [`CommandPool`] and its
+//! [`CommandBuffer`]s stay on the thread that created them. [`CommandPools`] hands every thread
+//! its own pool.
+//!
+//! A [`CommandBuffer`] keeps everything its commands use alive, see
+//! [`CommandBuffer::keep_alive`]. Submitting it returns a [`Submission`] that owns the command
+//! buffer until the device is done with it.
+use super::buffer::Buffer;
+use super::descriptor::BindableSet;
+use super::device::{Device, Queue};
+use super::error::VkError;
+use super::handle::{own, Owned};
+use super::image::{format_aspect, Image, ImageView};
+use super::pipeline::ComputePipeline;
+use super::sync::{Fence, Semaphore, TimelineSemaphore};
+use super::types::Pod;
+use ash::vk;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Anything that can be bound with [`CommandBuffer::bind_pipeline`].
+pub trait Pipeline: Send + Sync + 'static {
+    fn raw(&self) -> vk::Pipeline;
+    fn layout(&self) -> vk::PipelineLayout;
+    fn bind_point(&self) -> vk::PipelineBindPoint;
+    /// The push constant range of the layout, if it has one.
+    fn push_constants(&self) -> Option<vk::PushConstantRange>;
+}
+
+/// What [`CommandBuffer::bind_pipeline`] bound to a bind point.
+#[derive(Debug, Clone, Copy)]
+struct Bound {
+    layout: vk::PipelineLayout,
+    push_constants: Option<vk::PushConstantRange>,
+}
+
+/// Element types of index buffers.
+pub trait IndexType: Pod {
+    const INDEX_TYPE: vk::IndexType;
+}
+impl IndexType for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+impl IndexType for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+struct PoolInner {
+    raw: Owned<vk::CommandPool>,
+    family_index: u32,
+    /// Command buffers given back, reset when they are begun again.
+    free: RefCell<Vec<(vk::CommandBufferLevel, vk::CommandBuffer)>>,
+}
+
+impl PoolInner {
+    fn device(&self) -> &Arc<Device> {
+        &self.raw.owner
+    }
+}
+
+/// Allocates command buffers for one queue family. Destroyed when it and all of its command
+/// buffers are dropped.
+#[derive(Clone)]
+pub struct CommandPool(Rc<PoolInner>);
+
+impl std::fmt::Debug for CommandPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandPool")
+            .field("raw", &*self.0.raw)
+            .field("family_index", &self.0.family_index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandPool {
+    /// # Errors
+    /// If the pool cannot be created.
+    pub fn new(device: &Arc<Device>, family_index: u32) -> Result<Self, VkError> {
+        let create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(family_index);
+        let raw = unsafe {
+            device
+                .raw()
+                .create_command_pool(&create_info, device.allocation_callbacks())
+        }?;
+        Ok(Self(Rc::new(PoolInner {
+            raw: own(device, raw),
+            family_index,
+            free: RefCell::default(),
+        })))
+    }
+
+    pub fn raw(&self) -> vk::CommandPool {
+        *self.0.raw
+    }
+    pub fn family_index(&self) -> u32 {
+        self.0.family_index
+    }
+
+    /// A primary command buffer, ready to record.
+    /// # Errors
+    /// If the command buffer cannot be allocated or begun.
+    pub fn primary(&self) -> Result<CommandBuffer, VkError> {
+        let command_buffer = self.allocate(vk::CommandBufferLevel::PRIMARY)?;
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            self.0
+                .device()
+                .raw()
+                .begin_command_buffer(command_buffer.raw, &begin_info)
+        }?;
+        Ok(command_buffer)
+    }
+
+    /// A secondary command buffer, ready to record. With `rendering` it continues the dynamic
+    /// render pass it is executed in, see [`RenderingDescription::secondary_command_buffers`].
+    /// # Errors
+    /// If the command buffer cannot be allocated or begun.
+    pub fn secondary(
+        &self,
+        rendering: Option<&RenderingFormats>,
+    ) -> Result<CommandBuffer, VkError> {
+        let command_buffer = self.allocate(vk::CommandBufferLevel::SECONDARY)?;
+        let mut rendering_info = rendering.map(|formats| {
+            vk::CommandBufferInheritanceRenderingInfo::default()
+                .color_attachment_formats(&formats.color)
+                .depth_attachment_format(formats.depth)
+                .stencil_attachment_format(formats.stencil)
+                .rasterization_samples(formats.samples)
+        });
+        let mut inheritance = vk::CommandBufferInheritanceInfo::default();
+        let mut flags = vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT;
+        if let Some(rendering_info) = &mut rendering_info {
+            inheritance = inheritance.push_next(rendering_info);
+            flags |= vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE;
+        }
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(flags)
+            .inheritance_info(&inheritance);
+        unsafe {
+            self.0
+                .device()
+                .raw()
+                .begin_command_buffer(command_buffer.raw, &begin_info)
+        }?;
+        Ok(command_buffer)
+    }
+
+    fn allocate(&self, level: vk::CommandBufferLevel) -> Result<CommandBuffer, VkError> {
+        let recycled = {
+            let mut free = self.0.free.borrow_mut();
+            free.iter()
+                .position(|(x, _)| *x == level)
+                .map(|index| free.swap_remove(index).1)
+        };
+        let raw = match recycled {
+            Some(raw) => raw,
+            None => {
+                let allocate_info = vk::CommandBufferAllocateInfo::default()
+                    .command_pool(*self.0.raw)
+                    .level(level)
+                    .command_buffer_count(1);
+                unsafe {
+                    self.0
+                        .device()
+                        .raw()
+                        .allocate_command_buffers(&allocate_info)
+                }?[0]
+            }
+        };
+        Ok(CommandBuffer {
+            pool: self.0.clone(),
+            raw,
+            level,
+            resources: vec![],
+            secondaries: vec![],
+            bound: HashMap::new(),
+            labels: 0,
+        })
+    }
+}
+
+static NEXT_POOLS_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static THREAD_POOLS: RefCell<HashMap<(u64, u32), CommandPool>> = RefCell::default();
+}
+
+/// One [`CommandPool`] per thread and queue family, created on first use.
+///
+/// The pools live in thread local storage until the thread exits, or until this is dropped
+/// on the thread that uses them.
+#[derive(Debug)]
+pub struct CommandPools {
+    device: Arc<Device>,
+    id: u64,
+}
+
+impl CommandPools {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: device.clone(),
+            id: NEXT_POOLS_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The pool of the calling thread for `family_index`.
+    /// # Errors
+    /// If the pool has to be created and cannot be.
+    pub fn current(&self, family_index: u32) -> Result<CommandPool, VkError> {
+        THREAD_POOLS.with(|pools| {
+            let mut pools = pools.borrow_mut();
+            if let Some(pool) = pools.get(&(self.id, family_index)) {
+                return Ok(pool.clone());
+            }
+            let pool = CommandPool::new(&self.device, family_index)?;
+            pools.insert((self.id, family_index), pool.clone());
+            Ok(pool)
+        })
+    }
+}
+
+impl Drop for CommandPools {
+    fn drop(&mut self) {
+        // Other threads drop their pools when they exit.
+        let _ = THREAD_POOLS.try_with(|pools| {
+            pools.borrow_mut().retain(|(id, _), _| *id != self.id);
+        });
+    }
+}
+
+/// Formats of the attachments of a dynamic render pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderingFormats {
+    pub color: Vec<vk::Format>,
+    /// `UNDEFINED` if there is none.
+    pub depth: vk::Format,
+    /// `UNDEFINED` if there is none.
+    pub stencil: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+/// How a multisampled [`Attachment`] is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolve {
+    pub view: vk::ImageView,
+    pub layout: vk::ImageLayout,
+    pub mode: vk::ResolveModeFlags,
+}
+
+impl Resolve {
+    /// Resolves into `view` of `format` with [`resolve_mode`].
+    pub fn new(view: vk::ImageView, layout: vk::ImageLayout, format: vk::Format) -> Self {
+        Self {
+            view,
+            layout,
+            mode: resolve_mode(format),
+        }
+    }
+}
+
+/// `AVERAGE` for float and normalized color formats. Integer, depth and stencil formats do not
+/// support it and take sample zero.
+pub fn resolve_mode(format: vk::Format) -> vk::ResolveModeFlags {
+    use vk::Format as F;
+    let integer = matches!(
+        format,
+        F::R8_UINT
+            | F::R8_SINT
+            | F::R8G8_UINT
+            | F::R8G8_SINT
+            | F::R8G8B8_UINT
+            | F::R8G8B8_SINT
+            | F::B8G8R8_UINT
+            | F::B8G8R8_SINT
+            | F::R8G8B8A8_UINT
+            | F::R8G8B8A8_SINT
+            | F::B8G8R8A8_UINT
+            | F::B8G8R8A8_SINT
+            | F::A8B8G8R8_UINT_PACK32
+            | F::A8B8G8R8_SINT_PACK32
+            | F::A2R10G10B10_UINT_PACK32
+            | F::A2R10G10B10_SINT_PACK32
+            | F::A2B10G10R10_UINT_PACK32
+            | F::A2B10G10R10_SINT_PACK32
+            | F::R16_UINT
+            | F::R16_SINT
+            | F::R16G16_UINT
+            | F::R16G16_SINT
+            | F::R16G16B16_UINT
+            | F::R16G16B16_SINT
+            | F::R16G16B16A16_UINT
+            | F::R16G16B16A16_SINT
+            | F::R32_UINT
+            | F::R32_SINT
+            | F::R32G32_UINT
+            | F::R32G32_SINT
+            | F::R32G32B32_UINT
+            | F::R32G32B32_SINT
+            | F::R32G32B32A32_UINT
+            | F::R32G32B32A32_SINT
+            | F::R64_UINT
+            | F::R64_SINT
+            | F::R64G64_UINT
+            | F::R64G64_SINT
+            | F::R64G64B64_UINT
+            | F::R64G64B64_SINT
+            | F::R64G64B64A64_UINT
+            | F::R64G64B64A64_SINT
+    );
+    if integer || format_aspect(format) != vk::ImageAspectFlags::COLOR {
+        vk::ResolveModeFlags::SAMPLE_ZERO
+    } else {
+        vk::ResolveModeFlags::AVERAGE
+    }
+}
+
+/// An image or view an [`Attachment`] renders to.
+pub trait AttachmentView: Send + Sync + 'static {
+    fn attachment_view(&self) -> vk::ImageView;
+    fn attachment_format(&self) -> vk::Format;
+}
+
+impl AttachmentView for Image {
+    fn attachment_view(&self) -> vk::ImageView {
+        self.view()
+    }
+    fn attachment_format(&self) -> vk::Format {
+        self.description().format
+    }
+}
+
+impl AttachmentView for ImageView {
+    fn attachment_view(&self) -> vk::ImageView {
+        self.raw()
+    }
+    fn attachment_format(&self) -> vk::Format {
+        self.description().format
+    }
+}
+
+/// An attachment of a dynamic render pass.
+///
+/// The views are kept alive until the command buffers the attachment is used in are done
+/// executing, except for those given to the `unsafe` constructors.
+#[derive(Clone)]
+pub struct Attachment {
+    view: vk::ImageView,
+    pub layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+    /// Multisampled attachments are resolved into this view.
+    resolve: Option<Resolve>,
+    /// Kept alive by [`CommandBuffer::begin_rendering`].
+    resources: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+// `vk::ClearValue` is a union.
+impl std::fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Attachment")
+            .field("view", &self.view)
+            .field("layout", &self.layout)
+            .field("load_op", &self.load_op)
+            .field("store_op", &self.store_op)
+            .field("resolve", &self.resolve)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Attachment {
+    /// Cleared to `color` and stored.
+    pub fn clear_color<V: AttachmentView>(view: &Arc<V>, color: [f32; 4]) -> Self {
+        unsafe { Self::clear_color_raw(view.attachment_view(), color) }.keep_alive(view.clone())
+    }
+    /// Cleared to `depth` and not stored.
+    pub fn clear_depth<V: AttachmentView>(view: &Arc<V>, depth: f32) -> Self {
+        unsafe { Self::clear_depth_raw(view.attachment_view(), depth) }.keep_alive(view.clone())
+    }
+    /// [`Self::clear_color`] for a view that is not reference counted, e.g. of a
+    /// [`RenderTarget`](crate::offscreen::RenderTarget) or
+    /// [`PassResources::view`](crate::graph::PassResources::view).
+    /// # Safety
+    /// `view` has to stay alive until the command buffers the attachment is used in are done
+    /// executing, unless it is kept alive with [`Self::keep_alive`].
+    pub unsafe fn clear_color_raw(view: vk::ImageView, color: [f32; 4]) -> Self {
+        Self {
+            view,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue { float32: color },
+            },
+            resolve: None,
+            resources: vec![],
+        }
+    }
+    /// [`Self::clear_depth`] for a view that is not reference counted.
+    /// # Safety
+    /// See [`Self::clear_color_raw`].
+    pub unsafe fn clear_depth_raw(view: vk::ImageView, depth: f32) -> Self {
+        Self {
+            view,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            },
+            resolve: None,
+            resources: vec![],
+        }
+    }
+    /// Keeps `resource`, e.g. the image of a view given to [`Self::clear_color_raw`], alive until
+    /// the command buffers the attachment is used in are done executing.
+    #[must_use]
+    pub fn keep_alive<R: Send + Sync + 'static>(mut self, resource: Arc<R>) -> Self {
+        self.resources.push(resource);
+        self
+    }
+    /// Resolves into `view`, with the [`resolve_mode`] of its format, and keeps it alive.
+    #[must_use]
+    pub fn resolve_into<V: AttachmentView>(
+        mut self,
+        view: &Arc<V>,
+        layout: vk::ImageLayout,
+    ) -> Self {
+        self.resolve = Some(Resolve::new(
+            view.attachment_view(),
+            layout,
+            view.attachment_format(),
+        ));
+        self.keep_alive(view.clone())
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+    pub fn resolve(&self) -> Option<&Resolve> {
+        self.resolve.as_ref()
+    }
+
+    fn info(&self) -> vk::RenderingAttachmentInfo<'static> {
+        let info = vk::RenderingAttachmentInfo::default()
+            .image_view(self.view)
+            .image_layout(self.layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value);
+        match self.resolve {
+            Some(resolve) => info
+                .resolve_mode(resolve.mode)
+                .resolve_image_view(resolve.view)
+                .resolve_image_layout(resolve.layout),
+            None => info,
+        }
+    }
+}
+
+/// See [`CommandBuffer::begin_rendering`].
+#[derive(Debug, Clone)]
+pub struct RenderingDescription<'a> {
+    pub area: vk::Rect2D,
+    pub color_attachments: &'a [Attachment],
+    pub depth_attachment: Option<Attachment>,
+    pub stencil_attachment: Option<Attachment>,
+    /// The contents are recorded in secondary command buffers, see [`CommandPool::secondary`].
+    pub secondary_command_buffers: bool,
+}
+
+impl<'a> RenderingDescription<'a> {
+    pub fn new(extent: vk::Extent2D, color_attachments: &'a [Attachment]) -> Self {
+        Self {
+            area: extent.into(),
+            color_attachments,
+            depth_attachment: None,
+            stencil_attachment: None,
+            secondary_command_buffers: false,
+        }
+    }
+}
+
+/// A command buffer being recorded.
+///
+/// Resources passed as [`Arc`]s are kept alive until the command buffer is done executing.
+/// Dropping it without submitting gives it back to its pool.
+pub struct CommandBuffer {
+    pool: Rc<PoolInner>,
+    raw: vk::CommandBuffer,
+    level: vk::CommandBufferLevel,
+    resources: Vec<Arc<dyn Any + Send + Sync>>,
+    secondaries: Vec<CommandBuffer>,
+    /// Layout of the pipeline bound to each bind point.
+    bound: HashMap<vk::PipelineBindPoint, Bound>,
+    /// Labels begun and not yet ended.
+    labels: u32,
+}
+
+impl std::fmt::Debug for CommandBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandBuffer")
+            .field("raw", &self.raw)
+            .field("level", &self.level)
+            .field("resources", &self.resources.len())
+            .field("secondaries", &self.secondaries)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for CommandBuffer {
+    fn drop(&mut self) {
+        // It may still be recording, and can only be begun again once it is not. The
+        // submission holding it has waited for it to execute.
+        let reset = unsafe {
+            self.device()
+                .reset_command_buffer(self.raw, vk::CommandBufferResetFlags::empty())
+        };
+        if reset.is_ok() {
+            self.pool.free.borrow_mut().push((self.level, self.raw));
+        }
+    }
+}
+
+impl CommandBuffer {
+    pub fn raw(&self) -> vk::CommandBuffer {
+        self.raw
+    }
+    pub fn level(&self) -> vk::CommandBufferLevel {
+        self.level
+    }
+    /// The queue family it can be submitted to.
+    pub fn family_index(&self) -> u32 {
+        self.pool.family_index
+    }
+    fn device(&self) -> &ash::Device {
+        self.pool.device().raw()
+    }
+
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.pool.device().set_debug_name(self.raw, name);
+    }
+
+    /// Starts a region of commands named `name` in validation messages and captures. A zero
+    /// `color` leaves it to the tool.
+    pub fn begin_label(&mut self, name: &str, color: [f32; 4]) {
+        self.labels += 1;
+        if let Some(debug_utils) = self.pool.device().debug_utils() {
+            let name = crate::debug::c_name(name);
+            let label = vk::DebugUtilsLabelEXT::default()
+                .label_name(&name)
+                .color(color);
+            unsafe { debug_utils.cmd_begin_debug_utils_label(self.raw, &label) };
+        }
+    }
+    /// Ends the last region begun with [`Self::begin_label`].
+    /// # Panics
+    /// If no label is open.
+    pub fn end_label(&mut self) {
+        assert!(self.labels > 0, "No label to end");
+        self.labels -= 1;
+        if let Some(debug_utils) = self.pool.device().debug_utils() {
+            unsafe { debug_utils.cmd_end_debug_utils_label(self.raw) };
+        }
+    }
+    /// Marks a single point in the commands.
+    pub fn insert_label(&mut self, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = self.pool.device().debug_utils() {
+            let name = crate::debug::c_name(name);
+            let label = vk::DebugUtilsLabelEXT::default()
+                .label_name(&name)
+                .color(color);
+            unsafe { debug_utils.cmd_insert_debug_utils_label(self.raw, &label) };
+        }
+    }
+    /// Labels the commands recorded through the returned scope, and enters a `tracing` span
+    /// with the same name while it is alive, so messages logged while recording are attributed
+    /// to it.
+    pub fn label(&mut self, name: &str) -> LabelScope<'_> {
+        let span = tracing::debug_span!("label", name).entered();
+        self.begin_label(name, [0.0; 4]);
+        LabelScope {
+            command_buffer: self,
+            _span: span,
+        }
+    }
+    /// Labels that are begun and not yet ended.
+    pub fn open_labels(&self) -> u32 {
+        self.labels
+    }
+
+    /// Keeps `resource` alive until the command buffer is done executing.
+    pub fn keep_alive<R: Send + Sync + 'static>(&mut self, resource: Arc<R>) {
+        self.resources.push(resource);
+    }
+
+    /// A barrier on all memory.
+    pub fn memory_barrier(
+        &mut self,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let barrier = vk::MemoryBarrier::default()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+        unsafe {
+            self.device().cmd_pipeline_barrier(
+                self.raw,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            )
+        };
+    }
+
+    /// See [`Image::transition`].
+    pub fn transition(
+        &mut self,
+        image: &Arc<Image>,
+        range: vk::ImageSubresourceRange,
+        new_layout: vk::ImageLayout,
+    ) {
+        image.transition(self.raw, range, new_layout);
+        self.keep_alive(image.clone());
+    }
+
+    /// [`Self::transition`] of the whole image.
+    pub fn transition_all(&mut self, image: &Arc<Image>, new_layout: vk::ImageLayout) {
+        self.transition(image, image.full_range(), new_layout);
+    }
+
+    /// Begins a dynamic render pass. Needs the `dynamicRendering` feature. Keeps what the
+    /// attachments hold alive.
+    pub fn begin_rendering(&mut self, description: &RenderingDescription) {
+        let color = description
+            .color_attachments
+            .iter()
+            .map(Attachment::info)
+            .collect::<Vec<_>>();
+        let depth = description.depth_attachment.as_ref().map(Attachment::info);
+        let stencil = description
+            .stencil_attachment
+            .as_ref()
+            .map(Attachment::info);
+        let mut info = vk::RenderingInfo::default()
+            .render_area(description.area)
+            .layer_count(1)
+            .color_attachments(&color);
+        if let Some(depth) = &depth {
+            info = info.depth_attachment(depth);
+        }
+        if let Some(stencil) = &stencil {
+            info = info.stencil_attachment(stencil);
+        }
+        if description.secondary_command_buffers {
+            info = info.flags(vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS);
+        }
+        unsafe { self.device().cmd_begin_rendering(self.raw, &info) };
+        let attachments = description
+            .color_attachments
+            .iter()
+            .chain(&description.depth_attachment)
+            .chain(&description.stencil_attachment);
+        for attachment in attachments {
+            self.resources.extend(attachment.resources.iter().cloned());
+        }
+    }
+
+    pub fn end_rendering(&mut self) {
+        unsafe { self.device().cmd_end_rendering(self.raw) };
+    }
+
+    /// Viewport and scissor covering `extent`, with depth from 0 to 1.
+    pub fn set_viewport(&mut self, extent: vk::Extent2D) {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        unsafe {
+            self.device().cmd_set_viewport(self.raw, 0, &[viewport]);
+            self.device().cmd_set_scissor(self.raw, 0, &[extent.into()]);
+        }
+    }
+
+    pub fn bind_pipeline<P: Pipeline>(&mut self, pipeline: &Arc<P>) {
+        unsafe {
+            self.device()
+                .cmd_bind_pipeline(self.raw, pipeline.bind_point(), pipeline.raw())
+        };
+        let bound = Bound {
+            layout: pipeline.layout(),
+            push_constants: pipeline.push_constants(),
+        };
+        self.bound.insert(pipeline.bind_point(), bound);
+        self.keep_alive(pipeline.clone());
+    }
+
+    fn bound(&self, bind_point: vk::PipelineBindPoint) -> Bound {
+        *self
+            .bound
+            .get(&bind_point)
+            .expect("A pipeline has to be bound first")
+    }
+
+    /// Binds `sets` from `first_set` on, using the layout of the bound pipeline. Keeps what the
+    /// sets are allocated from alive.
+    /// # Panics
+    /// If no pipeline is bound to `bind_point`.
+    pub fn bind_descriptor_sets(
+        &mut self,
+        bind_point: vk::PipelineBindPoint,
+        first_set: u32,
+        sets: &[&dyn BindableSet],
+        dynamic_offsets: &[u32],
+    ) {
+        let layout = self.bound(bind_point).layout;
+        let raw = sets.iter().map(|x| x.raw()).collect::<Vec<_>>();
+        unsafe {
+            self.device().cmd_bind_descriptor_sets(
+                self.raw,
+                bind_point,
+                layout,
+                first_set,
+                &raw,
+                dynamic_offsets,
+            )
+        };
+        for set in sets {
+            self.resources.push(set.owner());
+        }
+    }
+
+    /// Pushes `value`, e.g. the `PushConstants` struct generated by the shader macro, using the
+    /// layout of the pipeline bound to `bind_point`.
+    /// # Panics
+    /// If no pipeline is bound to `bind_point`, or `value` at `offset` does not fit the push
+    /// constant range of its layout for exactly the `stages` of the range.
+    pub fn push_constants<T: Pod>(
+        &mut self,
+        bind_point: vk::PipelineBindPoint,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        value: &T,
+    ) {
+        let bound = self.bound(bind_point);
+        let size = std::mem::size_of::<T>() as u32;
+        let range = bound
+            .push_constants
+            .expect("The bound pipeline has no push constants");
+        assert!(
+            range.offset <= offset && offset + size <= range.offset + range.size,
+            "{size} bytes at {offset} do not fit the push constant range {range:?}"
+        );
+        assert_eq!(
+            stages, range.stage_flags,
+            "Push constants are shared by every stage of the range"
+        );
+        assert!(
+            offset.is_multiple_of(4) && size.is_multiple_of(4),
+            "Push constants are written in words"
+        );
+        let bytes =
+            unsafe { std::slice::from_raw_parts((value as *const T).cast::<u8>(), size as usize) };
+        unsafe {
+            self.device()
+                .cmd_push_constants(self.raw, bound.layout, stages, offset, bytes)
+        };
+    }
+
+    pub fn bind_vertex_buffer<T: Pod>(&mut self, binding: u32, buffer: &Arc<Buffer<T>>) {
+        unsafe {
+            self.device()
+                .cmd_bind_vertex_buffers(self.raw, binding, &[buffer.raw()], &[0])
+        };
+        self.keep_alive(buffer.clone());
+    }
+
+    pub fn bind_index_buffer<I: IndexType>(&mut self, buffer: &Arc<Buffer<I>>) {
+        unsafe {
+            self.device()
+                .cmd_bind_index_buffer(self.raw, buffer.raw(), 0, I::INDEX_TYPE)
+        };
+        self.keep_alive(buffer.clone());
+    }
+
+    pub fn draw(&mut self, vertices: std::ops::Range<u32>, instances: std::ops::Range<u32>) {
+        unsafe {
+            self.device().cmd_draw(
+                self.raw,
+                vertices.len() as u32,
+                instances.len() as u32,
+                vertices.start,
+                instances.start,
+            )
+        };
+    }
+
+    pub fn draw_indexed(
+        &mut self,
+        indices: std::ops::Range<u32>,
+        vertex_offset: i32,
+        instances: std::ops::Range<u32>,
+    ) {
+        unsafe {
+            self.device().cmd_draw_indexed(
+                self.raw,
+                indices.len() as u32,
+                instances.len() as u32,
+                indices.start,
+                vertex_offset,
+                instances.start,
+            )
+        };
+    }
+
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        unsafe { self.device().cmd_dispatch(self.raw, x, y, z) };
+    }
+    /// Dispatches enough workgroups of the bound `pipeline` to cover `threads` invocations.
+    pub fn dispatch_threads(&mut self, pipeline: &ComputePipeline, threads: [u32; 3]) {
+        let [x, y, z] = pipeline.group_count(threads);
+        self.dispatch(x, y, z);
+    }
+    /// Dispatches the workgroup count at `index` of `buffer`, as written by an earlier command.
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn dispatch_indirect(
+        &mut self,
+        buffer: &Arc<Buffer<vk::DispatchIndirectCommand>>,
+        index: usize,
+    ) {
+        assert!(index < buffer.len());
+        let offset = (index * std::mem::size_of::<vk::DispatchIndirectCommand>()) as u64;
+        unsafe {
+            self.device()
+                .cmd_dispatch_indirect(self.raw, buffer.raw(), offset)
+        };
+        self.keep_alive(buffer.clone());
+    }
+
+    /// Copies `src[src_range]` to `dst` starting at element `dst_offset`.
+    /// # Panics
+    /// If either range is out of bounds.
+    pub fn copy_buffer<T: Pod>(
+        &mut self,
+        src: &Arc<Buffer<T>>,
+        src_range: std::ops::Range<usize>,
+        dst: &Arc<Buffer<T>>,
+        dst_offset: usize,
+    ) {
+        assert!(src_range.end <= src.len() && dst_offset + src_range.len() <= dst.len());
+        let size = std::mem::size_of::<T>() as u64;
+        let region = vk::BufferCopy {
+            src_offset: src_range.start as u64 * size,
+            dst_offset: dst_offset as u64 * size,
+            size: src_range.len() as u64 * size,
+        };
+        self.copy_buffer_untracked(src.raw(), dst.raw(), &[region]);
+        self.keep_alive(src.clone());
+        self.keep_alive(dst.clone());
+    }
+
+    /// For buffers the caller keeps alive until the submission is done.
+    pub(crate) fn copy_buffer_untracked(
+        &mut self,
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        regions: &[vk::BufferCopy],
+    ) {
+        unsafe { self.device().cmd_copy_buffer(self.raw, src, dst, regions) };
+    }
+
+    /// Copies into `image`, moving the copied subresources to `TRANSFER_DST_OPTIMAL` first.
+    pub fn copy_buffer_to_image<T: Pod>(
+        &mut self,
+        src: &Arc<Buffer<T>>,
+        dst: &Arc<Image>,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        let layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        for region in regions {
+            self.transition(dst, subresource_range(&region.image_subresource), layout);
+        }
+        unsafe {
+            self.device()
+                .cmd_copy_buffer_to_image(self.raw, src.raw(), dst.raw(), layout, regions)
+        };
+        self.keep_alive(src.clone());
+    }
+
+    /// Copies from `image`, moving the copied subresources to `TRANSFER_SRC_OPTIMAL` first.
+    pub fn copy_image_to_buffer<T: Pod>(
+        &mut self,
+        src: &Arc<Image>,
+        dst: &Arc<Buffer<T>>,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        let layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        for region in regions {
+            self.transition(src, subresource_range(&region.image_subresource), layout);
+        }
+        unsafe {
+            self.device()
+                .cmd_copy_image_to_buffer(self.raw, src.raw(), layout, dst.raw(), regions)
+        };
+        self.keep_alive(dst.clone());
+    }
+
+    /// Records `secondaries`, they are kept until this command buffer is done executing.
+    /// # Errors
+    /// If a secondary command buffer cannot be ended.
+    pub fn execute_commands(&mut self, secondaries: Vec<CommandBuffer>) -> Result<(), VkError> {
+        for secondary in &secondaries {
+            debug_assert_eq!(secondary.level, vk::CommandBufferLevel::SECONDARY);
+            unsafe { self.device().end_command_buffer(secondary.raw) }?;
+        }
+        let raw = secondaries.iter().map(|x| x.raw).collect::<Vec<_>>();
+        unsafe { self.device().cmd_execute_commands(self.raw, &raw) };
+        self.secondaries.extend(secondaries);
+        Ok(())
+    }
+
+    /// Ends the command buffer and submits it to `queue`, see [`QueueSubmit`] to wait on or
+    /// signal semaphores.
+    /// # Errors
+    /// If ending or submitting fails.
+    /// # Panics
+    /// If this is a secondary command buffer.
+    pub fn submit(self, queue: &Queue) -> Result<Submission, VkError> {
+        let device = self.pool.device().clone();
+        QueueSubmit::new(&device).command_buffer(self).submit(queue)
+    }
+}
+
+fn subresource_range(layers: &vk::ImageSubresourceLayers) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: layers.aspect_mask,
+        base_mip_level: layers.mip_level,
+        level_count: 1,
+        base_array_layer: layers.base_array_layer,
+        layer_count: layers.layer_count,
+    }
+}
+
+/// A labeled region of a [`CommandBuffer`], ended when dropped. See [`CommandBuffer::label`].
+#[must_use = "The label ends when the scope is dropped"]
+pub struct LabelScope<'a> {
+    command_buffer: &'a mut CommandBuffer,
+    _span: tracing::span::EnteredSpan,
+}
+
+impl std::fmt::Debug for LabelScope<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LabelScope")
+            .field("command_buffer", &self.command_buffer.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::ops::Deref for LabelScope<'_> {
+    type Target = CommandBuffer;
+    fn deref(&self) -> &CommandBuffer {
+        self.command_buffer
+    }
+}
+impl std::ops::DerefMut for LabelScope<'_> {
+    fn deref_mut(&mut self) -> &mut CommandBuffer {
+        self.command_buffer
+    }
+}
+
+impl Drop for LabelScope<'_> {
+    fn drop(&mut self) {
+        self.command_buffer.end_label();
+    }
+}
+
+/// Command buffers submitted together, with the semaphores they wait on and signal.
+pub struct QueueSubmit {
+    device: Arc<Device>,
+    command_buffers: Vec<CommandBuffer>,
+    /// Semaphore, timeline value and stage.
+    waits: Vec<(vk::Semaphore, u64, vk::PipelineStageFlags)>,
+    /// Semaphore and timeline value.
+    signals: Vec<(vk::Semaphore, u64)>,
+    timeline_used: bool,
+    semaphores: Vec<Arc<dyn Any + Send + Sync>>,
+    /// The last timeline signaled, the submission waits on it instead of a fence.
+    completion: Option<(Arc<TimelineSemaphore>, u64)>,
+}
+
+impl std::fmt::Debug for QueueSubmit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueueSubmit")
+            .field("command_buffers", &self.command_buffers)
+            .field("waits", &self.waits)
+            .field("signals", &self.signals)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QueueSubmit {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: device.clone(),
+            command_buffers: vec![],
+            waits: vec![],
+            signals: vec![],
+            timeline_used: false,
+            semaphores: vec![],
+            completion: None,
+        }
+    }
+
+    /// # Panics
+    /// If this is a secondary command buffer.
+    #[must_use]
+    pub fn command_buffer(mut self, command_buffer: CommandBuffer) -> Self {
+        assert_eq!(
+            command_buffer.level,
+            vk::CommandBufferLevel::PRIMARY,
+            "Only primary command buffers can be submitted"
+        );
+        self.command_buffers.push(command_buffer);
+        self
+    }
+    /// Waits for `semaphore` before `stage`.
+    #[must_use]
+    pub fn wait(mut self, semaphore: &Arc<Semaphore>, stage: vk::PipelineStageFlags) -> Self {
+        self.waits.push((semaphore.raw(), 0, stage));
+        self.semaphores.push(semaphore.clone());
+        self
+    }
+    /// Waits for `semaphore` to reach `value` before `stage`.
+    #[must_use]
+    pub fn wait_timeline(
+        mut self,
+        semaphore: &Arc<TimelineSemaphore>,
+        value: u64,
+        stage: vk::PipelineStageFlags,
+    ) -> Self {
+        self.waits.push((semaphore.raw(), value, stage));
+        self.timeline_used = true;
+        self.semaphores.push(semaphore.clone());
+        self
+    }
+    #[must_use]
+    pub fn signal(mut self, semaphore: &Arc<Semaphore>) -> Self {
+        self.signals.push((semaphore.raw(), 0));
+        self.semaphores.push(semaphore.clone());
+        self
+    }
+    /// Sets `semaphore` to `value` once the command buffers are done executing.
+    #[must_use]
+    pub fn signal_timeline(mut self, semaphore: &Arc<TimelineSemaphore>, value: u64) -> Self {
+        self.signals.push((semaphore.raw(), value));
+        self.timeline_used = true;
+        self.completion = Some((semaphore.clone(), value));
+        self
+    }
+
+    /// Ends the command buffers and submits them to `queue`.
+    /// # Errors
+    /// If ending or submitting fails.
+    pub fn submit(self, queue: &Queue) -> Result<Submission, VkError> {
+        let device = self.device.raw();
+        for command_buffer in &self.command_buffers {
+            unsafe { device.end_command_buffer(command_buffer.raw) }?;
+        }
+        let completion = match self.completion {
+            Some((timeline, value)) => Completion::Timeline(timeline, value),
+            None => Completion::Fence(Fence::new(&self.device, false)?),
+        };
+        let fence = match &completion {
+            Completion::Fence(fence) => fence.raw(),
+            Completion::Timeline(..) => vk::Fence::null(),
+        };
+
+        let command_buffers = self
+            .command_buffers
+            .iter()
+            .map(|x| x.raw)
+            .collect::<Vec<_>>();
+        let wait_semaphores = self.waits.iter().map(|x| x.0).collect::<Vec<_>>();
+        let wait_values = self.waits.iter().map(|x| x.1).collect::<Vec<_>>();
+        let wait_stages = self.waits.iter().map(|x| x.2).collect::<Vec<_>>();
+        let signal_semaphores = self.signals.iter().map(|x| x.0).collect::<Vec<_>>();
+        let signal_values = self.signals.iter().map(|x| x.1).collect::<Vec<_>>();
+        // Values of binary semaphores are ignored.
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let mut submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .signal_semaphores(&signal_semaphores);
+        if self.timeline_used {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+        let queue = queue.lock();
+        let result = unsafe { device.queue_submit(*queue, &[submit_info], fence) };
+        // Device lost hooks may use the queue.
+        drop(queue);
+        self.device.check(result)?;
+        Ok(Submission {
+            completion,
+            command_buffers: self.command_buffers,
+            semaphores: self.semaphores,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Completion {
+    Fence(Fence),
+    Timeline(Arc<TimelineSemaphore>, u64),
+}
+
+/// Submitted command buffers, with everything they use. Waits for the device when dropped.
+pub struct Submission {
+    completion: Completion,
+    command_buffers: Vec<CommandBuffer>,
+    semaphores: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+impl std::fmt::Debug for Submission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Submission")
+            .field("completion", &self.completion)
+            .field("command_buffers", &self.command_buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Submission {
+    /// # Errors
+    /// If the device is lost.
+    pub fn is_complete(&self) -> Result<bool, VkError> {
+        match &self.completion {
+            Completion::Fence(fence) => fence.is_signaled(),
+            Completion::Timeline(timeline, value) => Ok(timeline.value()? >= *value),
+        }
+    }
+    /// # Errors
+    /// If the device is lost.
+    pub fn wait(&self) -> Result<(), VkError> {
+        match &self.completion {
+            Completion::Fence(fence) => fence.wait(u64::MAX),
+            Completion::Timeline(timeline, value) => timeline.wait(*value, u64::MAX),
+        }?;
+        Ok(())
+    }
+}
+
+impl Drop for Submission {
+    fn drop(&mut self) {
+        // Nothing is executing anymore if the device is lost.
+        let _ = self.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::BufferDescription;
+    use crate::image::{ImageDescription, ImageDimensions};
+    use crate::memory::Allocator;
+
+    #[test]
+    fn resolve_modes() {
+        use vk::ResolveModeFlags as M;
+        assert_eq!(resolve_mode(vk::Format::R8G8B8A8_SRGB), M::AVERAGE);
+        assert_eq!(resolve_mode(vk::Format::R16G16B16A16_SFLOAT), M::AVERAGE);
+        assert_eq!(resolve_mode(vk::Format::R32_UINT), M::SAMPLE_ZERO);
+        assert_eq!(resolve_mode(vk::Format::R8G8_SINT), M::SAMPLE_ZERO);
+        assert_eq!(resolve_mode(vk::Format::D32_SFLOAT), M::SAMPLE_ZERO);
+        assert_eq!(resolve_mode(vk::Format::D24_UNORM_S8_UINT), M::SAMPLE_ZERO);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn record_and_submit() {
+        let (device, queue) = crate::test_support::device(
+            "command test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::TRANSFER,
+        );
+        let allocator = Allocator::new(&device, Default::default());
+        let pools = CommandPools::new(&device);
+        let pool = pools.current(queue.family_index()).unwrap();
+        assert_eq!(
+            pools.current(queue.family_index()).unwrap().raw(),
+            pool.raw()
+        );
+
+        let pixels = vec![0xff00ff00u32; 16 * 16];
+        let staging = Arc::new(
+            Buffer::from_slice(
+                &allocator,
+                &queue,
+                &pixels,
+                BufferDescription::new(crate::memory::MemoryUsage::Upload)
+                    .transfer_src()
+                    .name("staging"),
+            )
+            .unwrap(),
+        );
+        let readback = Arc::new(
+            Buffer::<u32>::new(
+                &allocator,
+                pixels.len(),
+                BufferDescription::new(crate::memory::MemoryUsage::Readback).transfer_dst(),
+            )
+            .unwrap(),
+        );
+        let image = Image::new(
+            &allocator,
+            &ImageDescription::new(ImageDimensions::d2(16, 16), vk::Format::R8G8B8A8_UNORM)
+                .transfer_src()
+                .transfer_dst()
+                .name("image"),
+        )
+        .unwrap();
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(image.extent());
+
+        let mut command_buffer = pool.primary().unwrap();
+        command_buffer.set_debug_name("record and submit");
+        {
+            let mut upload = command_buffer.label("upload");
+            upload.copy_buffer_to_image(&staging, &image, &[region]);
+            let mut readback_label = upload.label("readback");
+            assert_eq!(readback_label.open_labels(), 2);
+            readback_label.insert_label("copy", [1.0, 0.0, 0.0, 1.0]);
+            readback_label.copy_image_to_buffer(&image, &readback, &[region]);
+        }
+        assert_eq!(command_buffer.open_labels(), 0);
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_READ,
+        );
+        let secondary = pool.secondary(None).unwrap();
+        command_buffer.execute_commands(vec![secondary]).unwrap();
+        let submission = command_buffer.submit(&queue).unwrap();
+
+        // The submission keeps the image and buffers alive.
+        let weak = Arc::downgrade(&image);
+        drop(image);
+        assert!(weak.upgrade().is_some());
+        submission.wait().unwrap();
+        assert!(submission.is_complete().unwrap());
+        drop(submission);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(readback.read(0..pixels.len()).unwrap(), pixels);
+
+        // Command buffers are recycled.
+        let first = pool.primary().unwrap();
+        let raw = first.raw();
+        drop(first);
+        assert_eq!(pool.primary().unwrap().raw(), raw);
+    }
+}
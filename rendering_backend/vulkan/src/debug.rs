@@ -0,0 +1,442 @@
+//! Routing of `VK_EXT_debug_utils` messages, including the validation layer, into `tracing`.
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Identifies a debug message, either by its name (the VUID for validation messages) or by
+/// its number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageId {
+    Name(String),
+    Number(i32),
+}
+
+impl From<&str> for MessageId {
+    fn from(value: &str) -> Self {
+        Self::Name(value.to_string())
+    }
+}
+impl From<String> for MessageId {
+    fn from(value: String) -> Self {
+        Self::Name(value)
+    }
+}
+impl From<i32> for MessageId {
+    fn from(value: i32) -> Self {
+        Self::Number(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugMessengerConfig {
+    pub severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub types: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Messages with these ids are neither logged nor captured.
+    pub suppressed: Vec<MessageId>,
+}
+
+impl DebugMessengerConfig {
+    pub fn suppress(mut self, id: impl Into<MessageId>) -> Self {
+        self.suppressed.push(id.into());
+        self
+    }
+
+    fn default_severity() -> ash::vk::DebugUtilsMessageSeverityFlagsEXT {
+        #[cfg(build_type = "debug")]
+        {
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                | ash::vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+        }
+        #[cfg(not(build_type = "debug"))]
+        {
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+        }
+    }
+    fn default_types() -> ash::vk::DebugUtilsMessageTypeFlagsEXT {
+        #[cfg(build_type = "dist")]
+        {
+            ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+        }
+        #[cfg(not(build_type = "dist"))]
+        {
+            ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | ash::vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+        }
+    }
+}
+
+impl Default for DebugMessengerConfig {
+    fn default() -> Self {
+        Self {
+            severity: Self::default_severity(),
+            types: Self::default_types(),
+            suppressed: vec![],
+        }
+    }
+}
+
+/// An object referenced by a debug message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugObject {
+    pub ty: ash::vk::ObjectType,
+    pub handle: u64,
+    pub name: Option<String>,
+}
+
+impl std::fmt::Display for DebugObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{:?} {:#x} \"{}\"", self.ty, self.handle, name),
+            None => write!(f, "{:?} {:#x}", self.ty, self.handle),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugMessage {
+    pub severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub types: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+    pub id_name: String,
+    pub id_number: i32,
+    pub message: String,
+    pub objects: Vec<DebugObject>,
+    pub queue_labels: Vec<String>,
+    pub command_buffer_labels: Vec<String>,
+}
+
+impl DebugMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity
+            .contains(ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+    pub fn is_validation(&self) -> bool {
+        self.types
+            .contains(ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
+    }
+    pub fn matches(&self, id: &MessageId) -> bool {
+        match id {
+            MessageId::Name(name) => self.id_name == *name,
+            MessageId::Number(number) => self.id_number == *number,
+        }
+    }
+
+    /// # Safety
+    /// `data` must be the callback data passed by the Vulkan implementation.
+    unsafe fn from_raw(
+        severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+        types: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+        data: &ash::vk::DebugUtilsMessengerCallbackDataEXT,
+    ) -> Self {
+        unsafe fn string(ptr: *const std::os::raw::c_char) -> Option<String> {
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+        unsafe fn slice<'a, T>(ptr: *const T, len: u32) -> &'a [T] {
+            if ptr.is_null() || len == 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts(ptr, len as usize)
+            }
+        }
+        let labels = |labels: &[ash::vk::DebugUtilsLabelEXT]| {
+            labels
+                .iter()
+                .filter_map(|label| string(label.p_label_name))
+                .collect::<Vec<_>>()
+        };
+        Self {
+            severity,
+            types,
+            id_name: string(data.p_message_id_name).unwrap_or_default(),
+            id_number: data.message_id_number,
+            message: string(data.p_message).unwrap_or_default(),
+            objects: slice(data.p_objects, data.object_count)
+                .iter()
+                .map(|object| DebugObject {
+                    ty: object.object_type,
+                    handle: object.object_handle,
+                    name: string(object.p_object_name),
+                })
+                .collect(),
+            queue_labels: labels(slice(data.p_queue_labels, data.queue_label_count)),
+            command_buffer_labels: labels(slice(data.p_cmd_buf_labels, data.cmd_buf_label_count)),
+        }
+    }
+
+    fn emit(&self) {
+        let objects = self
+            .objects
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        macro_rules! emit {
+            ($level:ident) => {
+                tracing::$level!(
+                    target: "vulkan",
+                    id = %self.id_name,
+                    id_number = self.id_number,
+                    types = ?self.types,
+                    objects = ?objects,
+                    queue_labels = ?self.queue_labels,
+                    command_buffer_labels = ?self.command_buffer_labels,
+                    "{}",
+                    self.message
+                )
+            };
+        }
+        match self.severity {
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => emit!(error),
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => emit!(warn),
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO => emit!(info),
+            _ => emit!(debug),
+        }
+    }
+}
+
+type CaptureBuffer = Arc<Mutex<Vec<DebugMessage>>>;
+
+/// Shared with the debug messenger callback, which may be invoked from any thread.
+#[derive(Debug, Default)]
+pub struct DebugCallBackData {
+    suppressed: Mutex<Vec<MessageId>>,
+    captures: Mutex<Vec<CaptureBuffer>>,
+}
+
+impl DebugCallBackData {
+    pub(crate) fn new(config: &DebugMessengerConfig) -> Self {
+        Self {
+            suppressed: Mutex::new(config.suppressed.clone()),
+            captures: Mutex::default(),
+        }
+    }
+
+    pub fn suppress(&self, id: impl Into<MessageId>) {
+        lock(&self.suppressed).push(id.into());
+    }
+    pub fn unsuppress(&self, id: &MessageId) {
+        lock(&self.suppressed).retain(|x| x != id);
+    }
+
+    fn handle(&self, message: DebugMessage) {
+        if lock(&self.suppressed).iter().any(|id| message.matches(id)) {
+            return;
+        }
+        message.emit();
+        for capture in lock(&self.captures).iter() {
+            lock(capture).push(message.clone());
+        }
+    }
+}
+
+/// `name` as a C string for `VK_EXT_debug_utils`, without the nul characters it cannot hold.
+pub(crate) fn c_name(name: &str) -> std::ffi::CString {
+    std::ffi::CString::new(name.replace('\0', "")).expect("Nul characters are removed")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A panic while holding these locks cannot leave the data inconsistent.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Records every message that is not suppressed while it is alive. Captures may be nested.
+///
+/// Captures nothing if the instance has no debug messenger, see
+/// [`DebugCapture::is_active`].
+#[must_use = "Messages are only captured while the capture is alive"]
+#[derive(Debug)]
+pub struct DebugCapture {
+    buffer: CaptureBuffer,
+    data: Option<Arc<DebugCallBackData>>,
+}
+
+impl DebugCapture {
+    pub(crate) fn new(data: Option<Arc<DebugCallBackData>>) -> Self {
+        let buffer = CaptureBuffer::default();
+        if let Some(data) = &data {
+            lock(&data.captures).push(buffer.clone());
+        }
+        Self { buffer, data }
+    }
+
+    /// Whether a debug messenger is feeding this capture.
+    pub fn is_active(&self) -> bool {
+        self.data.is_some()
+    }
+
+    pub fn messages(&self) -> Vec<DebugMessage> {
+        lock(&self.buffer).clone()
+    }
+
+    pub fn errors(&self) -> Vec<DebugMessage> {
+        lock(&self.buffer)
+            .iter()
+            .filter(|x| x.is_error())
+            .cloned()
+            .collect()
+    }
+
+    /// # Panics
+    /// Panics listing the validation errors received since the capture started.
+    #[track_caller]
+    pub fn assert_no_validation_errors(&self) {
+        let errors = lock(&self.buffer)
+            .iter()
+            .filter(|x| x.is_error() && x.is_validation())
+            .map(|x| format!("[{}] {}", x.id_name, x.message))
+            .collect::<Vec<_>>();
+        assert!(
+            errors.is_empty(),
+            "{} validation error(s):\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+}
+
+impl Drop for DebugCapture {
+    fn drop(&mut self) {
+        if let Some(data) = &self.data {
+            lock(&data.captures).retain(|x| !Arc::ptr_eq(x, &self.buffer));
+        }
+    }
+}
+
+pub(crate) fn messenger_create_info<'a>(
+    config: &DebugMessengerConfig,
+    callback_data: &'a Arc<DebugCallBackData>,
+) -> ash::vk::DebugUtilsMessengerCreateInfoEXT<'a> {
+    tracing::info!(
+        "Vulkan message callback severity set to: {:?}, types set to: {:?}",
+        config.severity,
+        config.types
+    );
+    ash::vk::DebugUtilsMessengerCreateInfoEXT::default()
+        .message_severity(config.severity)
+        .message_type(config.types)
+        .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(Arc::as_ptr(callback_data) as *mut std::os::raw::c_void)
+}
+
+/// A lambda function you can pass to vulkan.
+unsafe extern "system" fn vulkan_debug_callback(
+    message_severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const ash::vk::DebugUtilsMessengerCallbackDataEXT,
+    user_data: *mut std::os::raw::c_void,
+) -> ash::vk::Bool32 {
+    if p_callback_data.is_null() || user_data.is_null() {
+        return ash::vk::FALSE;
+    }
+    let callback_data = &*(user_data as *const DebugCallBackData);
+    let message = DebugMessage::from_raw(message_severity, message_type, &*p_callback_data);
+    // Unwinding into the Vulkan implementation is undefined behaviour.
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        callback_data.handle(message);
+    }));
+
+    ash::vk::FALSE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT, id: &str) -> DebugMessage {
+        DebugMessage {
+            severity,
+            types: ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            id_name: id.to_string(),
+            id_number: 7,
+            message: "message".to_string(),
+            objects: vec![],
+            queue_labels: vec![],
+            command_buffer_labels: vec![],
+        }
+    }
+
+    #[test]
+    fn suppression_and_capture() {
+        let data = Arc::new(DebugCallBackData::new(
+            &DebugMessengerConfig::default().suppress("VUID-suppressed"),
+        ));
+        let outer = DebugCapture::new(Some(data.clone()));
+        data.handle(message(
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            "VUID-warning",
+        ));
+        {
+            let inner = DebugCapture::new(Some(data.clone()));
+            data.handle(message(
+                ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                "VUID-suppressed",
+            ));
+            inner.assert_no_validation_errors();
+            data.handle(message(
+                ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                "VUID-error",
+            ));
+            assert_eq!(inner.errors().len(), 1);
+        }
+        data.suppress(7);
+        data.handle(message(
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            "VUID-other",
+        ));
+        let ids = outer
+            .messages()
+            .into_iter()
+            .map(|x| x.id_name)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["VUID-warning", "VUID-error"]);
+        assert_eq!(lock(&data.captures).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 validation error(s)")]
+    fn assert_no_validation_errors() {
+        let data = Arc::new(DebugCallBackData::default());
+        let capture = DebugCapture::new(Some(data.clone()));
+        data.handle(message(
+            ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            "VUID-error",
+        ));
+        capture.assert_no_validation_errors();
+    }
+
+    /// Needs a Vulkan implementation with `VK_EXT_debug_utils`.
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    #[cfg(not(build_type = "dist"))]
+    fn capture_from_instance() {
+        let instance = crate::test_support::instance(crate::InstanceCreateInfo::compute_usage(
+            "debug capture test",
+        ));
+        let messenger = instance
+            .debug_messenger_instance
+            .as_ref()
+            .expect("VK_EXT_debug_utils is unavailable");
+        let capture = instance.capture_debug_messages();
+        assert!(capture.is_active());
+        instance.suppress_debug_message("horizon-suppressed");
+        for id in [c"horizon-test", c"horizon-suppressed"] {
+            let data = ash::vk::DebugUtilsMessengerCallbackDataEXT::default()
+                .message_id_name(id)
+                .message(c"injected");
+            unsafe {
+                messenger._debug_utils.submit_debug_utils_message(
+                    ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                    ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                    &data,
+                )
+            };
+        }
+        let errors = capture.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].id_name, "horizon-test");
+        assert_eq!(errors[0].message, "injected");
+    }
+}
@@ -0,0 +1,782 @@
+//! Descriptor sets.
+//!
+//! Set layouts are cached by the device, keyed by their [`SetLayoutDescription`]. A
+//! [`DescriptorAllocator`] keeps growing pools for every layout and recycles all of its sets at
+//! once; every frame of a [`FrameContext`](crate::sync::FrameContext) has one. A
+//! [`DescriptorWriter`] checks what is bound against the bindings the shaders declare.
+use super::bindless;
+use super::buffer::Buffer;
+use super::device::Device;
+use super::error::{DescriptorError, PipelineError, VkError};
+use super::handle::{own, Owned};
+use super::image::{Image, ImageView};
+use super::pipeline::{DescriptorBinding, PipelineLayout};
+use super::types::Pod;
+use ash::vk;
+use spirv_reflect::{BindingCount, DescriptorInfo, DescriptorType};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// A binding of a descriptor set layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SetLayoutBinding {
+    pub binding: u32,
+    pub ty: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub flags: vk::DescriptorBindingFlags,
+}
+
+/// The bindings of a descriptor set layout, see [`Device::descriptor_set_layout`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SetLayoutDescription {
+    /// Sorted by binding.
+    bindings: Vec<SetLayoutBinding>,
+}
+
+impl SetLayoutDescription {
+    pub fn new(mut bindings: Vec<SetLayoutBinding>) -> Self {
+        bindings.sort_by_key(|x| x.binding);
+        Self { bindings }
+    }
+
+    /// The layout of a set as the shaders declare it. A set with unbounded arrays is the
+    /// [`bindless`](crate::bindless) set.
+    /// # Errors
+    /// If a set with unbounded arrays does not match the bindless set.
+    pub fn from_reflection(
+        bindings: &BTreeMap<u32, DescriptorBinding>,
+    ) -> Result<Self, PipelineError> {
+        if bindings
+            .values()
+            .any(|x| x.info.binding_count == BindingCount::Unbounded)
+        {
+            let bindless = bindless::set_layout_description();
+            let matches = bindings.iter().all(|(binding, x)| {
+                bindless.bindings.iter().any(|y| {
+                    y.binding == *binding
+                        && y.ty == vk_descriptor_type(x.info.ty)
+                        && x.info.binding_count == BindingCount::Unbounded
+                })
+            });
+            if !matches {
+                return Err(PipelineError::InvalidDescription(
+                    "unbounded descriptor arrays are only supported in the bindless set",
+                ));
+            }
+            return Ok(bindless);
+        }
+        let bindings = bindings
+            .iter()
+            .map(|(binding, x)| {
+                Ok(SetLayoutBinding {
+                    binding: *binding,
+                    ty: vk_descriptor_type(x.info.ty),
+                    count: descriptor_count(&x.info)?,
+                    stages: x.stages,
+                    flags: vk::DescriptorBindingFlags::empty(),
+                })
+            })
+            .collect::<Result<_, PipelineError>>()?;
+        Ok(Self::new(bindings))
+    }
+
+    pub fn bindings(&self) -> &[SetLayoutBinding] {
+        &self.bindings
+    }
+
+    pub(crate) fn vk_bindings(&self) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .iter()
+            .map(|x| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(x.binding)
+                    .descriptor_type(x.ty)
+                    .descriptor_count(x.count)
+                    .stage_flags(x.stages)
+            })
+            .collect()
+    }
+
+    pub(crate) fn vk_binding_flags(&self) -> Vec<vk::DescriptorBindingFlags> {
+        self.bindings.iter().map(|x| x.flags).collect()
+    }
+
+    /// Whether sets of this layout come from pools created with `UPDATE_AFTER_BIND`.
+    pub fn update_after_bind(&self) -> bool {
+        self.bindings.iter().any(|x| {
+            x.flags
+                .contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND)
+        })
+    }
+
+    /// Descriptors needed for `sets` sets of this layout.
+    pub(crate) fn pool_sizes(&self, sets: u32) -> Vec<vk::DescriptorPoolSize> {
+        let mut sizes = BTreeMap::<i32, u32>::new();
+        for binding in &self.bindings {
+            *sizes.entry(binding.ty.as_raw()).or_default() += binding.count * sets;
+        }
+        sizes
+            .into_iter()
+            .map(|(ty, descriptor_count)| vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::from_raw(ty),
+                descriptor_count,
+            })
+            .collect()
+    }
+}
+
+/// The reflected types mirror the Vulkan values.
+pub(crate) fn vk_descriptor_type(ty: DescriptorType) -> vk::DescriptorType {
+    vk::DescriptorType::from_raw(ty as i32)
+}
+
+pub(crate) fn descriptor_count(info: &DescriptorInfo) -> Result<u32, PipelineError> {
+    match info.binding_count {
+        BindingCount::One => Ok(1),
+        BindingCount::StaticSized(count) => Ok(count as u32),
+        BindingCount::Unbounded => Err(PipelineError::InvalidDescription(
+            "unbounded descriptor arrays are not supported",
+        )),
+    }
+}
+
+/// The pools of a layout.
+#[derive(Debug, Default)]
+struct LayoutPools {
+    /// Pools with sets left.
+    available: Vec<(Arc<Owned<vk::DescriptorPool>>, u32)>,
+    /// Pools without sets left, available again after a reset.
+    full: Vec<(Arc<Owned<vk::DescriptorPool>>, u32)>,
+}
+
+/// Allocates descriptor sets from pools that grow as needed, one list of pools per layout.
+///
+/// Sets are not freed one by one; [`DescriptorAllocator::reset`] recycles all of them.
+pub struct DescriptorAllocator {
+    device: Arc<Device>,
+    pools: Mutex<HashMap<vk::DescriptorSetLayout, LayoutPools>>,
+}
+
+impl std::fmt::Debug for DescriptorAllocator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DescriptorAllocator")
+            .field("pools", &self.pools)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DescriptorAllocator {
+    const FIRST_POOL_SETS: u32 = 16;
+    const MAX_POOL_SETS: u32 = 1024;
+
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: device.clone(),
+            pools: Mutex::default(),
+        }
+    }
+
+    /// A set with the layout of `set` in `layout`, valid until the allocator is reset or
+    /// dropped. `name` is shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If a pool or the set cannot be created.
+    /// # Panics
+    /// If `layout` has no such set.
+    pub fn allocate(
+        &self,
+        layout: &Arc<PipelineLayout>,
+        set: u32,
+        name: Option<&str>,
+    ) -> Result<DescriptorSet, VkError> {
+        let (raw, pool) = self.allocate_from_pool(
+            layout.set_layouts()[set as usize],
+            &layout.set_descriptions()[set as usize],
+        )?;
+        let set = DescriptorSet {
+            raw,
+            index: set,
+            layout: layout.clone(),
+            resources: Arc::new(Mutex::new(Arc::new(SetResources {
+                pool,
+                written: BTreeMap::new(),
+            }))),
+        };
+        if let Some(name) = name {
+            set.set_debug_name(name);
+        }
+        Ok(set)
+    }
+
+    /// A set with the layout described by `description`.
+    /// # Errors
+    /// If a pool or the set cannot be created.
+    pub fn allocate_raw(
+        &self,
+        set_layout: vk::DescriptorSetLayout,
+        description: &SetLayoutDescription,
+    ) -> Result<vk::DescriptorSet, VkError> {
+        Ok(self.allocate_from_pool(set_layout, description)?.0)
+    }
+
+    fn allocate_from_pool(
+        &self,
+        set_layout: vk::DescriptorSetLayout,
+        description: &SetLayoutDescription,
+    ) -> Result<(vk::DescriptorSet, Arc<Owned<vk::DescriptorPool>>), VkError> {
+        let mut pools = self.pools.lock().unwrap();
+        let pools = pools.entry(set_layout).or_default();
+        loop {
+            let (pool, created) = match pools.available.last() {
+                Some((pool, _)) => (pool.clone(), false),
+                None => {
+                    let capacity = pools
+                        .available
+                        .iter()
+                        .chain(&pools.full)
+                        .map(|x| x.1 * 2)
+                        .max()
+                        .unwrap_or(Self::FIRST_POOL_SETS)
+                        .min(Self::MAX_POOL_SETS);
+                    let pool = Arc::new(self.create_pool(description, capacity)?);
+                    pools.available.push((pool.clone(), capacity));
+                    (pool, true)
+                }
+            };
+            let set_layouts = [set_layout];
+            let allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(**pool)
+                .set_layouts(&set_layouts);
+            match unsafe { self.device.raw().allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => return Ok((sets[0], pool)),
+                // A new pool failing would only fail again.
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                    if !created =>
+                {
+                    let full = pools.available.pop().unwrap();
+                    pools.full.push(full);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn create_pool(
+        &self,
+        description: &SetLayoutDescription,
+        sets: u32,
+    ) -> Result<Owned<vk::DescriptorPool>, VkError> {
+        let mut pool_sizes = description.pool_sizes(sets);
+        // A pool needs at least one size, even for empty layouts.
+        if pool_sizes.is_empty() {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: 1,
+            });
+        }
+        let flags = if description.update_after_bind() {
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+        } else {
+            vk::DescriptorPoolCreateFlags::empty()
+        };
+        let create_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(flags)
+            .pool_sizes(&pool_sizes)
+            .max_sets(sets);
+        let pool = unsafe {
+            self.device
+                .raw()
+                .create_descriptor_pool(&create_info, self.device.allocation_callbacks())
+        }?;
+        Ok(own(&self.device, pool))
+    }
+
+    /// Recycles every set.
+    /// # Errors
+    /// If a pool cannot be reset.
+    /// # Safety
+    /// The device must be done with every set allocated since the last reset, and none of them
+    /// may be written or bound afterwards.
+    pub unsafe fn reset(&mut self) -> Result<(), VkError> {
+        for pools in self.pools.get_mut().unwrap().values_mut() {
+            let full = std::mem::take(&mut pools.full);
+            pools.available.extend(full);
+            for (pool, _) in &pools.available {
+                unsafe {
+                    self.device
+                        .raw()
+                        .reset_descriptor_pool(***pool, vk::DescriptorPoolResetFlags::empty())
+                }?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A descriptor set [`CommandBuffer::bind_descriptor_sets`](crate::command::CommandBuffer::bind_descriptor_sets)
+/// can bind.
+pub trait BindableSet {
+    fn raw(&self) -> vk::DescriptorSet;
+    /// What the set is allocated from, kept alive until the command buffer the set is bound in
+    /// is done executing.
+    fn owner(&self) -> Arc<dyn Any + Send + Sync>;
+}
+
+/// What a set keeps alive: its pool and what is written to it.
+struct SetResources {
+    pool: Arc<Owned<vk::DescriptorPool>>,
+    /// By binding and array element.
+    written: BTreeMap<(u32, u32), Arc<dyn Any + Send + Sync>>,
+}
+
+impl std::fmt::Debug for SetResources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SetResources")
+            .field("pool", &self.pool)
+            .field("written", &self.written.keys())
+            .finish()
+    }
+}
+
+/// A set allocated for a [`PipelineLayout`], see [`DescriptorAllocator::allocate`].
+#[derive(Debug, Clone)]
+pub struct DescriptorSet {
+    raw: vk::DescriptorSet,
+    index: u32,
+    layout: Arc<PipelineLayout>,
+    /// Replaced on every update, so command buffers the set was bound in keep what they use.
+    resources: Arc<Mutex<Arc<SetResources>>>,
+}
+
+impl BindableSet for DescriptorSet {
+    fn raw(&self) -> vk::DescriptorSet {
+        self.raw
+    }
+    fn owner(&self) -> Arc<dyn Any + Send + Sync> {
+        self.resources.lock().unwrap().clone()
+    }
+}
+
+impl DescriptorSet {
+    pub fn raw(&self) -> vk::DescriptorSet {
+        self.raw
+    }
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.layout.device().set_debug_name(self.raw, name);
+    }
+    /// The set number in the pipeline layout.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+    /// The bindings as the shaders declare them.
+    pub fn bindings(&self) -> &BTreeMap<u32, DescriptorBinding> {
+        static EMPTY: BTreeMap<u32, DescriptorBinding> = BTreeMap::new();
+        self.layout.sets().get(&self.index).unwrap_or(&EMPTY)
+    }
+    pub fn write(&self) -> DescriptorWriter<'_> {
+        DescriptorWriter {
+            set: self,
+            buffers: vec![],
+            images: vec![],
+            resources: vec![],
+        }
+    }
+}
+
+/// What is written to a binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resource {
+    Buffer,
+    Image,
+    ImageWithSampler,
+    Sampler,
+}
+
+impl Resource {
+    fn accepts(self, ty: DescriptorType) -> bool {
+        match self {
+            Self::Buffer => matches!(
+                ty,
+                DescriptorType::UniformBuffer
+                    | DescriptorType::UniformBufferDynamic
+                    | DescriptorType::StorageBuffer
+                    | DescriptorType::StorageBufferDynamic
+            ),
+            Self::Image => matches!(
+                ty,
+                DescriptorType::SampledImage
+                    | DescriptorType::StorageImage
+                    | DescriptorType::InputAttachment
+            ),
+            Self::ImageWithSampler => ty == DescriptorType::CombinedImageSampler,
+            Self::Sampler => ty == DescriptorType::Sampler,
+        }
+    }
+}
+
+/// The usage a buffer needs for a binding of type `ty`.
+fn buffer_usage(ty: DescriptorType) -> vk::BufferUsageFlags {
+    match ty {
+        DescriptorType::UniformBuffer | DescriptorType::UniformBufferDynamic => {
+            vk::BufferUsageFlags::UNIFORM_BUFFER
+        }
+        _ => vk::BufferUsageFlags::STORAGE_BUFFER,
+    }
+}
+
+/// The usage an image needs for a binding of type `ty`.
+fn image_usage(ty: DescriptorType) -> vk::ImageUsageFlags {
+    match ty {
+        DescriptorType::StorageImage => vk::ImageUsageFlags::STORAGE,
+        DescriptorType::InputAttachment => vk::ImageUsageFlags::INPUT_ATTACHMENT,
+        _ => vk::ImageUsageFlags::SAMPLED,
+    }
+}
+
+/// Writes to a [`DescriptorSet`], checked against the reflected [`DescriptorInfo`] of every
+/// binding. Nothing is written until [`DescriptorWriter::update`].
+///
+/// The set keeps what is written alive, and passes it on to the command buffers it is bound in.
+pub struct DescriptorWriter<'a> {
+    set: &'a DescriptorSet,
+    /// Binding, first array element, type, infos.
+    buffers: Vec<(u32, u32, vk::DescriptorType, Vec<vk::DescriptorBufferInfo>)>,
+    images: Vec<(u32, u32, vk::DescriptorType, Vec<vk::DescriptorImageInfo>)>,
+    /// Binding, array element, resource.
+    resources: Vec<((u32, u32), Arc<dyn Any + Send + Sync>)>,
+}
+
+impl std::fmt::Debug for DescriptorWriter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DescriptorWriter")
+            .field("set", &self.set.raw)
+            .field("buffers", &self.buffers)
+            .field("images", &self.images)
+            .finish()
+    }
+}
+
+impl DescriptorWriter<'_> {
+    /// The reflected type of `binding`, if `count` elements of `resource` fit from `first`.
+    fn check(
+        &self,
+        binding: u32,
+        first: u32,
+        count: usize,
+        resource: Resource,
+    ) -> Result<DescriptorType, DescriptorError> {
+        let info = &self
+            .set
+            .bindings()
+            .get(&binding)
+            .ok_or(DescriptorError::UnknownBinding {
+                set: self.set.index,
+                binding,
+            })?
+            .info;
+        if !resource.accepts(info.ty) {
+            return Err(DescriptorError::WrongResource {
+                binding,
+                expected: info.ty,
+            });
+        }
+        let available = match info.binding_count {
+            BindingCount::One => 1,
+            BindingCount::StaticSized(count) => count,
+            BindingCount::Unbounded => usize::MAX,
+        };
+        if first as usize + count > available {
+            return Err(DescriptorError::TooManyElements {
+                binding,
+                count: available,
+                written: first as usize + count,
+            });
+        }
+        Ok(info.ty)
+    }
+
+    /// Writes whole `buffers` to the array elements of `binding` starting at `first`.
+    /// # Errors
+    /// If `binding` is not a uniform or storage buffer, has fewer elements, or a buffer lacks
+    /// the usage its type needs.
+    pub fn buffers<T: Pod>(
+        mut self,
+        binding: u32,
+        first: u32,
+        buffers: &[&Arc<Buffer<T>>],
+    ) -> Result<Self, DescriptorError> {
+        if buffers.is_empty() {
+            return Ok(self);
+        }
+        let ty = self.check(binding, first, buffers.len(), Resource::Buffer)?;
+        let needed = buffer_usage(ty);
+        if let Some(buffer) = buffers.iter().find(|x| !x.usage().contains(needed)) {
+            return Err(DescriptorError::MissingBufferUsage {
+                binding,
+                usage: buffer.usage(),
+            });
+        }
+        let infos = buffers
+            .iter()
+            .map(|x| vk::DescriptorBufferInfo {
+                buffer: x.raw(),
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            })
+            .collect();
+        self.buffers
+            .push((binding, first, vk_descriptor_type(ty), infos));
+        for (element, buffer) in (first..).zip(buffers) {
+            self.resources.push(((binding, element), (*buffer).clone()));
+        }
+        Ok(self)
+    }
+    /// # Errors
+    /// See [`DescriptorWriter::buffers`].
+    pub fn buffer<T: Pod>(
+        self,
+        binding: u32,
+        buffer: &Arc<Buffer<T>>,
+    ) -> Result<Self, DescriptorError> {
+        self.buffers(binding, 0, &[buffer])
+    }
+
+    /// Writes `infos`, keeping `resources` alive with the set.
+    fn images(
+        mut self,
+        binding: u32,
+        first: u32,
+        resource: Resource,
+        infos: Vec<vk::DescriptorImageInfo>,
+        resources: Vec<(vk::ImageUsageFlags, Arc<dyn Any + Send + Sync>)>,
+    ) -> Result<Self, DescriptorError> {
+        let ty = self.check(binding, first, infos.len(), resource)?;
+        if resource != Resource::Sampler {
+            let needed = image_usage(ty);
+            if let Some((usage, _)) = resources.iter().find(|x| !x.0.contains(needed)) {
+                return Err(DescriptorError::MissingImageUsage {
+                    binding,
+                    usage: *usage,
+                });
+            }
+        }
+        self.images
+            .push((binding, first, vk_descriptor_type(ty), infos));
+        for (element, (_, resource)) in (first..).zip(resources) {
+            self.resources.push(((binding, element), resource));
+        }
+        Ok(self)
+    }
+
+    /// Writes the default view of `image`, see [`DescriptorWriter::image_views`].
+    /// # Errors
+    /// See [`DescriptorWriter::image_views`].
+    pub fn image(
+        self,
+        binding: u32,
+        image: &Arc<Image>,
+        layout: vk::ImageLayout,
+        sampler: Option<&Arc<Owned<vk::Sampler>>>,
+    ) -> Result<Self, DescriptorError> {
+        let info = vk::DescriptorImageInfo {
+            sampler: sampler.map_or_else(vk::Sampler::null, |x| ***x),
+            image_view: image.view(),
+            image_layout: layout,
+        };
+        let resource: Arc<dyn Any + Send + Sync> = match sampler {
+            Some(sampler) => Arc::new((image.clone(), sampler.clone())),
+            None => image.clone(),
+        };
+        let resources = vec![(image.description().usage, resource)];
+        self.images(binding, 0, image_resource(sampler), vec![info], resources)
+    }
+    /// Writes `views` in `layout` to the array elements of `binding` starting at `first`. With a
+    /// `sampler` the binding has to be a combined image sampler, otherwise a sampled image,
+    /// storage image or input attachment.
+    /// # Errors
+    /// If `binding` expects something else, has fewer elements, or an image lacks the usage its
+    /// type needs.
+    pub fn image_views(
+        self,
+        binding: u32,
+        first: u32,
+        views: &[&Arc<ImageView>],
+        layout: vk::ImageLayout,
+        sampler: Option<&Arc<Owned<vk::Sampler>>>,
+    ) -> Result<Self, DescriptorError> {
+        if views.is_empty() {
+            return Ok(self);
+        }
+        let infos = views
+            .iter()
+            .map(|x| vk::DescriptorImageInfo {
+                sampler: sampler.map_or_else(vk::Sampler::null, |x| ***x),
+                image_view: x.raw(),
+                image_layout: layout,
+            })
+            .collect();
+        let resources = views
+            .iter()
+            .map(|x| {
+                let resource: Arc<dyn Any + Send + Sync> = match sampler {
+                    Some(sampler) => Arc::new(((*x).clone(), sampler.clone())),
+                    None => (*x).clone(),
+                };
+                (x.image().description().usage, resource)
+            })
+            .collect();
+        self.images(binding, first, image_resource(sampler), infos, resources)
+    }
+    /// A sampler from [`SamplerCache::get_shared`](crate::sampler::SamplerCache::get_shared).
+    /// # Errors
+    /// If `binding` is not a sampler.
+    pub fn sampler(
+        self,
+        binding: u32,
+        sampler: &Arc<Owned<vk::Sampler>>,
+    ) -> Result<Self, DescriptorError> {
+        let info = vk::DescriptorImageInfo {
+            sampler: ***sampler,
+            ..Default::default()
+        };
+        let resources = vec![(vk::ImageUsageFlags::empty(), sampler.clone() as _)];
+        self.images(binding, 0, Resource::Sampler, vec![info], resources)
+    }
+
+    /// Writes everything to the set.
+    pub fn update(self) {
+        let buffers = self.buffers.iter().map(|(binding, first, ty, infos)| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(self.set.raw)
+                .dst_binding(*binding)
+                .dst_array_element(*first)
+                .descriptor_type(*ty)
+                .buffer_info(infos)
+        });
+        let images = self.images.iter().map(|(binding, first, ty, infos)| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(self.set.raw)
+                .dst_binding(*binding)
+                .dst_array_element(*first)
+                .descriptor_type(*ty)
+                .image_info(infos)
+        });
+        let writes = buffers.chain(images).collect::<Vec<_>>();
+        // Also keeps concurrent updates of the set apart.
+        let mut resources = self.set.resources.lock().unwrap();
+        unsafe {
+            self.set
+                .layout
+                .device()
+                .raw()
+                .update_descriptor_sets(&writes, &[])
+        };
+        let mut written = resources.written.clone();
+        written.extend(self.resources);
+        *resources = Arc::new(SetResources {
+            pool: resources.pool.clone(),
+            written,
+        });
+    }
+}
+
+fn image_resource(sampler: Option<&Arc<Owned<vk::Sampler>>>) -> Resource {
+    if sampler.is_some() {
+        Resource::ImageWithSampler
+    } else {
+        Resource::Image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layout_description() {
+        let binding = |binding, ty, count| SetLayoutBinding {
+            binding,
+            ty,
+            count,
+            stages: vk::ShaderStageFlags::COMPUTE,
+            flags: vk::DescriptorBindingFlags::empty(),
+        };
+        let description = SetLayoutDescription::new(vec![
+            binding(3, vk::DescriptorType::STORAGE_BUFFER, 2),
+            binding(0, vk::DescriptorType::UNIFORM_BUFFER, 1),
+            binding(1, vk::DescriptorType::STORAGE_BUFFER, 1),
+        ]);
+        let order = description
+            .bindings()
+            .iter()
+            .map(|x| x.binding)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [0, 1, 3]);
+        let sizes = description
+            .pool_sizes(4)
+            .iter()
+            .map(|x| (x.ty, x.descriptor_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [
+                (vk::DescriptorType::UNIFORM_BUFFER, 4),
+                (vk::DescriptorType::STORAGE_BUFFER, 12)
+            ]
+        );
+
+        // The order bindings are given in does not matter to the cache.
+        let mut keys = std::collections::HashSet::new();
+        assert!(keys.insert(description.clone()));
+        let mut reversed = description.bindings().to_vec();
+        reversed.reverse();
+        assert!(!keys.insert(SetLayoutDescription::new(reversed)));
+
+        assert!(Resource::Buffer.accepts(DescriptorType::StorageBuffer));
+        assert!(!Resource::Buffer.accepts(DescriptorType::StorageImage));
+        assert!(!Resource::Image.accepts(DescriptorType::CombinedImageSampler));
+        // A buffer with both usages fits either binding.
+        let usage = vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER;
+        assert!(usage.contains(buffer_usage(DescriptorType::StorageBuffer)));
+        assert!(usage.contains(buffer_usage(DescriptorType::UniformBuffer)));
+        // The binding, not the layout, decides what an image is used as.
+        assert_eq!(
+            image_usage(DescriptorType::SampledImage),
+            vk::ImageUsageFlags::SAMPLED
+        );
+        assert_eq!(
+            image_usage(DescriptorType::StorageImage),
+            vk::ImageUsageFlags::STORAGE
+        );
+    }
+
+    #[test]
+    fn bindless_reflection() {
+        let binding = |ty, binding_count| DescriptorBinding {
+            info: DescriptorInfo {
+                ty,
+                binding_count,
+                name: String::new(),
+            },
+            stages: vk::ShaderStageFlags::FRAGMENT,
+        };
+        let textures = BTreeMap::from([(
+            0,
+            binding(DescriptorType::SampledImage, BindingCount::Unbounded),
+        )]);
+        assert_eq!(
+            SetLayoutDescription::from_reflection(&textures).unwrap(),
+            bindless::set_layout_description()
+        );
+        let mismatched = BTreeMap::from([(
+            0,
+            binding(DescriptorType::StorageBuffer, BindingCount::Unbounded),
+        )]);
+        assert!(SetLayoutDescription::from_reflection(&mismatched).is_err());
+    }
+}
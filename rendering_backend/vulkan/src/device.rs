@@ -1,13 +1,18 @@
 //! All the necessary Rust binding for Vulkan attributes
+use crate::descriptor::SetLayoutDescription;
+use crate::features::{DeviceFeatures, ShaderSupport};
 use crate::instance::Instance;
-use crate::types::ExtensionProperties;
+use crate::queue_solver;
+use crate::selection::{Check, DeviceRequirements, Importance, PhysicalDevice, SelectionReport};
+use crate::swapchain::{SwapChain, SwapChainDescription};
 use crate::types::Layer;
-use infrastructure::Promise;
+use spirv_reflect::requirements::{ShaderRequirements, VulkanFeature};
 
 use super::error;
 use super::types::ExtensionName;
-use std::rc::Rc;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 pub use ash::vk::QueueFlags;
 
@@ -15,7 +20,11 @@ pub use ash::vk::QueueFlags;
 pub struct QueueDescription {
     pub flags: ash::vk::QueueFlags,
     pub priorities: Vec<f32>,
-    pub supports: Option<SwapChainPromise>,
+    /// The queues present to this swapchain, so they have to be able to.
+    pub supports: Option<SwapChainHandle>,
+    /// The queues render to the images of this swapchain without presenting them, e.g. a
+    /// graphics queue of another family than the presenting queue.
+    pub renders_to: Option<SwapChainHandle>,
 }
 
 impl QueueDescription {
@@ -34,55 +43,75 @@ impl QueueDescription {
             ..Default::default()
         }
     }
+
+    /// Whether the queues access the images of `swapchain`.
+    pub(crate) fn uses(&self, swapchain: SwapChainHandle) -> bool {
+        self.supports == Some(swapchain) || self.renders_to == Some(swapchain)
+    }
 }
 
 /// GPU Queue
+///
+/// Vulkan requires submissions to a queue to be externally synchronized, so the handle is only
+/// reachable through [`Queue::lock`].
 #[derive(Debug)]
 pub struct Queue {
-    inner: ash::vk::Queue,
+    inner: Mutex<ash::vk::Queue>,
+    /// Capabilities of the family this queue belongs to.
     capabilities: ash::vk::QueueFlags,
+    family_index: u32,
+    index: u32,
 }
 
-type QueuePromise = Rc<Promise<Rc<Queue>, QueueDescription>>;
 impl Queue {
-    pub fn new_promise(desc: QueueDescription) -> QueuePromise {
-        Promise::new_rc(desc)
+    /// Locks the queue for submitting or presenting, until the guard is dropped.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ash::vk::Queue> {
+        self.inner.lock().unwrap()
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct SwapChainDescription {
-    pub surface: Rc<super::Surface>,
-}
-
-#[derive(Debug)]
-pub struct SwapChain {
-    inner: ash::vk::SwapchainKHR,
-    surface: Rc<super::Surface>,
-    capabilities: ash::vk::QueueFlags,
-}
-
-type SwapChainPromise = Rc<Promise<Rc<SwapChain>, SwapChainDescription>>;
-impl SwapChain {
-    pub fn new_promise(desc: SwapChainDescription) -> SwapChainPromise {
-        Promise::new_rc(desc)
+    pub fn capabilities(&self) -> ash::vk::QueueFlags {
+        self.capabilities
+    }
+    pub fn family_index(&self) -> u32 {
+        self.family_index
+    }
+    /// Index of the queue within its family.
+    pub fn index(&self) -> u32 {
+        self.index
     }
 }
 
 pub struct Device {
     raw: ash::Device,
-    physical_device: ash::vk::PhysicalDevice,
-    queues: Vec<Rc<Queue>>,
+    physical_device: PhysicalDevice,
+    queues: Vec<Arc<Queue>>,
+    enabled_features: DeviceFeatures,
+    /// Descriptor set layouts by their bindings, destroyed with the device.
+    set_layouts: Mutex<HashMap<SetLayoutDescription, ash::vk::DescriptorSetLayout>>,
+    /// Loaded if the instance enabled `VK_EXT_debug_utils`.
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    lost: AtomicBool,
+    lost_hooks: Mutex<Vec<DeviceLostHook>>,
     instance: Arc<Instance>,
 }
+
+/// Called once when a call on the device returns `ERROR_DEVICE_LOST`, see
+/// [`Device::on_device_lost`].
+pub type DeviceLostHook = Box<dyn FnOnce(&Device) + Send + Sync>;
 unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
 impl Drop for Device {
     fn drop(&mut self) {
+        let allocation_callbacks = self.instance.allocation_callbacks();
+        for (_, layout) in self.set_layouts.get_mut().unwrap().drain() {
+            unsafe {
+                self.raw
+                    .destroy_descriptor_set_layout(layout, allocation_callbacks)
+            };
+        }
         unsafe {
             self.raw
-                .destroy_device(self.instance.allocation_callbacks.as_deref());
+                .destroy_device(self.instance.allocation_callbacks());
         }
     }
 }
@@ -98,70 +127,116 @@ impl std::fmt::Debug for Device {
     }
 }
 
-/**
-
-Order the devices.
-Returning `None` means that the device is not suitable.
-Otherwise higher the number, higher the priority.
-Errors are not propagated back, if it does not match the predicate make sure to log it as an
-error!
-*/
-pub struct DeviceOrdering(pub fn(&mut PhysicalDeviceProperties) -> Option<u32>);
-
-impl Default for DeviceOrdering {
-    /// Must support all extensions.
-    fn default() -> Self {
-        fn default_device_ordering(desc: &mut PhysicalDeviceProperties) -> Option<u32> {
-            let mut fail: bool = false;
-            let mut score = 0;
-
-            fail |= desc.extensions.iter().any(|x| !x.is_supported());
-            fail |= desc.queues.descriptions.iter().any(|x| !x.is_supported());
-            // if desc.features.geometry_shader == ash::vk::Bool32::from(false) {
-            //     fail = true;
-            // }
-            // if desc.features.sampler_anisotropy == ash::vk::Bool32::from(false) {
-            //     fail = true;
-            // }
-
-            score += match desc.properties.device_type {
-                ash::vk::PhysicalDeviceType::DISCRETE_GPU => 4,
-                ash::vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
-                ash::vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
-                ash::vk::PhysicalDeviceType::CPU => 1,
-                _ => 0,
-            };
-
-            match fail {
-                true => None,
-                false => Some(score),
-            }
-        }
-        Self(default_device_ordering)
-    }
-}
-
+/// Without swapchains and with queues that do not support one, nothing here needs a surface,
+/// so the instance can be created without the surface extensions, see
+/// [`InstanceCreateInfo::compute_usage`](crate::InstanceCreateInfo::compute_usage).
+#[derive(Default)]
 pub struct DeviceCreationInfo {
     pub physical_device_creation_info: PhysicalDeviceCreationInfo,
+    /// Required extensions, see [`DeviceRequirements`] for optional ones.
     pub extensions: Vec<ExtensionName>,
     pub layers: Vec<Layer>,
-    /// After using `Device::new` these promises will be filled out.
-    pub queues: Vec<QueuePromise>,
-    pub swapchain: Vec<SwapChainPromise>,
-    /// If you can work with missing extensions, overdefine this variable
-    /// set it to change variables in your domain and handle it there.
-    pub order: DeviceOrdering,
+    /// Added with [`Self::request_queue`].
+    queues: Vec<QueueDescription>,
+    /// Added with [`Self::request_swapchain`].
+    swapchains: Vec<SwapChainRequest>,
+    /// Added with [`Self::shader`].
+    shaders: Vec<ShaderRequirements>,
 }
 
-pub struct SwapChainSupport {
-    pub capabilities: ash::vk::SurfaceCapabilitiesKHR,
-    pub extent: ash::vk::Extent2D,
-    pub format: ash::vk::SurfaceFormatKHR,
-    pub present_mode: ash::vk::PresentModeKHR,
+impl DeviceCreationInfo {
+    /// Picks the best physical device meeting `requirements`.
+    pub fn requirements(mut self, requirements: DeviceRequirements) -> Self {
+        self.physical_device_creation_info.requirements = requirements;
+        self
+    }
+
+    /// Enables the features and extensions a shader needs, if the chosen device has them. Only
+    /// features requested here, through [`DeviceRequirements`], or in
+    /// [`HORIZON_FEATURES`](crate::features::HORIZON_FEATURES) get enabled.
+    /// Use [`spirv_reflect::Reflection::get_vulkan_requirements`] to get the requirements.
+    pub fn shader(mut self, requirements: ShaderRequirements) -> Self {
+        self.shaders.push(requirements);
+        self
+    }
+
+    /// Requests one queue per priority of `description`, see [`DeviceCreation::queues`].
+    pub fn request_queue(&mut self, description: QueueDescription) -> QueueHandle {
+        self.queues.push(description);
+        QueueHandle(self.queues.len() - 1)
+    }
+
+    /// Requests a swapchain created with the device, see [`DeviceCreation::take_swapchain`].
+    /// Its images are shared between the queues that support or render to it.
+    pub fn request_swapchain(
+        &mut self,
+        description: SwapChainDescription,
+        window_extent: ash::vk::Extent2D,
+    ) -> SwapChainHandle {
+        self.swapchains.push(SwapChainRequest {
+            description,
+            window_extent,
+        });
+        SwapChainHandle(self.swapchains.len() - 1)
+    }
 }
 
-#[derive(Default)]
-pub struct PhysicalDeviceCreationInfo {}
+struct SwapChainRequest {
+    description: SwapChainDescription,
+    window_extent: ash::vk::Extent2D,
+}
+
+/// Identifies queues requested with [`DeviceCreationInfo::request_queue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueueHandle(usize);
+
+/// Identifies a swapchain requested with [`DeviceCreationInfo::request_swapchain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SwapChainHandle(usize);
+
+/// What [`Device::new`] created, looked up by the handles returned when requesting it.
+///
+/// The handles index into the [`DeviceCreationInfo`] they came from, using one from another
+/// panics or returns the wrong object.
+#[derive(Debug)]
+pub struct DeviceCreation {
+    pub device: Arc<Device>,
+    queues: Vec<Vec<Arc<Queue>>>,
+    swapchains: Vec<Option<SwapChain>>,
+}
+
+impl DeviceCreation {
+    /// One queue per priority of the description. Queues may be shared between descriptions
+    /// if the device does not have enough of them.
+    pub fn queues(&self, handle: QueueHandle) -> &[Arc<Queue>] {
+        &self.queues[handle.0]
+    }
+
+    /// The first queue of [`Self::queues`].
+    /// # Panics
+    /// If the description had no priorities.
+    pub fn queue(&self, handle: QueueHandle) -> &Arc<Queue> {
+        &self.queues(handle)[0]
+    }
+
+    /// # Panics
+    /// If the swapchain was already taken.
+    pub fn take_swapchain(&mut self, handle: SwapChainHandle) -> SwapChain {
+        self.swapchains[handle.0]
+            .take()
+            .expect("The swapchain was already taken")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PhysicalDeviceCreationInfo {
+    /// The best device meeting these is used. The features, extensions and extension features
+    /// it satisfies are enabled.
+    pub requirements: DeviceRequirements,
+    /// Use this device instead of picking one, see [`PhysicalDevice::enumerate`].
+    /// It still has to meet the requirements.
+    pub device: Option<PhysicalDevice>,
+}
 
 enum Support<Value, ID> {
     Supported((Value, ID)),
@@ -187,511 +262,558 @@ impl<Value: std::fmt::Debug, ID: std::fmt::Debug> std::fmt::Debug for Support<Va
     }
 }
 
-type Indices = u16;
 struct QueueSupportData {
-    queue_datas: Vec<ash::vk::QueueFamilyProperties>,
-    /// The indices are for the previous array.
-    descriptions: Vec<Support<Vec<Indices>, QueuePromise>>,
-}
-pub struct PhysicalDeviceProperties {
-    /// List of requested items.
-    extensions: Vec<Support<ExtensionProperties, ExtensionName>>,
-    queues: QueueSupportData,
-    properties: ash::vk::PhysicalDeviceProperties,
-    features: ash::vk::PhysicalDeviceFeatures,
+    families: Vec<ash::vk::QueueFamilyProperties>,
+    /// The family indices that can serve each description.
+    descriptions: Vec<Support<Vec<u32>, QueueDescription>>,
 }
-
-struct PickPhysicalDevice {
-    device: ash::vk::PhysicalDevice,
-    extensions: Vec<ExtensionName>,
-    queues: Vec<Queue>,
-}
-
 mod physical {
 
     use super::*;
-    pub fn get_init_details(
-        instance: &Arc<Instance>,
-        physical_device: &ash::vk::PhysicalDevice,
-        extensions: &[ExtensionName],
-        queues: &[QueuePromise],
-    ) -> Result<PhysicalDeviceProperties, error::InitError> {
-        let properties = instance.get_physical_device_properties(physical_device);
-        let features = instance.get_physical_device_features(physical_device);
-        let queue_family_properties =
-            instance.get_physical_device_queue_family_properties(physical_device);
-        let supported_extensions =
-            instance.enumerate_device_extension_properties(physical_device)?;
-
-        let extensions = extensions
+    pub fn queue_support(
+        device: &PhysicalDevice,
+        queues: &[QueueDescription],
+        swapchains: &[SwapChainRequest],
+    ) -> Result<QueueSupportData, error::InitError> {
+        let families = device.queue_families().to_vec();
+        let descriptions = queues
             .iter()
-            .map(|extension| {
-                supported_extensions
-                    .iter()
-                    .find(|x| x.name == *extension)
-                    .map(|x| Support::Supported((x.clone(), extension.clone())))
-                    .unwrap_or_else(|| Support::Unsupported(extension.clone()))
-            })
-            .collect();
-
-        let queue_data = {
-            fn queue_matches_description(
-                x: &ash::vk::QueueFamilyProperties,
-                desc: &QueueDescription,
-            ) -> bool {
-                x.queue_count > 0 && x.queue_flags.contains(desc.flags)
-            }
-            let queue_families = queue_family_properties
-                .into_iter()
-                .filter(|x| {
-                    queues
-                        .iter()
-                        .any(|desc| queue_matches_description(x, &desc.description))
-                })
-                .collect::<Vec<_>>();
-
-            let indices = queues
-                .iter()
-                .map(|desc| {
-                    let matching_families: Vec<_> = queue_families
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, x)| queue_matches_description(x, &desc.description))
-                        .map(|(ind, _)| ind as u16)
-                        .collect();
-                    match matching_families.is_empty() {
-                        true => Support::Unsupported((*desc).clone()),
-                        false => Support::Supported((matching_families, (*desc).clone())),
-                    }
+            .map(|desc| {
+                let present_support = match desc.supports {
+                    Some(swapchain) => Some(present_support(
+                        &swapchains[swapchain.0].description.surface,
+                        device.raw(),
+                        families.len(),
+                    )?),
+                    None => None,
+                };
+                let candidates = queue_solver::candidate_families(
+                    &families,
+                    desc.flags,
+                    present_support.as_deref(),
+                );
+                Ok(match candidates.is_empty() {
+                    true => Support::Unsupported(desc.clone()),
+                    false => Support::Supported((candidates, desc.clone())),
                 })
-                .collect();
-            QueueSupportData {
-                queue_datas: queue_families,
-                descriptions: indices,
-            }
-        };
-        let res = PhysicalDeviceProperties {
-            extensions,
-            queues: queue_data,
-            properties,
-            features,
-        };
-        Ok(res)
+            })
+            .collect::<Result<Vec<_>, error::InitError>>()?;
+        Ok(QueueSupportData {
+            families,
+            descriptions,
+        })
     }
 
-    fn query_surface_support(
+    fn present_support(
         surface: &crate::instance::Surface,
         physical_device: ash::vk::PhysicalDevice,
-        window_inner_size: (u32, u32),
-    ) -> Option<SwapChainSupport> {
-        let formats = unsafe {
-            surface
-                .surface_loader
-                .get_physical_device_surface_formats(physical_device, surface.raw)
-        }
-        .unwrap();
-        let present_modes = unsafe {
-            surface
-                .surface_loader
-                .get_physical_device_surface_present_modes(physical_device, surface.raw)
-        }
-        .unwrap();
-        if !(!formats.is_empty() && !present_modes.is_empty()) {
-            return None;
-        }
-        let best_format = formats
-            .into_iter()
-            .max_by_key(|x| {
-                let mut score = match x.format {
-                    ash::vk::Format::B8G8R8A8_SRGB => 1,
-                    _ => 0,
-                };
-                score += match x.color_space {
-                    ash::vk::ColorSpaceKHR::SRGB_NONLINEAR => 2,
-                    _ => 0,
-                };
-                score
-            })
-            .unwrap();
-        let best_present_mode = present_modes
-            .into_iter()
-            .max_by_key(|x| {
-                match *x {
-                    // isn't this guaranteed to be available?
-                    ash::vk::PresentModeKHR::FIFO => 3,
-                    ash::vk::PresentModeKHR::MAILBOX => 2,
-                    ash::vk::PresentModeKHR::IMMEDIATE => 1,
-                    _ => 0,
+        family_count: usize,
+    ) -> Result<Vec<bool>, error::InitError> {
+        (0..family_count as u32)
+            .map(|family| {
+                unsafe {
+                    surface.surface_loader.get_physical_device_surface_support(
+                        physical_device,
+                        family,
+                        surface.raw(),
+                    )
                 }
+                .map_err(Into::into)
             })
-            .unwrap();
-        let present_capabilities = unsafe {
-            surface
-                .surface_loader
-                .get_physical_device_surface_capabilities(physical_device, surface.raw)
-        }
-        .unwrap();
-        let mut extent: ash::vk::Extent2D = if present_capabilities.current_extent.width != u32::MAX
-        {
-            ash::vk::Extent2D {
-                width: window_inner_size.0,
-                height: window_inner_size.1,
-            }
-        } else {
-            present_capabilities.current_extent
-        };
-        extent.width = extent.width.clamp(
-            present_capabilities.min_image_extent.width,
-            present_capabilities.max_image_extent.width,
-        );
-        extent.height = extent.height.clamp(
-            present_capabilities.min_image_extent.height,
-            present_capabilities.max_image_extent.height,
-        );
-        let swap_chain_support = SwapChainSupport {
-            format: best_format,
-            present_mode: best_present_mode,
-            extent,
-            capabilities: present_capabilities,
-        };
-        Some(swap_chain_support)
+            .collect()
     }
 }
 impl Device {
+    /// Picks a physical device, creates the device with the requested queues, then the
+    /// requested swapchains.
+    /// # Errors
+    /// If no device fits, or the device or a swapchain cannot be created.
+    /// # Panics
+    /// If a [`QueueDescription::supports`] handle is from another [`DeviceCreationInfo`].
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(
         instance: &Arc<Instance>,
         info: DeviceCreationInfo,
-    ) -> Result<Arc<Self>, error::InitError> {
-        let physical_devices = unsafe { instance.raw.enumerate_physical_devices() }
-            .map_err(|_| error::InitError::EnumeratePhysicalDevicesFailed)?;
-        let physical_device = physical_devices
+    ) -> Result<DeviceCreation, error::InitError> {
+        let PhysicalDeviceCreationInfo {
+            requirements,
+            device,
+        } = info.physical_device_creation_info;
+        let devices = match device {
+            Some(device) => vec![device],
+            None => PhysicalDevice::enumerate(instance)?,
+        };
+        let evaluations = devices
             .into_iter()
-            .filter_map(|physical_device| {
-                let res = physical::get_init_details(
-                    instance,
-                    &physical_device,
-                    &info.extensions,
-                    &info.queues,
-                );
-
-                match res {
-                    Ok(properties) => Some((physical_device, properties)),
+            .filter_map(|device| {
+                let mut evaluation = requirements.evaluate(device);
+                for extension in &info.extensions {
+                    let supported = evaluation
+                        .device
+                        .features()
+                        .has_extension(&extension.to_string_lossy());
+                    evaluation.record(
+                        Check::Extension(Box::new(extension.clone())),
+                        Importance::Required,
+                        supported,
+                    );
+                }
+                match physical::queue_support(&evaluation.device, &info.queues, &info.swapchains) {
+                    Ok(queues) => {
+                        for desc in &queues.descriptions {
+                            let (supported, desc) = match desc {
+                                Support::Supported((_, desc)) => (true, desc),
+                                Support::Unsupported(desc) => (false, desc),
+                            };
+                            evaluation.record(
+                                Check::QueueDescription(desc.clone()),
+                                Importance::Required,
+                                supported,
+                            );
+                        }
+                        Some(evaluation)
+                    }
                     Err(x) => {
                         tracing::error!(
-                            "Physical device properties could not be parsed: {:?}. Ignoring",
+                            "Queue support of {} could not be queried: {:?}. Ignoring",
+                            evaluation.device.name(),
                             x
                         );
                         None
                     }
                 }
             })
-            .filter_map(|(physical_device, mut properties)| {
-                (info.order.0)(&mut properties).map(|score| (physical_device, properties, score))
-            })
-            .max_by_key(|(_, _, score)| *score)
-            .map(|(physical_device, properties, _)| (physical_device, properties));
+            .collect();
+        let report = SelectionReport::new(evaluations);
+        tracing::info!("Physical device selection:\n{}", report);
+        let Some(best) = report.best() else {
+            return Err(error::InitError::SuitablePhysicalDeviceNotFound(report));
+        };
+        let physical_device = best.device.clone();
 
-        let (physical_device, properties) =
-            physical_device.ok_or(error::InitError::SuitablePhysicalDeviceNotFound)?;
+        let mut requested_features = best
+            .satisfied()
+            .filter_map(|check| match check {
+                Check::Feature(x) => Some(*x),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut shader_extensions = vec![];
+        for shader in &info.shaders {
+            physical_device.features().shader_needs(
+                shader,
+                &mut requested_features,
+                &mut shader_extensions,
+            );
+        }
 
-        let extensions = properties
+        let extensions = physical_device
+            .features()
             .extensions
-            .into_iter()
-            .filter_map(|supp| match supp {
-                Support::Unsupported(_) => None,
-                Support::Supported((val, _)) => Some(val),
+            .iter()
+            .filter(|x| {
+                info.extensions.contains(&x.name)
+                    || shader_extensions
+                        .iter()
+                        .any(|name| x.name.to_bytes() == name.as_bytes())
+                    || best.satisfied().any(|check| match check {
+                        Check::Extension(name) => **name == x.name,
+                        Check::Feature(VulkanFeature::Extension { extension, .. }) => {
+                            x.name.to_bytes() == extension.as_bytes()
+                        }
+                        _ => false,
+                    })
             })
+            .cloned()
             .collect::<Vec<_>>();
         let extension_names = extensions
             .iter()
             .map(|prop| prop.name.to_str().as_ptr())
             .collect::<Vec<_>>();
-        let enabled_features = properties.features;
-
-        let solved_queues = solve_queues(properties.queues)?;
-        // TODO: This is not correct for multiple queues
-        let queue_create_info = solved_queues
-            .solved_queues
+        let enabled_features = physical_device
+            .features()
+            .enabled_subset(&extensions, &requested_features);
+
+        let queue_support =
+            physical::queue_support(&physical_device, &info.queues, &info.swapchains)?;
+        let (queue_families, layout) = solve_queues(queue_support)?;
+        let queue_create_info = layout
+            .families
             .iter()
-            .map(|x| {
-                to_create_info(
-                    solved_queues.queue_family_buffer.get(x.0 as usize).unwrap(),
-                    &x.1.description,
-                )
+            .map(|(family, priorities)| {
+                ash::vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(*family)
+                    .queue_priorities(priorities)
             })
             .collect::<Vec<_>>();
 
-        let device_create_info = ash::vk::DeviceCreateInfo::default()
-            .enabled_extension_names(&extension_names)
-            .enabled_features(&enabled_features)
-            .queue_create_infos(&queue_create_info);
+        // The chain points into this copy, so it has to outlive the call.
+        let mut chain = enabled_features.clone();
+        let device_create_info = chain.push_next(
+            ash::vk::DeviceCreateInfo::default()
+                .enabled_extension_names(&extension_names)
+                .enabled_features(&enabled_features.core)
+                .queue_create_infos(&queue_create_info),
+        );
         let device = unsafe {
             instance.raw.create_device(
-                physical_device,
+                physical_device.raw(),
                 &device_create_info,
-                instance.allocation_callbacks.as_deref(),
+                instance.allocation_callbacks(),
             )?
         };
 
-        let queues = solved_queues
-            .solved_queues
+        let queues = layout
+            .families
             .iter()
-            .map(|(family, desc)| {
-                let queue = unsafe { device.get_device_queue(*family as u32, 0) };
-                let q = Rc::new(Queue {
-                    inner: queue,
-                    capabilities: desc.description.flags,
-                });
-                *desc.result.borrow_mut() = Some(Rc::clone(&q));
-                q
+            .flat_map(|(family, priorities)| {
+                (0..priorities.len() as u32).map(|index| {
+                    Arc::new(Queue {
+                        inner: Mutex::new(unsafe { device.get_device_queue(*family, index) }),
+                        capabilities: queue_families[*family as usize].queue_flags,
+                        family_index: *family,
+                        index,
+                    })
+                })
             })
             .collect::<Vec<_>>();
+        // In the order of the requests.
+        let assigned = layout
+            .assignments
+            .iter()
+            .map(|assignment| {
+                assignment
+                    .queue_indices
+                    .iter()
+                    .map(|index| {
+                        queues
+                            .iter()
+                            .find(|x| x.family_index == assignment.family && x.index == *index)
+                            .cloned()
+                            .expect("Every assigned queue is created")
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let debug_utils = instance
+            .report()
+            .has_extension(ash::ext::debug_utils::NAME)
+            .then(|| ash::ext::debug_utils::Device::new(&instance.raw, &device));
         let device = Self {
             raw: device,
+            debug_utils,
             instance: instance.clone(),
             physical_device,
             queues,
+            enabled_features,
+            set_layouts: Mutex::default(),
+            lost: AtomicBool::new(false),
+            lost_hooks: Mutex::default(),
         };
-        Ok(Arc::new(device))
+        let device = Arc::new(device);
+
+        let swapchains = info
+            .swapchains
+            .into_iter()
+            .enumerate()
+            .map(|(index, request)| {
+                let queues = info
+                    .queues
+                    .iter()
+                    .zip(&assigned)
+                    .filter(|(desc, _)| desc.uses(SwapChainHandle(index)))
+                    .flat_map(|(_, queues)| queues.iter().map(AsRef::as_ref))
+                    .collect::<Vec<_>>();
+                SwapChain::new(
+                    &device,
+                    &request.description,
+                    request.window_extent,
+                    &queues,
+                )
+                .map(Some)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DeviceCreation {
+            device,
+            queues: assigned,
+            swapchains,
+        })
     }
 
-    pub fn create_swapchain(
-        &self,
-        instance: &Arc<Instance>,
-        surface: &crate::instance::Surface,
-        details: SwapChainSupport,
-    ) -> Result<SwapChain, error::InitError> {
-        let image_count = (details.capabilities.min_image_count + 1).max(2);
-        let max_image_count = if details.capabilities.max_image_count == 0 {
-            u32::MAX
-        } else {
-            details.capabilities.max_image_count
+    pub fn raw(&self) -> &ash::Device {
+        &self.raw
+    }
+
+    pub(crate) fn allocation_callbacks(&self) -> Option<&ash::vk::AllocationCallbacks<'static>> {
+        self.instance.allocation_callbacks()
+    }
+
+    /// The `VK_EXT_debug_utils` functions, if the instance enabled it.
+    pub fn debug_utils(&self) -> Option<&ash::ext::debug_utils::Device> {
+        self.debug_utils.as_ref()
+    }
+
+    /// Names `handle` in validation messages and captures. Does nothing without
+    /// `VK_EXT_debug_utils`; failing to name an object is only logged.
+    pub fn set_debug_name(&self, handle: impl ash::vk::Handle, name: &str) {
+        let Some(debug_utils) = &self.debug_utils else {
+            return;
         };
-        let image_count = image_count.min(max_image_count);
-        let (image_sharing_mode, queue_family_indices) =
-        // TODO:This is bad, this means that only a single queue may access the swapchain at all.
-            // if device.graphic_family_index != device.present_family_index {
-            //     (
-            //         ash::vk::SharingMode::CONCURRENT,
-            //         vec![device.graphic_family_index, device.present_family_index],
-            //     )
-            // } else {
-                (ash::vk::SharingMode::EXCLUSIVE, vec![])
-            // }
-        ;
-        let create_info = ash::vk::SwapchainCreateInfoKHR::default()
-            .surface(surface.raw)
-            .min_image_count(image_count)
-            .image_format(details.format.format)
-            .image_color_space(details.format.color_space)
-            .image_extent(details.extent)
-            .image_array_layers(1)
-            .image_usage(ash::vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(image_sharing_mode)
-            .queue_family_indices(&queue_family_indices)
-            .pre_transform(details.capabilities.current_transform)
-            .composite_alpha(ash::vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(details.present_mode)
-            .clipped(true);
-
-        let swapchain_loader =
-            unsafe { ash::khr::swapchain::Device::new(&self.instance.raw, &self.raw) };
-        let swapchain = unsafe {
-            swapchain_loader
-                .create_swapchain(&create_info, self.instance.allocation_callbacks.as_deref())
-                .map_err(|x| error::InitError::SwapchainCreationFailed(x.into()))
-        }?;
+        let name = crate::debug::c_name(name);
+        let name_info = ash::vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        if let Err(err) = unsafe { debug_utils.set_debug_utils_object_name(&name_info) } {
+            tracing::warn!("Could not name {name:?}: {err}");
+        }
+    }
 
-        let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }?;
+    /// Whether a call on the device returned `ERROR_DEVICE_LOST`. Nothing submitted to a lost
+    /// device executes anymore, and it cannot be recovered.
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Acquire)
+    }
 
-        let image_views = swapchain_images
-            .iter()
-            .map(|x| self.create_image_view(x, details.format.format).unwrap())
-            // .map(|image| {
-            //     let create_info = ImageViewCreateInfo::builder()
-            //         .image(*image)
-            //         .view_type(ash::vk::ImageViewType::TYPE_2D)
-            //         .format(details.format.format)
-            //         .components(ash::vk::ComponentMapping {
-            //             r: ash::vk::ComponentSwizzle::IDENTITY,
-            //             g: ash::vk::ComponentSwizzle::IDENTITY,
-            //             b: ash::vk::ComponentSwizzle::IDENTITY,
-            //             a: ash::vk::ComponentSwizzle::IDENTITY,
-            //         })
-            //         .subresource_range(ash::vk::ImageSubresourceRange {
-            //             aspect_mask: ash::vk::ImageAspectFlags::COLOR,
-            //             base_mip_level: 0,
-            //             level_count: 1,
-            //             base_array_layer: 0,
-            //             layer_count: 1,
-            //         })
-            //         .build();
-            //     unsafe { device.device.create_image_view(&create_info, None) }.unwrap()
-            // })
-            .collect::<Vec<_>>();
+    /// Runs `hook` once the device is lost, or right away if it already is.
+    ///
+    /// The hook is called from whichever thread noticed the loss, e.g. while waiting on a
+    /// fence. To recover, let it tell the application to drop everything created from the
+    /// device and create a new one on the same instance:
+    /// ```ignore
+    /// let lost = Arc::new(AtomicBool::new(false));
+    /// device.on_device_lost({
+    ///     let lost = lost.clone();
+    ///     move |device| lost.store(true, Ordering::Release)
+    /// });
+    /// // Each frame:
+    /// if lost.load(Ordering::Acquire) {
+    ///     renderer = Renderer::new(&instance, &window)?;
+    /// }
+    /// ```
+    pub fn on_device_lost(&self, hook: impl FnOnce(&Device) + Send + Sync + 'static) {
+        let mut hooks = self.lost_hooks.lock().unwrap();
+        if self.is_lost() {
+            drop(hooks);
+            hook(self);
+        } else {
+            hooks.push(Box::new(hook));
+        }
+    }
 
-        Ok(SwapChain {
-            swapchain,
-            swapchain_loader,
-            _images: swapchain_images,
-            image_views,
-            extent: details.extent,
-            surface_format: details.format,
-        })
+    /// Passes `result` through, running the device lost hooks the first time it is
+    /// `ERROR_DEVICE_LOST`.
+    pub(crate) fn check<T>(&self, result: ash::prelude::VkResult<T>) -> ash::prelude::VkResult<T> {
+        if result.as_ref().err() == Some(&ash::vk::Result::ERROR_DEVICE_LOST) {
+            self.mark_lost();
+        }
+        result
     }
-}
 
-fn to_create_info<'q>(
-    family: &ash::vk::QueueFamilyProperties,
-    desc: &'q QueueDescription,
-) -> ash::vk::DeviceQueueCreateInfo<'q> {
-    ash::vk::DeviceQueueCreateInfo::default()
-        .queue_family_index(family.queue_flags.as_raw())
-        .queue_priorities(&desc.priorities)
-}
+    fn mark_lost(&self) {
+        let hooks = {
+            let mut hooks = self.lost_hooks.lock().unwrap();
+            if self.lost.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            std::mem::take(&mut *hooks)
+        };
+        tracing::error!("Device {} was lost", self.physical_device.name());
+        for hook in hooks {
+            hook(self);
+        }
+    }
 
-struct SolvedQueueLayout {
-    queue_family_buffer: Vec<ash::vk::QueueFamilyProperties>,
-    solved_queues: Vec<(u16, QueuePromise)>,
-}
-fn solve_queues(inp: QueueSupportData) -> Result<SolvedQueueLayout, error::InitError> {
-    let QueueSupportData {
-        queue_datas: mut queues,
-        descriptions: indices,
-    } = inp;
+    /// Waits until the device has finished all submitted work. Submitting or presenting on
+    /// another thread blocks until then, since waiting on the device synchronizes every queue.
+    /// # Errors
+    /// If the device is lost or out of memory.
+    pub fn wait_idle(&self) -> Result<(), error::VkError> {
+        let queues = self.queues.iter().map(|x| x.lock()).collect::<Vec<_>>();
+        let result = unsafe { self.raw.device_wait_idle() };
+        // Device lost hooks may use the queues.
+        drop(queues);
+        self.check(result)?;
+        Ok(())
+    }
 
-    if indices.is_empty() {
-        return Ok(SolvedQueueLayout {
-            queue_family_buffer: vec![],
-            solved_queues: vec![],
-        });
+    pub fn physical_device(&self) -> &PhysicalDevice {
+        &self.physical_device
+    }
+
+    /// The features and extensions enabled on this device.
+    pub fn enabled_features(&self) -> &DeviceFeatures {
+        &self.enabled_features
     }
 
-    enum State {
-        Unsolved((Vec<u16>, QueuePromise)),
-        Solved((u16, QueuePromise)),
+    /// Checks that a shader can run on this device with the enabled features.
+    /// Use [`spirv_reflect::Reflection::get_vulkan_requirements`] to get the requirements.
+    pub fn check_shader_requirements(
+        &self,
+        requirements: &spirv_reflect::requirements::ShaderRequirements,
+    ) -> ShaderSupport {
+        self.enabled_features
+            .check_shader_requirements(requirements)
     }
 
-    let mut res: Vec<State> = indices
+    /// The descriptor set layout with `description`'s bindings, created on first use and
+    /// valid as long as the device is.
+    /// # Errors
+    /// If the layout cannot be created.
+    pub fn descriptor_set_layout(
+        &self,
+        description: &SetLayoutDescription,
+    ) -> Result<ash::vk::DescriptorSetLayout, error::VkError> {
+        let mut layouts = self.set_layouts.lock().unwrap();
+        if let Some(layout) = layouts.get(description) {
+            return Ok(*layout);
+        }
+        let bindings = description.vk_bindings();
+        let binding_flags = description.vk_binding_flags();
+        let mut flags_info = ash::vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+            .binding_flags(&binding_flags);
+        let mut create_info = ash::vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        if description.update_after_bind() {
+            create_info = create_info
+                .flags(ash::vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                .push_next(&mut flags_info);
+        }
+        let layout = unsafe {
+            self.raw
+                .create_descriptor_set_layout(&create_info, self.allocation_callbacks())
+        }?;
+        layouts.insert(description.clone(), layout);
+        Ok(layout)
+    }
+
+    /// See [`SwapChain::new`].
+    /// # Errors
+    /// If the surface does not fit the policy, or the swapchain cannot be created.
+    pub fn create_swapchain(
+        self: &Arc<Self>,
+        description: &SwapChainDescription,
+        window_extent: ash::vk::Extent2D,
+        queues: &[&Queue],
+    ) -> Result<SwapChain, error::SwapChainError> {
+        SwapChain::new(self, description, window_extent, queues)
+    }
+}
+
+/// Returns the queue families of the device and the solved layout, with the assignments in
+/// the order of the requests.
+fn solve_queues(
+    inp: QueueSupportData,
+) -> Result<
+    (
+        Vec<ash::vk::QueueFamilyProperties>,
+        queue_solver::QueueLayout,
+    ),
+    error::InitError,
+> {
+    let QueueSupportData {
+        families,
+        descriptions,
+    } = inp;
+
+    let (requests, descriptions): (Vec<_>, Vec<_>) = descriptions
         .into_iter()
-        .filter_map(|support| match support {
-            Support::Supported((indices, desc)) => Some(State::Unsolved((indices, desc))),
+        .map(|support| match support {
+            Support::Supported((candidates, desc)) => Ok((
+                queue_solver::QueueRequest {
+                    flags: desc.flags,
+                    candidates,
+                    priorities: desc.priorities.clone(),
+                },
+                desc,
+            )),
             Support::Unsupported(desc) => {
-                tracing::error!(
-                    "Queue description {:?} is not supported but got through filtering. Ignoring.",
-                    desc
-                );
-                None
+                Err(error::InitError::QueueDescriptionCouldNotBeFilled(desc))
             }
         })
-        .collect();
-
-    let mut changed = true;
-
-    while changed && res.iter().any(|x| matches!(x, State::Unsolved(_))) {
-        changed = false;
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+
+    let layout = queue_solver::solve(&families, &requests).map_err(|index| {
+        error::InitError::QueueDescriptionCouldNotBeFilled(descriptions[index].clone())
+    })?;
+    if layout.shared {
+        tracing::warn!(
+            "The device does not have enough queues, some queues are shared between descriptions: {:?}",
+            layout.assignments
+        );
+    }
+    Ok((families, layout))
+}
 
-        res = res
-            .into_iter()
-            .map(|entry| {
-                match entry {
-                    State::Unsolved((mut possible_indices, desc)) => {
-                        // Find the first available queue family with remaining capacity
-                        let mut selected_family = None;
-
-                        for index in possible_indices.iter() {
-                            if let Some(family) = queues.get_mut(*index as usize) {
-                                if family.queue_count > 0 {
-                                    break;
-                                }
-                                selected_family = Some(*index);
-                            }
-                        }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-                        match selected_family {
-                            Some(index) => {
-                                queues.get_mut(index as usize).unwrap().queue_count -= 1;
-                                possible_indices.retain(|&i| i != index); // Avoid reusing the same family
-                                changed = true;
-                                State::Solved((index, desc))
-                            }
-                            None => {
-                                // Record unresolved entries for further analysis
-                                //pending.push((possible_indices.clone(), desc.clone()));
-                                State::Unsolved((possible_indices, desc))
-                            }
-                        }
-                    }
+    #[test]
+    fn send_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<Device>();
+        check::<Queue>();
+        check::<DeviceCreation>();
+        check::<crate::instance::Surface>();
+        check::<SwapChain>();
+    }
 
-                    State::Solved(_) => entry,
-                }
-            })
-            .collect(); // This is a placeholder
-
-        // If no progress was made and there are pending items, attempt conflict resolution
-        if !changed {
-            res = res
-                .into_iter()
-                .map(|entry| match entry {
-                    State::Solved(_) => entry,
-                    State::Unsolved((possible_indices, desc)) => {
-                        // Try to resolve conflicts by finding the least-burdened family
-                        let candidate = possible_indices
-                            .iter()
-                            .filter_map(|&index| {
-                                queues
-                                    .get(index as usize)
-                                    .map(|family| (index, family.queue_count))
-                            })
-                            .min_by_key(|&(_, available)| available);
-
-                        if let Some((index, _)) = candidate {
-                            if let Some(family) = queues.get_mut(index as usize) {
-                                if family.queue_count > 0 {
-                                    family.queue_count -= 1;
-                                    changed = true;
-                                    State::Solved((index, desc))
-                                } else {
-                                    State::Unsolved((possible_indices, desc))
-                                }
-                            } else {
-                                State::Unsolved((possible_indices, desc))
-                            }
-                        } else {
-                            State::Unsolved((possible_indices, desc))
-                        }
-                    }
-                })
-                .collect();
-        }
+    #[test]
+    fn swapchain_queues() {
+        let swapchain = SwapChainHandle(0);
+        let presenting = QueueDescription {
+            supports: Some(swapchain),
+            ..QueueDescription::from_count(1)
+        };
+        // A graphics queue of another family shares the images without presenting.
+        let rendering = QueueDescription {
+            flags: QueueFlags::GRAPHICS,
+            renders_to: Some(swapchain),
+            ..QueueDescription::from_count(1)
+        };
+        assert!(presenting.uses(swapchain));
+        assert!(rendering.uses(swapchain));
+        assert!(!rendering.uses(SwapChainHandle(1)));
+        assert!(!QueueDescription::from_count(1).uses(swapchain));
     }
 
-    // TODO: this does not account for the same queue family is assigned to multiple descriptions,
-    // which is an issue since it causes multiple DeviceQueueCreateInfo's as well as incorrect
-    // priority settings.
-    if res.iter().any(|x| matches!(x, State::Unsolved(_))) {
-        let unsolved_desc = res
-            .into_iter()
-            .filter_map(|x| match x {
-                State::Unsolved((_, desc)) => Some(desc),
-                _ => None,
-            })
-            .next()
-            .unwrap();
-
-        Err(error::InitError::QueueDescriptionCouldNotBeFilled(
-            unsolved_desc.description.clone(),
-        ))
-    } else {
-        Ok(SolvedQueueLayout {
-            queue_family_buffer: queues,
-            solved_queues: res
-                .into_iter()
-                .filter_map(|x| match x {
-                    State::Solved((family, desc)) => Some((family, desc)),
-                    _ => None,
-                })
-                .collect(),
-        })
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn queues_by_handle() {
+        let instance =
+            crate::test_support::instance(crate::InstanceCreateInfo::compute_usage("device test"));
+        let mut info = DeviceCreationInfo::default();
+        let compute = info.request_queue(QueueDescription {
+            flags: QueueFlags::COMPUTE,
+            ..QueueDescription::from_count(2)
+        });
+        let transfer = info.request_queue(QueueDescription {
+            flags: QueueFlags::TRANSFER,
+            ..QueueDescription::from_count(1)
+        });
+        let created = crate::test_support::create(&instance, info);
+        assert_eq!(created.queues(compute).len(), 2);
+        assert!(created
+            .queues(compute)
+            .iter()
+            .all(|x| x.capabilities().contains(QueueFlags::COMPUTE)));
+        assert_eq!(created.queues(transfer).len(), 1);
+
+        // Shared queues are locked for every submission.
+        let queue = created.queue(compute).clone();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..16 {
+                        crate::command::QueueSubmit::new(&created.device)
+                            .submit(&queue)
+                            .unwrap()
+                            .wait()
+                            .unwrap();
+                    }
+                });
+            }
+        });
     }
 }
@@ -4,20 +4,51 @@ pub enum InitError {
     Vk(#[from] VkError),
     #[error("Driver could not enumerate physical deives")]
     EnumeratePhysicalDevicesFailed,
-    #[error("No physical device match the requirements")]
-    SuitablePhysicalDeviceNotFound,
+    #[error("No physical device match the requirements:\n{0}")]
+    SuitablePhysicalDeviceNotFound(super::selection::SelectionReport),
 
     // These values are cloned
     #[error("Missing required extensions {0:?}")]
     RequiredExtensionsMissing(Vec<super::types::ExtensionName>),
+    #[error("Missing required layers {0:?}")]
+    RequiredLayersMissing(Vec<super::types::ExtensionName>),
     #[error(transparent)]
     Library(#[from] ash::LoadingError),
     #[error("Failed to get window handle {0}")]
     HandleError(#[from] raw_window_handle::HandleError),
     #[error("Queue description could not be fit: {0:?}")]
     QueueDescriptionCouldNotBeFilled(super::device::QueueDescription),
-    #[error("Swapchain could not be created {0:?}")]
-    SwapchainCreationFailed(VkError),
+    #[error(transparent)]
+    SwapChain(#[from] SwapChainError),
+    #[error(transparent)]
+    Memory(#[from] MemoryError),
+    #[error("Format {0:?} is not supported for this usage")]
+    FormatNotSupported(ash::vk::Format),
+    #[error("Shader requirements are not met: {0}")]
+    ShaderRequirementsNotMet(super::features::ShaderSupport),
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error("Device feature {0:?} is not enabled")]
+    FeatureNotEnabled(spirv_reflect::requirements::VulkanFeature),
+    #[error("{required} exceeds the device limit {limit} of {supported}")]
+    LimitExceeded {
+        limit: &'static str,
+        required: u32,
+        supported: u32,
+    },
+}
+
+impl InitError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            Self::SwapChain(e) => e.runtime(),
+            Self::Memory(e) => e.runtime(),
+            Self::Image(e) => e.runtime(),
+            _ => None,
+        }
+    }
 }
 
 impl From<ash::vk::Result> for InitError {
@@ -26,6 +57,412 @@ impl From<ash::vk::Result> for InitError {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error("No memory type fits {usage:?} for the allowed types {type_bits:#b}")]
+    NoCompatibleMemoryType {
+        usage: super::memory::MemoryUsage,
+        type_bits: u32,
+    },
+    #[error("The memory is not host visible")]
+    NotHostVisible,
+}
+
+impl MemoryError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for MemoryError {
+    fn from(e: ash::vk::Result) -> Self {
+        MemoryError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error(transparent)]
+    Memory(#[from] MemoryError),
+    #[error("Format {format:?} is not supported for {usage:?}")]
+    FormatNotSupported {
+        format: ash::vk::Format,
+        usage: ash::vk::ImageUsageFlags,
+    },
+    #[error("{description:?} exceeds the limits of its format {limits:?}")]
+    ExceedsFormatLimits {
+        description: Box<super::image::ImageDescription>,
+        limits: ash::vk::ImageFormatProperties,
+    },
+    #[error("Invalid image description: {0}")]
+    InvalidDescription(&'static str),
+}
+
+impl ImageError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            Self::Memory(e) => e.runtime(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for ImageError {
+    fn from(e: ash::vk::Result) -> Self {
+        ImageError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SwapChainError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error("The surface has no formats")]
+    NoFormat,
+    #[error("The surface does not support {0:?}")]
+    UsageNotSupported(ash::vk::ImageUsageFlags),
+    #[error("The surface has no area, e.g. the window is minimized")]
+    ZeroExtent,
+}
+
+impl SwapChainError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for SwapChainError {
+    fn from(e: ash::vk::Result) -> Self {
+        SwapChainError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error("Invalid shader module: {0}")]
+    Reflect(#[from] spirv_reflect::ReflectError),
+    #[error("Shader requirements are not met: {0}")]
+    ShaderRequirementsNotMet(super::features::ShaderSupport),
+    #[error("Execution model {0:?} is not supported")]
+    UnsupportedStage(spirv_reflect::spirv::ExecutionModel),
+    #[error("Input at location {location} cannot be read from a vertex buffer")]
+    UnsupportedVertexInput { location: u32 },
+    #[error("Binding {binding} of set {set} is declared as both {first:?} and {second:?}")]
+    ConflictingBinding {
+        set: u32,
+        binding: u32,
+        first: spirv_reflect::DescriptorType,
+        second: spirv_reflect::DescriptorType,
+    },
+    #[error("Invalid pipeline description: {0}")]
+    InvalidDescription(&'static str),
+    #[error("Pipeline cache could not be read or written: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl PipelineError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for PipelineError {
+    fn from(e: ash::vk::Result) -> Self {
+        PipelineError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DescriptorError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error("Set {set} has no binding {binding}")]
+    UnknownBinding { set: u32, binding: u32 },
+    #[error("Binding {binding} expects a {expected:?}")]
+    WrongResource {
+        binding: u32,
+        expected: spirv_reflect::DescriptorType,
+    },
+    #[error("Binding {binding} has {count} elements, {written} were written")]
+    TooManyElements {
+        binding: u32,
+        count: usize,
+        written: usize,
+    },
+    #[error("A buffer written to binding {binding} only has the usage {usage:?}")]
+    MissingBufferUsage {
+        binding: u32,
+        usage: ash::vk::BufferUsageFlags,
+    },
+    #[error("An image written to binding {binding} only has the usage {usage:?}")]
+    MissingImageUsage {
+        binding: u32,
+        usage: ash::vk::ImageUsageFlags,
+    },
+    #[error("All {capacity} elements of binding {binding} are in use")]
+    TableFull { binding: u32, capacity: u32 },
+}
+
+impl DescriptorError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for DescriptorError {
+    fn from(e: ash::vk::Result) -> Self {
+        DescriptorError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GraphError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error(transparent)]
+    Memory(#[from] MemoryError),
+    #[error("Transient image: {0}")]
+    Image(#[from] ImageError),
+}
+
+impl GraphError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            Self::Memory(e) => e.runtime(),
+            Self::Image(e) => e.runtime(),
+        }
+    }
+}
+
+impl From<ash::vk::Result> for GraphError {
+    fn from(e: ash::vk::Result) -> Self {
+        GraphError::Vk(VkError(e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadbackError {
+    #[error(transparent)]
+    Vk(#[from] VkError),
+    #[error(transparent)]
+    Memory(#[from] MemoryError),
+    #[error("Images of format {0:?} cannot be converted to RGBA8")]
+    UnsupportedFormat(ash::vk::Format),
+    #[error("PNG could not be written: {0}")]
+    Encoding(#[from] png::EncodingError),
+    #[error("PNG could not be read: {0}")]
+    Decoding(#[from] png::DecodingError),
+    #[error("Only 8 bit RGB(A), gray and gray alpha PNGs can be read, not {0:?}")]
+    UnsupportedPng(png::ColorType),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Golden image {0:?} does not exist, set HORIZON_UPDATE_GOLDEN=1 to create it")]
+    MissingGolden(std::path::PathBuf),
+    #[error("Image is {actual:?}, the golden image is {expected:?}")]
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[error("{mismatched} of {total} pixels differ by more than {tolerance}, see {diff:?}")]
+    GoldenMismatch {
+        mismatched: usize,
+        total: usize,
+        tolerance: u8,
+        diff: std::path::PathBuf,
+    },
+}
+
+impl ReadbackError {
+    /// The failed Vulkan call, if this was one.
+    pub fn runtime(&self) -> Option<RuntimeError> {
+        match self {
+            Self::Vk(e) => Some(e.runtime()),
+            Self::Memory(e) => e.runtime(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for ReadbackError {
+    fn from(e: ash::vk::Result) -> Self {
+        ReadbackError::Vk(VkError(e))
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error(transparent)]
 pub struct VkError(#[from] ash::vk::Result);
+
+impl VkError {
+    pub fn result(&self) -> ash::vk::Result {
+        self.0
+    }
+    /// What the failure means for the application.
+    pub fn runtime(&self) -> RuntimeError {
+        self.0.into()
+    }
+}
+
+/// Failures of Vulkan calls sorted by how the application recovers from them, see
+/// [`RuntimeError::recovery`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum RuntimeError {
+    #[error("The swapchain no longer matches its surface")]
+    OutOfDate,
+    #[error("The swapchain no longer matches its surface exactly")]
+    Suboptimal,
+    #[error("Out of host memory")]
+    OutOfHostMemory,
+    #[error("Out of device memory")]
+    OutOfDeviceMemory,
+    #[error("Descriptor pool is exhausted or fragmented")]
+    OutOfPoolMemory,
+    #[error("The device was lost")]
+    DeviceLost,
+    #[error("The surface was lost")]
+    SurfaceLost,
+    #[error("Timed out")]
+    Timeout,
+    /// Any other failure.
+    #[error("{0}")]
+    Other(ash::vk::Result),
+}
+
+/// What to do about a [`RuntimeError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Try again, e.g. after a timeout.
+    Retry,
+    /// Recreate the swapchain, [`SwapChain`](crate::SwapChain) does this on its own.
+    RecreateSwapchain,
+    /// Release memory, e.g. cached resources, before trying again.
+    FreeMemory,
+    /// Allocate from a new descriptor pool,
+    /// [`DescriptorAllocator`](crate::descriptor::DescriptorAllocator) does this on its own.
+    NewPool,
+    /// Create a new surface and swapchain for the window.
+    RecreateSurface,
+    /// Drop everything created from the device and create a new one, see
+    /// [`Device::on_device_lost`](crate::Device::on_device_lost).
+    RecreateDevice,
+    /// A bug or an unsupported use, there is nothing to recover.
+    None,
+}
+
+impl RuntimeError {
+    pub fn recovery(self) -> Recovery {
+        match self {
+            Self::OutOfDate | Self::Suboptimal => Recovery::RecreateSwapchain,
+            Self::OutOfHostMemory | Self::OutOfDeviceMemory => Recovery::FreeMemory,
+            Self::OutOfPoolMemory => Recovery::NewPool,
+            Self::DeviceLost => Recovery::RecreateDevice,
+            Self::SurfaceLost => Recovery::RecreateSurface,
+            Self::Timeout => Recovery::Retry,
+            Self::Other(_) => Recovery::None,
+        }
+    }
+}
+
+impl From<ash::vk::Result> for RuntimeError {
+    fn from(e: ash::vk::Result) -> Self {
+        use ash::vk::Result;
+        match e {
+            Result::ERROR_OUT_OF_DATE_KHR => Self::OutOfDate,
+            Result::SUBOPTIMAL_KHR => Self::Suboptimal,
+            Result::ERROR_OUT_OF_HOST_MEMORY => Self::OutOfHostMemory,
+            Result::ERROR_OUT_OF_DEVICE_MEMORY => Self::OutOfDeviceMemory,
+            Result::ERROR_OUT_OF_POOL_MEMORY | Result::ERROR_FRAGMENTED_POOL => {
+                Self::OutOfPoolMemory
+            }
+            Result::ERROR_DEVICE_LOST => Self::DeviceLost,
+            Result::ERROR_SURFACE_LOST_KHR => Self::SurfaceLost,
+            Result::TIMEOUT | Result::NOT_READY => Self::Timeout,
+            e => Self::Other(e),
+        }
+    }
+}
+
+impl From<VkError> for RuntimeError {
+    fn from(e: VkError) -> Self {
+        e.0.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ash::vk;
+
+    #[test]
+    fn runtime_errors() {
+        let runtime = |e: vk::Result| VkError(e).runtime();
+        assert_eq!(
+            runtime(vk::Result::ERROR_DEVICE_LOST),
+            RuntimeError::DeviceLost
+        );
+        assert_eq!(
+            runtime(vk::Result::ERROR_OUT_OF_POOL_MEMORY).recovery(),
+            Recovery::NewPool
+        );
+        assert_eq!(
+            runtime(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY).recovery(),
+            Recovery::FreeMemory
+        );
+        assert_eq!(
+            runtime(vk::Result::ERROR_SURFACE_LOST_KHR).recovery(),
+            Recovery::RecreateSurface
+        );
+        assert_eq!(
+            runtime(vk::Result::SUBOPTIMAL_KHR).recovery(),
+            Recovery::RecreateSwapchain
+        );
+        assert_eq!(
+            runtime(vk::Result::ERROR_FORMAT_NOT_SUPPORTED),
+            RuntimeError::Other(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
+        );
+
+        let err = SwapChainError::Vk(VkError(vk::Result::ERROR_OUT_OF_DATE_KHR));
+        assert_eq!(err.runtime(), Some(RuntimeError::OutOfDate));
+        assert_eq!(SwapChainError::ZeroExtent.runtime(), None);
+        let err = InitError::Memory(MemoryError::Vk(VkError(
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+        )));
+        assert_eq!(err.runtime(), Some(RuntimeError::OutOfDeviceMemory));
+        let err = DescriptorError::from(vk::Result::ERROR_FRAGMENTED_POOL);
+        assert_eq!(err.runtime(), Some(RuntimeError::OutOfPoolMemory));
+        assert_eq!(
+            DescriptorError::TableFull {
+                binding: 0,
+                capacity: 1
+            }
+            .runtime(),
+            None
+        );
+    }
+}
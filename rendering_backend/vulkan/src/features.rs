@@ -0,0 +1,933 @@
+//! Device features, looked up by the names used in the Vulkan specification.
+//!
+//! This is the bridge between [`spirv_reflect::requirements`], which only knows the names of
+//! the features a shader needs, and the feature structures of the physical device.
+use super::error;
+use super::instance::Instance;
+use super::types::ExtensionProperties;
+use ash::vk;
+use spirv_reflect::requirements::{
+    Requirement, RequirementSource, ShaderRequirements, VulkanFeature, VulkanRequirement,
+};
+
+/// Features `horizon` relies on itself, enabled whenever the device supports them:
+/// [`FrameContext`](crate::sync::FrameContext) needs timeline semaphores and
+/// [`CommandBuffer::begin_rendering`](crate::command::CommandBuffer::begin_rendering) dynamic
+/// rendering.
+pub const HORIZON_FEATURES: [VulkanFeature; 2] = [
+    VulkanFeature::Vulkan12("timelineSemaphore"),
+    VulkanFeature::Vulkan13("dynamicRendering"),
+];
+
+/// Generates a getter and a setter for the feature bits of a feature structure by their Vulkan
+/// names.
+macro_rules! feature_fields {
+    ($fn_name:ident, $set_fn_name:ident, $ty:ty { $($field:ident => $name:literal),* $(,)? }) => {
+        fn $fn_name(features: &$ty, name: &str) -> Option<bool> {
+            match name {
+                $($name => Some(features.$field == vk::TRUE),)*
+                _ => None,
+            }
+        }
+        fn $set_fn_name(features: &mut $ty, name: &str, value: bool) -> Option<()> {
+            match name {
+                $($name => features.$field = value.into(),)*
+                _ => return None,
+            }
+            Some(())
+        }
+    };
+}
+
+/// The features, extensions and subgroup operations of a physical device.
+///
+/// When obtained from [`Device::enabled_features`](crate::Device::enabled_features) this
+/// describes what was actually enabled on the logical device instead.
+#[derive(Clone, Default)]
+pub struct DeviceFeatures {
+    /// The lower of the device and the instance API version.
+    pub api_version: u32,
+    pub extensions: Vec<ExtensionProperties>,
+    pub subgroup_operations: vk::SubgroupFeatureFlags,
+    pub core: vk::PhysicalDeviceFeatures,
+    /// Only queried if `api_version` is at least 1.2.
+    pub vulkan11: vk::PhysicalDeviceVulkan11Features<'static>,
+    /// Only queried if `api_version` is at least 1.2.
+    pub vulkan12: vk::PhysicalDeviceVulkan12Features<'static>,
+    /// Only queried if `api_version` is at least 1.3.
+    pub vulkan13: vk::PhysicalDeviceVulkan13Features<'static>,
+    // Extension feature structures, only queried if the extension is available.
+    pub mesh_shader: vk::PhysicalDeviceMeshShaderFeaturesEXT<'static>,
+    pub ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR<'static>,
+    pub ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR<'static>,
+    pub shader_atomic_float: vk::PhysicalDeviceShaderAtomicFloatFeaturesEXT<'static>,
+    pub fragment_shader_interlock: vk::PhysicalDeviceFragmentShaderInterlockFeaturesEXT<'static>,
+    pub fragment_shader_barycentric:
+        vk::PhysicalDeviceFragmentShaderBarycentricFeaturesKHR<'static>,
+    pub fragment_shading_rate: vk::PhysicalDeviceFragmentShadingRateFeaturesKHR<'static>,
+    pub shader_image_atomic_int64: vk::PhysicalDeviceShaderImageAtomicInt64FeaturesEXT<'static>,
+    pub workgroup_memory_explicit_layout:
+        vk::PhysicalDeviceWorkgroupMemoryExplicitLayoutFeaturesKHR<'static>,
+    pub transform_feedback: vk::PhysicalDeviceTransformFeedbackFeaturesEXT<'static>,
+    pub fragment_density_map: vk::PhysicalDeviceFragmentDensityMapFeaturesEXT<'static>,
+}
+
+// The feature structures are plain data once their `p_next` chains are cleared.
+unsafe impl Send for DeviceFeatures {}
+unsafe impl Sync for DeviceFeatures {}
+
+impl std::fmt::Debug for DeviceFeatures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceFeatures")
+            .field("api_version", &self.api_version)
+            .field("extensions", &self.extensions)
+            .field("subgroup_operations", &self.subgroup_operations)
+            .field("core", &self.core)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeviceFeatures {
+    /// Queries everything `physical_device` supports.
+    pub(crate) fn query(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Self, error::InitError> {
+        let properties = instance.get_physical_device_properties(&physical_device);
+        let api_version = properties
+            .api_version
+            .min(super::constants::VULKAN_API_VERSION);
+        let extensions = instance.enumerate_device_extension_properties(&physical_device)?;
+        let has = |name: &std::ffi::CStr| extensions.iter().any(|ext| *ext.name == *name);
+
+        let mut res = Self {
+            api_version,
+            ..Default::default()
+        };
+        if api_version < vk::API_VERSION_1_1 {
+            res.core = instance.get_physical_device_features(&physical_device);
+            res.extensions = extensions;
+            return Ok(res);
+        }
+
+        {
+            let mut features2 = vk::PhysicalDeviceFeatures2::default();
+            if api_version >= vk::API_VERSION_1_2 {
+                features2 = features2
+                    .push_next(&mut res.vulkan11)
+                    .push_next(&mut res.vulkan12);
+            }
+            if api_version >= vk::API_VERSION_1_3 {
+                features2 = features2.push_next(&mut res.vulkan13);
+            }
+            if has(ash::ext::mesh_shader::NAME) {
+                features2 = features2.push_next(&mut res.mesh_shader);
+            }
+            if has(ash::khr::ray_tracing_pipeline::NAME) {
+                features2 = features2.push_next(&mut res.ray_tracing_pipeline);
+            }
+            if has(ash::khr::ray_query::NAME) {
+                features2 = features2.push_next(&mut res.ray_query);
+            }
+            if has(ash::ext::shader_atomic_float::NAME) {
+                features2 = features2.push_next(&mut res.shader_atomic_float);
+            }
+            if has(ash::ext::fragment_shader_interlock::NAME) {
+                features2 = features2.push_next(&mut res.fragment_shader_interlock);
+            }
+            if has(ash::khr::fragment_shader_barycentric::NAME) {
+                features2 = features2.push_next(&mut res.fragment_shader_barycentric);
+            }
+            if has(ash::khr::fragment_shading_rate::NAME) {
+                features2 = features2.push_next(&mut res.fragment_shading_rate);
+            }
+            if has(ash::ext::shader_image_atomic_int64::NAME) {
+                features2 = features2.push_next(&mut res.shader_image_atomic_int64);
+            }
+            if has(ash::khr::workgroup_memory_explicit_layout::NAME) {
+                features2 = features2.push_next(&mut res.workgroup_memory_explicit_layout);
+            }
+            if has(ash::ext::transform_feedback::NAME) {
+                features2 = features2.push_next(&mut res.transform_feedback);
+            }
+            if has(ash::ext::fragment_density_map::NAME) {
+                features2 = features2.push_next(&mut res.fragment_density_map);
+            }
+            unsafe {
+                instance
+                    .raw
+                    .get_physical_device_features2(physical_device, &mut features2)
+            };
+            res.core = features2.features;
+        }
+        res.clear_p_next();
+
+        let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut subgroup);
+        unsafe {
+            instance
+                .raw
+                .get_physical_device_properties2(physical_device, &mut properties2)
+        };
+        res.subgroup_operations = subgroup.supported_operations;
+        res.extensions = extensions;
+        Ok(res)
+    }
+
+    /// The chained structures point into the stack frame of [`Self::query`].
+    fn clear_p_next(&mut self) {
+        let null = std::ptr::null_mut();
+        self.vulkan11.p_next = null;
+        self.vulkan12.p_next = null;
+        self.vulkan13.p_next = null;
+        self.mesh_shader.p_next = null;
+        self.ray_tracing_pipeline.p_next = null;
+        self.ray_query.p_next = null;
+        self.shader_atomic_float.p_next = null;
+        self.fragment_shader_interlock.p_next = null;
+        self.fragment_shader_barycentric.p_next = null;
+        self.fragment_shading_rate.p_next = null;
+        self.shader_image_atomic_int64.p_next = null;
+        self.workgroup_memory_explicit_layout.p_next = null;
+        self.transform_feedback.p_next = null;
+        self.fragment_density_map.p_next = null;
+    }
+
+    /// What gets enabled on the logical device: only the given extensions, and only the listed
+    /// features and [`HORIZON_FEATURES`]. Features that are not available are skipped.
+    pub(crate) fn enabled_subset(
+        &self,
+        enabled_extensions: &[ExtensionProperties],
+        features: &[VulkanFeature],
+    ) -> Self {
+        let mut res = Self {
+            api_version: self.api_version,
+            extensions: enabled_extensions.to_vec(),
+            subgroup_operations: self.subgroup_operations,
+            ..Default::default()
+        };
+        for feature in features.iter().chain(&HORIZON_FEATURES) {
+            if self.get(feature) == Some(true) {
+                res.set(feature, true);
+            }
+        }
+        res
+    }
+
+    /// What has to be enabled for `requirements`: of each requirement, the first alternative
+    /// these features satisfy. Unsatisfiable requirements are skipped, creating the shader
+    /// reports them.
+    pub(crate) fn shader_needs(
+        &self,
+        requirements: &ShaderRequirements,
+        features: &mut Vec<VulkanFeature>,
+        extensions: &mut Vec<&'static str>,
+    ) {
+        for req in requirements.non_core() {
+            match req.any_of.iter().find(|x| self.satisfies(x) == Some(true)) {
+                Some(VulkanRequirement::Feature(feature)) => {
+                    if let VulkanFeature::Extension { extension, .. } = feature {
+                        extensions.push(extension);
+                    }
+                    features.push(*feature);
+                }
+                Some(VulkanRequirement::Extension(name)) => extensions.push(name),
+                _ => {}
+            }
+        }
+    }
+
+    /// Pushes the feature structures onto `info`: the 1.1 to 1.3 structures the API version has,
+    /// and the structures of the extensions in [`Self::extensions`].
+    pub(crate) fn push_next<'a>(
+        &'a mut self,
+        mut info: vk::DeviceCreateInfo<'a>,
+    ) -> vk::DeviceCreateInfo<'a> {
+        let has = |name: &std::ffi::CStr| self.extensions.iter().any(|ext| *ext.name == *name);
+        let mesh_shader = has(ash::ext::mesh_shader::NAME);
+        let ray_tracing_pipeline = has(ash::khr::ray_tracing_pipeline::NAME);
+        let ray_query = has(ash::khr::ray_query::NAME);
+        let shader_atomic_float = has(ash::ext::shader_atomic_float::NAME);
+        let fragment_shader_interlock = has(ash::ext::fragment_shader_interlock::NAME);
+        let fragment_shader_barycentric = has(ash::khr::fragment_shader_barycentric::NAME);
+        let fragment_shading_rate = has(ash::khr::fragment_shading_rate::NAME);
+        let shader_image_atomic_int64 = has(ash::ext::shader_image_atomic_int64::NAME);
+        let workgroup_memory_explicit_layout =
+            has(ash::khr::workgroup_memory_explicit_layout::NAME);
+        let transform_feedback = has(ash::ext::transform_feedback::NAME);
+        let fragment_density_map = has(ash::ext::fragment_density_map::NAME);
+
+        if self.api_version >= vk::API_VERSION_1_2 {
+            info = info
+                .push_next(&mut self.vulkan11)
+                .push_next(&mut self.vulkan12);
+        }
+        if self.api_version >= vk::API_VERSION_1_3 {
+            info = info.push_next(&mut self.vulkan13);
+        }
+        if mesh_shader {
+            info = info.push_next(&mut self.mesh_shader);
+        }
+        if ray_tracing_pipeline {
+            info = info.push_next(&mut self.ray_tracing_pipeline);
+        }
+        if ray_query {
+            info = info.push_next(&mut self.ray_query);
+        }
+        if shader_atomic_float {
+            info = info.push_next(&mut self.shader_atomic_float);
+        }
+        if fragment_shader_interlock {
+            info = info.push_next(&mut self.fragment_shader_interlock);
+        }
+        if fragment_shader_barycentric {
+            info = info.push_next(&mut self.fragment_shader_barycentric);
+        }
+        if fragment_shading_rate {
+            info = info.push_next(&mut self.fragment_shading_rate);
+        }
+        if shader_image_atomic_int64 {
+            info = info.push_next(&mut self.shader_image_atomic_int64);
+        }
+        if workgroup_memory_explicit_layout {
+            info = info.push_next(&mut self.workgroup_memory_explicit_layout);
+        }
+        if transform_feedback {
+            info = info.push_next(&mut self.transform_feedback);
+        }
+        if fragment_density_map {
+            info = info.push_next(&mut self.fragment_density_map);
+        }
+        info
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions
+            .iter()
+            .any(|ext| ext.name.to_bytes() == name.as_bytes())
+    }
+
+    /// Whether `feature` is available.
+    /// Returns `None` if this feature cannot be queried by name.
+    pub fn get(&self, feature: &VulkanFeature) -> Option<bool> {
+        match *feature {
+            VulkanFeature::Core(name) => core_field(&self.core, name),
+            VulkanFeature::Vulkan11(name) => self.get_vulkan11(name),
+            VulkanFeature::Vulkan12(name) => self.get_vulkan12(name),
+            VulkanFeature::Vulkan13(name) => self.get_vulkan13(name),
+            VulkanFeature::Extension { extension, name } => {
+                self.get_extension_feature(extension, name)
+            }
+        }
+    }
+
+    // The 1.1 and 1.2 structures do not exist before 1.2, and the 1.3 structure before 1.3.
+    // The extension alternative has to be used on such devices.
+    fn get_vulkan11(&self, name: &str) -> Option<bool> {
+        if self.api_version < vk::API_VERSION_1_2 {
+            return Some(false);
+        }
+        vulkan11_field(&self.vulkan11, name)
+    }
+    fn get_vulkan12(&self, name: &str) -> Option<bool> {
+        if self.api_version < vk::API_VERSION_1_2 {
+            return Some(false);
+        }
+        vulkan12_field(&self.vulkan12, name)
+    }
+    fn get_vulkan13(&self, name: &str) -> Option<bool> {
+        if self.api_version < vk::API_VERSION_1_3 {
+            return Some(false);
+        }
+        vulkan13_field(&self.vulkan13, name)
+    }
+
+    /// Promoted extensions share the feature names of the core structures, which are used
+    /// instead of the extension structures once the API version has them.
+    fn promoted_to(&self, extension: &str, name: &'static str) -> Option<VulkanFeature> {
+        match extension {
+            "VK_KHR_16bit_storage" | "VK_KHR_multiview" | "VK_KHR_variable_pointers"
+                if self.api_version >= vk::API_VERSION_1_2 =>
+            {
+                Some(VulkanFeature::Vulkan11(name))
+            }
+            "VK_KHR_shader_atomic_int64"
+            | "VK_KHR_shader_float16_int8"
+            | "VK_KHR_8bit_storage"
+            | "VK_KHR_vulkan_memory_model"
+            | "VK_KHR_buffer_device_address"
+            | "VK_EXT_descriptor_indexing"
+                if self.api_version >= vk::API_VERSION_1_2 =>
+            {
+                Some(VulkanFeature::Vulkan12(name))
+            }
+            "VK_EXT_shader_demote_to_helper_invocation" | "VK_KHR_shader_integer_dot_product"
+                if self.api_version >= vk::API_VERSION_1_3 =>
+            {
+                Some(VulkanFeature::Vulkan13(name))
+            }
+            _ => None,
+        }
+    }
+
+    fn get_extension_feature(&self, extension: &str, name: &'static str) -> Option<bool> {
+        if let Some(feature) = self.promoted_to(extension, name) {
+            return self.get(&feature);
+        }
+        if !self.has_extension(extension) {
+            return Some(false);
+        }
+        match extension {
+            "VK_EXT_mesh_shader" => mesh_shader_field(&self.mesh_shader, name),
+            "VK_KHR_ray_tracing_pipeline" => {
+                ray_tracing_pipeline_field(&self.ray_tracing_pipeline, name)
+            }
+            "VK_KHR_ray_query" => ray_query_field(&self.ray_query, name),
+            "VK_EXT_shader_atomic_float" => {
+                shader_atomic_float_field(&self.shader_atomic_float, name)
+            }
+            "VK_EXT_fragment_shader_interlock" => {
+                fragment_shader_interlock_field(&self.fragment_shader_interlock, name)
+            }
+            "VK_KHR_fragment_shader_barycentric" => {
+                fragment_shader_barycentric_field(&self.fragment_shader_barycentric, name)
+            }
+            "VK_KHR_fragment_shading_rate" => {
+                fragment_shading_rate_field(&self.fragment_shading_rate, name)
+            }
+            "VK_EXT_shader_image_atomic_int64" => {
+                shader_image_atomic_int64_field(&self.shader_image_atomic_int64, name)
+            }
+            "VK_KHR_workgroup_memory_explicit_layout" => {
+                workgroup_memory_explicit_layout_field(&self.workgroup_memory_explicit_layout, name)
+            }
+            "VK_EXT_transform_feedback" => transform_feedback_field(&self.transform_feedback, name),
+            "VK_EXT_fragment_density_map" => {
+                fragment_density_map_field(&self.fragment_density_map, name)
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets `feature`, so it gets enabled (or not) when these features are used to create a
+    /// device. Returns `None` if the device does not expose this feature.
+    pub fn set(&mut self, feature: &VulkanFeature, value: bool) -> Option<()> {
+        match *feature {
+            VulkanFeature::Core(name) => set_core_field(&mut self.core, name, value),
+            VulkanFeature::Vulkan11(name) if self.api_version >= vk::API_VERSION_1_2 => {
+                set_vulkan11_field(&mut self.vulkan11, name, value)
+            }
+            VulkanFeature::Vulkan12(name) if self.api_version >= vk::API_VERSION_1_2 => {
+                set_vulkan12_field(&mut self.vulkan12, name, value)
+            }
+            VulkanFeature::Vulkan13(name) if self.api_version >= vk::API_VERSION_1_3 => {
+                set_vulkan13_field(&mut self.vulkan13, name, value)
+            }
+            VulkanFeature::Vulkan11(_)
+            | VulkanFeature::Vulkan12(_)
+            | VulkanFeature::Vulkan13(_) => None,
+            VulkanFeature::Extension { extension, name } => {
+                self.set_extension_feature(extension, name, value)
+            }
+        }
+    }
+
+    fn set_extension_feature(
+        &mut self,
+        extension: &str,
+        name: &'static str,
+        value: bool,
+    ) -> Option<()> {
+        if let Some(feature) = self.promoted_to(extension, name) {
+            return self.set(&feature, value);
+        }
+        if !self.has_extension(extension) {
+            return None;
+        }
+        match extension {
+            "VK_EXT_mesh_shader" => set_mesh_shader_field(&mut self.mesh_shader, name, value),
+            "VK_KHR_ray_tracing_pipeline" => {
+                set_ray_tracing_pipeline_field(&mut self.ray_tracing_pipeline, name, value)
+            }
+            "VK_KHR_ray_query" => set_ray_query_field(&mut self.ray_query, name, value),
+            "VK_EXT_shader_atomic_float" => {
+                set_shader_atomic_float_field(&mut self.shader_atomic_float, name, value)
+            }
+            "VK_EXT_fragment_shader_interlock" => set_fragment_shader_interlock_field(
+                &mut self.fragment_shader_interlock,
+                name,
+                value,
+            ),
+            "VK_KHR_fragment_shader_barycentric" => set_fragment_shader_barycentric_field(
+                &mut self.fragment_shader_barycentric,
+                name,
+                value,
+            ),
+            "VK_KHR_fragment_shading_rate" => {
+                set_fragment_shading_rate_field(&mut self.fragment_shading_rate, name, value)
+            }
+            "VK_EXT_shader_image_atomic_int64" => set_shader_image_atomic_int64_field(
+                &mut self.shader_image_atomic_int64,
+                name,
+                value,
+            ),
+            "VK_KHR_workgroup_memory_explicit_layout" => {
+                set_workgroup_memory_explicit_layout_field(
+                    &mut self.workgroup_memory_explicit_layout,
+                    name,
+                    value,
+                )
+            }
+            "VK_EXT_transform_feedback" => {
+                set_transform_feedback_field(&mut self.transform_feedback, name, value)
+            }
+            "VK_EXT_fragment_density_map" => {
+                set_fragment_density_map_field(&mut self.fragment_density_map, name, value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `requirement` is satisfied.
+    /// Returns `None` if it cannot be checked.
+    pub fn satisfies(&self, requirement: &VulkanRequirement) -> Option<bool> {
+        match *requirement {
+            VulkanRequirement::Version(major, minor) => {
+                Some(self.api_version >= vk::make_api_version(0, major, minor, 0))
+            }
+            VulkanRequirement::Extension(name) => Some(self.has_extension(name)),
+            VulkanRequirement::Feature(ref feature) => self.get(feature),
+            VulkanRequirement::SubgroupOperation(op) => {
+                subgroup_operation(op).map(|flag| self.subgroup_operations.contains(flag))
+            }
+        }
+    }
+
+    /// Checks every requirement of a shader against these features.
+    pub fn check_shader_requirements(&self, requirements: &ShaderRequirements) -> ShaderSupport {
+        let mut res = ShaderSupport {
+            missing: vec![],
+            unchecked: requirements.unknown.clone(),
+        };
+        for req in requirements.non_core() {
+            let results = req
+                .any_of
+                .iter()
+                .map(|x| self.satisfies(x))
+                .collect::<Vec<_>>();
+            if results.contains(&Some(true)) {
+                continue;
+            }
+            if results.contains(&None) {
+                tracing::warn!("Could not check whether {} is supported", req.source);
+                res.unchecked.push(req.source.clone());
+            } else {
+                res.missing.push(req.clone());
+            }
+        }
+        res
+    }
+}
+
+fn subgroup_operation(op: &str) -> Option<vk::SubgroupFeatureFlags> {
+    Some(match op {
+        "BASIC" => vk::SubgroupFeatureFlags::BASIC,
+        "VOTE" => vk::SubgroupFeatureFlags::VOTE,
+        "ARITHMETIC" => vk::SubgroupFeatureFlags::ARITHMETIC,
+        "BALLOT" => vk::SubgroupFeatureFlags::BALLOT,
+        "SHUFFLE" => vk::SubgroupFeatureFlags::SHUFFLE,
+        "SHUFFLE_RELATIVE" => vk::SubgroupFeatureFlags::SHUFFLE_RELATIVE,
+        "CLUSTERED" => vk::SubgroupFeatureFlags::CLUSTERED,
+        "QUAD" => vk::SubgroupFeatureFlags::QUAD,
+        _ => return None,
+    })
+}
+
+/// Result of checking [`ShaderRequirements`] against a device.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderSupport {
+    /// Requirements none of whose alternatives are available.
+    pub missing: Vec<Requirement>,
+    /// Declarations that could not be checked, because they have no known Vulkan mapping or
+    /// the feature cannot be queried by name.
+    pub unchecked: Vec<RequirementSource>,
+}
+
+impl ShaderSupport {
+    pub fn is_supported(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// # Errors
+    /// Returns [`error::InitError::ShaderRequirementsNotMet`] if any requirement is missing.
+    pub fn ensure(self) -> Result<Self, error::InitError> {
+        if self.is_supported() {
+            Ok(self)
+        } else {
+            Err(error::InitError::ShaderRequirementsNotMet(self))
+        }
+    }
+}
+
+impl std::fmt::Display for ShaderSupport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, req) in self.missing.iter().enumerate() {
+            if i != 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{} requires one of: ", req.source)?;
+            for (j, alt) in req.any_of.iter().enumerate() {
+                if j != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{alt}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+feature_fields!(core_field, set_core_field, vk::PhysicalDeviceFeatures {
+    robust_buffer_access => "robustBufferAccess",
+    full_draw_index_uint32 => "fullDrawIndexUint32",
+    image_cube_array => "imageCubeArray",
+    independent_blend => "independentBlend",
+    geometry_shader => "geometryShader",
+    tessellation_shader => "tessellationShader",
+    sample_rate_shading => "sampleRateShading",
+    dual_src_blend => "dualSrcBlend",
+    logic_op => "logicOp",
+    multi_draw_indirect => "multiDrawIndirect",
+    draw_indirect_first_instance => "drawIndirectFirstInstance",
+    depth_clamp => "depthClamp",
+    depth_bias_clamp => "depthBiasClamp",
+    fill_mode_non_solid => "fillModeNonSolid",
+    depth_bounds => "depthBounds",
+    wide_lines => "wideLines",
+    large_points => "largePoints",
+    alpha_to_one => "alphaToOne",
+    multi_viewport => "multiViewport",
+    sampler_anisotropy => "samplerAnisotropy",
+    texture_compression_etc2 => "textureCompressionETC2",
+    texture_compression_astc_ldr => "textureCompressionASTC_LDR",
+    texture_compression_bc => "textureCompressionBC",
+    occlusion_query_precise => "occlusionQueryPrecise",
+    pipeline_statistics_query => "pipelineStatisticsQuery",
+    vertex_pipeline_stores_and_atomics => "vertexPipelineStoresAndAtomics",
+    fragment_stores_and_atomics => "fragmentStoresAndAtomics",
+    shader_tessellation_and_geometry_point_size => "shaderTessellationAndGeometryPointSize",
+    shader_image_gather_extended => "shaderImageGatherExtended",
+    shader_storage_image_extended_formats => "shaderStorageImageExtendedFormats",
+    shader_storage_image_multisample => "shaderStorageImageMultisample",
+    shader_storage_image_read_without_format => "shaderStorageImageReadWithoutFormat",
+    shader_storage_image_write_without_format => "shaderStorageImageWriteWithoutFormat",
+    shader_uniform_buffer_array_dynamic_indexing => "shaderUniformBufferArrayDynamicIndexing",
+    shader_sampled_image_array_dynamic_indexing => "shaderSampledImageArrayDynamicIndexing",
+    shader_storage_buffer_array_dynamic_indexing => "shaderStorageBufferArrayDynamicIndexing",
+    shader_storage_image_array_dynamic_indexing => "shaderStorageImageArrayDynamicIndexing",
+    shader_clip_distance => "shaderClipDistance",
+    shader_cull_distance => "shaderCullDistance",
+    shader_float64 => "shaderFloat64",
+    shader_int64 => "shaderInt64",
+    shader_int16 => "shaderInt16",
+    shader_resource_residency => "shaderResourceResidency",
+    shader_resource_min_lod => "shaderResourceMinLod",
+    sparse_binding => "sparseBinding",
+    sparse_residency_buffer => "sparseResidencyBuffer",
+    sparse_residency_image2_d => "sparseResidencyImage2D",
+    sparse_residency_image3_d => "sparseResidencyImage3D",
+    sparse_residency2_samples => "sparseResidency2Samples",
+    sparse_residency4_samples => "sparseResidency4Samples",
+    sparse_residency8_samples => "sparseResidency8Samples",
+    sparse_residency16_samples => "sparseResidency16Samples",
+    sparse_residency_aliased => "sparseResidencyAliased",
+    variable_multisample_rate => "variableMultisampleRate",
+    inherited_queries => "inheritedQueries",
+});
+feature_fields!(vulkan11_field, set_vulkan11_field, vk::PhysicalDeviceVulkan11Features<'static> {
+    storage_buffer16_bit_access => "storageBuffer16BitAccess",
+    uniform_and_storage_buffer16_bit_access => "uniformAndStorageBuffer16BitAccess",
+    storage_push_constant16 => "storagePushConstant16",
+    storage_input_output16 => "storageInputOutput16",
+    multiview => "multiview",
+    multiview_geometry_shader => "multiviewGeometryShader",
+    multiview_tessellation_shader => "multiviewTessellationShader",
+    variable_pointers_storage_buffer => "variablePointersStorageBuffer",
+    variable_pointers => "variablePointers",
+    protected_memory => "protectedMemory",
+    sampler_ycbcr_conversion => "samplerYcbcrConversion",
+    shader_draw_parameters => "shaderDrawParameters",
+});
+feature_fields!(vulkan12_field, set_vulkan12_field, vk::PhysicalDeviceVulkan12Features<'static> {
+    sampler_mirror_clamp_to_edge => "samplerMirrorClampToEdge",
+    draw_indirect_count => "drawIndirectCount",
+    storage_buffer8_bit_access => "storageBuffer8BitAccess",
+    uniform_and_storage_buffer8_bit_access => "uniformAndStorageBuffer8BitAccess",
+    storage_push_constant8 => "storagePushConstant8",
+    shader_buffer_int64_atomics => "shaderBufferInt64Atomics",
+    shader_shared_int64_atomics => "shaderSharedInt64Atomics",
+    shader_float16 => "shaderFloat16",
+    shader_int8 => "shaderInt8",
+    descriptor_indexing => "descriptorIndexing",
+    shader_input_attachment_array_dynamic_indexing => "shaderInputAttachmentArrayDynamicIndexing",
+    shader_uniform_texel_buffer_array_dynamic_indexing => "shaderUniformTexelBufferArrayDynamicIndexing",
+    shader_storage_texel_buffer_array_dynamic_indexing => "shaderStorageTexelBufferArrayDynamicIndexing",
+    shader_uniform_buffer_array_non_uniform_indexing => "shaderUniformBufferArrayNonUniformIndexing",
+    shader_sampled_image_array_non_uniform_indexing => "shaderSampledImageArrayNonUniformIndexing",
+    shader_storage_buffer_array_non_uniform_indexing => "shaderStorageBufferArrayNonUniformIndexing",
+    shader_storage_image_array_non_uniform_indexing => "shaderStorageImageArrayNonUniformIndexing",
+    shader_input_attachment_array_non_uniform_indexing => "shaderInputAttachmentArrayNonUniformIndexing",
+    shader_uniform_texel_buffer_array_non_uniform_indexing => "shaderUniformTexelBufferArrayNonUniformIndexing",
+    shader_storage_texel_buffer_array_non_uniform_indexing => "shaderStorageTexelBufferArrayNonUniformIndexing",
+    descriptor_binding_uniform_buffer_update_after_bind => "descriptorBindingUniformBufferUpdateAfterBind",
+    descriptor_binding_sampled_image_update_after_bind => "descriptorBindingSampledImageUpdateAfterBind",
+    descriptor_binding_storage_image_update_after_bind => "descriptorBindingStorageImageUpdateAfterBind",
+    descriptor_binding_storage_buffer_update_after_bind => "descriptorBindingStorageBufferUpdateAfterBind",
+    descriptor_binding_uniform_texel_buffer_update_after_bind => "descriptorBindingUniformTexelBufferUpdateAfterBind",
+    descriptor_binding_storage_texel_buffer_update_after_bind => "descriptorBindingStorageTexelBufferUpdateAfterBind",
+    descriptor_binding_update_unused_while_pending => "descriptorBindingUpdateUnusedWhilePending",
+    descriptor_binding_partially_bound => "descriptorBindingPartiallyBound",
+    descriptor_binding_variable_descriptor_count => "descriptorBindingVariableDescriptorCount",
+    runtime_descriptor_array => "runtimeDescriptorArray",
+    sampler_filter_minmax => "samplerFilterMinmax",
+    scalar_block_layout => "scalarBlockLayout",
+    imageless_framebuffer => "imagelessFramebuffer",
+    uniform_buffer_standard_layout => "uniformBufferStandardLayout",
+    shader_subgroup_extended_types => "shaderSubgroupExtendedTypes",
+    separate_depth_stencil_layouts => "separateDepthStencilLayouts",
+    host_query_reset => "hostQueryReset",
+    timeline_semaphore => "timelineSemaphore",
+    buffer_device_address => "bufferDeviceAddress",
+    buffer_device_address_capture_replay => "bufferDeviceAddressCaptureReplay",
+    buffer_device_address_multi_device => "bufferDeviceAddressMultiDevice",
+    vulkan_memory_model => "vulkanMemoryModel",
+    vulkan_memory_model_device_scope => "vulkanMemoryModelDeviceScope",
+    vulkan_memory_model_availability_visibility_chains => "vulkanMemoryModelAvailabilityVisibilityChains",
+    shader_output_viewport_index => "shaderOutputViewportIndex",
+    shader_output_layer => "shaderOutputLayer",
+    subgroup_broadcast_dynamic_id => "subgroupBroadcastDynamicId",
+});
+feature_fields!(vulkan13_field, set_vulkan13_field, vk::PhysicalDeviceVulkan13Features<'static> {
+    robust_image_access => "robustImageAccess",
+    inline_uniform_block => "inlineUniformBlock",
+    descriptor_binding_inline_uniform_block_update_after_bind => "descriptorBindingInlineUniformBlockUpdateAfterBind",
+    pipeline_creation_cache_control => "pipelineCreationCacheControl",
+    private_data => "privateData",
+    shader_demote_to_helper_invocation => "shaderDemoteToHelperInvocation",
+    shader_terminate_invocation => "shaderTerminateInvocation",
+    subgroup_size_control => "subgroupSizeControl",
+    compute_full_subgroups => "computeFullSubgroups",
+    synchronization2 => "synchronization2",
+    texture_compression_astc_hdr => "textureCompressionASTC_HDR",
+    shader_zero_initialize_workgroup_memory => "shaderZeroInitializeWorkgroupMemory",
+    dynamic_rendering => "dynamicRendering",
+    shader_integer_dot_product => "shaderIntegerDotProduct",
+    maintenance4 => "maintenance4",
+});
+feature_fields!(mesh_shader_field, set_mesh_shader_field, vk::PhysicalDeviceMeshShaderFeaturesEXT<'static> {
+    task_shader => "taskShader",
+    mesh_shader => "meshShader",
+    multiview_mesh_shader => "multiviewMeshShader",
+    primitive_fragment_shading_rate_mesh_shader => "primitiveFragmentShadingRateMeshShader",
+    mesh_shader_queries => "meshShaderQueries",
+});
+feature_fields!(ray_tracing_pipeline_field, set_ray_tracing_pipeline_field, vk::PhysicalDeviceRayTracingPipelineFeaturesKHR<'static> {
+    ray_tracing_pipeline => "rayTracingPipeline",
+    ray_tracing_pipeline_shader_group_handle_capture_replay => "rayTracingPipelineShaderGroupHandleCaptureReplay",
+    ray_tracing_pipeline_shader_group_handle_capture_replay_mixed => "rayTracingPipelineShaderGroupHandleCaptureReplayMixed",
+    ray_tracing_pipeline_trace_rays_indirect => "rayTracingPipelineTraceRaysIndirect",
+    ray_traversal_primitive_culling => "rayTraversalPrimitiveCulling",
+});
+feature_fields!(ray_query_field, set_ray_query_field, vk::PhysicalDeviceRayQueryFeaturesKHR<'static> {
+    ray_query => "rayQuery",
+});
+feature_fields!(shader_atomic_float_field, set_shader_atomic_float_field, vk::PhysicalDeviceShaderAtomicFloatFeaturesEXT<'static> {
+    shader_buffer_float32_atomics => "shaderBufferFloat32Atomics",
+    shader_buffer_float32_atomic_add => "shaderBufferFloat32AtomicAdd",
+    shader_buffer_float64_atomics => "shaderBufferFloat64Atomics",
+    shader_buffer_float64_atomic_add => "shaderBufferFloat64AtomicAdd",
+    shader_shared_float32_atomics => "shaderSharedFloat32Atomics",
+    shader_shared_float32_atomic_add => "shaderSharedFloat32AtomicAdd",
+    shader_shared_float64_atomics => "shaderSharedFloat64Atomics",
+    shader_shared_float64_atomic_add => "shaderSharedFloat64AtomicAdd",
+    shader_image_float32_atomics => "shaderImageFloat32Atomics",
+    shader_image_float32_atomic_add => "shaderImageFloat32AtomicAdd",
+    sparse_image_float32_atomics => "sparseImageFloat32Atomics",
+    sparse_image_float32_atomic_add => "sparseImageFloat32AtomicAdd",
+});
+feature_fields!(fragment_shader_interlock_field, set_fragment_shader_interlock_field, vk::PhysicalDeviceFragmentShaderInterlockFeaturesEXT<'static> {
+    fragment_shader_sample_interlock => "fragmentShaderSampleInterlock",
+    fragment_shader_pixel_interlock => "fragmentShaderPixelInterlock",
+    fragment_shader_shading_rate_interlock => "fragmentShaderShadingRateInterlock",
+});
+feature_fields!(fragment_shader_barycentric_field, set_fragment_shader_barycentric_field, vk::PhysicalDeviceFragmentShaderBarycentricFeaturesKHR<'static> {
+    fragment_shader_barycentric => "fragmentShaderBarycentric",
+});
+feature_fields!(fragment_shading_rate_field, set_fragment_shading_rate_field, vk::PhysicalDeviceFragmentShadingRateFeaturesKHR<'static> {
+    pipeline_fragment_shading_rate => "pipelineFragmentShadingRate",
+    primitive_fragment_shading_rate => "primitiveFragmentShadingRate",
+    attachment_fragment_shading_rate => "attachmentFragmentShadingRate",
+});
+feature_fields!(shader_image_atomic_int64_field, set_shader_image_atomic_int64_field, vk::PhysicalDeviceShaderImageAtomicInt64FeaturesEXT<'static> {
+    shader_image_int64_atomics => "shaderImageInt64Atomics",
+    sparse_image_int64_atomics => "sparseImageInt64Atomics",
+});
+feature_fields!(workgroup_memory_explicit_layout_field, set_workgroup_memory_explicit_layout_field, vk::PhysicalDeviceWorkgroupMemoryExplicitLayoutFeaturesKHR<'static> {
+    workgroup_memory_explicit_layout => "workgroupMemoryExplicitLayout",
+    workgroup_memory_explicit_layout_scalar_block_layout => "workgroupMemoryExplicitLayoutScalarBlockLayout",
+    workgroup_memory_explicit_layout8_bit_access => "workgroupMemoryExplicitLayout8BitAccess",
+    workgroup_memory_explicit_layout16_bit_access => "workgroupMemoryExplicitLayout16BitAccess",
+});
+feature_fields!(transform_feedback_field, set_transform_feedback_field, vk::PhysicalDeviceTransformFeedbackFeaturesEXT<'static> {
+    transform_feedback => "transformFeedback",
+    geometry_streams => "geometryStreams",
+});
+feature_fields!(fragment_density_map_field, set_fragment_density_map_field, vk::PhysicalDeviceFragmentDensityMapFeaturesEXT<'static> {
+    fragment_density_map => "fragmentDensityMap",
+    fragment_density_map_dynamic => "fragmentDensityMapDynamic",
+    fragment_density_map_non_subsampled_images => "fragmentDensityMapNonSubsampledImages",
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use spirv_reflect::spirv::{Capability, ExecutionModel};
+
+    #[test]
+    fn shader_requirements() {
+        let mut features = DeviceFeatures {
+            api_version: vk::API_VERSION_1_3,
+            ..Default::default()
+        };
+        features.vulkan12.shader_float16 = vk::TRUE;
+        features.subgroup_operations = vk::SubgroupFeatureFlags::BASIC;
+
+        let supported = ShaderRequirements::from_declarations(
+            &[
+                Capability::Shader,
+                Capability::Float16,
+                Capability::GroupNonUniform,
+            ],
+            &[],
+            &[ExecutionModel::Fragment],
+        );
+        let support = features.check_shader_requirements(&supported);
+        assert!(support.is_supported());
+        assert!(support.unchecked.is_empty());
+
+        let unsupported = ShaderRequirements::from_declarations(
+            &[Capability::Int8, Capability::MeshShadingEXT],
+            &[],
+            &[ExecutionModel::TaskEXT],
+        );
+        let support = features.check_shader_requirements(&unsupported);
+        let missing = support
+            .missing
+            .iter()
+            .map(|x| x.source.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            vec![
+                RequirementSource::Capability(Capability::Int8),
+                RequirementSource::Capability(Capability::MeshShadingEXT),
+                RequirementSource::ExecutionModel(ExecutionModel::TaskEXT),
+            ]
+        );
+        assert!(support.ensure().is_err());
+    }
+
+    #[test]
+    fn promoted_extension_features() {
+        let mut features = DeviceFeatures {
+            api_version: vk::API_VERSION_1_2,
+            ..Default::default()
+        };
+        features.vulkan12.storage_buffer8_bit_access = vk::TRUE;
+        let feature = VulkanFeature::Extension {
+            extension: "VK_KHR_8bit_storage",
+            name: "storageBuffer8BitAccess",
+        };
+        assert_eq!(features.get(&feature), Some(true));
+
+        features.api_version = vk::API_VERSION_1_1;
+        assert_eq!(features.get(&feature), Some(false));
+    }
+
+    #[test]
+    fn enable_extension_features() {
+        let mesh_shader = ExtensionProperties {
+            name: crate::types::ExtensionName::from_cstr(ash::ext::mesh_shader::NAME),
+            spec_version: 1,
+        };
+        let mut supported = DeviceFeatures {
+            api_version: vk::API_VERSION_1_3,
+            extensions: vec![mesh_shader.clone()],
+            ..Default::default()
+        };
+        let task_shader = VulkanFeature::Extension {
+            extension: "VK_EXT_mesh_shader",
+            name: "taskShader",
+        };
+        let mesh = VulkanFeature::Extension {
+            extension: "VK_EXT_mesh_shader",
+            name: "meshShader",
+        };
+        assert_eq!(supported.set(&mesh, true), Some(()));
+        assert_eq!(
+            supported.set(&VulkanFeature::Core("notAFeature"), true),
+            None
+        );
+        assert_eq!(supported.get(&mesh), Some(true));
+        assert_eq!(supported.get(&task_shader), Some(false));
+
+        // Only features that are both requested and supported get enabled.
+        let enabled = supported.enabled_subset(&[mesh_shader], &[mesh, task_shader]);
+        assert_eq!(enabled.get(&mesh), Some(true));
+        assert_eq!(enabled.get(&task_shader), Some(false));
+        let enabled = supported.enabled_subset(&[], &[mesh]);
+        assert_eq!(enabled.get(&mesh), Some(false));
+    }
+
+    #[test]
+    fn enable_only_requested_core_features() {
+        let robust = VulkanFeature::Core("robustBufferAccess");
+        let int64 = VulkanFeature::Core("shaderInt64");
+        let mut supported = DeviceFeatures {
+            api_version: vk::API_VERSION_1_3,
+            ..Default::default()
+        };
+        for feature in [robust, int64].iter().chain(&HORIZON_FEATURES) {
+            supported.set(feature, true);
+        }
+
+        let enabled = supported.enabled_subset(&[], &[int64]);
+        assert_eq!(enabled.get(&int64), Some(true));
+        assert_eq!(enabled.get(&robust), Some(false));
+        for feature in &HORIZON_FEATURES {
+            assert_eq!(enabled.get(feature), Some(true));
+        }
+    }
+
+    #[test]
+    fn shader_needs_first_supported_alternative() {
+        let features = DeviceFeatures {
+            api_version: vk::API_VERSION_1_3,
+            core: vk::PhysicalDeviceFeatures {
+                shader_int64: vk::TRUE,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let requirements = ShaderRequirements::from_declarations(
+            &[Capability::Int64, Capability::Int16],
+            &[],
+            &[],
+        );
+        let mut needed = vec![];
+        let mut extensions = vec![];
+        features.shader_needs(&requirements, &mut needed, &mut extensions);
+        assert_eq!(needed, [VulkanFeature::Core("shaderInt64")]);
+        assert!(extensions.is_empty());
+    }
+}
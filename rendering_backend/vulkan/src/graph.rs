@@ -0,0 +1,1733 @@
+//! A render graph for one frame.
+//!
+//! Passes declare how they access images and buffers. [`RenderGraph::compile`] culls the passes
+//! nothing depends on, derives the barriers and layout transitions between the others, and
+//! places transient images whose lifetimes do not overlap in the same memory. Passes run on the
+//! graphics, compute or transfer queue; dependencies between queues wait on semaphores and
+//! transfer the ownership of the resources.
+//!
+//! Transient images are named after their resource and every pass is recorded in a debug label
+//! with its name, see [`CommandBuffer::label`].
+//!
+//! ```ignore
+//! let mut graph = RenderGraph::new();
+//! let target = graph.import_image("swapchain", &image);
+//! let depth = graph.create_image("depth", ImageDescription::new(dimensions, vk::Format::D32_SFLOAT));
+//! graph
+//!     .add_pass("scene", QueueType::Graphics)
+//!     .image(target, Access::COLOR_ATTACHMENT)
+//!     .image(depth, Access::DEPTH_ATTACHMENT)
+//!     .record(|command_buffer, resources| {
+//!         // Draw to `resources.view(target)`...
+//!     });
+//! graph.export_image(target, vk::ImageLayout::PRESENT_SRC_KHR);
+//! let compiled = graph.compile(&allocator, &queues)?;
+//! std::fs::write("frame.dot", compiled.graphviz())?;
+//! compiled.execute(&queues, &pools, |queue, submit, _| frame.submit(queue, submit))?;
+//! ```
+use super::buffer::Buffer;
+use super::command::{CommandBuffer, CommandPools, QueueSubmit};
+use super::device::{Device, Queue};
+use super::error::{GraphError, VkError};
+use super::handle::{own, Owned};
+use super::image::{layout_scope, Image, ImageDescription, ImageViewDescription};
+use super::memory::{Allocation, Allocator, MemoryUsage, ResourceKind};
+use super::sync::Semaphore;
+use super::types::Pod;
+use ash::vk;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// An image of a [`RenderGraph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+/// A buffer of a [`RenderGraph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferId(usize);
+
+/// The queue a pass runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueueType {
+    Graphics,
+    Compute,
+    Transfer,
+}
+
+/// The queues passes run on. Compute passes fall back to the graphics queue, transfer passes to
+/// the compute queue and then the graphics queue.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphQueues<'a> {
+    pub graphics: &'a Queue,
+    pub compute: Option<&'a Queue>,
+    pub transfer: Option<&'a Queue>,
+}
+
+impl<'a> GraphQueues<'a> {
+    pub fn new(graphics: &'a Queue) -> Self {
+        Self {
+            graphics,
+            compute: None,
+            transfer: None,
+        }
+    }
+    #[must_use]
+    pub fn compute(mut self, queue: &'a Queue) -> Self {
+        self.compute = Some(queue);
+        self
+    }
+    #[must_use]
+    pub fn transfer(mut self, queue: &'a Queue) -> Self {
+        self.transfer = Some(queue);
+        self
+    }
+
+    pub fn get(&self, ty: QueueType) -> &'a Queue {
+        match ty {
+            QueueType::Graphics => self.graphics,
+            QueueType::Compute => self.compute.unwrap_or(self.graphics),
+            QueueType::Transfer => self.transfer.or(self.compute).unwrap_or(self.graphics),
+        }
+    }
+
+    /// Distinct queues, and the slot of every queue type in them.
+    fn slots(&self) -> (Vec<&'a Queue>, [QueueSlot; 3]) {
+        let mut queues: Vec<&'a Queue> = vec![];
+        let mut slot = |ty| {
+            let queue = self.get(ty);
+            let id = queues
+                .iter()
+                .position(|x| {
+                    x.family_index() == queue.family_index() && x.index() == queue.index()
+                })
+                .unwrap_or_else(|| {
+                    queues.push(queue);
+                    queues.len() - 1
+                });
+            QueueSlot {
+                id,
+                family: queue.family_index(),
+            }
+        };
+        let slots = [
+            slot(QueueType::Graphics),
+            slot(QueueType::Compute),
+            slot(QueueType::Transfer),
+        ];
+        (queues, slots)
+    }
+}
+
+/// A queue the graph submits to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QueueSlot {
+    id: usize,
+    family: u32,
+}
+
+const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::SHADER_WRITE.as_raw()
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags::HOST_WRITE.as_raw()
+        | vk::AccessFlags::MEMORY_WRITE.as_raw(),
+);
+
+/// How a pass uses a resource. The layout is ignored for buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    pub layout: vk::ImageLayout,
+}
+
+impl Access {
+    pub const COLOR_ATTACHMENT: Self = Self::new(
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags::from_raw(
+            vk::AccessFlags::COLOR_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw(),
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    );
+    pub const DEPTH_ATTACHMENT: Self = Self::new(
+        vk::PipelineStageFlags::from_raw(
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS.as_raw()
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        vk::AccessFlags::from_raw(
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    );
+    /// Depth testing without writing.
+    pub const DEPTH_READ: Self = Self::new(
+        vk::PipelineStageFlags::from_raw(
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS.as_raw()
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+    );
+    pub const TRANSFER_READ: Self = Self::new(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_READ,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    );
+    pub const TRANSFER_WRITE: Self = Self::new(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+    pub const VERTEX_BUFFER: Self = Self::new(
+        vk::PipelineStageFlags::VERTEX_INPUT,
+        vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        vk::ImageLayout::UNDEFINED,
+    );
+    pub const INDEX_BUFFER: Self = Self::new(
+        vk::PipelineStageFlags::VERTEX_INPUT,
+        vk::AccessFlags::INDEX_READ,
+        vk::ImageLayout::UNDEFINED,
+    );
+    pub const INDIRECT_BUFFER: Self = Self::new(
+        vk::PipelineStageFlags::DRAW_INDIRECT,
+        vk::AccessFlags::INDIRECT_COMMAND_READ,
+        vk::ImageLayout::UNDEFINED,
+    );
+
+    pub const fn new(
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+        layout: vk::ImageLayout,
+    ) -> Self {
+        Self {
+            stage,
+            access,
+            layout,
+        }
+    }
+    /// A sampled image read by shaders in `stage`.
+    pub const fn sampled(stage: vk::PipelineStageFlags) -> Self {
+        Self::new(
+            stage,
+            vk::AccessFlags::SHADER_READ,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+    pub const fn uniform(stage: vk::PipelineStageFlags) -> Self {
+        Self::new(
+            stage,
+            vk::AccessFlags::UNIFORM_READ,
+            vk::ImageLayout::UNDEFINED,
+        )
+    }
+    /// A storage image or buffer read by shaders in `stage`.
+    pub const fn storage_read(stage: vk::PipelineStageFlags) -> Self {
+        Self::new(
+            stage,
+            vk::AccessFlags::SHADER_READ,
+            vk::ImageLayout::GENERAL,
+        )
+    }
+    pub const fn storage_write(stage: vk::PipelineStageFlags) -> Self {
+        Self::new(
+            stage,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::ImageLayout::GENERAL,
+        )
+    }
+    pub const fn storage_read_write(stage: vk::PipelineStageFlags) -> Self {
+        Self::new(
+            stage,
+            vk::AccessFlags::from_raw(
+                vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw(),
+            ),
+            vk::ImageLayout::GENERAL,
+        )
+    }
+
+    pub fn is_write(self) -> bool {
+        self.access.intersects(WRITE_ACCESS)
+    }
+
+    /// The usage an image needs for this access.
+    fn image_usage(self) -> vk::ImageUsageFlags {
+        use vk::AccessFlags as A;
+        use vk::ImageUsageFlags as U;
+        let mut usage = U::empty();
+        if self
+            .access
+            .intersects(A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE)
+        {
+            usage |= U::COLOR_ATTACHMENT;
+        }
+        if self
+            .access
+            .intersects(A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        {
+            usage |= U::DEPTH_STENCIL_ATTACHMENT;
+        }
+        if self.access.contains(A::INPUT_ATTACHMENT_READ) {
+            usage |= U::INPUT_ATTACHMENT;
+        }
+        if self.access.intersects(A::SHADER_READ | A::SHADER_WRITE) {
+            usage |= if self.layout == vk::ImageLayout::GENERAL {
+                U::STORAGE
+            } else {
+                U::SAMPLED
+            };
+        }
+        if self.access.contains(A::TRANSFER_READ) {
+            usage |= U::TRANSFER_SRC;
+        }
+        if self.access.contains(A::TRANSFER_WRITE) {
+            usage |= U::TRANSFER_DST;
+        }
+        usage
+    }
+
+    /// Both accesses in one, for resources a pass uses more than once.
+    fn merge(self, other: Self) -> Self {
+        assert_eq!(
+            self.layout, other.layout,
+            "A pass can only use an image in one layout"
+        );
+        Self::new(
+            self.stage | other.stage,
+            self.access | other.access,
+            self.layout,
+        )
+    }
+}
+
+enum ImageSource {
+    Imported(Arc<Image>),
+    Transient(ImageDescription),
+}
+
+struct ImageResource {
+    name: String,
+    source: ImageSource,
+    final_layout: Option<vk::ImageLayout>,
+}
+
+struct BufferResource {
+    name: String,
+    raw: vk::Buffer,
+    resource: Arc<dyn Any + Send + Sync>,
+}
+
+type Record = Box<dyn FnOnce(&mut CommandBuffer, &PassResources)>;
+
+struct Pass {
+    name: String,
+    queue: QueueType,
+    images: Vec<(ImageId, Access)>,
+    buffers: Vec<(BufferId, Access)>,
+    side_effects: bool,
+    record: Option<Record>,
+}
+
+/// The passes and resources of a frame, see the [module](self) documentation.
+#[derive(Default)]
+pub struct RenderGraph {
+    images: Vec<ImageResource>,
+    buffers: Vec<BufferResource>,
+    passes: Vec<Pass>,
+}
+
+impl std::fmt::Debug for RenderGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderGraph")
+            .field("images", &self.images.len())
+            .field("buffers", &self.buffers.len())
+            .field("passes", &self.passes.len())
+            .finish()
+    }
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An image that outlives the graph, in the layout its first subresource is tracked in.
+    /// Passes writing it are never culled.
+    pub fn import_image(&mut self, name: impl Into<String>, image: &Arc<Image>) -> ImageId {
+        self.images.push(ImageResource {
+            name: name.into(),
+            source: ImageSource::Imported(image.clone()),
+            final_layout: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+    /// An image that only lives during the graph, created with the usage its passes need. Its
+    /// memory may be shared with other transient images.
+    pub fn create_image(
+        &mut self,
+        name: impl Into<String>,
+        description: ImageDescription,
+    ) -> ImageId {
+        self.images.push(ImageResource {
+            name: name.into(),
+            source: ImageSource::Transient(description),
+            final_layout: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+    /// Moves an imported image to `layout` after the graph, e.g. `PRESENT_SRC_KHR`.
+    /// # Panics
+    /// If the image is transient.
+    pub fn export_image(&mut self, image: ImageId, layout: vk::ImageLayout) {
+        let image = &mut self.images[image.0];
+        assert!(
+            matches!(image.source, ImageSource::Imported(_)),
+            "Only imported images can be exported"
+        );
+        image.final_layout = Some(layout);
+    }
+    /// A buffer that outlives the graph. Passes writing it are never culled.
+    pub fn import_buffer<T: Pod>(
+        &mut self,
+        name: impl Into<String>,
+        buffer: &Arc<Buffer<T>>,
+    ) -> BufferId {
+        self.buffers.push(BufferResource {
+            name: name.into(),
+            raw: buffer.raw(),
+            resource: buffer.clone(),
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// A pass, added once it is given what to record.
+    pub fn add_pass(&mut self, name: impl Into<String>, queue: QueueType) -> PassBuilder<'_> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name: name.into(),
+                queue,
+                images: vec![],
+                buffers: vec![],
+                side_effects: false,
+                record: None,
+            },
+        }
+    }
+
+    /// Images first, then buffers.
+    fn resource_infos(&self) -> Vec<ResourceInfo> {
+        let images = self.images.iter().map(|x| match &x.source {
+            ImageSource::Imported(image) => ResourceInfo {
+                image: true,
+                imported: true,
+                initial_layout: image.layout(0, 0),
+                final_layout: x.final_layout,
+            },
+            ImageSource::Transient(_) => ResourceInfo {
+                image: true,
+                imported: false,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: None,
+            },
+        });
+        let buffers = self.buffers.iter().map(|_| ResourceInfo {
+            image: false,
+            imported: true,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: None,
+        });
+        images.chain(buffers).collect()
+    }
+
+    fn pass_infos(&self, slots: &[QueueSlot; 3]) -> Vec<PassInfo> {
+        let images = self.images.len();
+        self.passes
+            .iter()
+            .map(|x| PassInfo {
+                queue: slots[x.queue as usize],
+                accesses: x
+                    .images
+                    .iter()
+                    .map(|(id, access)| (id.0, *access))
+                    .chain(
+                        x.buffers
+                            .iter()
+                            .map(|(id, access)| (images + id.0, *access)),
+                    )
+                    .collect(),
+                side_effects: x.side_effects,
+            })
+            .collect()
+    }
+
+    /// Culls the passes, creates the transient images and plans the synchronization of the rest.
+    /// # Errors
+    /// If a transient image cannot be created or no memory fits it.
+    pub fn compile(
+        mut self,
+        allocator: &Arc<Allocator>,
+        queues: &GraphQueues<'_>,
+    ) -> Result<CompiledGraph, GraphError> {
+        let device = allocator.device().clone();
+        let (queue_list, slots) = queues.slots();
+        let resources = self.resource_infos();
+        let passes = self.pass_infos(&slots);
+        let mut plan = Plan::new(&resources, &passes);
+
+        // Transient images with the usage of every pass using them.
+        let mut transients = Transients {
+            views: vec![],
+            images: vec![],
+            allocations: vec![],
+        };
+        let mut images = vec![GraphImage::default(); self.images.len()];
+        let mut requirements = vec![None; resources.len()];
+        for (index, image) in self.images.iter().enumerate() {
+            match &image.source {
+                ImageSource::Imported(x) => {
+                    images[index] = GraphImage {
+                        raw: x.raw(),
+                        view: x.view(),
+                        range: x.full_range(),
+                        description: Some(x.description().clone()),
+                    };
+                }
+                ImageSource::Transient(description) => {
+                    let usage = passes
+                        .iter()
+                        .enumerate()
+                        .filter(|(pass, _)| plan.alive[*pass])
+                        .flat_map(|(_, x)| &x.accesses)
+                        .filter(|(resource, _)| *resource == index)
+                        .fold(vk::ImageUsageFlags::empty(), |usage, (_, access)| {
+                            usage | access.image_usage()
+                        });
+                    if usage.is_empty() {
+                        continue;
+                    }
+                    let description = description.clone().with_usage(usage);
+                    description.validate()?;
+                    let raw = unsafe {
+                        device
+                            .raw()
+                            .create_image(&description.create_info(), device.allocation_callbacks())
+                    }?;
+                    transients.images.push(own(&device, raw));
+                    device.set_debug_name(raw, &image.name);
+                    requirements[index] =
+                        Some(unsafe { device.raw().get_image_memory_requirements(raw) });
+                    images[index] = GraphImage {
+                        raw,
+                        view: vk::ImageView::null(),
+                        range: description.full_range(),
+                        description: Some(description),
+                    };
+                }
+            }
+        }
+
+        let memory = assign_memory(&plan.lifetimes(&resources, &passes), &requirements);
+        for slot in &memory.slots {
+            transients.allocations.push(allocator.allocate(
+                *slot,
+                MemoryUsage::GpuOnly,
+                ResourceKind::OptimalImage,
+            )?);
+        }
+        for (index, image) in images.iter_mut().enumerate() {
+            let Some(slot) = memory.slot[index] else {
+                continue;
+            };
+            let allocation = &transients.allocations[slot];
+            unsafe {
+                device
+                    .raw()
+                    .bind_image_memory(image.raw, allocation.memory(), allocation.offset())
+            }?;
+            let description = image.description.as_ref().unwrap();
+            let view_info =
+                ImageViewDescription::new(description.dimensions.view_type(), description.format)
+                    .create_info(image.raw);
+            image.view = unsafe {
+                device
+                    .raw()
+                    .create_image_view(&view_info, device.allocation_callbacks())
+            }?;
+            transients.views.push(own(&device, image.view));
+            device.set_debug_name(image.view, &self.images[index].name);
+        }
+        plan.synchronize(&resources, &passes, &memory.aliases);
+
+        let mut retained = Retained {
+            transients,
+            images: vec![],
+            buffers: vec![],
+        };
+        let mut imported = vec![];
+        for (index, image) in self.images.iter().enumerate() {
+            if let ImageSource::Imported(x) = &image.source {
+                retained.images.push(x.clone());
+                imported.push((x.clone(), plan.end_layouts[index]));
+            }
+        }
+        retained.buffers = self.buffers.iter().map(|x| x.resource.clone()).collect();
+
+        let info = GraphInfo {
+            passes: self
+                .passes
+                .iter()
+                .map(|x| (x.name.clone(), x.queue))
+                .collect(),
+            resources: self
+                .images
+                .iter()
+                .map(|x| x.name.clone())
+                .chain(self.buffers.iter().map(|x| x.name.clone()))
+                .collect(),
+            slots: memory.slot,
+        };
+        Ok(CompiledGraph {
+            device,
+            queues: queue_list
+                .iter()
+                .map(|x| (x.family_index(), x.index()))
+                .collect(),
+            records: self.passes.iter_mut().map(|x| x.record.take()).collect(),
+            resources: PassResources {
+                images,
+                buffers: self.buffers.iter().map(|x| x.raw).collect(),
+            },
+            retained: Arc::new(retained),
+            imported,
+            plan,
+            passes,
+            info,
+        })
+    }
+}
+
+/// Declares the accesses of a pass, see [`RenderGraph::add_pass`].
+#[must_use = "The pass is only added by `record`"]
+pub struct PassBuilder<'a> {
+    graph: &'a mut RenderGraph,
+    pass: Pass,
+}
+
+impl std::fmt::Debug for PassBuilder<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PassBuilder")
+            .field("name", &self.pass.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PassBuilder<'_> {
+    /// # Panics
+    /// If the pass already uses `image` in another layout.
+    pub fn image(mut self, image: ImageId, access: Access) -> Self {
+        match self.pass.images.iter_mut().find(|x| x.0 == image) {
+            Some(x) => x.1 = x.1.merge(access),
+            None => self.pass.images.push((image, access)),
+        }
+        self
+    }
+    pub fn buffer(mut self, buffer: BufferId, access: Access) -> Self {
+        match self.pass.buffers.iter_mut().find(|x| x.0 == buffer) {
+            Some(x) => x.1 = x.1.merge(access),
+            None => self.pass.buffers.push((buffer, access)),
+        }
+        self
+    }
+    /// Keeps the pass even if nothing uses what it writes, e.g. for readbacks on the host.
+    pub fn side_effects(mut self) -> Self {
+        self.pass.side_effects = true;
+        self
+    }
+    /// Adds the pass. `record` runs during [`CompiledGraph::execute`], after the barriers of the
+    /// pass.
+    pub fn record(mut self, record: impl FnOnce(&mut CommandBuffer, &PassResources) + 'static) {
+        self.pass.record = Some(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GraphImage {
+    raw: vk::Image,
+    view: vk::ImageView,
+    range: vk::ImageSubresourceRange,
+    description: Option<ImageDescription>,
+}
+
+/// The handles of the resources of a graph, given to the passes as they are recorded.
+#[derive(Debug)]
+pub struct PassResources {
+    images: Vec<GraphImage>,
+    buffers: Vec<vk::Buffer>,
+}
+
+impl PassResources {
+    pub fn image(&self, image: ImageId) -> vk::Image {
+        self.images[image.0].raw
+    }
+    /// A view of the whole image.
+    pub fn view(&self, image: ImageId) -> vk::ImageView {
+        self.images[image.0].view
+    }
+    /// The description, with the usage of a transient image.
+    pub fn description(&self, image: ImageId) -> Option<&ImageDescription> {
+        self.images[image.0].description.as_ref()
+    }
+    pub fn buffer(&self, buffer: BufferId) -> vk::Buffer {
+        self.buffers[buffer.0]
+    }
+}
+
+/// Transient images and the memory they share, destroyed once no batch uses them.
+/// Dropped in declaration order: the views, the images, then their memory.
+struct Transients {
+    views: Vec<Owned<vk::ImageView>>,
+    images: Vec<Owned<vk::Image>>,
+    allocations: Vec<Allocation>,
+}
+
+/// Kept alive by the command buffer of every batch.
+struct Retained {
+    transients: Transients,
+    images: Vec<Arc<Image>>,
+    buffers: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+/// Names for the Graphviz dump.
+struct GraphInfo {
+    passes: Vec<(String, QueueType)>,
+    resources: Vec<String>,
+    slots: Vec<Option<usize>>,
+}
+
+/// Which submission of a graph [`CompiledGraph::execute`] hands out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchInfo {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// A graph ready to record, see [`RenderGraph::compile`].
+pub struct CompiledGraph {
+    device: Arc<Device>,
+    /// Family and index of the queue of every slot.
+    queues: Vec<(u32, u32)>,
+    records: Vec<Option<Record>>,
+    resources: PassResources,
+    retained: Arc<Retained>,
+    /// With the layout they are left in.
+    imported: Vec<(Arc<Image>, vk::ImageLayout)>,
+    plan: Plan,
+    passes: Vec<PassInfo>,
+    info: GraphInfo,
+}
+
+impl std::fmt::Debug for CompiledGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompiledGraph")
+            .field("batches", &self.plan.batches.len())
+            .field("culled", &self.plan.alive.iter().filter(|x| !**x).count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CompiledGraph {
+    /// Passes that were culled.
+    pub fn culled(&self) -> Vec<&str> {
+        self.info
+            .passes
+            .iter()
+            .zip(&self.plan.alive)
+            .filter(|(_, alive)| !**alive)
+            .map(|((name, _), _)| name.as_str())
+            .collect()
+    }
+    /// Number of submissions [`Self::execute`] makes.
+    pub fn batches(&self) -> usize {
+        self.plan.batches.len()
+    }
+
+    /// The compiled graph in the Graphviz dot language.
+    pub fn graphviz(&self) -> String {
+        graphviz(&self.info, &self.passes, &self.plan)
+    }
+
+    /// Records every batch and hands it to `submit` with the queue to submit it to, in an order
+    /// the semaphores between them allow. `queues` have to be the ones the graph was compiled
+    /// with.
+    /// # Errors
+    /// If recording or `submit` fails.
+    /// # Panics
+    /// If `queues` differ from the compiled ones.
+    pub fn execute(
+        mut self,
+        queues: &GraphQueues<'_>,
+        pools: &CommandPools,
+        mut submit: impl FnMut(&Queue, QueueSubmit, BatchInfo) -> Result<(), VkError>,
+    ) -> Result<(), VkError> {
+        let (queue_list, _) = queues.slots();
+        assert_eq!(
+            queue_list
+                .iter()
+                .map(|x| (x.family_index(), x.index()))
+                .collect::<Vec<_>>(),
+            self.queues,
+            "A graph has to be executed on the queues it was compiled for"
+        );
+        let mut semaphores = BTreeMap::new();
+        for (batch, x) in self.plan.batches.iter().enumerate() {
+            for (source, _) in &x.waits {
+                let name = format!("graph batch {source} to {batch}");
+                let semaphore = Semaphore::new(&self.device, Some(&name))?;
+                semaphores.insert((*source, batch), Arc::new(semaphore));
+            }
+        }
+        let count = self.plan.batches.len();
+        for (index, batch) in self.plan.batches.iter().enumerate() {
+            let queue = queue_list[batch.queue.id];
+            let mut command_buffer = pools.current(queue.family_index())?.primary()?;
+            command_buffer.keep_alive(self.retained.clone());
+            for pass in &batch.passes {
+                let mut command_buffer = command_buffer.label(&self.info.passes[*pass].0);
+                self.record_barriers(&command_buffer, &self.plan.barriers[*pass]);
+                if let Some(record) = self.records[*pass].take() {
+                    record(&mut command_buffer, &self.resources);
+                }
+            }
+            self.record_barriers(&command_buffer, &batch.after);
+
+            let mut queue_submit = QueueSubmit::new(&self.device).command_buffer(command_buffer);
+            for (source, stage) in &batch.waits {
+                queue_submit = queue_submit.wait(&semaphores[&(*source, index)], *stage);
+            }
+            for ((_, target), semaphore) in semaphores.range((index, 0)..(index + 1, 0)) {
+                debug_assert!(*target > index);
+                queue_submit = queue_submit.signal(semaphore);
+            }
+            submit(queue, queue_submit, BatchInfo { index, count })?;
+        }
+        for (image, layout) in &self.imported {
+            image.assume_layout(image.full_range(), *layout);
+        }
+        Ok(())
+    }
+
+    fn record_barriers(&self, command_buffer: &CommandBuffer, barriers: &[Barrier]) {
+        if barriers.is_empty() {
+            return;
+        }
+        let images = self.resources.images.len();
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let mut dst_stage = vk::PipelineStageFlags::empty();
+        let mut image_barriers = vec![];
+        let mut buffer_barriers = vec![];
+        for barrier in barriers {
+            src_stage |= barrier.src_stage;
+            dst_stage |= barrier.dst_stage;
+            if barrier.resource < images {
+                let image = &self.resources.images[barrier.resource];
+                image_barriers.push(
+                    vk::ImageMemoryBarrier::default()
+                        .src_access_mask(barrier.src_access)
+                        .dst_access_mask(barrier.dst_access)
+                        .old_layout(barrier.old_layout)
+                        .new_layout(barrier.new_layout)
+                        .src_queue_family_index(barrier.src_family)
+                        .dst_queue_family_index(barrier.dst_family)
+                        .image(image.raw)
+                        .subresource_range(image.range),
+                );
+            } else {
+                buffer_barriers.push(
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(barrier.src_access)
+                        .dst_access_mask(barrier.dst_access)
+                        .src_queue_family_index(barrier.src_family)
+                        .dst_queue_family_index(barrier.dst_family)
+                        .buffer(self.resources.buffers[barrier.resource - images])
+                        .offset(0)
+                        .size(vk::WHOLE_SIZE),
+                );
+            }
+        }
+        unsafe {
+            self.device.raw().cmd_pipeline_barrier(
+                command_buffer.raw(),
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            )
+        };
+    }
+}
+
+/// What planning needs to know about a resource.
+#[derive(Debug, Clone, Copy)]
+struct ResourceInfo {
+    image: bool,
+    imported: bool,
+    initial_layout: vk::ImageLayout,
+    final_layout: Option<vk::ImageLayout>,
+}
+
+/// What planning needs to know about a pass. Resources are indexed like [`ResourceInfo`]s.
+#[derive(Debug, Clone)]
+struct PassInfo {
+    queue: QueueSlot,
+    accesses: Vec<(usize, Access)>,
+    side_effects: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Barrier {
+    resource: usize,
+    src_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_family: u32,
+    dst_family: u32,
+}
+
+/// Consecutive passes on the same queue, recorded into one command buffer.
+#[derive(Debug, Clone)]
+struct Batch {
+    queue: QueueSlot,
+    passes: Vec<usize>,
+    /// Earlier batches on other queues to wait for, at the stages that depend on them.
+    waits: Vec<(usize, vk::PipelineStageFlags)>,
+    /// Ownership releases and final layout transitions.
+    after: Vec<Barrier>,
+}
+
+/// The passes using a transient image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lifetime {
+    first: usize,
+    last: usize,
+    /// If every pass runs on the same queue.
+    queue: Option<usize>,
+}
+
+/// An access done before the graph, synchronized with barriers on the first queue using it.
+const EXTERNAL: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Use {
+    batch: usize,
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    layout: vk::ImageLayout,
+    family: Option<u32>,
+    write: Option<Use>,
+    /// Since the last write.
+    reads: Vec<Use>,
+    /// Stages and accesses the last write is visible to, on the queue it was made visible on.
+    visible: (usize, vk::PipelineStageFlags, vk::AccessFlags),
+    last: Option<Use>,
+}
+
+#[derive(Debug, Clone)]
+struct Plan {
+    alive: Vec<bool>,
+    pass_batch: Vec<Option<usize>>,
+    batches: Vec<Batch>,
+    /// Before every pass.
+    barriers: Vec<Vec<Barrier>>,
+    /// The layout every image is left in.
+    end_layouts: Vec<vk::ImageLayout>,
+}
+
+impl Plan {
+    /// Culls the passes and groups the others into batches.
+    fn new(resources: &[ResourceInfo], passes: &[PassInfo]) -> Self {
+        // Every access depends on the last write before it.
+        let mut last_writer = vec![None; resources.len()];
+        let mut dependencies = vec![vec![]; passes.len()];
+        for (pass, info) in passes.iter().enumerate() {
+            for (resource, access) in &info.accesses {
+                dependencies[pass].extend(last_writer[*resource]);
+                if access.is_write() {
+                    last_writer[*resource] = Some(pass);
+                }
+            }
+        }
+        let mut alive = passes
+            .iter()
+            .map(|x| {
+                x.side_effects
+                    || x.accesses.iter().any(|(resource, access)| {
+                        access.is_write() && resources[*resource].imported
+                    })
+            })
+            .collect::<Vec<_>>();
+        // Dependencies are earlier passes, so one pass from the back finds all of them.
+        for pass in (0..passes.len()).rev() {
+            if alive[pass] {
+                for dependency in &dependencies[pass] {
+                    alive[*dependency] = true;
+                }
+            }
+        }
+
+        let mut batches: Vec<Batch> = vec![];
+        let mut pass_batch = vec![None; passes.len()];
+        for (pass, info) in passes.iter().enumerate() {
+            if !alive[pass] {
+                continue;
+            }
+            match batches.last_mut() {
+                Some(batch) if batch.queue == info.queue => batch.passes.push(pass),
+                _ => batches.push(Batch {
+                    queue: info.queue,
+                    passes: vec![pass],
+                    waits: vec![],
+                    after: vec![],
+                }),
+            }
+            pass_batch[pass] = Some(batches.len() - 1);
+        }
+        Self {
+            alive,
+            pass_batch,
+            batches,
+            barriers: vec![vec![]; passes.len()],
+            end_layouts: resources.iter().map(|x| x.initial_layout).collect(),
+        }
+    }
+
+    /// The passes using every transient resource, `None` for imported or unused ones.
+    fn lifetimes(&self, resources: &[ResourceInfo], passes: &[PassInfo]) -> Vec<Option<Lifetime>> {
+        let mut lifetimes: Vec<Option<Lifetime>> = vec![None; resources.len()];
+        for (pass, info) in passes.iter().enumerate() {
+            if !self.alive[pass] {
+                continue;
+            }
+            for (resource, _) in &info.accesses {
+                if resources[*resource].imported {
+                    continue;
+                }
+                let lifetime = lifetimes[*resource].get_or_insert(Lifetime {
+                    first: pass,
+                    last: pass,
+                    queue: Some(info.queue.id),
+                });
+                lifetime.last = pass;
+                if lifetime.queue != Some(info.queue.id) {
+                    lifetime.queue = None;
+                }
+            }
+        }
+        lifetimes
+    }
+
+    /// Plans the barriers, semaphores and ownership transfers between the alive passes.
+    /// `aliases` is the resource that used the memory of a transient one before it.
+    fn synchronize(
+        &mut self,
+        resources: &[ResourceInfo],
+        passes: &[PassInfo],
+        aliases: &[Option<usize>],
+    ) {
+        let mut states = resources
+            .iter()
+            .map(|x| {
+                let external = if x.image {
+                    layout_scope(x.initial_layout)
+                } else {
+                    (
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                        vk::AccessFlags::MEMORY_WRITE,
+                    )
+                };
+                State {
+                    layout: x.initial_layout,
+                    family: None,
+                    write: x.imported.then_some(Use {
+                        batch: EXTERNAL,
+                        stage: external.0,
+                        access: external.1,
+                    }),
+                    reads: vec![],
+                    visible: (
+                        EXTERNAL,
+                        vk::PipelineStageFlags::empty(),
+                        vk::AccessFlags::empty(),
+                    ),
+                    last: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut started = vec![false; resources.len()];
+
+        for (pass, info) in passes.iter().enumerate() {
+            let Some(batch) = self.pass_batch[pass] else {
+                continue;
+            };
+            let queue = info.queue;
+            for (resource, access) in &info.accesses {
+                let resource = *resource;
+                if !started[resource] {
+                    started[resource] = true;
+                    // Wait for the image that used the memory before.
+                    if let Some(alias) = aliases[resource] {
+                        let previous = states[alias].clone();
+                        states[resource].write = previous.write;
+                        states[resource].reads = previous.reads;
+                    }
+                }
+                let state = &mut states[resource];
+                let image = resources[resource].image;
+                let layout_change = image && state.layout != access.layout;
+                let write = access.is_write() || layout_change;
+
+                let mut dependencies = vec![];
+                if let Some(last_write) = state.write {
+                    let (visible_queue, stages, accesses) = state.visible;
+                    let visible = visible_queue == queue.id
+                        && stages.contains(access.stage)
+                        && accesses.contains(access.access);
+                    if write || !visible {
+                        dependencies.push(last_write);
+                    }
+                }
+                if write {
+                    dependencies.extend(state.reads.iter().copied());
+                }
+
+                let old_layout = if image {
+                    state.layout
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                };
+                let new_layout = if image {
+                    access.layout
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                };
+                let mut src_family = vk::QUEUE_FAMILY_IGNORED;
+                let mut dst_family = vk::QUEUE_FAMILY_IGNORED;
+                if let (Some(family), Some(last)) = (state.family, state.last) {
+                    // Without a write there is nothing to keep.
+                    if family != queue.family && state.write.is_some() {
+                        src_family = family;
+                        dst_family = queue.family;
+                        let release_stage = state
+                            .write
+                            .iter()
+                            .chain(&state.reads)
+                            .filter(|x| {
+                                x.batch != EXTERNAL && self.batches[x.batch].queue.family == family
+                            })
+                            .fold(vk::PipelineStageFlags::empty(), |stage, x| stage | x.stage);
+                        self.batches[last.batch].after.push(Barrier {
+                            resource,
+                            src_stage: release_stage,
+                            src_access: state
+                                .write
+                                .map_or(vk::AccessFlags::empty(), |x| x.access & WRITE_ACCESS),
+                            dst_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            dst_access: vk::AccessFlags::empty(),
+                            old_layout,
+                            new_layout,
+                            src_family,
+                            dst_family,
+                        });
+                        // The acquire waits for the release.
+                        dependencies.push(last);
+                    }
+                }
+                let mut src_stage = vk::PipelineStageFlags::empty();
+                let mut src_access = vk::AccessFlags::empty();
+                let mut waited = false;
+                for dependency in &dependencies {
+                    if dependency.batch != EXTERNAL
+                        && self.batches[dependency.batch].queue.id != queue.id
+                    {
+                        let waits = &mut self.batches[batch].waits;
+                        match waits.iter_mut().find(|x| x.0 == dependency.batch) {
+                            Some(wait) => wait.1 |= access.stage,
+                            None => waits.push((dependency.batch, access.stage)),
+                        }
+                        waited = true;
+                    } else {
+                        src_stage |= dependency.stage;
+                        src_access |= dependency.access & WRITE_ACCESS;
+                    }
+                }
+                let transfer = src_family != dst_family;
+                if waited && (layout_change || transfer) {
+                    // Chains with the semaphore wait.
+                    src_stage |= access.stage;
+                }
+
+                if !src_stage.is_empty() || layout_change || transfer {
+                    if src_stage.is_empty() {
+                        src_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
+                    }
+                    self.barriers[pass].push(Barrier {
+                        resource,
+                        src_stage,
+                        src_access: if transfer {
+                            vk::AccessFlags::empty()
+                        } else {
+                            src_access
+                        },
+                        dst_stage: access.stage,
+                        dst_access: access.access,
+                        old_layout,
+                        new_layout,
+                        src_family,
+                        dst_family,
+                    });
+                }
+
+                let current = Use {
+                    batch,
+                    stage: access.stage,
+                    access: access.access,
+                };
+                if write {
+                    state.write = Some(current);
+                    state.reads.clear();
+                    state.visible = (queue.id, access.stage, access.access);
+                } else {
+                    state.reads.push(current);
+                    if state.visible.0 == queue.id {
+                        state.visible.1 |= access.stage;
+                        state.visible.2 |= access.access;
+                    } else {
+                        state.visible = (queue.id, access.stage, access.access);
+                    }
+                }
+                if image {
+                    state.layout = access.layout;
+                }
+                state.family = Some(queue.family);
+                state.last = Some(current);
+            }
+        }
+
+        for (resource, info) in resources.iter().enumerate() {
+            let state = &states[resource];
+            if let (Some(final_layout), Some(last)) = (info.final_layout, state.last) {
+                if final_layout != state.layout {
+                    let queue = self.batches[last.batch].queue.id;
+                    let (src_stage, src_access) = state
+                        .write
+                        .iter()
+                        .chain(&state.reads)
+                        .filter(|x| x.batch != EXTERNAL && self.batches[x.batch].queue.id == queue)
+                        .fold(
+                            (vk::PipelineStageFlags::empty(), vk::AccessFlags::empty()),
+                            |(stage, access), x| {
+                                (stage | x.stage, access | (x.access & WRITE_ACCESS))
+                            },
+                        );
+                    let (dst_stage, dst_access) = layout_scope(final_layout);
+                    self.batches[last.batch].after.push(Barrier {
+                        resource,
+                        src_stage,
+                        src_access,
+                        dst_stage,
+                        dst_access,
+                        old_layout: state.layout,
+                        new_layout: final_layout,
+                        src_family: vk::QUEUE_FAMILY_IGNORED,
+                        dst_family: vk::QUEUE_FAMILY_IGNORED,
+                    });
+                    self.end_layouts[resource] = final_layout;
+                    continue;
+                }
+            }
+            self.end_layouts[resource] = state.layout;
+        }
+    }
+}
+
+/// Where the transient images live.
+#[derive(Debug, Clone, Default)]
+struct MemoryPlan {
+    /// The memory every resource is bound to.
+    slot: Vec<Option<usize>>,
+    /// The resource that used the memory before.
+    aliases: Vec<Option<usize>>,
+    /// What every memory has to fit.
+    slots: Vec<vk::MemoryRequirements>,
+}
+
+/// Places transient images in shared memory, greedily by first use. Images only share memory
+/// when all of their passes run on the same queue, one after the other.
+fn assign_memory(
+    lifetimes: &[Option<Lifetime>],
+    requirements: &[Option<vk::MemoryRequirements>],
+) -> MemoryPlan {
+    let mut plan = MemoryPlan {
+        slot: vec![None; lifetimes.len()],
+        aliases: vec![None; lifetimes.len()],
+        slots: vec![],
+    };
+    // Last user and the queue of every slot.
+    let mut users: Vec<(usize, Option<usize>)> = vec![];
+    let mut order = (0..lifetimes.len())
+        .filter_map(|x| Some((x, lifetimes[x]?, requirements[x]?)))
+        .collect::<Vec<_>>();
+    order.sort_by_key(|(_, lifetime, _)| lifetime.first);
+    for (resource, lifetime, requirement) in order {
+        let fits = (0..plan.slots.len()).find(|slot| {
+            let (user, queue) = users[*slot];
+            lifetime.queue.is_some()
+                && queue == lifetime.queue
+                && lifetimes[user].unwrap().last < lifetime.first
+                && plan.slots[*slot].memory_type_bits & requirement.memory_type_bits != 0
+        });
+        match fits {
+            Some(slot) => {
+                let memory = &mut plan.slots[slot];
+                memory.size = memory.size.max(requirement.size);
+                memory.alignment = memory.alignment.max(requirement.alignment);
+                memory.memory_type_bits &= requirement.memory_type_bits;
+                plan.aliases[resource] = Some(users[slot].0);
+                users[slot] = (resource, lifetime.queue);
+                plan.slot[resource] = Some(slot);
+            }
+            None => {
+                plan.slots.push(requirement);
+                users.push((resource, lifetime.queue));
+                plan.slot[resource] = Some(plan.slots.len() - 1);
+            }
+        }
+    }
+    plan
+}
+
+fn graphviz(info: &GraphInfo, passes: &[PassInfo], plan: &Plan) -> String {
+    let escape = |x: &str| x.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut dot = String::from("digraph render_graph {\n    rankdir=LR;\n");
+    for (pass, (name, queue)) in info.passes.iter().enumerate() {
+        let name = escape(name);
+        let (label, style) = match plan.pass_batch[pass] {
+            Some(batch) => (
+                format!(
+                    "{name}\\n{queue:?}, batch {batch}\\n{} barriers",
+                    plan.barriers[pass].len()
+                ),
+                "solid",
+            ),
+            None => (format!("{name}\\nculled"), "dashed"),
+        };
+        let _ = writeln!(
+            dot,
+            "    pass{pass} [shape=box, style={style}, label=\"{label}\"];"
+        );
+    }
+    for (resource, name) in info.resources.iter().enumerate() {
+        let name = escape(name);
+        let label = match info.slots.get(resource).copied().flatten() {
+            Some(slot) => format!("{name}\\nmemory {slot}"),
+            None => name,
+        };
+        let _ = writeln!(
+            dot,
+            "    resource{resource} [shape=ellipse, label=\"{label}\"];"
+        );
+    }
+    for (pass, x) in passes.iter().enumerate() {
+        let style = if plan.alive[pass] { "solid" } else { "dashed" };
+        for (resource, access) in &x.accesses {
+            let layout =
+                if *resource < info.slots.len() && access.layout != vk::ImageLayout::UNDEFINED {
+                    format!("{:?}", access.layout)
+                } else {
+                    String::new()
+                };
+            let (from, to) = if access.is_write() {
+                (format!("pass{pass}"), format!("resource{resource}"))
+            } else {
+                (format!("resource{resource}"), format!("pass{pass}"))
+            };
+            let _ = writeln!(
+                dot,
+                "    {from} -> {to} [style={style}, label=\"{layout}\"];"
+            );
+        }
+    }
+    for (batch, x) in plan.batches.iter().enumerate() {
+        for (source, _) in &x.waits {
+            let from = plan.batches[*source].passes.last().unwrap();
+            let to = x.passes[0];
+            let _ = writeln!(
+                dot,
+                "    pass{from} -> pass{to} [color=red, label=\"semaphore {source} -> {batch}\"];"
+            );
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GRAPHICS: QueueSlot = QueueSlot { id: 0, family: 0 };
+    const COMPUTE: QueueSlot = QueueSlot { id: 1, family: 1 };
+
+    fn transient() -> ResourceInfo {
+        ResourceInfo {
+            image: true,
+            imported: false,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: None,
+        }
+    }
+    fn imported(final_layout: vk::ImageLayout) -> ResourceInfo {
+        ResourceInfo {
+            image: true,
+            imported: true,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: Some(final_layout),
+        }
+    }
+    fn pass(queue: QueueSlot, accesses: &[(usize, Access)]) -> PassInfo {
+        PassInfo {
+            queue,
+            accesses: accesses.to_vec(),
+            side_effects: false,
+        }
+    }
+    fn plan(resources: &[ResourceInfo], passes: &[PassInfo]) -> Plan {
+        let mut plan = Plan::new(resources, passes);
+        plan.synchronize(resources, passes, &vec![None; resources.len()]);
+        plan
+    }
+    const FRAGMENT: vk::PipelineStageFlags = vk::PipelineStageFlags::FRAGMENT_SHADER;
+    const COMPUTE_SHADER: vk::PipelineStageFlags = vk::PipelineStageFlags::COMPUTE_SHADER;
+
+    #[test]
+    fn culling() {
+        let resources = [
+            imported(vk::ImageLayout::PRESENT_SRC_KHR),
+            transient(),
+            transient(),
+        ];
+        let passes = [
+            pass(GRAPHICS, &[(1, Access::COLOR_ATTACHMENT)]),
+            // Nothing reads what it writes.
+            pass(GRAPHICS, &[(2, Access::COLOR_ATTACHMENT)]),
+            pass(
+                GRAPHICS,
+                &[
+                    (1, Access::sampled(FRAGMENT)),
+                    (0, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+        ];
+        let plan = plan(&resources, &passes);
+        assert_eq!(plan.alive, [true, false, true]);
+        assert_eq!(plan.batches.len(), 1);
+        assert_eq!(plan.batches[0].passes, [0, 2]);
+        assert!(plan.barriers[1].is_empty());
+
+        let mut side_effects = passes.clone();
+        side_effects[1].side_effects = true;
+        assert_eq!(Plan::new(&resources, &side_effects).alive, [true; 3]);
+    }
+
+    #[test]
+    fn barriers() {
+        let resources = [imported(vk::ImageLayout::PRESENT_SRC_KHR), transient()];
+        let passes = [
+            pass(GRAPHICS, &[(1, Access::COLOR_ATTACHMENT)]),
+            pass(
+                GRAPHICS,
+                &[
+                    (1, Access::sampled(FRAGMENT)),
+                    (0, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+            // The write is already visible to the fragment shader.
+            pass(
+                GRAPHICS,
+                &[
+                    (1, Access::sampled(FRAGMENT)),
+                    (0, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+        ];
+        let plan = plan(&resources, &passes);
+        assert_eq!(
+            plan.barriers[0],
+            [Barrier {
+                resource: 1,
+                src_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                src_access: vk::AccessFlags::empty(),
+                dst_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_access: Access::COLOR_ATTACHMENT.access,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                src_family: vk::QUEUE_FAMILY_IGNORED,
+                dst_family: vk::QUEUE_FAMILY_IGNORED,
+            }]
+        );
+        let sample = plan.barriers[1].iter().find(|x| x.resource == 1).unwrap();
+        assert_eq!(
+            sample.src_stage,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(sample.src_access, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(sample.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        // The target is written again, which waits for the previous write.
+        assert_eq!(plan.barriers[2].len(), 1);
+        assert_eq!(plan.barriers[2][0].resource, 0);
+        assert_eq!(
+            plan.barriers[2][0].old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+
+        let after = &plan.batches[0].after;
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].new_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+        assert_eq!(plan.end_layouts[0], vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn cross_queue() {
+        let resources = [imported(vk::ImageLayout::PRESENT_SRC_KHR), transient()];
+        let passes = [
+            pass(GRAPHICS, &[(1, Access::COLOR_ATTACHMENT)]),
+            pass(
+                COMPUTE,
+                &[
+                    (1, Access::sampled(COMPUTE_SHADER)),
+                    (0, Access::storage_write(COMPUTE_SHADER)),
+                ],
+            ),
+            pass(GRAPHICS, &[(0, Access::COLOR_ATTACHMENT)]),
+        ];
+        let plan = plan(&resources, &passes);
+        assert_eq!(plan.batches.len(), 3);
+        assert_eq!(plan.batches[1].waits, [(0, COMPUTE_SHADER)]);
+        assert_eq!(
+            plan.batches[2].waits,
+            [(1, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)]
+        );
+
+        // The graphics family releases the transient image after writing it...
+        let release = &plan.batches[0].after[0];
+        assert_eq!((release.src_family, release.dst_family), (0, 1));
+        assert_eq!(
+            release.src_stage,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        );
+        // ...and the compute family acquires it after the semaphore.
+        let acquire = plan.barriers[1].iter().find(|x| x.resource == 1).unwrap();
+        assert_eq!((acquire.src_family, acquire.dst_family), (0, 1));
+        assert_eq!(acquire.src_stage, COMPUTE_SHADER);
+        assert_eq!(
+            (release.old_layout, release.new_layout),
+            (acquire.old_layout, acquire.new_layout)
+        );
+        // The imported image was not written on the graphics queue before, so it has no owner
+        // to release it.
+        let storage = plan.barriers[1].iter().find(|x| x.resource == 0).unwrap();
+        assert_eq!(storage.src_family, vk::QUEUE_FAMILY_IGNORED);
+        let back = plan.barriers[2].iter().find(|x| x.resource == 0).unwrap();
+        assert_eq!((back.src_family, back.dst_family), (1, 0));
+    }
+
+    #[test]
+    fn aliasing() {
+        let resources = [
+            imported(vk::ImageLayout::PRESENT_SRC_KHR),
+            transient(),
+            transient(),
+            transient(),
+        ];
+        let passes = [
+            pass(GRAPHICS, &[(1, Access::COLOR_ATTACHMENT)]),
+            pass(
+                GRAPHICS,
+                &[
+                    (1, Access::sampled(FRAGMENT)),
+                    (2, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+            pass(
+                GRAPHICS,
+                &[
+                    (2, Access::sampled(FRAGMENT)),
+                    (3, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+            pass(
+                GRAPHICS,
+                &[
+                    (3, Access::sampled(FRAGMENT)),
+                    (0, Access::COLOR_ATTACHMENT),
+                ],
+            ),
+        ];
+        let mut plan = Plan::new(&resources, &passes);
+        let lifetimes = plan.lifetimes(&resources, &passes);
+        assert_eq!(lifetimes[0], None);
+        assert_eq!(
+            lifetimes[2],
+            Some(Lifetime {
+                first: 1,
+                last: 2,
+                queue: Some(0),
+            })
+        );
+        let requirement = |size, memory_type_bits| {
+            Some(vk::MemoryRequirements {
+                size,
+                alignment: 256,
+                memory_type_bits,
+            })
+        };
+        let memory = assign_memory(
+            &lifetimes,
+            &[
+                None,
+                requirement(1024, 0b1),
+                requirement(512, 0b1),
+                requirement(2048, 0b10),
+            ],
+        );
+        // The first and second image overlap, the third does not fit the memory type of the first.
+        assert_eq!(memory.slot, [None, Some(0), Some(1), Some(2)]);
+        let memory = assign_memory(
+            &lifetimes,
+            &[
+                None,
+                requirement(1024, 0b11),
+                requirement(512, 0b1),
+                requirement(2048, 0b1),
+            ],
+        );
+        assert_eq!(memory.slot, [None, Some(0), Some(1), Some(0)]);
+        assert_eq!(memory.aliases[3], Some(1));
+        assert_eq!(memory.slots[0].size, 2048);
+        assert_eq!(memory.slots[0].memory_type_bits, 0b1);
+
+        // The aliasing image waits for the reads of the previous one.
+        plan.synchronize(&resources, &passes, &memory.aliases);
+        let barrier = plan.barriers[2].iter().find(|x| x.resource == 3).unwrap();
+        assert_eq!(barrier.src_stage, FRAGMENT);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::UNDEFINED);
+    }
+
+    #[test]
+    fn graphviz_output() {
+        let resources = [imported(vk::ImageLayout::PRESENT_SRC_KHR), transient()];
+        let passes = [
+            pass(GRAPHICS, &[(1, Access::COLOR_ATTACHMENT)]),
+            pass(GRAPHICS, &[(0, Access::TRANSFER_WRITE)]),
+        ];
+        let plan = plan(&resources, &passes);
+        let info = GraphInfo {
+            passes: vec![
+                ("unused".into(), QueueType::Graphics),
+                ("\"clear\"".into(), QueueType::Transfer),
+            ],
+            resources: vec!["target".into(), "scratch".into()],
+            slots: vec![None, None],
+        };
+        let dot = graphviz(&info, &passes, &plan);
+        assert!(dot.starts_with("digraph render_graph {"));
+        assert!(dot.contains("pass0 [shape=box, style=dashed, label=\"unused\\nculled\"];"));
+        assert!(dot.contains("label=\"\\\"clear\\\"\\nTransfer, batch 0"));
+        assert!(dot.contains("pass1 -> resource0 [style=solid, label=\"TRANSFER_DST_OPTIMAL\"];"));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn copy_buffers() {
+        let (device, queue) = crate::test_support::device(
+            "graph test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::COMPUTE,
+        );
+        let allocator = Allocator::new(&device, Default::default());
+        let data = (0..64).collect::<Vec<u32>>();
+        let description = crate::buffer::BufferDescription::new(MemoryUsage::Dynamic)
+            .transfer_src()
+            .transfer_dst();
+        let source =
+            Arc::new(Buffer::from_slice(&allocator, &queue, &data, description.clone()).unwrap());
+        let target =
+            Arc::new(Buffer::from_slice(&allocator, &queue, &[0; 64], description).unwrap());
+
+        let mut graph = RenderGraph::new();
+        let source_id = graph.import_buffer("source", &source);
+        let target_id = graph.import_buffer("target", &target);
+        let unused = graph.create_image(
+            "unused",
+            ImageDescription::new(
+                crate::image::ImageDimensions::D2 {
+                    width: 16,
+                    height: 16,
+                    layers: 1,
+                },
+                vk::Format::R8G8B8A8_UNORM,
+            ),
+        );
+        graph
+            .add_pass("unused", QueueType::Compute)
+            .image(unused, Access::TRANSFER_WRITE)
+            .record(|_, _| panic!("Culled passes are not recorded"));
+        let (copied_source, copied_target) = (source.clone(), target.clone());
+        graph
+            .add_pass("copy", QueueType::Transfer)
+            .buffer(source_id, Access::TRANSFER_READ)
+            .buffer(target_id, Access::TRANSFER_WRITE)
+            .record(move |command_buffer, resources| {
+                assert_eq!(resources.buffer(target_id), copied_target.raw());
+                command_buffer.copy_buffer(&copied_source, 0..64, &copied_target, 0);
+            });
+        graph
+            .add_pass("readback", QueueType::Compute)
+            .buffer(
+                target_id,
+                Access::new(
+                    vk::PipelineStageFlags::HOST,
+                    vk::AccessFlags::HOST_READ,
+                    vk::ImageLayout::UNDEFINED,
+                ),
+            )
+            .side_effects()
+            .record(|_, _| {});
+
+        let queues = GraphQueues::new(&queue);
+        let compiled = graph.compile(&allocator, &queues).unwrap();
+        assert_eq!(compiled.culled(), ["unused"]);
+        assert_eq!(compiled.batches(), 1);
+        let pools = CommandPools::new(&device);
+        compiled
+            .execute(&queues, &pools, |queue, submit, batch| {
+                assert_eq!(batch, BatchInfo { index: 0, count: 1 });
+                submit.submit(queue)?.wait()
+            })
+            .unwrap();
+        assert_eq!(target.read(0..data.len()).unwrap(), data);
+    }
+}
@@ -0,0 +1,208 @@
+//! Ownership of Vulkan handles.
+//!
+//! Every object owns its handle through an [`Owned`] handle, an [`infrastructure::ResourceRef`]
+//! whose owner is the [`Parent`] the handle was created from. The parent stays alive as long as
+//! the handle does, so the [`Instance`] outlives its devices and surfaces, and a [`Device`]
+//! outlives everything created from it, in whichever order the application drops them:
+//! ```ignore
+//! let fence = Fence::new(&device, false)?;
+//! drop(device);
+//! // The device is destroyed after the fence.
+//! drop(fence);
+//! ```
+//! Objects holding several handles declare them in the order they are destroyed in, e.g. a view
+//! before its image.
+//!
+//! Dropping the last reference to an [`Owned`] handle destroys it right away, whether or not the
+//! device still uses it. Objects are therefore only handed to the device through
+//! [`CommandBuffer`](crate::command::CommandBuffer) methods taking an `Arc`, which keep them alive
+//! until the command buffer is done executing. A raw handle recorded any other way, e.g. the view
+//! of an [`Attachment`](crate::command::Attachment) or a call through [`ash`] directly, has to be
+//! kept alive by the caller through
+//! [`CommandBuffer::keep_alive`](crate::command::CommandBuffer::keep_alive) or
+//! [`Frame::defer_destroy`](crate::sync::Frame::defer_destroy).
+use super::device::Device;
+use super::instance::Instance;
+use ash::vk;
+use infrastructure::{ResourceDeleter, ResourceRef};
+use std::sync::Arc;
+
+/// A handle destroyed by its parent `P` when dropped, see the [module](self) for keeping it alive
+/// while the device uses it.
+pub type Owned<H, P = Device> = ResourceRef<H, Parent<P>>;
+
+/// Takes ownership of `handle`, which was created from `parent`. Only objects of this crate own
+/// handles, so that every use of them on the device keeps them alive.
+pub(crate) fn own<H: Destroy<P>, P>(parent: &Arc<P>, handle: H) -> Owned<H, P> {
+    ResourceRef {
+        value: handle,
+        owner: Parent(parent.clone()),
+    }
+}
+
+/// Keeps the parent of a handle alive and destroys the handle with it.
+#[derive(derive_more::Deref)]
+pub struct Parent<P>(Arc<P>);
+
+impl<P> std::fmt::Debug for Parent<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Parent").finish_non_exhaustive()
+    }
+}
+
+/// A handle created from `P`.
+pub trait Destroy<P> {
+    /// # Safety
+    /// The handle has to be created from `parent` and must not be in use anymore.
+    unsafe fn destroy(&mut self, parent: &P);
+}
+
+impl<P, H: Destroy<P>> ResourceDeleter<H> for Parent<P> {
+    fn delete(&mut self, handle: &mut H) {
+        unsafe { handle.destroy(&self.0) };
+    }
+}
+
+macro_rules! destroy_with_device {
+    ($($handle:ty => $function:ident,)*) => {$(
+        impl Destroy<Device> for $handle {
+            unsafe fn destroy(&mut self, device: &Device) {
+                unsafe { device.raw().$function(*self, device.allocation_callbacks()) };
+            }
+        }
+    )*};
+}
+
+destroy_with_device! {
+    vk::Fence => destroy_fence,
+    vk::Semaphore => destroy_semaphore,
+    vk::Buffer => destroy_buffer,
+    vk::Image => destroy_image,
+    vk::ImageView => destroy_image_view,
+    vk::Sampler => destroy_sampler,
+    vk::ShaderModule => destroy_shader_module,
+    vk::PipelineLayout => destroy_pipeline_layout,
+    vk::Pipeline => destroy_pipeline,
+    vk::PipelineCache => destroy_pipeline_cache,
+    vk::CommandPool => destroy_command_pool,
+    vk::DescriptorPool => destroy_descriptor_pool,
+    vk::QueryPool => destroy_query_pool,
+    vk::DeviceMemory => free_memory,
+}
+
+impl Destroy<Device> for vk::SwapchainKHR {
+    unsafe fn destroy(&mut self, device: &Device) {
+        let instance = &device.physical_device().instance().raw;
+        let loader = ash::khr::swapchain::Device::new(instance, device.raw());
+        unsafe { loader.destroy_swapchain(*self, device.allocation_callbacks()) };
+    }
+}
+
+impl Destroy<Instance> for vk::SurfaceKHR {
+    unsafe fn destroy(&mut self, instance: &Instance) {
+        unsafe {
+            instance
+                .surface_loader()
+                .destroy_surface(*self, instance.allocation_callbacks())
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records what is destroyed, and when the parent itself is dropped.
+    #[derive(Default)]
+    struct Log(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Log {
+        fn drop(&mut self) {
+            self.0.lock().unwrap().push("parent");
+        }
+    }
+
+    struct Handle(&'static str);
+
+    impl Destroy<Log> for Handle {
+        unsafe fn destroy(&mut self, parent: &Log) {
+            parent.0.lock().unwrap().push(self.0);
+        }
+    }
+
+    /// Fields are dropped in declaration order.
+    struct Object {
+        _view: Owned<Handle, Log>,
+        _image: Owned<Handle, Log>,
+    }
+
+    #[test]
+    fn drop_order() {
+        let parent = Arc::new(Log::default());
+        let log = parent.0.clone();
+        let object = Object {
+            _view: own(&parent, Handle("view")),
+            _image: own(&parent, Handle("image")),
+        };
+        let buffer = own(&parent, Handle("buffer"));
+        assert_eq!(Arc::strong_count(&parent), 4);
+
+        drop(parent);
+        assert!(log.lock().unwrap().is_empty());
+        drop(object);
+        assert_eq!(*log.lock().unwrap(), ["view", "image"]);
+        drop(buffer);
+        assert_eq!(*log.lock().unwrap(), ["view", "image", "buffer", "parent"]);
+    }
+
+    /// The instance and device are dropped before the objects created from them, and
+    /// destroyed after them without leaking host memory.
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn parents_outlive_objects() {
+        use crate::buffer::{Buffer, BufferDescription};
+        use crate::image::{Image, ImageDescription, ImageDimensions, ImageViewDescription};
+        use crate::memory::{Allocator, MemoryUsage};
+        use crate::sync::Fence;
+        use crate::{DeviceCreationInfo, QueueDescription};
+
+        let instance = crate::test_support::instance(
+            crate::InstanceCreateInfo::compute_usage("handle test").with_host_allocation_tracking(),
+        );
+        let tracker = instance.host_allocations().unwrap().clone();
+        let mut info = DeviceCreationInfo::default();
+        info.request_queue(QueueDescription {
+            flags: vk::QueueFlags::TRANSFER,
+            ..QueueDescription::from_count(1)
+        });
+        let device = crate::test_support::create(&instance, info).device;
+        let allocator = Allocator::new(&device, Default::default());
+        let fence = Fence::new(&device, false).unwrap();
+        let buffer = Buffer::<u32>::new(
+            &allocator,
+            16,
+            BufferDescription::new(MemoryUsage::GpuOnly).storage(),
+        )
+        .unwrap();
+        let image = Image::new(
+            &allocator,
+            &ImageDescription::new(ImageDimensions::d2(4, 4), vk::Format::R8G8B8A8_UNORM)
+                .transfer_dst(),
+        )
+        .unwrap();
+        let view = image
+            .create_view(&ImageViewDescription::new(
+                vk::ImageViewType::TYPE_2D,
+                vk::Format::R8G8B8A8_UNORM,
+            ))
+            .unwrap();
+
+        drop((instance, device, allocator, image));
+        assert!(tracker.stats().live_allocations() > 0);
+        drop((fence, buffer));
+        assert!(tracker.stats().live_allocations() > 0);
+        drop(view);
+        assert!(!tracker.report_leaks());
+    }
+}
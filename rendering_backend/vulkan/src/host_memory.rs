@@ -0,0 +1,373 @@
+//! Host memory used by the Vulkan implementation.
+//!
+//! With [`InstanceCreateInfo::with_host_allocation_tracking`](crate::InstanceCreateInfo::with_host_allocation_tracking)
+//! the loader, the layers and the driver allocate through [`HostAllocationTracker`], which
+//! forwards to the Rust global allocator and counts what is alive per
+//! [`vk::SystemAllocationScope`]. Allocations still alive after the instance is destroyed are
+//! reported as leaks.
+//! ```ignore
+//! let instance = Instance::new_dynamic(InstanceCreateInfo::compute_usage("app").with_host_allocation_tracking())?;
+//! let stats = instance.host_allocations().unwrap().stats();
+//! println!("{stats}");
+//! ```
+use ash::vk;
+use std::alloc::Layout;
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Every [`vk::SystemAllocationScope`], in the order of [`HostMemoryStats::scopes`].
+pub const SCOPES: [vk::SystemAllocationScope; 5] = [
+    vk::SystemAllocationScope::COMMAND,
+    vk::SystemAllocationScope::OBJECT,
+    vk::SystemAllocationScope::CACHE,
+    vk::SystemAllocationScope::DEVICE,
+    vk::SystemAllocationScope::INSTANCE,
+];
+
+/// Live and total host allocations of one scope.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScopeStats {
+    pub allocations: usize,
+    pub bytes: usize,
+    /// The most bytes that were alive at once.
+    pub peak_bytes: usize,
+    /// Allocations made since the instance was created, including freed ones.
+    pub total_allocations: u64,
+}
+
+impl fmt::Display for ScopeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations, {} bytes (peak {} bytes, {} total allocations)",
+            self.allocations, self.bytes, self.peak_bytes, self.total_allocations
+        )
+    }
+}
+
+/// A snapshot of a [`HostAllocationTracker`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostMemoryStats {
+    /// Allocations made through the callbacks, indexed like [`SCOPES`].
+    pub scopes: [ScopeStats; 5],
+    /// Memory the implementation allocated itself and only reported, e.g. executable code.
+    pub internal: ScopeStats,
+}
+
+impl HostMemoryStats {
+    pub fn scope(&self, scope: vk::SystemAllocationScope) -> &ScopeStats {
+        &self.scopes[scope_index(scope)]
+    }
+    /// Live allocations over every scope, without internal ones.
+    pub fn live_allocations(&self) -> usize {
+        self.scopes.iter().map(|x| x.allocations).sum()
+    }
+    /// Live bytes over every scope, without internal ones.
+    pub fn live_bytes(&self) -> usize {
+        self.scopes.iter().map(|x| x.bytes).sum()
+    }
+}
+
+impl fmt::Display for HostMemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (scope, stats) in SCOPES.iter().zip(&self.scopes) {
+            writeln!(f, "{scope:?}: {stats}")?;
+        }
+        write!(f, "internal: {}", self.internal)
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    allocations: AtomicUsize,
+    bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    total_allocations: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.total_allocations.fetch_add(1, Ordering::Relaxed);
+        let bytes = self.bytes.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes.fetch_max(bytes, Ordering::Relaxed);
+    }
+    fn remove(&self, size: usize) {
+        self.allocations.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+    fn load(&self) -> ScopeStats {
+        ScopeStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            total_allocations: self.total_allocations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Host allocation callbacks on top of the Rust global allocator that count live allocations
+/// per scope. Shared with an [`Instance`](crate::Instance), see
+/// [`Instance::host_allocations`](crate::Instance::host_allocations).
+#[derive(Default)]
+pub struct HostAllocationTracker {
+    scopes: [Counters; 5],
+    internal: Counters,
+}
+
+impl fmt::Debug for HostAllocationTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostAllocationTracker")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// Stored in front of every allocation, since frees only get the pointer.
+#[derive(Clone, Copy)]
+struct Header {
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+}
+
+impl HostAllocationTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> HostMemoryStats {
+        HostMemoryStats {
+            scopes: std::array::from_fn(|i| self.scopes[i].load()),
+            internal: self.internal.load(),
+        }
+    }
+
+    /// Logs every allocation that is still alive as an error.
+    /// Returns whether there were any.
+    pub fn report_leaks(&self) -> bool {
+        let stats = self.stats();
+        let leaked = stats.live_allocations() > 0 || stats.internal.allocations > 0;
+        if leaked {
+            tracing::error!(
+                "{} host allocations with {} bytes leaked by the Vulkan implementation:\n{stats}",
+                stats.live_allocations(),
+                stats.live_bytes()
+            );
+        }
+        leaked
+    }
+
+    /// Callbacks that call into `self`.
+    ///
+    /// # Safety
+    /// `self` has to outlive every object created with the callbacks.
+    pub unsafe fn callbacks(&self) -> vk::AllocationCallbacks<'static> {
+        vk::AllocationCallbacks {
+            p_user_data: std::ptr::from_ref(self).cast_mut().cast(),
+            pfn_allocation: Some(allocation),
+            pfn_reallocation: Some(reallocation),
+            pfn_free: Some(free),
+            pfn_internal_allocation: Some(internal_allocation),
+            pfn_internal_free: Some(internal_free),
+            ..Default::default()
+        }
+    }
+
+    fn counters(&self, scope: vk::SystemAllocationScope) -> &Counters {
+        &self.scopes[scope_index(scope)]
+    }
+
+    fn allocate(
+        &self,
+        size: usize,
+        alignment: usize,
+        scope: vk::SystemAllocationScope,
+    ) -> *mut c_void {
+        let Some((layout, offset)) = layout(size, alignment) else {
+            return std::ptr::null_mut();
+        };
+        let base = unsafe { std::alloc::alloc(layout) };
+        if base.is_null() {
+            return std::ptr::null_mut();
+        }
+        let ptr = unsafe { base.add(offset) };
+        let header = Header {
+            size,
+            alignment,
+            scope,
+        };
+        unsafe { ptr.cast::<Header>().sub(1).write(header) };
+        self.counters(scope).add(size);
+        ptr.cast()
+    }
+
+    /// # Safety
+    /// `ptr` has to come from [`Self::allocate`] and not be freed yet.
+    unsafe fn free(&self, ptr: *mut c_void) {
+        let header = unsafe { ptr.cast::<Header>().sub(1).read() };
+        let (layout, offset) = layout(header.size, header.alignment).unwrap();
+        unsafe { std::alloc::dealloc(ptr.cast::<u8>().sub(offset), layout) };
+        self.counters(header.scope).remove(header.size);
+    }
+}
+
+fn scope_index(scope: vk::SystemAllocationScope) -> usize {
+    // The scopes are numbered from 0 in the order of `SCOPES`. Unknown ones are counted as
+    // instance scope rather than unwinding out of a callback.
+    scope.as_raw().clamp(0, SCOPES.len() as i32 - 1) as usize
+}
+
+/// The layout of an allocation and its header, and where the returned memory starts.
+fn layout(size: usize, alignment: usize) -> Option<(Layout, usize)> {
+    let alignment = alignment.max(std::mem::align_of::<Header>());
+    let offset = std::mem::size_of::<Header>().next_multiple_of(alignment);
+    let layout = Layout::from_size_align(offset.checked_add(size)?, alignment).ok()?;
+    Some((layout, offset))
+}
+
+/// # Safety
+/// `user_data` has to point to a live [`HostAllocationTracker`].
+unsafe fn tracker<'a>(user_data: *mut c_void) -> &'a HostAllocationTracker {
+    unsafe { &*user_data.cast::<HostAllocationTracker>() }
+}
+
+unsafe extern "system" fn allocation(
+    user_data: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    unsafe { tracker(user_data) }.allocate(size, alignment, scope)
+}
+
+unsafe extern "system" fn reallocation(
+    user_data: *mut c_void,
+    original: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let tracker = unsafe { tracker(user_data) };
+    if original.is_null() {
+        return tracker.allocate(size, alignment, scope);
+    }
+    if size == 0 {
+        unsafe { tracker.free(original) };
+        return std::ptr::null_mut();
+    }
+    let old_size = unsafe { original.cast::<Header>().sub(1).read() }.size;
+    let ptr = tracker.allocate(size, alignment, scope);
+    // On failure the original allocation stays valid.
+    if !ptr.is_null() {
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                original.cast::<u8>(),
+                ptr.cast::<u8>(),
+                old_size.min(size),
+            );
+            tracker.free(original);
+        }
+    }
+    ptr
+}
+
+unsafe extern "system" fn free(user_data: *mut c_void, ptr: *mut c_void) {
+    if !ptr.is_null() {
+        unsafe { tracker(user_data).free(ptr) };
+    }
+}
+
+unsafe extern "system" fn internal_allocation(
+    user_data: *mut c_void,
+    size: usize,
+    _ty: vk::InternalAllocationType,
+    _scope: vk::SystemAllocationScope,
+) {
+    unsafe { tracker(user_data) }.internal.add(size);
+}
+
+unsafe extern "system" fn internal_free(
+    user_data: *mut c_void,
+    size: usize,
+    _ty: vk::InternalAllocationType,
+    _scope: vk::SystemAllocationScope,
+) {
+    unsafe { tracker(user_data) }.internal.remove(size);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn callbacks_count_per_scope() {
+        let tracker = HostAllocationTracker::new();
+        let callbacks = unsafe { tracker.callbacks() };
+        let user_data = callbacks.p_user_data;
+        let object = vk::SystemAllocationScope::OBJECT;
+        unsafe {
+            let a = callbacks.pfn_allocation.unwrap()(user_data, 100, 64, object);
+            assert_eq!(a as usize % 64, 0);
+            let b = callbacks.pfn_allocation.unwrap()(
+                user_data,
+                8,
+                1,
+                vk::SystemAllocationScope::COMMAND,
+            );
+            assert_eq!(tracker.stats().scope(object).bytes, 100);
+            assert_eq!(tracker.stats().live_allocations(), 2);
+
+            a.cast::<u8>().write_bytes(7, 100);
+            let a = callbacks.pfn_reallocation.unwrap()(user_data, a, 200, 128, object);
+            assert_eq!(a as usize % 128, 0);
+            assert_eq!(*a.cast::<u8>().add(99), 7);
+            let stats = tracker.stats();
+            assert_eq!(stats.scope(object).allocations, 1);
+            assert_eq!(stats.scope(object).bytes, 200);
+            assert_eq!(stats.scope(object).peak_bytes, 300);
+            assert_eq!(stats.scope(object).total_allocations, 2);
+
+            callbacks.pfn_internal_allocation.unwrap()(
+                user_data,
+                4096,
+                vk::InternalAllocationType::EXECUTABLE,
+                object,
+            );
+            assert!(tracker.report_leaks());
+            callbacks.pfn_internal_free.unwrap()(
+                user_data,
+                4096,
+                vk::InternalAllocationType::EXECUTABLE,
+                object,
+            );
+
+            assert!(callbacks.pfn_reallocation.unwrap()(user_data, a, 0, 128, object).is_null());
+            callbacks.pfn_free.unwrap()(user_data, b);
+            callbacks.pfn_free.unwrap()(user_data, std::ptr::null_mut());
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.live_allocations(), 0);
+        assert_eq!(stats.live_bytes(), 0);
+        assert_eq!(stats.internal.peak_bytes, 4096);
+        assert!(!tracker.report_leaks());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn instance_allocations() {
+        let instance = crate::test_support::instance(
+            crate::InstanceCreateInfo::compute_usage("host memory test")
+                .with_host_allocation_tracking(),
+        );
+        let tracker = instance.host_allocations().unwrap().clone();
+        assert!(tracker
+            .stats()
+            .scopes
+            .iter()
+            .any(|x| x.total_allocations > 0));
+        drop(instance);
+        assert_eq!(tracker.stats().live_allocations(), 0);
+    }
+}
@@ -0,0 +1,811 @@
+//! Images and image views.
+//!
+//! An [`Image`] tracks the layout of every mip level and array layer, so
+//! [`Image::transition`] can record the barriers to a new layout without the caller knowing the
+//! old one. Layouts are tracked in recording order: command buffers have to be submitted in
+//! the order they were recorded in, or [`Image::assume_layout`] has to be used to correct the
+//! tracked state.
+use super::error::ImageError;
+use super::handle::{own, Owned};
+use super::memory::{AllocatedImage, Allocator, MemoryUsage};
+use ash::vk;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+/// Type and size of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDimensions {
+    D1 {
+        width: u32,
+        layers: u32,
+    },
+    D2 {
+        width: u32,
+        height: u32,
+        layers: u32,
+    },
+    D3 {
+        width: u32,
+        height: u32,
+        depth: u32,
+    },
+    /// Six 2D layers per cube.
+    Cube {
+        size: u32,
+        cubes: u32,
+    },
+}
+
+impl ImageDimensions {
+    /// A single 2D image.
+    pub const fn d2(width: u32, height: u32) -> Self {
+        Self::D2 {
+            width,
+            height,
+            layers: 1,
+        }
+    }
+    pub fn image_type(self) -> vk::ImageType {
+        match self {
+            Self::D1 { .. } => vk::ImageType::TYPE_1D,
+            Self::D2 { .. } | Self::Cube { .. } => vk::ImageType::TYPE_2D,
+            Self::D3 { .. } => vk::ImageType::TYPE_3D,
+        }
+    }
+    pub fn extent(self) -> vk::Extent3D {
+        let (width, height, depth) = match self {
+            Self::D1 { width, .. } => (width, 1, 1),
+            Self::D2 { width, height, .. } => (width, height, 1),
+            Self::D3 {
+                width,
+                height,
+                depth,
+            } => (width, height, depth),
+            Self::Cube { size, .. } => (size, size, 1),
+        };
+        vk::Extent3D {
+            width,
+            height,
+            depth,
+        }
+    }
+    pub fn array_layers(self) -> u32 {
+        match self {
+            Self::D1 { layers, .. } | Self::D2 { layers, .. } => layers,
+            Self::D3 { .. } => 1,
+            Self::Cube { cubes, .. } => 6 * cubes,
+        }
+    }
+    /// The view type that covers the whole image.
+    pub fn view_type(self) -> vk::ImageViewType {
+        match self {
+            Self::D1 { layers: 1, .. } => vk::ImageViewType::TYPE_1D,
+            Self::D1 { .. } => vk::ImageViewType::TYPE_1D_ARRAY,
+            Self::D2 { layers: 1, .. } => vk::ImageViewType::TYPE_2D,
+            Self::D2 { .. } => vk::ImageViewType::TYPE_2D_ARRAY,
+            Self::D3 { .. } => vk::ImageViewType::TYPE_3D,
+            Self::Cube { cubes: 1, .. } => vk::ImageViewType::CUBE,
+            Self::Cube { .. } => vk::ImageViewType::CUBE_ARRAY,
+        }
+    }
+    fn create_flags(self) -> vk::ImageCreateFlags {
+        match self {
+            Self::Cube { .. } => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            _ => vk::ImageCreateFlags::empty(),
+        }
+    }
+    /// Number of levels in a full mip chain, down to 1x1x1.
+    pub fn max_mip_levels(self) -> u32 {
+        let extent = self.extent();
+        let largest = extent.width.max(extent.height).max(extent.depth);
+        u32::BITS - largest.leading_zeros()
+    }
+}
+
+/// The aspects an image of `format` has.
+pub fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// How an [`Image`] is created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageDescription {
+    pub dimensions: ImageDimensions,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub tiling: vk::ImageTiling,
+    pub memory: MemoryUsage,
+    /// Shown by validation messages and graphics debuggers.
+    pub name: Option<Cow<'static, str>>,
+}
+
+impl ImageDescription {
+    /// A device local, optimally tiled image with one mip level and sample, and no usage.
+    pub const fn new(dimensions: ImageDimensions, format: vk::Format) -> Self {
+        Self {
+            dimensions,
+            format,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage: vk::ImageUsageFlags::empty(),
+            tiling: vk::ImageTiling::OPTIMAL,
+            memory: MemoryUsage::GpuOnly,
+            name: None,
+        }
+    }
+    #[must_use]
+    pub fn name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    #[must_use]
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+    #[must_use]
+    pub fn full_mip_chain(self) -> Self {
+        let mip_levels = self.dimensions.max_mip_levels();
+        self.mip_levels(mip_levels)
+    }
+    #[must_use]
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+    #[must_use]
+    pub fn with_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+    #[must_use]
+    pub fn sampled(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::SAMPLED)
+    }
+    #[must_use]
+    pub fn storage(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::STORAGE)
+    }
+    #[must_use]
+    pub fn color_attachment(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+    }
+    #[must_use]
+    pub fn depth_stencil_attachment(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+    }
+    #[must_use]
+    pub fn transfer_src(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+    #[must_use]
+    pub fn transfer_dst(self) -> Self {
+        self.with_usage(vk::ImageUsageFlags::TRANSFER_DST)
+    }
+
+    pub(crate) fn create_info(&self) -> vk::ImageCreateInfo<'static> {
+        vk::ImageCreateInfo::default()
+            .flags(self.dimensions.create_flags())
+            .image_type(self.dimensions.image_type())
+            .format(self.format)
+            .extent(self.dimensions.extent())
+            .mip_levels(self.mip_levels)
+            .array_layers(self.dimensions.array_layers())
+            .samples(self.samples)
+            .tiling(self.tiling)
+            .usage(self.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+    }
+
+    /// Every mip level and layer of every aspect.
+    pub fn full_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: format_aspect(self.format),
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.dimensions.array_layers(),
+        }
+    }
+
+    /// Catches what the format properties do not.
+    pub(crate) fn validate(&self) -> Result<(), ImageError> {
+        if self.mip_levels == 0 || self.dimensions.array_layers() == 0 {
+            return Err(ImageError::InvalidDescription(
+                "images need at least one mip level and layer",
+            ));
+        }
+        if self.mip_levels > self.dimensions.max_mip_levels() {
+            return Err(ImageError::InvalidDescription(
+                "more mip levels than the full chain",
+            ));
+        }
+        if self.samples != vk::SampleCountFlags::TYPE_1
+            && (self.mip_levels != 1
+                || !matches!(self.dimensions, ImageDimensions::D2 { .. })
+                || self.tiling != vk::ImageTiling::OPTIMAL)
+        {
+            return Err(ImageError::InvalidDescription(
+                "multisampled images must be optimally tiled 2D images without mip levels",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A view of part of an [`Image`].
+#[derive(Debug, Clone, Copy)]
+pub struct ImageViewDescription {
+    pub view_type: vk::ImageViewType,
+    pub format: vk::Format,
+    pub subresource_range: vk::ImageSubresourceRange,
+}
+
+impl ImageViewDescription {
+    /// A view of a whole image.
+    pub fn new(view_type: vk::ImageViewType, format: vk::Format) -> Self {
+        Self {
+            view_type,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: format_aspect(format),
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            },
+        }
+    }
+    #[must_use]
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.view_type = view_type;
+        self
+    }
+    /// Reinterprets the image, it has to be created with a compatible format.
+    #[must_use]
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.format = format;
+        self
+    }
+    /// E.g. only the depth of a depth stencil image, for sampling.
+    #[must_use]
+    pub fn aspect(mut self, aspect: vk::ImageAspectFlags) -> Self {
+        self.subresource_range.aspect_mask = aspect;
+        self
+    }
+    #[must_use]
+    pub fn mip_levels(mut self, levels: Range<u32>) -> Self {
+        self.subresource_range.base_mip_level = levels.start;
+        self.subresource_range.level_count = levels.len() as u32;
+        self
+    }
+    #[must_use]
+    pub fn array_layers(mut self, layers: Range<u32>) -> Self {
+        self.subresource_range.base_array_layer = layers.start;
+        self.subresource_range.layer_count = layers.len() as u32;
+        self
+    }
+    pub(crate) fn create_info(&self, image: vk::Image) -> vk::ImageViewCreateInfo<'static> {
+        vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(self.view_type)
+            .format(self.format)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(self.subresource_range)
+    }
+}
+
+/// An image with its memory, a view of the whole image and its tracked layouts.
+pub struct Image {
+    /// Declared first, so it is destroyed before the image.
+    view: Owned<vk::ImageView>,
+    inner: AllocatedImage,
+    description: ImageDescription,
+    /// One layout per subresource, `layer * mip_levels + mip`.
+    layouts: Mutex<Vec<vk::ImageLayout>>,
+}
+
+impl std::fmt::Debug for Image {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Image")
+            .field("raw", &self.inner.raw())
+            .field("view", &*self.view)
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Image {
+    /// # Errors
+    /// If the device does not support the description, or the image cannot be created.
+    pub fn new(
+        allocator: &Arc<Allocator>,
+        description: &ImageDescription,
+    ) -> Result<Arc<Self>, ImageError> {
+        description.validate()?;
+        let dimensions = description.dimensions;
+        let flags = dimensions.create_flags();
+        let limits = allocator
+            .device()
+            .physical_device()
+            .image_format_properties(
+                description.format,
+                dimensions.image_type(),
+                description.tiling,
+                description.usage,
+                flags,
+            )
+            .ok_or(ImageError::FormatNotSupported {
+                format: description.format,
+                usage: description.usage,
+            })?;
+        let extent = dimensions.extent();
+        if description.mip_levels > limits.max_mip_levels
+            || dimensions.array_layers() > limits.max_array_layers
+            || !limits.sample_counts.contains(description.samples)
+            || extent.width > limits.max_extent.width
+            || extent.height > limits.max_extent.height
+            || extent.depth > limits.max_extent.depth
+        {
+            return Err(ImageError::ExceedsFormatLimits {
+                description: Box::new(description.clone()),
+                limits,
+            });
+        }
+
+        let inner = allocator.create_image(&description.create_info(), description.memory)?;
+        let device = allocator.device();
+        let view_info = ImageViewDescription::new(dimensions.view_type(), description.format)
+            .create_info(inner.raw());
+        let view = unsafe {
+            device
+                .raw()
+                .create_image_view(&view_info, device.allocation_callbacks())
+        }?;
+        let subresources = description.mip_levels * dimensions.array_layers();
+        let image = Self {
+            view: own(device, view),
+            inner,
+            description: description.clone(),
+            layouts: Mutex::new(vec![vk::ImageLayout::UNDEFINED; subresources as usize]),
+        };
+        if let Some(name) = &description.name {
+            image.set_debug_name(name);
+        }
+        Ok(Arc::new(image))
+    }
+
+    /// Names the image and its view, see [`Device::set_debug_name`](crate::device::Device::set_debug_name).
+    pub fn set_debug_name(&self, name: &str) {
+        let device = self.allocator().device();
+        device.set_debug_name(self.raw(), name);
+        device.set_debug_name(*self.view, name);
+    }
+
+    pub fn raw(&self) -> vk::Image {
+        self.inner.raw()
+    }
+    /// View of the whole image, with the type from [`ImageDimensions::view_type`].
+    pub fn view(&self) -> vk::ImageView {
+        *self.view
+    }
+    pub fn description(&self) -> &ImageDescription {
+        &self.description
+    }
+    pub fn format(&self) -> vk::Format {
+        self.description.format
+    }
+    pub fn extent(&self) -> vk::Extent3D {
+        self.description.dimensions.extent()
+    }
+    pub fn mip_levels(&self) -> u32 {
+        self.description.mip_levels
+    }
+    pub fn array_layers(&self) -> u32 {
+        self.description.dimensions.array_layers()
+    }
+    pub fn aspect(&self) -> vk::ImageAspectFlags {
+        format_aspect(self.description.format)
+    }
+    pub fn inner(&self) -> &AllocatedImage {
+        &self.inner
+    }
+    pub fn allocator(&self) -> &Arc<Allocator> {
+        self.inner.allocator()
+    }
+    /// Every mip level and layer of every aspect.
+    pub fn full_range(&self) -> vk::ImageSubresourceRange {
+        self.description.full_range()
+    }
+
+    /// The tracked layout of a subresource.
+    /// # Panics
+    /// If the subresource is out of bounds.
+    pub fn layout(&self, mip_level: u32, array_layer: u32) -> vk::ImageLayout {
+        assert!(mip_level < self.mip_levels() && array_layer < self.array_layers());
+        self.layouts.lock().unwrap()[(array_layer * self.mip_levels() + mip_level) as usize]
+    }
+
+    /// Records the barriers that move `range` to `new_layout`. Subresources already in it are
+    /// left alone, the others are waited on based on the layout they were in.
+    pub fn transition(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        range: vk::ImageSubresourceRange,
+        new_layout: vk::ImageLayout,
+    ) {
+        let changes = {
+            let mut layouts = self.layouts.lock().unwrap();
+            layout_changes(&mut layouts, self.mip_levels(), &range, new_layout)
+        };
+        if changes.is_empty() {
+            return;
+        }
+        let (dst_stage, dst_access) = layout_scope(new_layout);
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let barriers = changes
+            .into_iter()
+            .map(|(old_layout, subresource_range)| {
+                let (stage, src_access) = layout_scope(old_layout);
+                src_stage |= stage;
+                vk::ImageMemoryBarrier::default()
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(self.raw())
+                    .subresource_range(subresource_range)
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.allocator().device().raw().cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            )
+        };
+    }
+
+    /// [`Self::transition`] of the whole image.
+    pub fn transition_all(&self, command_buffer: vk::CommandBuffer, new_layout: vk::ImageLayout) {
+        self.transition(command_buffer, self.full_range(), new_layout);
+    }
+
+    /// Updates the tracked layout without recording anything, for layout changes done
+    /// elsewhere, e.g. by a render pass.
+    pub fn assume_layout(&self, range: vk::ImageSubresourceRange, layout: vk::ImageLayout) {
+        let mut layouts = self.layouts.lock().unwrap();
+        layout_changes(&mut layouts, self.mip_levels(), &range, layout);
+    }
+
+    /// # Errors
+    /// If the view cannot be created.
+    pub fn create_view(
+        self: &Arc<Self>,
+        description: &ImageViewDescription,
+    ) -> Result<ImageView, ImageError> {
+        let device = self.allocator().device();
+        let raw = unsafe {
+            device.raw().create_image_view(
+                &description.create_info(self.raw()),
+                device.allocation_callbacks(),
+            )
+        }?;
+        Ok(ImageView {
+            raw: own(device, raw),
+            image: self.clone(),
+            description: *description,
+        })
+    }
+}
+
+/// A view that keeps its [`Image`] alive.
+#[derive(Debug)]
+pub struct ImageView {
+    raw: Owned<vk::ImageView>,
+    image: Arc<Image>,
+    description: ImageViewDescription,
+}
+
+impl ImageView {
+    pub fn raw(&self) -> vk::ImageView {
+        *self.raw
+    }
+    pub fn image(&self) -> &Arc<Image> {
+        &self.image
+    }
+    /// See [`Device::set_debug_name`](crate::device::Device::set_debug_name).
+    pub fn set_debug_name(&self, name: &str) {
+        self.image
+            .allocator()
+            .device()
+            .set_debug_name(*self.raw, name);
+    }
+    pub fn description(&self) -> &ImageViewDescription {
+        &self.description
+    }
+}
+
+/// The stages and accesses that use an image in `layout`.
+/// Shader reads wait on all commands, so the barriers are valid on any queue.
+pub(crate) fn layout_scope(layout: vk::ImageLayout) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    use vk::AccessFlags as A;
+    use vk::ImageLayout as L;
+    use vk::PipelineStageFlags as S;
+    match layout {
+        L::UNDEFINED => (S::TOP_OF_PIPE, A::empty()),
+        L::PREINITIALIZED => (S::HOST, A::HOST_WRITE),
+        L::TRANSFER_SRC_OPTIMAL => (S::TRANSFER, A::TRANSFER_READ),
+        L::TRANSFER_DST_OPTIMAL => (S::TRANSFER, A::TRANSFER_WRITE),
+        L::SHADER_READ_ONLY_OPTIMAL => (S::ALL_COMMANDS, A::SHADER_READ),
+        L::COLOR_ATTACHMENT_OPTIMAL => (
+            S::COLOR_ATTACHMENT_OUTPUT,
+            A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+        ),
+        L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        | L::DEPTH_ATTACHMENT_OPTIMAL
+        | L::STENCIL_ATTACHMENT_OPTIMAL => (
+            S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS,
+            A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        L::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+        | L::DEPTH_READ_ONLY_OPTIMAL
+        | L::STENCIL_READ_ONLY_OPTIMAL => (
+            S::ALL_COMMANDS,
+            A::DEPTH_STENCIL_ATTACHMENT_READ | A::SHADER_READ,
+        ),
+        // Presentation is synchronized with semaphores.
+        L::PRESENT_SRC_KHR => (S::BOTTOM_OF_PIPE, A::empty()),
+        _ => (S::ALL_COMMANDS, A::MEMORY_READ | A::MEMORY_WRITE),
+    }
+}
+
+/// Sets the subresources in `range` to `new_layout` and returns the ones that changed, merged
+/// into ranges with the same old layout.
+fn layout_changes(
+    layouts: &mut [vk::ImageLayout],
+    mip_levels: u32,
+    range: &vk::ImageSubresourceRange,
+    new_layout: vk::ImageLayout,
+) -> Vec<(vk::ImageLayout, vk::ImageSubresourceRange)> {
+    let array_layers = layouts.len() as u32 / mip_levels;
+    // `REMAINING_MIP_LEVELS` and `REMAINING_ARRAY_LAYERS` are both `!0`.
+    let resolve = |base: u32, count: u32, total: u32| {
+        if count == vk::REMAINING_MIP_LEVELS {
+            base..total
+        } else {
+            base..base + count
+        }
+    };
+    let mips = resolve(range.base_mip_level, range.level_count, mip_levels);
+    let layers = resolve(range.base_array_layer, range.layer_count, array_layers);
+    assert!(
+        mips.end <= mip_levels && layers.end <= array_layers,
+        "Subresource range {range:?} is out of bounds"
+    );
+
+    let mut changes: Vec<(vk::ImageLayout, vk::ImageSubresourceRange)> = Vec::new();
+    for layer in layers {
+        let mut mip = mips.start;
+        while mip < mips.end {
+            let index = |mip: u32| (layer * mip_levels + mip) as usize;
+            let old_layout = layouts[index(mip)];
+            let start = mip;
+            while mip < mips.end && layouts[index(mip)] == old_layout {
+                layouts[index(mip)] = new_layout;
+                mip += 1;
+            }
+            if old_layout == new_layout {
+                continue;
+            }
+            // Extend the same mip run of the previous layer if there is one.
+            let previous = changes.iter_mut().find(|(layout, x)| {
+                *layout == old_layout
+                    && x.base_mip_level == start
+                    && x.level_count == mip - start
+                    && x.base_array_layer + x.layer_count == layer
+            });
+            match previous {
+                Some((_, x)) => x.layer_count += 1,
+                None => changes.push((
+                    old_layout,
+                    vk::ImageSubresourceRange {
+                        aspect_mask: range.aspect_mask,
+                        base_mip_level: start,
+                        level_count: mip - start,
+                        base_array_layer: layer,
+                        layer_count: 1,
+                    },
+                )),
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dimensions() {
+        let cube = ImageDimensions::Cube {
+            size: 256,
+            cubes: 2,
+        };
+        assert_eq!(cube.array_layers(), 12);
+        assert_eq!(cube.view_type(), vk::ImageViewType::CUBE_ARRAY);
+        assert_eq!(cube.max_mip_levels(), 9);
+        assert_eq!(cube.create_flags(), vk::ImageCreateFlags::CUBE_COMPATIBLE);
+
+        let volume = ImageDimensions::D3 {
+            width: 4,
+            height: 64,
+            depth: 3,
+        };
+        assert_eq!(volume.image_type(), vk::ImageType::TYPE_3D);
+        assert_eq!(volume.array_layers(), 1);
+        assert_eq!(volume.max_mip_levels(), 7);
+        assert_eq!(ImageDimensions::d2(1, 1).max_mip_levels(), 1);
+        assert_eq!(
+            ImageDimensions::D1 {
+                width: 5,
+                layers: 3
+            }
+            .view_type(),
+            vk::ImageViewType::TYPE_1D_ARRAY
+        );
+
+        assert_eq!(
+            format_aspect(vk::Format::D24_UNORM_S8_UINT),
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        );
+        assert_eq!(
+            format_aspect(vk::Format::R8G8B8A8_SRGB),
+            vk::ImageAspectFlags::COLOR
+        );
+
+        let msaa = ImageDescription::new(ImageDimensions::d2(64, 64), vk::Format::R8G8B8A8_UNORM)
+            .samples(vk::SampleCountFlags::TYPE_4);
+        assert!(msaa.validate().is_ok());
+        assert!(msaa.full_mip_chain().validate().is_err());
+        let too_many = ImageDescription::new(cube, vk::Format::R8G8B8A8_UNORM).mip_levels(10);
+        assert!(too_many.validate().is_err());
+    }
+
+    #[test]
+    fn tracked_layouts() {
+        use vk::ImageLayout as L;
+        // 3 mips, 2 layers.
+        let mut layouts = vec![L::UNDEFINED; 6];
+        let range = |mips: Range<u32>, layers: Range<u32>| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: mips.start,
+            level_count: mips.len() as u32,
+            base_array_layer: layers.start,
+            layer_count: layers.len() as u32,
+        };
+        let all = vk::ImageSubresourceRange {
+            level_count: vk::REMAINING_MIP_LEVELS,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+            ..range(0..0, 0..0)
+        };
+
+        // Old layouts with the mips and layers they cover.
+        let mut change = |range: vk::ImageSubresourceRange, layout: vk::ImageLayout| {
+            layout_changes(&mut layouts, 3, &range, layout)
+                .into_iter()
+                .map(|(old, x)| {
+                    (
+                        old,
+                        x.base_mip_level..x.base_mip_level + x.level_count,
+                        x.base_array_layer..x.base_array_layer + x.layer_count,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The whole image is a single barrier.
+        assert_eq!(
+            change(all, L::TRANSFER_DST_OPTIMAL),
+            [(L::UNDEFINED, 0..3, 0..2)]
+        );
+
+        // Mip 0 of both layers, like a blit source when generating mips.
+        assert_eq!(
+            change(range(0..1, 0..2), L::TRANSFER_SRC_OPTIMAL),
+            [(L::TRANSFER_DST_OPTIMAL, 0..1, 0..2)]
+        );
+
+        // Mixed old layouts split up, subresources already in the layout are skipped.
+        change(range(2..3, 1..2), L::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(
+            change(all, L::SHADER_READ_ONLY_OPTIMAL),
+            [
+                (L::TRANSFER_SRC_OPTIMAL, 0..1, 0..2),
+                (L::TRANSFER_DST_OPTIMAL, 1..3, 0..1),
+                (L::TRANSFER_DST_OPTIMAL, 1..2, 1..2),
+            ]
+        );
+        assert!(change(all, L::SHADER_READ_ONLY_OPTIMAL).is_empty());
+        assert!(layouts.iter().all(|x| *x == L::SHADER_READ_ONLY_OPTIMAL));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn create_from_instance() {
+        let (device, queue) = crate::test_support::device(
+            "image test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::TRANSFER,
+        );
+        let allocator = Allocator::new(&device, Default::default());
+
+        let cube = Image::new(
+            &allocator,
+            &ImageDescription::new(
+                ImageDimensions::Cube { size: 64, cubes: 1 },
+                vk::Format::R8G8B8A8_UNORM,
+            )
+            .full_mip_chain()
+            .sampled()
+            .transfer_dst(),
+        )
+        .unwrap();
+        assert_eq!(cube.mip_levels(), 7);
+        assert_eq!(cube.array_layers(), 6);
+        let face = cube
+            .create_view(
+                &ImageViewDescription::new(vk::ImageViewType::TYPE_2D, cube.format())
+                    .array_layers(2..3)
+                    .mip_levels(0..1),
+            )
+            .unwrap();
+        assert_eq!(face.image().raw(), cube.raw());
+
+        let pool = crate::command::CommandPool::new(&device, queue.family_index()).unwrap();
+        let mut command_buffer = pool.primary().unwrap();
+        command_buffer.transition_all(&cube, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        command_buffer.submit(&queue).unwrap().wait().unwrap();
+        assert_eq!(cube.layout(6, 5), vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+
+        let depth = Image::new(
+            &allocator,
+            &ImageDescription::new(ImageDimensions::d2(32, 32), vk::Format::D32_SFLOAT)
+                .depth_stencil_attachment(),
+        )
+        .unwrap();
+        assert_eq!(depth.aspect(), vk::ImageAspectFlags::DEPTH);
+
+        assert!(matches!(
+            Image::new(
+                &allocator,
+                &ImageDescription::new(ImageDimensions::d2(32, 32), vk::Format::D32_SFLOAT)
+                    .storage()
+                    .samples(vk::SampleCountFlags::TYPE_64),
+            ),
+            Err(ImageError::FormatNotSupported { .. } | ImageError::ExceedsFormatLimits { .. })
+        ));
+    }
+}
@@ -1,33 +1,44 @@
+use super::debug::{DebugCallBackData, DebugCapture, DebugMessengerConfig, MessageId};
 use super::error;
+use super::handle::{own, Owned};
+use super::host_memory::HostAllocationTracker;
 use super::raw;
 use super::types::{ExtensionName, ExtensionProperties, Layer};
 use std::sync::Arc;
 use std::{ffi::CStr, ops::Deref};
 
-#[derive(derive_more::Deref)]
+/// A window surface, keeps its instance alive.
 pub struct Surface {
-    #[deref]
-    pub raw: ash::vk::SurfaceKHR,
+    raw: Owned<ash::vk::SurfaceKHR, Instance>,
     pub surface_loader: ash::khr::surface::Instance,
 }
 
-// TODO: figure out a better Debug impl
 impl std::fmt::Debug for Surface {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Surface").field("raw", &self.raw).finish()
+        f.debug_struct("Surface").field("raw", &*self.raw).finish()
     }
 }
+
+impl Surface {
+    pub fn raw(&self) -> ash::vk::SurfaceKHR {
+        *self.raw
+    }
+}
+
 #[cfg(not(build_type = "dist"))]
 pub struct DebugInstance {
     pub(crate) _debug_utils: ash::ext::debug_utils::Instance,
     pub(crate) _debug_messenger: ash::vk::DebugUtilsMessengerEXT,
-    pub(crate) _callback_data: Box<DebugCallBackData>,
-    allocation_callbacks: Option<std::rc::Rc<ash::vk::AllocationCallbacks<'static>>>,
+    pub(crate) callback_data: Arc<DebugCallBackData>,
+    allocation_callbacks: Option<ash::vk::AllocationCallbacks<'static>>,
 }
 pub struct Instance {
     pub(super) raw: ash::Instance,
-    pub allocation_callbacks: Option<std::rc::Rc<ash::vk::AllocationCallbacks<'static>>>,
+    allocation_callbacks: Option<ash::vk::AllocationCallbacks<'static>>,
+    /// Outlives `raw`, which is destroyed in `drop`, since the callbacks point into it.
+    host_allocations: Option<Arc<HostAllocationTracker>>,
     pub app_name: std::ffi::CString,
+    report: InstanceReport,
     #[cfg(not(build_type = "dist"))]
     pub(crate) debug_messenger_instance: Option<DebugInstance>,
 
@@ -51,7 +62,7 @@ impl Drop for DebugInstance {
         unsafe {
             self._debug_utils.destroy_debug_utils_messenger(
                 self._debug_messenger,
-                self.allocation_callbacks.as_deref(),
+                self.allocation_callbacks.as_ref(),
             );
         };
     }
@@ -60,88 +71,267 @@ impl Drop for Instance {
     fn drop(&mut self) {
         tracing::debug!("Dropping instance");
 
+        // The messenger has to be destroyed before its instance.
+        #[cfg(not(build_type = "dist"))]
+        drop(self.debug_messenger_instance.take());
         unsafe {
             self.raw
-                .destroy_instance(self.allocation_callbacks.as_deref());
+                .destroy_instance(self.allocation_callbacks.as_ref());
         };
-    }
-}
-
-impl infrastructure::ResourceDeleter<Surface> for Instance {
-    fn delete(&mut self, resource: &mut Surface) {
-        unsafe {
-            resource
-                .surface_loader
-                .destroy_surface(resource.raw, self.allocation_callbacks.as_deref())
+        if let Some(tracker) = &self.host_allocations {
+            tracker.report_leaks();
         }
     }
 }
 
-pub struct DebugCallBackData {}
-
-impl Instance {}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InstanceCreateInfo {
     // TODO: change to &str?
     pub application_name: String,
-    pub enabled_extensions: Vec<ExtensionName>,
+    /// Instance creation fails if any of these is unavailable.
+    pub required_extensions: Vec<ExtensionName>,
+    /// Enabled when available.
+    pub optional_extensions: Vec<ExtensionName>,
+    /// Instance creation fails if any of these is unavailable.
+    pub required_layers: Vec<ExtensionName>,
+    /// Enabled when available.
+    pub optional_layers: Vec<ExtensionName>,
     pub flags: ash::vk::InstanceCreateFlags,
-    pub enabled_layers: Vec<ExtensionName>,
+    /// Used if `VK_EXT_debug_utils` gets enabled.
+    pub debug_messenger: DebugMessengerConfig,
+    /// Allocate host memory through a [`HostAllocationTracker`].
+    pub track_host_allocations: bool,
 }
 
-// TODO: create multiple default impls for different use cases
-#[allow(dead_code, unreachable_code, unused_variables)]
 impl InstanceCreateInfo {
-    fn empty() -> Self {
-        let flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
-            ash::vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    /// Nothing enabled besides what the platform needs to enumerate devices.
+    pub fn new(application_name: impl Into<String>) -> Self {
+        let (flags, required_extensions) = if cfg!(any(target_os = "macos", target_os = "ios")) {
+            (
+                ash::vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR,
+                vec![
+                    ash::khr::portability_enumeration::NAME.into(),
+                    // Enabling this extension is a requirement when using `VK_KHR_portability_subset`
+                    ash::khr::get_physical_device_properties2::NAME.into(),
+                ],
+            )
         } else {
-            ash::vk::InstanceCreateFlags::default()
+            (ash::vk::InstanceCreateFlags::default(), vec![])
         };
         Self {
-            application_name: "Example".to_string(),
-            enabled_extensions: vec![],
-            enabled_layers: vec![],
+            application_name: application_name.into(),
+            required_extensions,
+            optional_extensions: vec![],
+            required_layers: vec![],
+            optional_layers: vec![],
             flags,
+            debug_messenger: DebugMessengerConfig::default(),
+            track_host_allocations: false,
         }
     }
-    fn with_app_name(app_name: String) -> Self {
-        Self {
-            application_name: app_name,
-            ..Self::empty()
-        }
+
+    /// For rendering to a window: the surface extensions of `display` are required.
+    /// Debug utils and validation are enabled when available outside of `dist` builds.
+    pub fn graphics_usage(
+        application_name: impl Into<String>,
+        display: impl raw_window_handle::HasDisplayHandle,
+    ) -> Result<Self, error::InitError> {
+        let surface_extensions = Instance::get_surface_required_extensions(display)?;
+        Ok(Self::new(application_name)
+            .with_debug_tooling()
+            .require_extensions(surface_extensions))
     }
 
-    fn graphics_usage(app_name: String) -> Self {
-        todo!();
-        Self {
-            application_name: app_name,
-            ..Self::empty()
-        }
+    /// For headless work: no surface extensions.
+    /// Debug utils and validation are enabled when available outside of `dist` builds.
+    pub fn compute_usage(application_name: impl Into<String>) -> Self {
+        Self::new(application_name).with_debug_tooling()
     }
-    fn compute_usage(app_name: String) -> Self {
-        todo!();
-        Self {
-            application_name: app_name,
-            ..Self::empty()
+
+    /// For applications that present when they can, but also run headless:
+    /// the surface extensions of `display` are only enabled when available.
+    /// Debug utils and validation are enabled when available outside of `dist` builds.
+    pub fn mixed_usage(
+        application_name: impl Into<String>,
+        display: Option<impl raw_window_handle::HasDisplayHandle>,
+    ) -> Result<Self, error::InitError> {
+        let surface_extensions = display
+            .map(Instance::get_surface_required_extensions)
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self::compute_usage(application_name).request_extensions(surface_extensions))
+    }
+
+    /// Requests `VK_EXT_debug_utils` and the Khronos validation layer, unless this is a `dist`
+    /// build.
+    pub fn with_debug_tooling(self) -> Self {
+        if cfg!(build_type = "dist") {
+            self
+        } else {
+            self.request_extension(ash::ext::debug_utils::NAME.into())
+                .request_layer(Layer::VALIDATIONLAYER)
         }
     }
-    fn mixed_usage(app_name: String) -> Self {
-        todo!();
-        Self {
-            application_name: app_name,
-            ..Self::empty()
+
+    pub fn require_extension(mut self, extension: ExtensionName) -> Self {
+        push_unique(&mut self.required_extensions, extension);
+        self
+    }
+    pub fn require_extensions(self, extensions: impl IntoIterator<Item = ExtensionName>) -> Self {
+        extensions
+            .into_iter()
+            .fold(self, |acc, ext| acc.require_extension(ext))
+    }
+    pub fn request_extension(mut self, extension: ExtensionName) -> Self {
+        push_unique(&mut self.optional_extensions, extension);
+        self
+    }
+    pub fn request_extensions(self, extensions: impl IntoIterator<Item = ExtensionName>) -> Self {
+        extensions
+            .into_iter()
+            .fold(self, |acc, ext| acc.request_extension(ext))
+    }
+    pub fn require_layer(mut self, layer: ExtensionName) -> Self {
+        push_unique(&mut self.required_layers, layer);
+        self
+    }
+    pub fn request_layer(mut self, layer: ExtensionName) -> Self {
+        push_unique(&mut self.optional_layers, layer);
+        self
+    }
+    pub fn with_debug_messenger(mut self, config: DebugMessengerConfig) -> Self {
+        self.debug_messenger = config;
+        self
+    }
+    /// See [`Instance::host_allocations`].
+    pub fn with_host_allocation_tracking(mut self) -> Self {
+        self.track_host_allocations = true;
+        self
+    }
+    pub fn with_flags(mut self, flags: ash::vk::InstanceCreateFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
+
+    /// Checks the requested layers and extensions against what the Vulkan implementation offers.
+    /// Extensions provided by an enabled layer count as available.
+    /// # Errors
+    /// Returns which required layers or extensions are missing.
+    pub fn resolve(&self, entry: &ash::Entry) -> Result<InstanceReport, error::InitError> {
+        let available_layers = unsafe { entry.enumerate_instance_layer_properties() }
+            .map_err(Into::<error::VkError>::into)?
+            .into_iter()
+            .map(|x| Layer::from(x).name)
+            .collect::<Vec<_>>();
+        let (layers, missing_optional_layers) = split_available(
+            &self.required_layers,
+            &self.optional_layers,
+            &available_layers,
+        )
+        .map_err(error::InitError::RequiredLayersMissing)?;
+
+        let mut available_extensions = enumerate_instance_extensions(entry, None)?;
+        for layer in &layers {
+            available_extensions.extend(enumerate_instance_extensions(entry, Some(layer))?);
+        }
+        let (extensions, missing_optional_extensions) = split_available(
+            &self.required_extensions,
+            &self.optional_extensions,
+            &available_extensions,
+        )
+        .map_err(error::InitError::RequiredExtensionsMissing)?;
+
+        for layer in &missing_optional_layers {
+            tracing::warn!("Optional layer {:?} is not available", layer);
         }
+        for extension in &missing_optional_extensions {
+            tracing::warn!(
+                "Optional instance extension {:?} is not available",
+                extension
+            );
+        }
+        Ok(InstanceReport {
+            extensions,
+            layers,
+            missing_optional_extensions,
+            missing_optional_layers,
+        })
     }
 }
 
 impl Default for InstanceCreateInfo {
     fn default() -> Self {
-        Self::empty()
+        Self::new("Example")
+    }
+}
+
+/// What got enabled on an [`Instance`], see [`InstanceCreateInfo::resolve`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstanceReport {
+    pub extensions: Vec<ExtensionName>,
+    pub layers: Vec<ExtensionName>,
+    /// Requested but unavailable, these were left out.
+    pub missing_optional_extensions: Vec<ExtensionName>,
+    /// Requested but unavailable, these were left out.
+    pub missing_optional_layers: Vec<ExtensionName>,
+}
+
+impl InstanceReport {
+    pub fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions.iter().any(|ext| **ext == *name)
+    }
+    pub fn has_layer(&self, name: &CStr) -> bool {
+        self.layers.iter().any(|layer| **layer == *name)
     }
 }
 
+fn push_unique(list: &mut Vec<ExtensionName>, name: ExtensionName) {
+    if !list.contains(&name) {
+        list.push(name);
+    }
+}
+
+/// Returns the enabled names and the unavailable optional names.
+/// # Errors
+/// Returns the unavailable required names.
+fn split_available(
+    required: &[ExtensionName],
+    optional: &[ExtensionName],
+    available: &[ExtensionName],
+) -> Result<(Vec<ExtensionName>, Vec<ExtensionName>), Vec<ExtensionName>> {
+    let missing_required = required
+        .iter()
+        .filter(|x| !available.contains(x))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing_required.is_empty() {
+        return Err(missing_required);
+    }
+    let mut enabled = required.to_vec();
+    let mut missing_optional = vec![];
+    for name in optional {
+        if available.contains(name) {
+            push_unique(&mut enabled, name.clone());
+        } else {
+            missing_optional.push(name.clone());
+        }
+    }
+    Ok((enabled, missing_optional))
+}
+
+fn enumerate_instance_extensions(
+    entry: &ash::Entry,
+    layer_name: Option<&ExtensionName>,
+) -> Result<Vec<ExtensionName>, error::InitError> {
+    let res =
+        unsafe { entry.enumerate_instance_extension_properties(layer_name.map(|ext| ext.deref())) }
+            .map_err(Into::<error::VkError>::into)?
+            .into_iter()
+            .map(|x| ExtensionProperties::from(x).name)
+            .collect();
+    Ok(res)
+}
+
 impl Instance {
     pub fn new_linked(info: InstanceCreateInfo) -> Result<Arc<Self>, error::InitError> {
         tracing::debug!("Creating VulkanLibrary");
@@ -157,7 +347,7 @@ impl Instance {
     pub fn create_surface<T>(
         self: &Arc<Instance>,
         handle: &T,
-    ) -> Result<std::rc::Rc<Surface>, error::InitError>
+    ) -> Result<Arc<Surface>, error::InitError>
     where
         T: raw_window_handle::HasDisplayHandle + raw_window_handle::HasWindowHandle,
     {
@@ -171,13 +361,13 @@ impl Instance {
                 &self.raw,
                 *display_handle.as_ref(),
                 *window_handle.as_ref(),
-                self.allocation_callbacks.as_deref(),
+                self.allocation_callbacks.as_ref(),
             )
             .map_err(Into::<error::VkError>::into)?
         };
-        Ok(std::rc::Rc::new(Surface {
-            raw: surface,
-            surface_loader: ash::khr::surface::Instance::new(&self.entry, &self.raw),
+        Ok(Arc::new(Surface {
+            raw: own(self, surface),
+            surface_loader: self.surface_loader(),
         }))
     }
 
@@ -196,47 +386,10 @@ impl Instance {
             .collect::<Vec<_>>())
     }
 
-    // TODO: change this to validate the create info, rather than set a baseline
-    // fn collect_instance_info(
-    //     entry: &ash::Entry,
-    //     required_extensions: Vec<ExtensionName>,
-    // ) -> Result<InstanceCreateInfo, error::Error> {
-    //     tracing::debug!("Collecting Vulkan instance info for ");
-    //     let required_extensions = {
-    //         let mut res = required_extensions;
-    //         if cfg!(any(target_os = "macos", target_os = "ios")) {
-    //             res.push(ash::vk::KHR_PORTABILITY_ENUMERATION_NAME.into());
-    //             // Enabling this extension is a requirement when using `VK_KHR_portability_subset`
-    //             res.push(ash::vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_NAME.into());
-    //         }
-    //         res
-    //     };
-    //     tracing::trace!("Required extensions {required_extensions:?}");
-    //     let optional_extensions = vec![ash::ext::debug_utils::NAME]
-    //         .into_iter()
-    //         .map(|x| x.into())
-    //         .collect();
-    //     let optional_extensions =
-    //         Instance::check_if_extensions_are_supported(&required_extensions, optional_extensions)?;
-    //     let enabled_extensions = required_extensions
-    //         .into_iter()
-    //         .chain(optional_extensions)
-    //         .collect::<Vec<_>>();
-    //     tracing::debug!("Optional extensions {:?}", enabled_extensions);
-    //     let layers = if cfg!(not(build_type = "dist")) {
-    //         vec![Layer::VALIDATIONLAYER]
-    //     } else {
-    //         vec![]
-    //     };
-    //     let validation_layers = self.filter_available_validation_layers(layers)?;
-    //     tracing::debug!("Validation layers {:?}", validation_layers);
-    //     let info = InstanceCreateInfo {
-    //         enabled_layers: validation_layers,
-    //         enabled_extensions,
-    //         ..Default::default()
-    //     };
-    //     Ok(info)
-    // }
+    pub(crate) fn surface_loader(&self) -> ash::khr::surface::Instance {
+        ash::khr::surface::Instance::new(&self.entry, &self.raw)
+    }
+
     pub(crate) fn get_physical_device_properties(
         &self,
         device: &ash::vk::PhysicalDevice,
@@ -271,9 +424,10 @@ impl Instance {
     }
 
     fn create_allocation_call_back(
-        _entry: &ash::Entry,
+        tracker: Option<&Arc<HostAllocationTracker>>,
     ) -> Option<ash::vk::AllocationCallbacks<'static>> {
-        None
+        // The instance keeps the tracker until it is destroyed, and every object outlives it.
+        tracker.map(|tracker| unsafe { tracker.callbacks() })
     }
 
     fn create_instance(
@@ -289,64 +443,121 @@ impl Instance {
             .engine_version(super::constants::ENGINE_VERSION)
             .engine_name(super::constants::ENGINE_NAME);
 
-        let enabled_extensions = info
-            .enabled_extensions
+        let report = info.resolve(&entry)?;
+        tracing::debug!("Enabled instance extensions {:?}", report.extensions);
+        tracing::debug!("Enabled instance layers {:?}", report.layers);
+
+        let enabled_extensions = report
+            .extensions
             .iter()
             .map(|ext| ext.to_str().as_ptr())
             .collect::<Vec<_>>();
 
-        let enabled_layer_names = info
-            .enabled_layers
+        let enabled_layer_names = report
+            .layers
             .iter()
             .map(|ext| ext.to_str().as_ptr())
             .collect::<Vec<_>>();
 
-        let allocation_call_back =
-            Instance::create_allocation_call_back(&entry).map(std::rc::Rc::new);
+        let host_allocations = info
+            .track_host_allocations
+            .then(|| Arc::new(HostAllocationTracker::new()));
+        let allocation_call_back = Instance::create_allocation_call_back(host_allocations.as_ref());
 
-        let mut debug_messenger_data = Box::new(DebugCallBackData {});
         let create_info = ash::vk::InstanceCreateInfo::default()
             .enabled_extension_names(&enabled_extensions)
             .enabled_layer_names(&enabled_layer_names)
             .flags(info.flags)
             .application_info(&app_info);
 
-        // TODO: check if this is correct?
         let instance = unsafe {
             entry
-                .create_instance(&create_info, allocation_call_back.as_deref())
+                .create_instance(&create_info, allocation_call_back.as_ref())
                 .map_err(Into::<error::VkError>::into)?
         };
 
+        #[allow(unused_mut)]
+        let mut res = Instance {
+            raw: instance,
+            allocation_callbacks: allocation_call_back,
+            host_allocations,
+            app_name,
+            report,
+            #[cfg(not(build_type = "dist"))]
+            debug_messenger_instance: None,
+            entry,
+        };
+
         #[cfg(not(build_type = "dist"))]
-        let debug_messenger_instance = {
-            let debug_creation_info = Self::messenger_create_info(&mut debug_messenger_data);
-            let debug_utils_loader = ash::ext::debug_utils::Instance::new(&entry, &instance);
+        if res.report.has_extension(ash::ext::debug_utils::NAME) {
+            let callback_data = Arc::new(DebugCallBackData::new(&info.debug_messenger));
+            let debug_creation_info =
+                super::debug::messenger_create_info(&info.debug_messenger, &callback_data);
+            let debug_utils_loader = ash::ext::debug_utils::Instance::new(&res.entry, &res.raw);
             let debug_call_back = unsafe {
-                debug_utils_loader
-                    .create_debug_utils_messenger(
-                        &debug_creation_info,
-                        allocation_call_back.as_deref(),
-                    )
-                    .unwrap()
+                debug_utils_loader.create_debug_utils_messenger(
+                    &debug_creation_info,
+                    res.allocation_callbacks.as_ref(),
+                )?
             };
-            DebugInstance {
+            res.debug_messenger_instance = Some(DebugInstance {
                 _debug_utils: debug_utils_loader,
                 _debug_messenger: debug_call_back,
-                _callback_data: debug_messenger_data,
-                allocation_callbacks: allocation_call_back.clone(),
-            }
-        };
+                callback_data,
+                allocation_callbacks: res.allocation_callbacks,
+            });
+        }
 
-        Ok(Arc::new(Instance {
-            raw: instance,
-            allocation_callbacks: allocation_call_back,
-            app_name,
-            #[cfg(not(build_type = "dist"))]
-            debug_messenger_instance: Some(debug_messenger_instance),
-            entry,
-        }))
+        Ok(Arc::new(res))
+    }
+
+    /// The callbacks every object of this instance is created and destroyed with.
+    pub fn allocation_callbacks(&self) -> Option<&ash::vk::AllocationCallbacks<'static>> {
+        self.allocation_callbacks.as_ref()
+    }
+
+    /// What got enabled when this instance was created.
+    pub fn report(&self) -> &InstanceReport {
+        &self.report
+    }
+
+    /// Host memory the Vulkan implementation allocated through this instance, if it was created
+    /// with [`InstanceCreateInfo::with_host_allocation_tracking`].
+    pub fn host_allocations(&self) -> Option<&Arc<HostAllocationTracker>> {
+        self.host_allocations.as_ref()
+    }
+
+    fn debug_callback_data(&self) -> Option<&Arc<DebugCallBackData>> {
+        #[cfg(not(build_type = "dist"))]
+        {
+            self.debug_messenger_instance
+                .as_ref()
+                .map(|x| &x.callback_data)
+        }
+        #[cfg(build_type = "dist")]
+        {
+            None
+        }
     }
+
+    /// Starts recording debug messages, e.g. to assert that no validation errors happened in
+    /// a test.
+    pub fn capture_debug_messages(&self) -> DebugCapture {
+        DebugCapture::new(self.debug_callback_data().cloned())
+    }
+
+    /// Stops logging and capturing messages with this id.
+    pub fn suppress_debug_message(&self, id: impl Into<MessageId>) {
+        if let Some(data) = self.debug_callback_data() {
+            data.suppress(id);
+        }
+    }
+    pub fn unsuppress_debug_message(&self, id: &MessageId) {
+        if let Some(data) = self.debug_callback_data() {
+            data.unsuppress(id);
+        }
+    }
+
     /// [`layer_name`] The layer to retrieve extensions from
     pub fn enumerate_instance_extension_properties(
         self: &Arc<Self>,
@@ -364,168 +575,59 @@ impl Instance {
         Ok(res)
     }
 
-    /// Ensures that all required and optional extensions are supported by the vulkan implementation. Throws an error if any of the required extensions are not supported.
-    /// Returns a list of supported optional extensions.
-    /// # Error
-    /// Returns which required extensions are not supported.
-    pub(crate) fn check_if_extensions_are_supported(
-        self: &Arc<Self>,
-        required_extensions: &[ExtensionName],
-        optional_extensions: Vec<ExtensionName>,
-    ) -> Result<Vec<ExtensionName>, error::InitError> {
-        let available_extensions = self
-            // TODO: what is this none?
-            .enumerate_instance_extension_properties(None)?;
-        let all_required_met = required_extensions
-            .iter()
-            .all(|ext| available_extensions.iter().any(|av| &av.name == ext));
-
-        if all_required_met {
-            Ok(optional_extensions
-                .into_iter()
-                .filter(|ext| available_extensions.iter().any(|av| &av.name == ext))
-                .collect::<Vec<_>>())
-        } else {
-            // Collect all the missing extensions
-            Err(error::InitError::RequiredExtensionsMissing(
-                required_extensions
-                    .iter()
-                    .filter(|&ext| !available_extensions.iter().any(|av| &av.name == ext))
-                    .cloned()
-                    .collect::<Vec<_>>(),
-            ))
-        }
-    }
-
     fn enumerate_instance_layer_properties(&self) -> Result<Vec<Layer>, error::InitError> {
         let r = unsafe { self.entry.enumerate_instance_layer_properties() }
             .map_err(Into::<error::VkError>::into)
             .map(|v| v.into_iter().map(Into::<Layer>::into).collect::<Vec<_>>())?;
         Ok(r)
     }
-    pub(crate) fn filter_available_validation_layers(
-        &self,
-        validation_layers: Vec<ExtensionName>,
-    ) -> Result<Vec<ExtensionName>, error::InitError> {
-        let layers = self.enumerate_instance_layer_properties()?;
-        let result = validation_layers
-            .into_iter()
-            .filter(|layer| {
-                let x = layers.iter().any(|layer2| layer2.name == *layer);
-                if !x {
-                    tracing::warn!("Validation layer {:?} is not available", layer);
-                }
-                x
-            })
-            .collect();
-        Ok(result)
-    }
-    fn message_severity() -> ash::vk::DebugUtilsMessageSeverityFlagsEXT {
-        let val = {
-            #[cfg(build_type = "debug")]
-            {
-                ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                    | ash::vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-            }
-            #[cfg(not(build_type = "debug"))]
-            {
-                ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-            }
-        };
-        tracing::info!("Vulkan message callback severity set to: {:?}", val);
-        val
-    }
-    fn message_type() -> ash::vk::DebugUtilsMessageTypeFlagsEXT {
-        let val = {
-            #[cfg(build_type = "dist")]
-            {
-                ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-            }
-            #[cfg(build_type = "debug")]
-            {
-                ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                    | ash::vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-            }
-            #[cfg(build_type = "release")]
-            {
-                ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                    | ash::vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-            }
-        };
-        tracing::info!("Vulkan message callback types set to: {:?}", val);
-        val
-    }
-
-    fn messenger_create_info(
-        callback_data: &mut Box<DebugCallBackData>,
-    ) -> ash::vk::DebugUtilsMessengerCreateInfoEXT {
-        ash::vk::DebugUtilsMessengerCreateInfoEXT::default()
-            .message_severity(Instance::message_severity())
-            .message_type(Instance::message_type())
-            .pfn_user_callback(Some(vulkan_debug_callback))
-            .user_data(
-                (callback_data.as_mut()) as *mut DebugCallBackData as *mut std::os::raw::c_void,
-            )
-    }
 }
-/// A lambda function you can pass to vulkan.
-unsafe extern "system" fn vulkan_debug_callback(
-    message_severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
-    _message_type: ash::vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const ash::vk::DebugUtilsMessengerCallbackDataEXT,
-    user_data: *mut std::os::raw::c_void,
-) -> ash::vk::Bool32 {
-    use std::ffi::CStr;
-    let _debug_callback_data: &mut DebugCallBackData =
-        unsafe { *(user_data as *mut &mut DebugCallBackData) };
-
-    let callback_data = *p_callback_data;
-    // let message_id_number = callback_data.message_id_number;
-
-    let _message_id_name = if callback_data.p_message_id_name.is_null() {
-        std::borrow::Cow::from("")
-    } else {
-        CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
-    };
-
-    let message = if callback_data.p_message.is_null() {
-        std::borrow::Cow::from("")
-    } else {
-        CStr::from_ptr(callback_data.p_message).to_string_lossy()
-    };
-    let _objects = if callback_data.p_objects.is_null() {
-        None
-    } else {
-        let objects = std::slice::from_raw_parts(
-            callback_data.p_objects,
-            callback_data.object_count as usize,
-        );
-        Some(objects)
-    };
-    let msg = format!("{}", message);
-    // let msg = format!("{}: [{}] ({:?})", message_id_name, message, objects);
-    match message_severity {
-        ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
-            tracing::error!(msg)
-        }
-        ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
-            tracing::warn!(msg)
-        }
-        ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
-            tracing::info!(msg)
-        }
-        ash::vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => {
-            tracing::debug!(msg)
-        }
-        _ => {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(names: &[&CStr]) -> Vec<ExtensionName> {
+        names.iter().map(|x| ExtensionName::from_cstr(x)).collect()
+    }
+
+    #[test]
+    fn split_available_extensions() {
+        let available = names(&[c"VK_KHR_surface", c"VK_EXT_debug_utils"]);
+        let (enabled, missing) = split_available(
+            &names(&[c"VK_KHR_surface"]),
+            &names(&[c"VK_EXT_debug_utils", c"VK_KHR_surface", c"VK_FOO_bar"]),
+            &available,
+        )
+        .unwrap();
+        assert_eq!(enabled, available);
+        assert_eq!(missing, names(&[c"VK_FOO_bar"]));
+
+        let err = split_available(
+            &names(&[c"VK_KHR_surface", c"VK_KHR_xcb_surface"]),
+            &[],
+            &available,
+        )
+        .unwrap_err();
+        assert_eq!(err, names(&[c"VK_KHR_xcb_surface"]));
     }
 
-    ash::vk::FALSE
+    #[test]
+    fn presets() {
+        let info = InstanceCreateInfo::compute_usage("Test")
+            .require_extension(ash::khr::surface::NAME.into())
+            .require_extension(ash::khr::surface::NAME.into());
+        assert_eq!(info.application_name, "Test");
+        assert!(info
+            .required_extensions
+            .ends_with(&names(&[ash::khr::surface::NAME])));
+        if cfg!(not(build_type = "dist")) {
+            assert_eq!(
+                info.optional_extensions,
+                names(&[ash::ext::debug_utils::NAME])
+            );
+            assert_eq!(info.optional_layers, vec![Layer::VALIDATIONLAYER]);
+        }
+        assert!(info.required_layers.is_empty());
+    }
 }
@@ -1,16 +1,38 @@
-#![feature(negative_impls)]
 // Start of code
 #![deny(clippy::correctness, clippy::complexity, clippy::all)]
 #![warn(clippy::perf, clippy::suspicious, clippy::style)]
 #![allow(dead_code)]
 pub mod constants;
 
+pub mod bindless;
+pub mod buffer;
+pub mod command;
+pub mod debug;
+pub mod descriptor;
 pub mod device;
 pub mod error;
+pub mod features;
+pub mod graph;
+pub mod handle;
+pub mod host_memory;
+pub mod image;
 pub mod instance;
 pub mod memory;
+pub mod offscreen;
+pub mod pipeline;
+pub mod profiling;
+mod queue_solver;
 pub mod raw;
+pub mod readback;
+pub mod sampler;
+pub mod selection;
+pub mod shader;
+pub mod swapchain;
+pub mod sync;
+#[cfg(test)]
+mod test_support;
 pub mod types;
 
 pub use device::*;
 pub use instance::*;
+pub use swapchain::*;
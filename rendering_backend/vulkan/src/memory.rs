@@ -1,2 +1,1048 @@
+//! Device memory.
+//!
+//! The [`Allocator`] sub-allocates buffers and images from large blocks, one pool of blocks per
+//! memory type. Large resources, and those the driver requires or prefers to, get dedicated
+//! allocations instead. Host visible blocks are mapped once for their whole lifetime.
+//!
+//! Every allocation is owned by an RAII handle ([`Allocation`], [`AllocatedBuffer`],
+//! [`AllocatedImage`]) that gives its memory back when dropped. The handles keep the allocator,
+//! and through it the device, alive.
+use super::device::Device;
+use super::error::MemoryError;
+use super::handle::{own, Owned};
+use super::types::Pod;
+use ash::vk;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
 
+/// What the memory is used for, this decides the memory type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryUsage {
+    /// Only accessed by the device. Device local if possible.
+    GpuOnly,
+    /// Written once by the host, then copied to [`MemoryUsage::GpuOnly`] memory. Staging
+    /// buffers.
+    Upload,
+    /// Written by the host often and read by the device directly, e.g. uniform buffers.
+    /// Prefers memory that is both host visible and device local.
+    Dynamic,
+    /// Written by the device and read back by the host.
+    Readback,
+}
 
+impl MemoryUsage {
+    fn required_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly => vk::MemoryPropertyFlags::empty(),
+            Self::Upload | Self::Dynamic => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            }
+            Self::Readback => vk::MemoryPropertyFlags::HOST_VISIBLE,
+        }
+    }
+    fn preferred_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly | Self::Dynamic => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            Self::Upload => vk::MemoryPropertyFlags::empty(),
+            Self::Readback => vk::MemoryPropertyFlags::HOST_CACHED,
+        }
+    }
+    /// Staging memory should not take up the usually small device local and host visible heap.
+    fn avoided_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly => vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            Self::Upload | Self::Readback => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            Self::Dynamic => vk::MemoryPropertyFlags::empty(),
+        }
+    }
+    pub fn is_host_visible(self) -> bool {
+        self != Self::GpuOnly
+    }
+}
+
+/// The memory type allowed by `type_bits` that fits `usage` best: it has all the required
+/// properties, the most preferred and the fewest avoided ones. Protected memory is never used.
+pub fn select_memory_type(
+    properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    usage: MemoryUsage,
+) -> Option<u32> {
+    let required = usage.required_flags();
+    properties
+        .memory_types_as_slice()
+        .iter()
+        .enumerate()
+        .filter(|(index, memory_type)| {
+            type_bits & (1 << index) != 0
+                && memory_type.property_flags.contains(required)
+                && !memory_type
+                    .property_flags
+                    .contains(vk::MemoryPropertyFlags::PROTECTED)
+        })
+        // `max_by_key` returns the last maximum, reversing keeps the lowest index on ties.
+        .rev()
+        .max_by_key(|(_, memory_type)| {
+            let flags = memory_type.property_flags;
+            let preferred = (flags & usage.preferred_flags()).as_raw().count_ones();
+            let avoided = (flags & usage.avoided_flags()).as_raw().count_ones();
+            (preferred, std::cmp::Reverse(avoided))
+        })
+        .map(|(index, _)| index as u32)
+}
+
+/// What is bound to an allocation. Linear and optimal resources are kept in separate blocks
+/// when the device has a `bufferImageGranularity` above one, so they never share a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    LinearImage,
+    OptimalImage,
+}
+
+impl ResourceKind {
+    fn is_linear(self) -> bool {
+        self != Self::OptimalImage
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AllocatorConfig {
+    /// Size of the blocks resources are sub-allocated from. Smaller heaps use an eighth of their
+    /// size instead.
+    pub block_size: u64,
+    /// Resources at least this large get their own allocation.
+    pub dedicated_threshold: u64,
+}
+
+impl Default for AllocatorConfig {
+    fn default() -> Self {
+        Self {
+            block_size: 64 * 1024 * 1024,
+            dedicated_threshold: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// Places allocations in non-coherent memory on whole `nonCoherentAtomSize` atoms, so flushing
+/// or invalidating one never touches its neighbours.
+fn atom_aligned(requirements: vk::MemoryRequirements, atom: u64) -> vk::MemoryRequirements {
+    vk::MemoryRequirements {
+        size: requirements.size.next_multiple_of(atom),
+        alignment: requirements.alignment.max(atom),
+        ..requirements
+    }
+}
+
+/// First-fit free list of the ranges of a block.
+#[derive(Debug)]
+struct FreeList {
+    size: u64,
+    /// `(offset, size)`, sorted by offset and never adjacent.
+    free: Vec<(u64, u64)>,
+}
+
+impl FreeList {
+    fn new(size: u64) -> Self {
+        Self {
+            size,
+            free: vec![(0, size)],
+        }
+    }
+
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let alignment = alignment.max(1);
+        let (index, offset, aligned) = self.free.iter().enumerate().find_map(|(i, &(o, s))| {
+            let aligned = o.next_multiple_of(alignment);
+            (aligned + size <= o + s).then_some((i, (o, s), aligned))
+        })?;
+        let (start, len) = offset;
+        let mut replacement = Vec::with_capacity(2);
+        if aligned > start {
+            replacement.push((start, aligned - start));
+        }
+        if aligned + size < start + len {
+            replacement.push((aligned + size, start + len - aligned - size));
+        }
+        self.free.splice(index..=index, replacement);
+        Some(aligned)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        let index = self.free.partition_point(|(o, _)| *o < offset);
+        self.free.insert(index, (offset, size));
+        // Merge with the next range, then with the previous one.
+        if index + 1 < self.free.len() && offset + size == self.free[index + 1].0 {
+            self.free[index].1 += self.free[index + 1].1;
+            self.free.remove(index + 1);
+        }
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+            self.free[index - 1].1 += self.free[index].1;
+            self.free.remove(index);
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.size - self.free.iter().map(|(_, s)| s).sum::<u64>()
+    }
+}
+
+struct Block {
+    memory: vk::DeviceMemory,
+    size: u64,
+    mapped: Option<NonNull<u8>>,
+    free_list: FreeList,
+    allocations: usize,
+}
+
+struct Pool {
+    memory_type: u32,
+    linear: bool,
+    /// Freed blocks leave a `None`, so allocations can keep referring to blocks by index.
+    blocks: Vec<Option<Block>>,
+}
+
+#[derive(Default)]
+struct State {
+    pools: Vec<Pool>,
+    /// Count and size of the dedicated allocations of each memory type.
+    dedicated: Vec<(usize, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStatistics {
+    /// Memory allocated from the driver.
+    pub reserved_bytes: u64,
+    /// Memory handed out to resources.
+    pub used_bytes: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStatistics {
+    pub blocks: usize,
+    pub dedicated_allocations: usize,
+    /// Sub-allocations and dedicated allocations.
+    pub allocations: usize,
+    pub reserved_bytes: u64,
+    pub used_bytes: u64,
+    pub heaps: Vec<HeapStatistics>,
+}
+
+/// Sub-allocating device memory allocator.
+pub struct Allocator {
+    device: Arc<Device>,
+    config: AllocatorConfig,
+    properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: u64,
+    non_coherent_atom_size: u64,
+    dedicated_allocation: bool,
+    state: Mutex<State>,
+}
+
+// The mapped pointers are only handed out through allocations, access to the pools is locked.
+unsafe impl Send for Allocator {}
+unsafe impl Sync for Allocator {}
+
+impl std::fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Allocator")
+            .field("config", &self.config)
+            .field("statistics", &self.statistics())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Allocator {
+    pub fn new(device: &Arc<Device>, config: AllocatorConfig) -> Arc<Self> {
+        let physical_device = device.physical_device();
+        let limits = physical_device.limits();
+        Arc::new(Self {
+            device: device.clone(),
+            config,
+            properties: *physical_device.memory_properties(),
+            buffer_image_granularity: limits.buffer_image_granularity,
+            non_coherent_atom_size: limits.non_coherent_atom_size.max(1),
+            // `VK_KHR_dedicated_allocation` is core since 1.1.
+            dedicated_allocation: device.enabled_features().api_version >= vk::API_VERSION_1_1,
+            state: Mutex::new(State {
+                pools: vec![],
+                dedicated: vec![(0, 0); vk::MAX_MEMORY_TYPES],
+            }),
+        })
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.properties
+    }
+
+    /// # Errors
+    /// Fails if no memory type fits, or if the device is out of memory.
+    pub fn allocate(
+        self: &Arc<Self>,
+        requirements: vk::MemoryRequirements,
+        usage: MemoryUsage,
+        kind: ResourceKind,
+    ) -> Result<Allocation, MemoryError> {
+        self.allocate_for(requirements, usage, kind, Dedicated::None, false)
+    }
+
+    /// `dedicated` forces a dedicated allocation, for resources the driver requires or prefers
+    /// to have one.
+    fn allocate_for(
+        self: &Arc<Self>,
+        requirements: vk::MemoryRequirements,
+        usage: MemoryUsage,
+        kind: ResourceKind,
+        resource: Dedicated,
+        dedicated: bool,
+    ) -> Result<Allocation, MemoryError> {
+        let memory_type =
+            select_memory_type(&self.properties, requirements.memory_type_bits, usage).ok_or(
+                MemoryError::NoCompatibleMemoryType {
+                    usage,
+                    type_bits: requirements.memory_type_bits,
+                },
+            )?;
+        let flags = self.properties.memory_types[memory_type as usize].property_flags;
+        let non_coherent = flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            && !flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        let requirements = if non_coherent {
+            atom_aligned(requirements, self.non_coherent_atom_size)
+        } else {
+            requirements
+        };
+        let block_size = self.block_size(memory_type);
+        if dedicated
+            || requirements.size >= self.config.dedicated_threshold
+            || requirements.size > block_size
+        {
+            return self.allocate_dedicated(requirements.size, memory_type, resource);
+        }
+
+        let linear = kind.is_linear() || self.buffer_image_granularity <= 1;
+        let mut state = self.state.lock().unwrap();
+        let pool_index = match state
+            .pools
+            .iter()
+            .position(|x| x.memory_type == memory_type && x.linear == linear)
+        {
+            Some(index) => index,
+            None => {
+                state.pools.push(Pool {
+                    memory_type,
+                    linear,
+                    blocks: vec![],
+                });
+                state.pools.len() - 1
+            }
+        };
+        let pool = &mut state.pools[pool_index];
+        for (block_index, block) in pool.blocks.iter_mut().enumerate() {
+            let Some(block) = block else { continue };
+            if let Some(offset) = block
+                .free_list
+                .allocate(requirements.size, requirements.alignment)
+            {
+                block.allocations += 1;
+                return Ok(self.sub_allocation(
+                    block,
+                    memory_type,
+                    pool_index,
+                    block_index,
+                    offset,
+                    requirements.size,
+                ));
+            }
+        }
+
+        let (memory, mapped) = self.allocate_memory(block_size, memory_type, Dedicated::None)?;
+        let mut block = Block {
+            memory,
+            size: block_size,
+            mapped,
+            free_list: FreeList::new(block_size),
+            allocations: 1,
+        };
+        let offset = block
+            .free_list
+            .allocate(requirements.size, requirements.alignment)
+            .expect("A new block fits the allocation");
+        let block_index = match pool.blocks.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                pool.blocks.push(None);
+                pool.blocks.len() - 1
+            }
+        };
+        let res = self.sub_allocation(
+            &block,
+            memory_type,
+            pool_index,
+            block_index,
+            offset,
+            requirements.size,
+        );
+        pool.blocks[block_index] = Some(block);
+        Ok(res)
+    }
+
+    fn sub_allocation(
+        self: &Arc<Self>,
+        block: &Block,
+        memory_type: u32,
+        pool: usize,
+        block_index: usize,
+        offset: u64,
+        size: u64,
+    ) -> Allocation {
+        Allocation {
+            allocator: self.clone(),
+            memory: block.memory,
+            offset,
+            size,
+            memory_type,
+            mapped: block
+                .mapped
+                .map(|x| unsafe { NonNull::new_unchecked(x.as_ptr().add(offset as usize)) }),
+            source: Source::Block {
+                pool,
+                block: block_index,
+            },
+        }
+    }
+
+    fn allocate_dedicated(
+        self: &Arc<Self>,
+        size: u64,
+        memory_type: u32,
+        resource: Dedicated,
+    ) -> Result<Allocation, MemoryError> {
+        let (memory, mapped) = self.allocate_memory(size, memory_type, resource)?;
+        let mut state = self.state.lock().unwrap();
+        let dedicated = &mut state.dedicated[memory_type as usize];
+        dedicated.0 += 1;
+        dedicated.1 += size;
+        Ok(Allocation {
+            allocator: self.clone(),
+            memory,
+            offset: 0,
+            size,
+            memory_type,
+            mapped,
+            source: Source::Dedicated,
+        })
+    }
+
+    fn allocate_memory(
+        &self,
+        size: u64,
+        memory_type: u32,
+        resource: Dedicated,
+    ) -> Result<(vk::DeviceMemory, Option<NonNull<u8>>), MemoryError> {
+        let raw = self.device.raw();
+        let mut dedicated_info = match resource {
+            Dedicated::Buffer(buffer) => vk::MemoryDedicatedAllocateInfo::default().buffer(buffer),
+            Dedicated::Image(image) => vk::MemoryDedicatedAllocateInfo::default().image(image),
+            Dedicated::None => vk::MemoryDedicatedAllocateInfo::default(),
+        };
+        let mut allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+        if resource != Dedicated::None && self.dedicated_allocation {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+        let memory =
+            unsafe { raw.allocate_memory(&allocate_info, self.device.allocation_callbacks()) }?;
+        let host_visible = self.properties.memory_types[memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        if !host_visible {
+            return Ok((memory, None));
+        }
+        match unsafe { raw.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) } {
+            Ok(ptr) => Ok((memory, NonNull::new(ptr.cast()))),
+            Err(err) => {
+                unsafe { raw.free_memory(memory, self.device.allocation_callbacks()) };
+                Err(err.into())
+            }
+        }
+    }
+
+    fn free_memory(&self, memory: vk::DeviceMemory) {
+        // Freeing implicitly unmaps.
+        unsafe {
+            self.device
+                .raw()
+                .free_memory(memory, self.device.allocation_callbacks())
+        };
+    }
+
+    fn free(&self, allocation: &Allocation) {
+        let mut state = self.state.lock().unwrap();
+        match allocation.source {
+            Source::Dedicated => {
+                let dedicated = &mut state.dedicated[allocation.memory_type as usize];
+                dedicated.0 -= 1;
+                dedicated.1 -= allocation.size;
+                drop(state);
+                self.free_memory(allocation.memory);
+            }
+            Source::Block { pool, block } => {
+                let pool = &mut state.pools[pool];
+                let other_blocks = pool.blocks.iter().flatten().count() > 1;
+                let entry = pool.blocks[block]
+                    .as_mut()
+                    .expect("Allocations keep their block alive");
+                entry.free_list.free(allocation.offset, allocation.size);
+                entry.allocations -= 1;
+                // Keep one block around, so a pool that is emptied and refilled every frame
+                // does not reallocate.
+                if entry.allocations == 0 && other_blocks {
+                    let memory = entry.memory;
+                    pool.blocks[block] = None;
+                    drop(state);
+                    self.free_memory(memory);
+                }
+            }
+        }
+    }
+
+    fn block_size(&self, memory_type: u32) -> u64 {
+        let heap = self.properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.properties.memory_heaps[heap as usize].size;
+        self.config.block_size.min(heap_size / 8).max(1)
+    }
+
+    pub fn statistics(&self) -> MemoryStatistics {
+        let state = self.state.lock().unwrap();
+        let mut res = MemoryStatistics {
+            heaps: self
+                .properties
+                .memory_heaps_as_slice()
+                .iter()
+                .map(|x| HeapStatistics {
+                    size: x.size,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let heap_of = |memory_type: u32| {
+            self.properties.memory_types[memory_type as usize].heap_index as usize
+        };
+        for pool in &state.pools {
+            let heap = heap_of(pool.memory_type);
+            for block in pool.blocks.iter().flatten() {
+                let used = block.free_list.used();
+                res.blocks += 1;
+                res.allocations += block.allocations;
+                res.reserved_bytes += block.size;
+                res.used_bytes += used;
+                res.heaps[heap].reserved_bytes += block.size;
+                res.heaps[heap].used_bytes += used;
+            }
+        }
+        for (memory_type, &(count, size)) in state.dedicated.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let heap = heap_of(memory_type as u32);
+            res.dedicated_allocations += count;
+            res.allocations += count;
+            res.reserved_bytes += size;
+            res.used_bytes += size;
+            res.heaps[heap].reserved_bytes += size;
+            res.heaps[heap].used_bytes += size;
+        }
+        res
+    }
+
+    /// The memory requirements of `resource`, and whether the driver requires or prefers it to
+    /// have a dedicated allocation. Without Vulkan 1.1 it never does.
+    fn memory_requirements(&self, resource: Dedicated) -> (vk::MemoryRequirements, bool) {
+        let raw = self.device.raw();
+        if !self.dedicated_allocation {
+            let requirements = match resource {
+                Dedicated::Buffer(buffer) => unsafe { raw.get_buffer_memory_requirements(buffer) },
+                Dedicated::Image(image) => unsafe { raw.get_image_memory_requirements(image) },
+                Dedicated::None => unreachable!("Only resources have memory requirements"),
+            };
+            return (requirements, false);
+        }
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+        match resource {
+            Dedicated::Buffer(buffer) => unsafe {
+                raw.get_buffer_memory_requirements2(
+                    &vk::BufferMemoryRequirementsInfo2::default().buffer(buffer),
+                    &mut requirements,
+                );
+            },
+            Dedicated::Image(image) => unsafe {
+                raw.get_image_memory_requirements2(
+                    &vk::ImageMemoryRequirementsInfo2::default().image(image),
+                    &mut requirements,
+                );
+            },
+            Dedicated::None => unreachable!("Only resources have memory requirements"),
+        }
+        let requirements = requirements.memory_requirements;
+        (
+            requirements,
+            dedicated.requires_dedicated_allocation == vk::TRUE
+                || dedicated.prefers_dedicated_allocation == vk::TRUE,
+        )
+    }
+
+    /// Creates a buffer and binds memory to it.
+    /// # Errors
+    /// Fails if the buffer cannot be created or no memory can be allocated for it.
+    pub fn create_buffer(
+        self: &Arc<Self>,
+        create_info: &vk::BufferCreateInfo,
+        usage: MemoryUsage,
+    ) -> Result<AllocatedBuffer, MemoryError> {
+        let raw = self.device.raw();
+        let buffer = unsafe { raw.create_buffer(create_info, self.device.allocation_callbacks()) }?;
+        // Destroyed again if binding memory fails.
+        let buffer = own(&self.device, buffer);
+        let (requirements, dedicated) = self.memory_requirements(Dedicated::Buffer(*buffer));
+        let allocation = self.allocate_for(
+            requirements,
+            usage,
+            ResourceKind::Buffer,
+            Dedicated::Buffer(*buffer),
+            dedicated,
+        )?;
+        unsafe { raw.bind_buffer_memory(*buffer, allocation.memory, allocation.offset) }?;
+        Ok(AllocatedBuffer {
+            raw: buffer,
+            size: create_info.size,
+            allocation,
+        })
+    }
+
+    /// Creates an image and binds memory to it.
+    /// # Errors
+    /// Fails if the image cannot be created or no memory can be allocated for it.
+    pub fn create_image(
+        self: &Arc<Self>,
+        create_info: &vk::ImageCreateInfo,
+        usage: MemoryUsage,
+    ) -> Result<AllocatedImage, MemoryError> {
+        let raw = self.device.raw();
+        let image = unsafe { raw.create_image(create_info, self.device.allocation_callbacks()) }?;
+        // Destroyed again if binding memory fails.
+        let image = own(&self.device, image);
+        let (requirements, dedicated) = self.memory_requirements(Dedicated::Image(*image));
+        let kind = match create_info.tiling {
+            vk::ImageTiling::LINEAR => ResourceKind::LinearImage,
+            _ => ResourceKind::OptimalImage,
+        };
+        let allocation = self.allocate_for(
+            requirements,
+            usage,
+            kind,
+            Dedicated::Image(*image),
+            dedicated,
+        )?;
+        unsafe { raw.bind_image_memory(*image, allocation.memory, allocation.offset) }?;
+        Ok(AllocatedImage {
+            raw: image,
+            allocation,
+        })
+    }
+}
+
+impl Drop for Allocator {
+    /// Allocations keep the allocator alive, so only empty blocks are left.
+    fn drop(&mut self) {
+        let pools = std::mem::take(&mut self.state.get_mut().unwrap().pools);
+        for pool in pools {
+            for block in pool.blocks.into_iter().flatten() {
+                self.free_memory(block.memory);
+            }
+        }
+    }
+}
+
+/// The resource a dedicated allocation is made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dedicated {
+    None,
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Dedicated,
+    Block { pool: usize, block: usize },
+}
+
+/// A range of device memory, given back to the [`Allocator`] when dropped.
+pub struct Allocation {
+    allocator: Arc<Allocator>,
+    memory: vk::DeviceMemory,
+    offset: u64,
+    size: u64,
+    memory_type: u32,
+    mapped: Option<NonNull<u8>>,
+    source: Source,
+}
+
+// The mapped range is exclusive to this allocation.
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl std::fmt::Debug for Allocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Allocation")
+            .field("memory", &self.memory)
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .field("memory_type", &self.memory_type)
+            .field("mapped", &self.mapped)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+    /// Offset into [`Self::memory`].
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn memory_type(&self) -> u32 {
+        self.memory_type
+    }
+    pub fn property_flags(&self) -> vk::MemoryPropertyFlags {
+        self.allocator.properties.memory_types[self.memory_type as usize].property_flags
+    }
+    /// Start of the allocation in host memory, if it is host visible.
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        self.mapped
+    }
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.mapped
+            .map(|x| unsafe { std::slice::from_raw_parts(x.as_ptr(), self.size as usize) })
+    }
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        self.mapped
+            .map(|x| unsafe { std::slice::from_raw_parts_mut(x.as_ptr(), self.size as usize) })
+    }
+
+    /// Copies `data` to `offset` bytes into the allocation, and flushes it if the memory is not
+    /// coherent.
+    /// # Errors
+    /// Fails if the allocation is not host visible.
+    /// # Panics
+    /// If the data does not fit.
+    pub fn write<T: Pod>(&mut self, offset: u64, data: &[T]) -> Result<(), MemoryError> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), std::mem::size_of_val(data))
+        };
+        let slice = self.mapped_slice_mut().ok_or(MemoryError::NotHostVisible)?;
+        slice[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+        self.flush(offset, bytes.len() as u64)
+    }
+
+    /// Copies `offset` bytes into the allocation to `data`, after invalidating the range if the
+    /// memory is not coherent.
+    /// # Errors
+    /// Fails if the allocation is not host visible.
+    /// # Panics
+    /// If the range is out of bounds.
+    pub fn read<T: Pod>(&self, offset: u64, data: &mut [T]) -> Result<(), MemoryError> {
+        let len = std::mem::size_of_val(data);
+        self.invalidate(offset, len as u64)?;
+        let slice = self.mapped_slice().ok_or(MemoryError::NotHostVisible)?;
+        let bytes = unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr().cast::<u8>(), len) };
+        bytes.copy_from_slice(&slice[offset as usize..offset as usize + len]);
+        Ok(())
+    }
+
+    /// Makes host writes visible to the device. Does nothing for coherent memory.
+    /// # Errors
+    /// Fails if the driver does.
+    pub fn flush(&self, offset: u64, size: u64) -> Result<(), MemoryError> {
+        match self.non_coherent_range(offset, size) {
+            Some(range) => {
+                unsafe {
+                    self.allocator
+                        .device
+                        .raw()
+                        .flush_mapped_memory_ranges(&[range])
+                }?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Makes device writes visible to the host. Does nothing for coherent memory.
+    /// # Errors
+    /// Fails if the driver does.
+    pub fn invalidate(&self, offset: u64, size: u64) -> Result<(), MemoryError> {
+        match self.non_coherent_range(offset, size) {
+            Some(range) => {
+                unsafe {
+                    self.allocator
+                        .device
+                        .raw()
+                        .invalidate_mapped_memory_ranges(&[range])
+                }?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The range to flush or invalidate, aligned to `nonCoherentAtomSize`.
+    fn non_coherent_range(&self, offset: u64, size: u64) -> Option<vk::MappedMemoryRange<'_>> {
+        if self.mapped.is_none()
+            || self
+                .property_flags()
+                .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+        {
+            return None;
+        }
+        let atom = self.allocator.non_coherent_atom_size;
+        let start = (self.offset + offset) / atom * atom;
+        let end = (self.offset + offset + size).next_multiple_of(atom);
+        // Non-coherent allocations start and end on atoms, so the range never reaches into a
+        // neighbouring allocation.
+        debug_assert!(start >= self.offset && end <= self.offset + self.size);
+        Some(
+            vk::MappedMemoryRange::default()
+                .memory(self.memory)
+                .offset(start)
+                .size(end - start),
+        )
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.allocator.free(self);
+    }
+}
+
+/// A buffer with its memory, both freed when dropped.
+#[derive(Debug)]
+pub struct AllocatedBuffer {
+    /// Declared first, so it is destroyed before its memory is freed.
+    raw: Owned<vk::Buffer>,
+    size: u64,
+    allocation: Allocation,
+}
+
+impl AllocatedBuffer {
+    pub fn raw(&self) -> vk::Buffer {
+        *self.raw
+    }
+    /// The size the buffer was created with, the allocation may be larger.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+    pub fn allocation_mut(&mut self) -> &mut Allocation {
+        &mut self.allocation
+    }
+    pub fn allocator(&self) -> &Arc<Allocator> {
+        &self.allocation.allocator
+    }
+}
+
+/// An image with its memory, both freed when dropped.
+#[derive(Debug)]
+pub struct AllocatedImage {
+    /// Declared first, so it is destroyed before its memory is freed.
+    raw: Owned<vk::Image>,
+    allocation: Allocation,
+}
+
+impl AllocatedImage {
+    pub fn raw(&self) -> vk::Image {
+        *self.raw
+    }
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+    pub fn allocator(&self) -> &Arc<Allocator> {
+        &self.allocation.allocator
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn properties(types: &[(vk::MemoryPropertyFlags, u32)]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        for (index, (flags, heap)) in types.iter().enumerate() {
+            properties.memory_types[index].property_flags = *flags;
+            properties.memory_types[index].heap_index = *heap;
+        }
+        properties
+    }
+
+    #[test]
+    fn memory_type_selection() {
+        let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let cached = host | vk::MemoryPropertyFlags::HOST_CACHED;
+        // Roughly a discrete GPU with resizable BAR.
+        let properties = properties(&[
+            (device_local, 0),
+            (host, 1),
+            (cached, 1),
+            (device_local | host, 0),
+        ]);
+        let all = 0b1111;
+        assert_eq!(
+            select_memory_type(&properties, all, MemoryUsage::GpuOnly),
+            Some(0)
+        );
+        assert_eq!(
+            select_memory_type(&properties, all, MemoryUsage::Upload),
+            Some(1)
+        );
+        assert_eq!(
+            select_memory_type(&properties, all, MemoryUsage::Dynamic),
+            Some(3)
+        );
+        assert_eq!(
+            select_memory_type(&properties, all, MemoryUsage::Readback),
+            Some(2)
+        );
+        // Falls back to what the resource allows.
+        assert_eq!(
+            select_memory_type(&properties, 0b0010, MemoryUsage::Dynamic),
+            Some(1)
+        );
+        assert_eq!(
+            select_memory_type(&properties, 0b0010, MemoryUsage::GpuOnly),
+            Some(1)
+        );
+        assert_eq!(
+            select_memory_type(&properties, 0b0001, MemoryUsage::Upload),
+            None
+        );
+        // Types past the count are ignored.
+        assert_eq!(
+            select_memory_type(&properties, 0b10000, MemoryUsage::GpuOnly),
+            None
+        );
+    }
+
+    #[test]
+    fn free_list() {
+        let mut list = FreeList::new(1024);
+        assert_eq!(list.allocate(100, 1), Some(0));
+        // Aligned, leaving a gap.
+        assert_eq!(list.allocate(100, 256), Some(256));
+        assert_eq!(list.allocate(10, 1), Some(100));
+        assert_eq!(list.used(), 210);
+        assert_eq!(list.allocate(2048, 1), None);
+
+        list.free(0, 100);
+        list.free(256, 100);
+        list.free(100, 10);
+        assert_eq!(list.free, vec![(0, 1024)]);
+        assert_eq!(list.used(), 0);
+
+        // Exactly filling the block.
+        assert_eq!(list.allocate(1024, 1024), Some(0));
+        assert_eq!(list.allocate(1, 1), None);
+        list.free(0, 1024);
+        assert_eq!(list.free, vec![(0, 1024)]);
+    }
+
+    #[test]
+    fn non_coherent_atoms() {
+        let requirements = vk::MemoryRequirements {
+            size: 100,
+            alignment: 16,
+            memory_type_bits: 1,
+        };
+        let placed = atom_aligned(requirements, 64);
+        assert_eq!((placed.size, placed.alignment), (128, 64));
+        assert_eq!(placed.memory_type_bits, 1);
+        let placed = atom_aligned(
+            vk::MemoryRequirements {
+                alignment: 256,
+                ..placed
+            },
+            64,
+        );
+        assert_eq!((placed.size, placed.alignment), (128, 256));
+
+        // Neighbours never share an atom.
+        let mut list = FreeList::new(1024);
+        let placed = atom_aligned(requirements, 64);
+        assert_eq!(list.allocate(placed.size, placed.alignment), Some(0));
+        assert_eq!(list.allocate(placed.size, placed.alignment), Some(128));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn allocate_from_instance() {
+        let (device, _) = crate::test_support::device(
+            "allocator test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::TRANSFER,
+        );
+        let allocator = Allocator::new(&device, AllocatorConfig::default());
+
+        let create_info = vk::BufferCreateInfo::default()
+            .size(256)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC);
+        let mut first = allocator
+            .create_buffer(&create_info, MemoryUsage::Upload)
+            .unwrap();
+        let second = allocator
+            .create_buffer(&create_info, MemoryUsage::Upload)
+            .unwrap();
+        // Both come from the same block.
+        assert_eq!(first.allocation().memory(), second.allocation().memory());
+        first.allocation_mut().write(0, &[1u32, 2, 3]).unwrap();
+        let mut read = [0u32; 3];
+        first.allocation().read(0, &mut read).unwrap();
+        assert_eq!(read, [1, 2, 3]);
+
+        let large = vk::BufferCreateInfo::default()
+            .size(AllocatorConfig::default().dedicated_threshold)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER);
+        let dedicated = allocator
+            .create_buffer(&large, MemoryUsage::GpuOnly)
+            .unwrap();
+        let statistics = allocator.statistics();
+        assert_eq!(statistics.allocations, 3);
+        assert_eq!(statistics.dedicated_allocations, 1);
+        assert!(statistics.used_bytes >= 512 + large.size);
+
+        drop((first, second, dedicated));
+        let statistics = allocator.statistics();
+        assert_eq!(statistics.allocations, 0);
+        assert_eq!(statistics.used_bytes, 0);
+        // The last block of a pool is kept.
+        assert_eq!(statistics.blocks, 1);
+    }
+}
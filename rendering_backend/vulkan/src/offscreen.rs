@@ -0,0 +1,194 @@
+//! Render targets that are not backed by a window.
+//!
+//! Headless tools and tests render into an [`OffscreenTarget`] instead of a [`SwapChain`], and
+//! code that only draws can take either through [`RenderTarget`].
+use super::error;
+use super::image::{Image, ImageDescription, ImageDimensions};
+use super::memory::Allocator;
+use super::swapchain::SwapChain;
+use ash::vk;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Images that can be rendered to.
+pub trait RenderTarget {
+    fn images(&self) -> &[vk::Image];
+    /// One color view per image, destroyed with the target. Command buffers do not keep them
+    /// alive, see [`Attachment::clear_color_raw`](crate::command::Attachment::clear_color_raw).
+    fn image_views(&self) -> &[vk::ImageView];
+    fn extent(&self) -> vk::Extent2D;
+    fn format(&self) -> vk::Format;
+}
+
+impl RenderTarget for SwapChain {
+    fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+    fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+    fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+    fn format(&self) -> vk::Format {
+        self.format.format
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OffscreenTargetDescription {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub image_count: u32,
+    /// Color attachment usage is always added.
+    pub usage: vk::ImageUsageFlags,
+    /// The images are named after it and their index.
+    pub name: Option<Cow<'static, str>>,
+}
+
+impl OffscreenTargetDescription {
+    /// A single image that can be rendered to and copied from.
+    pub fn new(extent: vk::Extent2D, format: vk::Format) -> Self {
+        Self {
+            extent,
+            format,
+            image_count: 1,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC,
+            name: None,
+        }
+    }
+}
+
+/// Device local color images, the headless counterpart of a [`SwapChain`].
+pub struct OffscreenTarget {
+    targets: Vec<Arc<Image>>,
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    extent: vk::Extent2D,
+    format: vk::Format,
+}
+
+impl std::fmt::Debug for OffscreenTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OffscreenTarget")
+            .field("images", &self.images)
+            .field("image_views", &self.image_views)
+            .field("extent", &self.extent)
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OffscreenTarget {
+    /// # Errors
+    /// Fails if the format cannot be rendered to, or if the images cannot be created.
+    pub fn new(
+        allocator: &Arc<Allocator>,
+        desc: &OffscreenTargetDescription,
+    ) -> Result<Self, error::InitError> {
+        let supported = allocator
+            .device()
+            .physical_device()
+            .format_properties(desc.format)
+            .optimal_tiling_features;
+        if !supported.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT) {
+            return Err(error::InitError::FormatNotSupported(desc.format));
+        }
+
+        let description = ImageDescription::new(
+            ImageDimensions::d2(desc.extent.width, desc.extent.height),
+            desc.format,
+        )
+        .with_usage(desc.usage)
+        .color_attachment();
+        let targets = (0..desc.image_count)
+            .map(|_| Image::new(allocator, &description))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(name) = &desc.name {
+            for (index, target) in targets.iter().enumerate() {
+                target.set_debug_name(&format!("{name} {index}"));
+            }
+        }
+        Ok(Self {
+            images: targets.iter().map(|x| x.raw()).collect(),
+            image_views: targets.iter().map(|x| x.view()).collect(),
+            targets,
+            extent: desc.extent,
+            format: desc.format,
+        })
+    }
+
+    /// The images with their tracked layouts.
+    pub fn targets(&self) -> &[Arc<Image>] {
+        &self.targets
+    }
+}
+
+impl RenderTarget for OffscreenTarget {
+    fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+    fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+    fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+    fn format(&self) -> vk::Format {
+        self.format
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DeviceCreationInfo, QueueDescription};
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn headless_device() {
+        let instance = crate::test_support::instance(crate::InstanceCreateInfo::compute_usage(
+            "headless test",
+        ));
+        assert!(!instance.report().has_extension(ash::khr::surface::NAME));
+
+        let mut info = DeviceCreationInfo::default();
+        let compute = info.request_queue(QueueDescription {
+            flags: vk::QueueFlags::COMPUTE,
+            ..QueueDescription::from_count(1)
+        });
+        let transfer = info.request_queue(QueueDescription {
+            flags: vk::QueueFlags::TRANSFER,
+            ..QueueDescription::from_count(1)
+        });
+        let created = crate::test_support::create(&instance, info);
+        let device = created.device.clone();
+        let compute = created.queues(compute);
+        assert_eq!(compute.len(), 1);
+        assert!(compute[0].capabilities().contains(vk::QueueFlags::COMPUTE));
+        assert_eq!(created.queues(transfer).len(), 1);
+
+        let desc = OffscreenTargetDescription {
+            image_count: 2,
+            name: Some("offscreen".into()),
+            ..OffscreenTargetDescription::new(
+                vk::Extent2D {
+                    width: 64,
+                    height: 32,
+                },
+                vk::Format::R8G8B8A8_UNORM,
+            )
+        };
+        let allocator = crate::memory::Allocator::new(&device, Default::default());
+        let target = OffscreenTarget::new(&allocator, &desc).unwrap();
+        assert_eq!(target.images().len(), 2);
+        assert_eq!(target.image_views().len(), 2);
+        assert_eq!(target.extent().width, 64);
+
+        let unsupported = OffscreenTargetDescription::new(desc.extent, vk::Format::UNDEFINED);
+        assert!(matches!(
+            OffscreenTarget::new(&allocator, &unsupported),
+            Err(crate::error::InitError::FormatNotSupported(_))
+        ));
+    }
+}
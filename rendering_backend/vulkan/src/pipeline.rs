@@ -0,0 +1,971 @@
+//! Pipelines whose layouts come from the reflection of their shaders, and a pipeline cache
+//! that persists between runs.
+use super::command::{CommandBuffer, CommandPool, Pipeline, RenderingFormats};
+use super::descriptor::SetLayoutDescription;
+use super::device::{Device, Queue};
+use super::error::{PipelineError, VkError};
+use super::handle::{own, Owned};
+use super::shader::{ShaderModule, ShaderReflection, VertexAttribute};
+use ash::vk;
+use spirv_reflect::DescriptorInfo;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A binding of a descriptor set, with every stage that uses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub info: DescriptorInfo,
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// The descriptor sets of every stage, by set and binding.
+pub type DescriptorSets = BTreeMap<u32, BTreeMap<u32, DescriptorBinding>>;
+
+fn merge_descriptor_sets(shaders: &[&ShaderReflection]) -> Result<DescriptorSets, PipelineError> {
+    let mut sets = DescriptorSets::new();
+    for shader in shaders {
+        for (set, bindings) in &shader.descriptor_sets {
+            for (binding, info) in bindings {
+                let entry = sets
+                    .entry(*set)
+                    .or_default()
+                    .entry(*binding)
+                    .or_insert_with(|| DescriptorBinding {
+                        info: info.clone(),
+                        stages: vk::ShaderStageFlags::empty(),
+                    });
+                if entry.info.ty != info.ty {
+                    return Err(PipelineError::ConflictingBinding {
+                        set: *set,
+                        binding: *binding,
+                        first: entry.info.ty,
+                        second: info.ty,
+                    });
+                }
+                entry.stages |= shader.stage;
+            }
+        }
+    }
+    Ok(sets)
+}
+
+/// A single range, large enough for every stage.
+fn push_constant_range(shaders: &[&ShaderReflection]) -> Option<vk::PushConstantRange> {
+    shaders
+        .iter()
+        .filter_map(|shader| shader.push_constants.map(|x| (shader.stage, x)))
+        .fold(
+            None,
+            |range: Option<vk::PushConstantRange>, (stage, info)| {
+                let range = range.unwrap_or_default();
+                Some(vk::PushConstantRange {
+                    stage_flags: range.stage_flags | stage,
+                    offset: 0,
+                    size: range.size.max(info.offset + info.size),
+                })
+            },
+        )
+}
+
+/// Descriptor set layouts and push constant range shared by the stages of a pipeline.
+pub struct PipelineLayout {
+    raw: Owned<vk::PipelineLayout>,
+    /// One for every set up to the highest one used, unused ones are empty. Owned by the
+    /// device.
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    set_descriptions: Vec<SetLayoutDescription>,
+    sets: DescriptorSets,
+    push_constants: Option<vk::PushConstantRange>,
+}
+
+impl std::fmt::Debug for PipelineLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PipelineLayout")
+            .field("raw", &*self.raw)
+            .field("sets", &self.sets)
+            .field("push_constants", &self.push_constants)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PipelineLayout {
+    /// Merges the descriptor sets and push constants of `shaders`.
+    /// # Errors
+    /// If two stages disagree on a binding, or the layouts cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        shaders: &[&ShaderReflection],
+    ) -> Result<Arc<Self>, PipelineError> {
+        let sets = merge_descriptor_sets(shaders)?;
+        let push_constants = push_constant_range(shaders);
+        let set_count = sets.keys().next_back().map_or(0, |x| x + 1);
+        let set_descriptions = (0..set_count)
+            .map(|set| {
+                sets.get(&set)
+                    .map_or(Ok(SetLayoutDescription::default()), |x| {
+                        SetLayoutDescription::from_reflection(x)
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let set_layouts = set_descriptions
+            .iter()
+            .map(|x| device.descriptor_set_layout(x))
+            .collect::<Result<Vec<_>, _>>()?;
+        let push_constant_ranges = push_constants.as_slice();
+        let create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let raw = unsafe {
+            device
+                .raw()
+                .create_pipeline_layout(&create_info, device.allocation_callbacks())
+        }?;
+        let layout = Self {
+            raw: own(device, raw),
+            set_layouts,
+            set_descriptions,
+            sets,
+            push_constants,
+        };
+        Ok(Arc::new(layout))
+    }
+
+    pub fn raw(&self) -> vk::PipelineLayout {
+        *self.raw
+    }
+    pub fn device(&self) -> &Arc<Device> {
+        &self.raw.owner
+    }
+    pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.set_layouts
+    }
+    pub fn set_descriptions(&self) -> &[SetLayoutDescription] {
+        &self.set_descriptions
+    }
+    pub fn sets(&self) -> &DescriptorSets {
+        &self.sets
+    }
+    pub fn push_constants(&self) -> Option<vk::PushConstantRange> {
+        self.push_constants
+    }
+}
+
+/// Vertex buffer bindings and the attributes read from them.
+#[derive(Debug, Clone, Default)]
+pub struct VertexInput {
+    pub bindings: Vec<vk::VertexInputBindingDescription>,
+    pub attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexInput {
+    /// One binding at 0, laid out like a `#[repr(C)]` struct of `attributes` in order, such as
+    /// the `Input` struct `shader!` generates.
+    pub fn interleaved(attributes: &[VertexAttribute], input_rate: vk::VertexInputRate) -> Self {
+        let mut offset = 0u32;
+        let mut alignment = 1;
+        let attributes = attributes
+            .iter()
+            .map(|x| {
+                alignment = alignment.max(x.alignment);
+                let attribute = vk::VertexInputAttributeDescription {
+                    location: x.location,
+                    binding: 0,
+                    format: x.format,
+                    offset: offset.next_multiple_of(x.alignment),
+                };
+                offset = attribute.offset + x.size;
+                attribute
+            })
+            .collect::<Vec<_>>();
+        let bindings = if attributes.is_empty() {
+            vec![]
+        } else {
+            vec![vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: offset.next_multiple_of(alignment),
+                input_rate,
+            }]
+        };
+        Self {
+            bindings,
+            attributes,
+        }
+    }
+}
+
+/// Common color blend equations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    PremultipliedAlpha,
+    Additive,
+}
+
+impl BlendMode {
+    pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let state = vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        let (src, dst) = match self {
+            Self::Opaque => return state,
+            Self::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            Self::PremultipliedAlpha => {
+                (vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            }
+            Self::Additive => (vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        };
+        state
+            .blend_enable(true)
+            .src_color_blend_factor(src)
+            .dst_color_blend_factor(dst)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(dst)
+            .alpha_blend_op(vk::BlendOp::ADD)
+    }
+}
+
+/// Depth test and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthState {
+    pub compare_op: vk::CompareOp,
+    pub write: bool,
+}
+
+impl DepthState {
+    /// Tests with `compare_op` and writes.
+    pub fn new(compare_op: vk::CompareOp) -> Self {
+        Self {
+            compare_op,
+            write: true,
+        }
+    }
+    #[must_use]
+    pub fn read_only(mut self) -> Self {
+        self.write = false;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterizationState {
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub line_width: f32,
+    pub depth_clamp: bool,
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl Default for RasterizationState {
+    /// Filled, back faces culled, counter-clockwise front faces.
+    fn default() -> Self {
+        Self {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_clamp: false,
+            depth_bias: None,
+        }
+    }
+}
+
+/// How to build a [`GraphicsPipeline`] for dynamic rendering.
+///
+/// The viewport and scissor are always dynamic, see
+/// [`CommandBuffer::set_viewport`](crate::command::CommandBuffer::set_viewport).
+#[derive(Debug, Clone)]
+pub struct GraphicsPipelineDescription {
+    pub shaders: Vec<Arc<ShaderModule>>,
+    /// Derived from the vertex shader with [`VertexInput::interleaved`] if `None`.
+    pub vertex_input: Option<VertexInput>,
+    pub topology: vk::PrimitiveTopology,
+    pub primitive_restart: bool,
+    pub rasterization: RasterizationState,
+    pub depth: Option<DepthState>,
+    /// One for every color attachment, or a single one used for all of them.
+    pub blend: Vec<vk::PipelineColorBlendAttachmentState>,
+    /// Besides the viewport and scissor.
+    pub dynamic_states: Vec<vk::DynamicState>,
+    /// The attachments rendered to, and the sample count.
+    pub formats: RenderingFormats,
+    /// Minimum fraction of samples shaded individually.
+    pub sample_shading: Option<f32>,
+    pub alpha_to_coverage: bool,
+    /// Shown by validation messages and graphics debuggers.
+    pub name: Option<Cow<'static, str>>,
+}
+
+impl GraphicsPipelineDescription {
+    /// Opaque triangle lists without a depth test.
+    pub fn new(shaders: Vec<Arc<ShaderModule>>, formats: RenderingFormats) -> Self {
+        Self {
+            shaders,
+            vertex_input: None,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            rasterization: RasterizationState::default(),
+            depth: None,
+            blend: vec![BlendMode::Opaque.attachment_state()],
+            dynamic_states: vec![],
+            formats,
+            sample_shading: None,
+            alpha_to_coverage: false,
+            name: None,
+        }
+    }
+    #[must_use]
+    pub fn name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    #[must_use]
+    pub fn vertex_input(mut self, vertex_input: VertexInput) -> Self {
+        self.vertex_input = Some(vertex_input);
+        self
+    }
+    #[must_use]
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+    #[must_use]
+    pub fn primitive_restart(mut self) -> Self {
+        self.primitive_restart = true;
+        self
+    }
+    #[must_use]
+    pub fn rasterization(mut self, rasterization: RasterizationState) -> Self {
+        self.rasterization = rasterization;
+        self
+    }
+    #[must_use]
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.rasterization.cull_mode = cull_mode;
+        self
+    }
+    #[must_use]
+    pub fn depth(mut self, depth: DepthState) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+    /// Blends every color attachment with `mode`.
+    #[must_use]
+    pub fn blend(mut self, mode: BlendMode) -> Self {
+        self.blend = vec![mode.attachment_state()];
+        self
+    }
+    #[must_use]
+    pub fn blend_attachments(mut self, blend: Vec<vk::PipelineColorBlendAttachmentState>) -> Self {
+        self.blend = blend;
+        self
+    }
+    #[must_use]
+    pub fn dynamic_state(mut self, state: vk::DynamicState) -> Self {
+        self.dynamic_states.push(state);
+        self
+    }
+    #[must_use]
+    pub fn sample_shading(mut self, min_fraction: f32) -> Self {
+        self.sample_shading = Some(min_fraction);
+        self
+    }
+    #[must_use]
+    pub fn alpha_to_coverage(mut self) -> Self {
+        self.alpha_to_coverage = true;
+        self
+    }
+
+    fn validate(&self) -> Result<(), PipelineError> {
+        let mut stages = vk::ShaderStageFlags::empty();
+        for shader in &self.shaders {
+            if stages.intersects(shader.stage()) {
+                return Err(PipelineError::InvalidDescription("a stage is given twice"));
+            }
+            stages |= shader.stage();
+        }
+        if !stages.intersects(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::MESH_EXT) {
+            return Err(PipelineError::InvalidDescription(
+                "a vertex or mesh shader is needed",
+            ));
+        }
+        if stages.contains(vk::ShaderStageFlags::COMPUTE) {
+            return Err(PipelineError::InvalidDescription(
+                "compute shaders need a compute pipeline",
+            ));
+        }
+        if self.blend.len() != 1 && self.blend.len() != self.formats.color.len() {
+            return Err(PipelineError::InvalidDescription(
+                "blend states do not match the color attachments",
+            ));
+        }
+        Ok(())
+    }
+
+    fn samples(&self) -> vk::SampleCountFlags {
+        if self.formats.samples.is_empty() {
+            vk::SampleCountFlags::TYPE_1
+        } else {
+            self.formats.samples
+        }
+    }
+}
+
+/// A graphics pipeline for dynamic rendering.
+pub struct GraphicsPipeline {
+    raw: Owned<vk::Pipeline>,
+    layout: Arc<PipelineLayout>,
+    description: GraphicsPipelineDescription,
+}
+
+impl std::fmt::Debug for GraphicsPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphicsPipeline")
+            .field("raw", &*self.raw)
+            .field("layout", &self.layout)
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GraphicsPipeline {
+    /// # Errors
+    /// If the description is invalid, the shaders disagree on their layouts, or the pipeline
+    /// cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        description: &GraphicsPipelineDescription,
+        cache: Option<&PipelineCache>,
+    ) -> Result<Arc<Self>, PipelineError> {
+        description.validate()?;
+        let reflections = description
+            .shaders
+            .iter()
+            .map(|x| x.reflection())
+            .collect::<Vec<_>>();
+        let layout = PipelineLayout::new(device, &reflections)?;
+
+        let stages = description
+            .shaders
+            .iter()
+            .map(|x| x.stage_info())
+            .collect::<Vec<_>>();
+        let vertex_input = description.vertex_input.clone().unwrap_or_else(|| {
+            let attributes = reflections
+                .iter()
+                .flat_map(|x| &x.vertex_attributes)
+                .copied()
+                .collect::<Vec<_>>();
+            VertexInput::interleaved(&attributes, vk::VertexInputRate::VERTEX)
+        });
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&vertex_input.bindings)
+            .vertex_attribute_descriptions(&vertex_input.attributes);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(description.topology)
+            .primitive_restart_enable(description.primitive_restart);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let raster = &description.rasterization;
+        let bias = raster.depth_bias.unwrap_or(DepthBias {
+            constant_factor: 0.0,
+            clamp: 0.0,
+            slope_factor: 0.0,
+        });
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(raster.polygon_mode)
+            .cull_mode(raster.cull_mode)
+            .front_face(raster.front_face)
+            .line_width(raster.line_width)
+            .depth_clamp_enable(raster.depth_clamp)
+            .depth_bias_enable(raster.depth_bias.is_some())
+            .depth_bias_constant_factor(bias.constant_factor)
+            .depth_bias_clamp(bias.clamp)
+            .depth_bias_slope_factor(bias.slope_factor);
+        let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(description.samples())
+            .sample_shading_enable(description.sample_shading.is_some())
+            .min_sample_shading(description.sample_shading.unwrap_or(0.0))
+            .alpha_to_coverage_enable(description.alpha_to_coverage);
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(description.depth.is_some())
+            .depth_write_enable(description.depth.is_some_and(|x| x.write))
+            .depth_compare_op(
+                description
+                    .depth
+                    .map_or(vk::CompareOp::ALWAYS, |x| x.compare_op),
+            );
+        let blend_attachments = if description.blend.len() == 1 {
+            vec![description.blend[0]; description.formats.color.len()]
+        } else {
+            description.blend.clone()
+        };
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        for state in &description.dynamic_states {
+            if !dynamic_states.contains(state) {
+                dynamic_states.push(*state);
+            }
+        }
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&description.formats.color)
+            .depth_attachment_format(description.formats.depth)
+            .stencil_attachment_format(description.formats.stencil);
+
+        let mut create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(layout.raw())
+            .push_next(&mut rendering);
+        // Mesh pipelines have no vertex input.
+        if !vertex_input.attributes.is_empty()
+            || reflections
+                .iter()
+                .any(|x| x.stage == vk::ShaderStageFlags::VERTEX)
+        {
+            create_info = create_info.vertex_input_state(&vertex_input_state);
+        }
+        let raw = unsafe {
+            device.raw().create_graphics_pipelines(
+                cache.map_or(vk::PipelineCache::null(), PipelineCache::raw),
+                &[create_info],
+                device.allocation_callbacks(),
+            )
+        }
+        .map_err(|(_, err)| err)?[0];
+        let pipeline = Self {
+            raw: own(device, raw),
+            layout,
+            description: description.clone(),
+        };
+        if let Some(name) = &description.name {
+            pipeline.set_debug_name(name);
+        }
+        Ok(Arc::new(pipeline))
+    }
+
+    /// Names the pipeline and its layout, see [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+        self.raw.owner.set_debug_name(self.layout.raw(), name);
+    }
+
+    pub fn pipeline_layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+    pub fn description(&self) -> &GraphicsPipelineDescription {
+        &self.description
+    }
+}
+
+impl Pipeline for GraphicsPipeline {
+    fn raw(&self) -> vk::Pipeline {
+        *self.raw
+    }
+    fn layout(&self) -> vk::PipelineLayout {
+        self.layout.raw()
+    }
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::GRAPHICS
+    }
+    fn push_constants(&self) -> Option<vk::PushConstantRange> {
+        self.layout.push_constants()
+    }
+}
+
+/// Workgroups of `local_size` needed to cover `threads` invocations.
+pub fn group_count(threads: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    std::array::from_fn(|i| threads[i].div_ceil(local_size[i].max(1)))
+}
+
+/// A compute pipeline, laid out as its shader declares.
+pub struct ComputePipeline {
+    raw: Owned<vk::Pipeline>,
+    layout: Arc<PipelineLayout>,
+    shader: Arc<ShaderModule>,
+    local_size: [u32; 3],
+}
+
+impl std::fmt::Debug for ComputePipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ComputePipeline")
+            .field("raw", &*self.raw)
+            .field("layout", &self.layout)
+            .field("local_size", &self.local_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ComputePipeline {
+    /// `name` is shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If `shader` is not a compute shader, or the pipeline cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        shader: &Arc<ShaderModule>,
+        cache: Option<&PipelineCache>,
+        name: Option<&str>,
+    ) -> Result<Arc<Self>, PipelineError> {
+        let local_size =
+            shader
+                .reflection()
+                .local_size
+                .ok_or(PipelineError::InvalidDescription(
+                    "a compute pipeline needs a compute shader",
+                ))?;
+        let layout = PipelineLayout::new(device, &[shader.reflection()])?;
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(shader.stage_info())
+            .layout(layout.raw());
+        let raw = unsafe {
+            device.raw().create_compute_pipelines(
+                cache.map_or(vk::PipelineCache::null(), PipelineCache::raw),
+                &[create_info],
+                device.allocation_callbacks(),
+            )
+        }
+        .map_err(|(_, err)| err)?[0];
+        let pipeline = Self {
+            raw: own(device, raw),
+            layout,
+            shader: shader.clone(),
+            local_size,
+        };
+        if let Some(name) = name {
+            pipeline.set_debug_name(name);
+        }
+        Ok(Arc::new(pipeline))
+    }
+
+    pub fn pipeline_layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+    pub fn shader(&self) -> &Arc<ShaderModule> {
+        &self.shader
+    }
+    /// Names the pipeline and its layout, see [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+        self.raw.owner.set_debug_name(self.layout.raw(), name);
+    }
+    pub fn local_size(&self) -> [u32; 3] {
+        self.local_size
+    }
+    /// Workgroups needed to cover `threads` invocations.
+    pub fn group_count(&self, threads: [u32; 3]) -> [u32; 3] {
+        group_count(threads, self.local_size)
+    }
+}
+
+impl Pipeline for ComputePipeline {
+    fn raw(&self) -> vk::Pipeline {
+        *self.raw
+    }
+    fn layout(&self) -> vk::PipelineLayout {
+        self.layout.raw()
+    }
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::COMPUTE
+    }
+    fn push_constants(&self) -> Option<vk::PushConstantRange> {
+        self.layout.push_constants()
+    }
+}
+
+/// Runs `pipeline` over `threads` invocations on `queue` and waits for it, for tools and tests.
+///
+/// `record` binds what the shader uses; the pipeline is already bound. Host reads of what the
+/// shader writes are made visible.
+/// # Errors
+/// If recording, submitting or waiting fails.
+pub fn run_compute(
+    queue: &Queue,
+    pipeline: &Arc<ComputePipeline>,
+    threads: [u32; 3],
+    record: impl FnOnce(&mut CommandBuffer),
+) -> Result<(), VkError> {
+    let pool = CommandPool::new(&pipeline.raw.owner, queue.family_index())?;
+    let mut command_buffer = pool.primary()?;
+    command_buffer.bind_pipeline(pipeline);
+    record(&mut command_buffer);
+    command_buffer.dispatch_threads(pipeline, threads);
+    command_buffer.memory_barrier(
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::AccessFlags::SHADER_WRITE,
+        vk::PipelineStageFlags::HOST,
+        vk::AccessFlags::HOST_READ,
+    );
+    command_buffer.submit(queue)?.wait()
+}
+
+/// Speeds up creating pipelines, across runs if saved to and loaded from disk.
+pub struct PipelineCache {
+    raw: Owned<vk::PipelineCache>,
+}
+
+impl std::fmt::Debug for PipelineCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PipelineCache")
+            .field("raw", &*self.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PipelineCache {
+    /// An empty cache.
+    /// # Errors
+    /// If the cache cannot be created.
+    pub fn new(device: &Arc<Device>) -> Result<Self, PipelineError> {
+        Self::with_data(device, &[])
+    }
+
+    /// Starts from the cache saved at `path`. It is ignored if it does not exist or was saved
+    /// by another device or driver.
+    /// # Errors
+    /// If the file cannot be read, or the cache cannot be created.
+    pub fn load(device: &Arc<Device>, path: &Path) -> Result<Self, PipelineError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        let properties = device.physical_device().properties();
+        if is_compatible(&data, properties) {
+            Self::with_data(device, &data)
+        } else {
+            tracing::info!("Ignoring incompatible pipeline cache {}", path.display());
+            Self::new(device)
+        }
+    }
+
+    fn with_data(device: &Arc<Device>, data: &[u8]) -> Result<Self, PipelineError> {
+        let raw = unsafe {
+            device.raw().create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo::default().initial_data(data),
+                device.allocation_callbacks(),
+            )
+        }?;
+        Ok(Self {
+            raw: own(device, raw),
+        })
+    }
+
+    pub fn raw(&self) -> vk::PipelineCache {
+        *self.raw
+    }
+
+    /// # Errors
+    /// If the device is out of memory.
+    pub fn data(&self) -> Result<Vec<u8>, PipelineError> {
+        Ok(unsafe { self.raw.owner.raw().get_pipeline_cache_data(*self.raw) }?)
+    }
+
+    /// Writes the cache to `path`, replacing it at once so a crash does not leave half a file.
+    /// # Errors
+    /// If the data cannot be retrieved or written.
+    pub fn save(&self, path: &Path) -> Result<(), PipelineError> {
+        let data = self.data()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, data)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Whether the header of `data` matches the device, see `VkPipelineCacheHeaderVersionOne`.
+fn is_compatible(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    const HEADER_SIZE: usize = 32;
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let word = |i: usize| u32::from_ne_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+    word(0) as usize >= HEADER_SIZE
+        && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(2) == properties.vendor_id
+        && word(3) == properties.device_id
+        && data[16..32] == properties.pipeline_cache_uuid
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::selection::DeviceRequirements;
+    use crate::shader::test::{compute_shader, vertex_shader};
+    use spirv_reflect::requirements::VulkanFeature;
+
+    #[test]
+    fn reflected_layout() {
+        let vertex = ShaderReflection::new(&vertex_shader()).unwrap();
+        let mut fragment = vertex.clone();
+        fragment.stage = vk::ShaderStageFlags::FRAGMENT;
+        fragment.push_constants = Some(spirv_reflect::PushConstantInfo {
+            offset: 0,
+            size: 16,
+        });
+        let sets = merge_descriptor_sets(&[&vertex, &fragment]).unwrap();
+        assert_eq!(
+            sets[&1][&2].stages,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+        let range = push_constant_range(&[&vertex, &fragment]).unwrap();
+        assert_eq!(range.stage_flags, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(range.size, 16);
+
+        let mut conflicting = fragment.clone();
+        conflicting
+            .descriptor_sets
+            .get_mut(&1)
+            .unwrap()
+            .get_mut(&2)
+            .unwrap()
+            .ty = spirv_reflect::DescriptorType::StorageBuffer;
+        assert!(matches!(
+            merge_descriptor_sets(&[&vertex, &conflicting]),
+            Err(PipelineError::ConflictingBinding {
+                set: 1,
+                binding: 2,
+                ..
+            })
+        ));
+
+        // vec3, then the two columns of a mat2.
+        let input =
+            VertexInput::interleaved(&vertex.vertex_attributes, vk::VertexInputRate::VERTEX);
+        let offsets = input
+            .attributes
+            .iter()
+            .map(|x| x.offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 12, 20]);
+        assert_eq!(input.bindings[0].stride, 28);
+    }
+
+    #[test]
+    fn cache_header() {
+        let properties = vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 42,
+            pipeline_cache_uuid: [7; 16],
+            ..Default::default()
+        };
+        let mut data = vec![];
+        for word in [32, 1, 0x10de, 42] {
+            data.extend_from_slice(&u32::to_ne_bytes(word));
+        }
+        data.extend_from_slice(&[7; 16]);
+        data.extend_from_slice(&[0; 64]);
+        assert!(is_compatible(&data, &properties));
+        assert!(!is_compatible(&data[..20], &properties));
+        data[16] = 8;
+        assert!(!is_compatible(&data, &properties));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn create_and_cache() {
+        let (device, _) = crate::test_support::device(
+            "pipeline test",
+            DeviceRequirements::default()
+                .require_feature(VulkanFeature::Vulkan13("dynamicRendering")),
+            vk::QueueFlags::GRAPHICS,
+        );
+        let path = std::env::temp_dir()
+            .join("horizon-pipeline-test")
+            .join("cache.bin");
+        let cache = PipelineCache::load(&device, &path).unwrap();
+        let vertex = ShaderModule::new(&device, &vertex_shader(), Some("vertex")).unwrap();
+        let formats = RenderingFormats {
+            color: vec![vk::Format::R8G8B8A8_UNORM],
+            ..Default::default()
+        };
+        let description = GraphicsPipelineDescription::new(vec![vertex], formats)
+            .blend(BlendMode::Alpha)
+            .cull_mode(vk::CullModeFlags::NONE);
+        let pipeline = GraphicsPipeline::new(&device, &description, Some(&cache)).unwrap();
+        assert_eq!(pipeline.pipeline_layout().set_layouts().len(), 2);
+        assert_eq!(pipeline.bind_point(), vk::PipelineBindPoint::GRAPHICS);
+
+        cache.save(&path).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(is_compatible(&data, device.physical_device().properties()));
+        PipelineCache::load(&device, &path).unwrap();
+    }
+
+    #[test]
+    fn group_counts() {
+        assert_eq!(group_count([256, 1, 1], [64, 1, 1]), [4, 1, 1]);
+        assert_eq!(group_count([257, 9, 0], [64, 8, 1]), [5, 2, 0]);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn run_compute_doubles() {
+        let (device, queue) = crate::test_support::device(
+            "compute test",
+            crate::selection::DeviceRequirements::default(),
+            vk::QueueFlags::COMPUTE,
+        );
+        let allocator = crate::memory::Allocator::new(&device, Default::default());
+
+        let shader = ShaderModule::new(&device, &compute_shader(), None).unwrap();
+        let pipeline = ComputePipeline::new(&device, &shader, None, Some("double")).unwrap();
+        assert_eq!(pipeline.group_count([256, 1, 1]), [4, 1, 1]);
+        let data = (0..256).collect::<Vec<u32>>();
+        let buffer = Arc::new(
+            crate::buffer::Buffer::from_slice(
+                &allocator,
+                &queue,
+                &data,
+                // The storage binding takes a buffer that could also be a uniform buffer.
+                crate::buffer::BufferDescription::new(crate::memory::MemoryUsage::Dynamic)
+                    .uniform()
+                    .storage(),
+            )
+            .unwrap(),
+        );
+
+        let descriptors = crate::descriptor::DescriptorAllocator::new(&device);
+        let set = descriptors
+            .allocate(pipeline.pipeline_layout(), 0, Some("double"))
+            .unwrap();
+        assert!(matches!(
+            set.write().buffer(1, &buffer),
+            Err(crate::error::DescriptorError::UnknownBinding { set: 0, binding: 1 })
+        ));
+        set.write().buffer(0, &buffer).unwrap().update();
+
+        run_compute(&queue, &pipeline, [256, 1, 1], |command_buffer| {
+            command_buffer.bind_descriptor_sets(vk::PipelineBindPoint::COMPUTE, 0, &[&set], &[]);
+        })
+        .unwrap();
+        let doubled = data.iter().map(|x| x * 2).collect::<Vec<_>>();
+        assert_eq!(buffer.read(0..data.len()).unwrap(), doubled);
+    }
+}
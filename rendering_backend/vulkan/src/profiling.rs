@@ -0,0 +1,781 @@
+//! GPU timings and pipeline statistics.
+//!
+//! A [`GpuProfiler`] keeps query pools for every frame in flight. Zones record a timestamp at
+//! their start and end, and optionally pipeline statistics. The results of a frame are read
+//! when its slot is used again, so they arrive `frames_in_flight` frames late without waiting
+//! on the device. They are emitted to `tracing` as nested `gpu_zone` spans under a `gpu_frame`
+//! span, with the `gpu` target.
+//!
+//! GPU timestamps are not calibrated against the CPU clock, so the spans are only created when
+//! the results are read and their own timing is meaningless. The GPU times are in their
+//! `gpu_start_us` and `gpu_duration_us` fields, relative to the start of the frame's first zone.
+//!
+//! ```ignore
+//! let profiler = GpuProfiler::new(&device, frames.frames_in_flight(), ProfilerConfig::default())?;
+//! let mut frame = frames.begin_frame()?;
+//! let mut command_buffer = pools.current(queue.family_index())?.primary()?;
+//! profiler.begin_frame(&frame, &mut command_buffer)?;
+//! {
+//!     let mut zone = profiler.zone(&mut command_buffer, "shadows");
+//!     zone.draw(0..3, 0..1);
+//! }
+//! frame.submit(&queue, QueueSubmit::new(&device).command_buffer(command_buffer))?;
+//! ```
+use super::command::CommandBuffer;
+use super::device::Device;
+use super::error::{InitError, VkError};
+use super::handle::{own, Owned};
+use super::sync::Frame;
+use ash::vk;
+use spirv_reflect::requirements::VulkanFeature;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Queries of one type, destroyed when dropped.
+pub struct QueryPool {
+    raw: Owned<vk::QueryPool>,
+    ty: vk::QueryType,
+    count: u32,
+    statistics: vk::QueryPipelineStatisticFlags,
+}
+
+impl std::fmt::Debug for QueryPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryPool")
+            .field("raw", &*self.raw)
+            .field("ty", &self.ty)
+            .field("count", &self.count)
+            .field("statistics", &self.statistics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QueryPool {
+    /// `statistics` is only used by [`vk::QueryType::PIPELINE_STATISTICS`] pools. `name` is
+    /// shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If the pool cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        ty: vk::QueryType,
+        count: u32,
+        statistics: vk::QueryPipelineStatisticFlags,
+        name: Option<&str>,
+    ) -> Result<Self, VkError> {
+        let statistics = if ty == vk::QueryType::PIPELINE_STATISTICS {
+            statistics
+        } else {
+            vk::QueryPipelineStatisticFlags::empty()
+        };
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(ty)
+            .query_count(count)
+            .pipeline_statistics(statistics);
+        let raw = unsafe {
+            device
+                .raw()
+                .create_query_pool(&create_info, device.allocation_callbacks())
+        }?;
+        if let Some(name) = name {
+            device.set_debug_name(raw, name);
+        }
+        Ok(Self {
+            raw: own(device, raw),
+            ty,
+            count,
+            statistics,
+        })
+    }
+
+    pub fn raw(&self) -> vk::QueryPool {
+        *self.raw
+    }
+    pub fn query_type(&self) -> vk::QueryType {
+        self.ty
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    /// Values every query returns.
+    pub fn values_per_query(&self) -> usize {
+        match self.ty {
+            vk::QueryType::PIPELINE_STATISTICS => self.statistics.as_raw().count_ones() as usize,
+            _ => 1,
+        }
+    }
+
+    /// Records resetting `queries`, which has to happen before they are used again.
+    pub fn reset(&self, command_buffer: &mut CommandBuffer, queries: std::ops::Range<u32>) {
+        unsafe {
+            self.raw.owner.raw().cmd_reset_query_pool(
+                command_buffer.raw(),
+                *self.raw,
+                queries.start,
+                queries.len() as u32,
+            )
+        };
+    }
+
+    /// The 64 bit results of `queries`, [`Self::values_per_query`] for each, or `None` if they
+    /// are not all available yet.
+    /// # Errors
+    /// If the device is lost.
+    pub fn results(&self, queries: std::ops::Range<u32>) -> Result<Option<Vec<u64>>, VkError> {
+        let stride = self.values_per_query();
+        let mut data = vec![0u64; queries.len() * stride];
+        if data.is_empty() {
+            return Ok(Some(data));
+        }
+        // `ash` derives the query count from the element type, which cannot hold a runtime
+        // number of statistics.
+        let result = unsafe {
+            (self.raw.owner.raw().fp_v1_0().get_query_pool_results)(
+                self.raw.owner.raw().handle(),
+                *self.raw,
+                queries.start,
+                queries.len() as u32,
+                std::mem::size_of_val(data.as_slice()),
+                data.as_mut_ptr().cast(),
+                (stride * std::mem::size_of::<u64>()) as u64,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match result {
+            vk::Result::SUCCESS => Ok(Some(data)),
+            vk::Result::NOT_READY => Ok(None),
+            err => Err(err.into()),
+        }
+    }
+
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfilerConfig {
+    /// Zones per frame, later ones are not timed.
+    pub max_zones: u32,
+    /// Collected by [`GpuProfiler::zone_with_statistics`]. Needs
+    /// [`GpuProfiler::STATISTICS_FEATURE`] if not empty.
+    pub statistics: vk::QueryPipelineStatisticFlags,
+}
+
+impl Default for ProfilerConfig {
+    fn default() -> Self {
+        Self {
+            max_zones: 256,
+            statistics: vk::QueryPipelineStatisticFlags::empty(),
+        }
+    }
+}
+
+impl ProfilerConfig {
+    /// Vertex, primitive and shader invocation counts.
+    #[must_use]
+    pub fn with_common_statistics(mut self) -> Self {
+        self.statistics = vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES
+            | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES
+            | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS
+            | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS;
+        self
+    }
+}
+
+/// A zone of a profiled frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneTiming {
+    pub name: String,
+    /// Number of zones it is nested in.
+    pub depth: u32,
+    /// GPU time since the start of the first zone of the frame.
+    pub start: Duration,
+    /// GPU time.
+    pub duration: Duration,
+    /// Every collected statistic with its value.
+    pub statistics: Vec<(vk::QueryPipelineStatisticFlags, u64)>,
+}
+
+/// The zones of a frame, in the order they were begun.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameTimings {
+    /// See [`Frame::number`].
+    pub frame: u64,
+    pub zones: Vec<ZoneTiming>,
+}
+
+impl FrameTimings {
+    /// Emits the zones as `gpu_zone` spans nested like the zones, inside a `gpu_frame` span.
+    /// The spans open and close right away; the GPU times are in their `gpu_start_us` and
+    /// `gpu_duration_us` fields.
+    pub fn emit(&self) {
+        let frame = tracing::debug_span!(target: "gpu", "gpu_frame", frame = self.frame);
+        let _frame = frame.enter();
+        let mut open: Vec<(u32, tracing::span::EnteredSpan)> = vec![];
+        for zone in &self.zones {
+            while open.last().is_some_and(|(depth, _)| *depth >= zone.depth) {
+                open.pop();
+            }
+            let span = tracing::debug_span!(
+                target: "gpu",
+                "gpu_zone",
+                name = %zone.name,
+                gpu_start_us = zone.start.as_secs_f64() * 1e6,
+                gpu_duration_us = zone.duration.as_secs_f64() * 1e6,
+            )
+            .entered();
+            if zone.statistics.is_empty() {
+                tracing::debug!(target: "gpu", "{}: {:?}", zone.name, zone.duration);
+            } else {
+                tracing::debug!(
+                    target: "gpu",
+                    statistics = ?zone.statistics,
+                    "{}: {:?}",
+                    zone.name,
+                    zone.duration
+                );
+            }
+            open.push((zone.depth, span));
+        }
+    }
+}
+
+struct Zone {
+    name: String,
+    depth: u32,
+    valid_bits: u32,
+    /// The query of the pipeline statistics pool.
+    statistics: Option<u32>,
+}
+
+/// Queries of one frame in flight.
+struct Slot {
+    timestamps: Arc<QueryPool>,
+    statistics: Option<Arc<QueryPool>>,
+    zones: Vec<Zone>,
+    frame: u64,
+}
+
+#[derive(Default)]
+struct Recording {
+    slot: Option<usize>,
+    depth: u32,
+    statistics_active: bool,
+    overflowed: bool,
+}
+
+/// Times zones of command buffers, see the [module](self) documentation.
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    slots: RefCell<Vec<Slot>>,
+    recording: RefCell<Recording>,
+    max_zones: u32,
+    statistics: vk::QueryPipelineStatisticFlags,
+    last: Option<FrameTimings>,
+}
+
+impl std::fmt::Debug for GpuProfiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuProfiler")
+            .field("frames_in_flight", &self.slots.borrow().len())
+            .field("max_zones", &self.max_zones)
+            .field("statistics", &self.statistics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GpuProfiler {
+    pub const STATISTICS_FEATURE: VulkanFeature = VulkanFeature::Core("pipelineStatisticsQuery");
+
+    /// # Errors
+    /// If statistics are requested without [`Self::STATISTICS_FEATURE`], the device has no
+    /// timestamp period, or the query pools cannot be created.
+    /// # Panics
+    /// If `frames_in_flight` is zero.
+    pub fn new(
+        device: &Arc<Device>,
+        frames_in_flight: usize,
+        config: ProfilerConfig,
+    ) -> Result<Self, InitError> {
+        assert!(
+            frames_in_flight > 0,
+            "At least one frame has to be in flight"
+        );
+        if !config.statistics.is_empty()
+            && !device
+                .enabled_features()
+                .get(&Self::STATISTICS_FEATURE)
+                .unwrap_or(false)
+        {
+            return Err(InitError::FeatureNotEnabled(Self::STATISTICS_FEATURE));
+        }
+        if device.physical_device().limits().timestamp_period <= 0.0 {
+            return Err(InitError::LimitExceeded {
+                limit: "timestampPeriod",
+                required: 1,
+                supported: 0,
+            });
+        }
+        let slots = (0..frames_in_flight)
+            .map(|index| {
+                let timestamps = QueryPool::new(
+                    device,
+                    vk::QueryType::TIMESTAMP,
+                    config.max_zones * 2,
+                    vk::QueryPipelineStatisticFlags::empty(),
+                    Some(&format!("profiler timestamps {index}")),
+                )?;
+                let statistics = (!config.statistics.is_empty())
+                    .then(|| {
+                        QueryPool::new(
+                            device,
+                            vk::QueryType::PIPELINE_STATISTICS,
+                            config.max_zones,
+                            config.statistics,
+                            Some(&format!("profiler statistics {index}")),
+                        )
+                    })
+                    .transpose()?;
+                Ok(Slot {
+                    timestamps: Arc::new(timestamps),
+                    statistics: statistics.map(Arc::new),
+                    zones: vec![],
+                    frame: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, VkError>>()?;
+        Ok(Self {
+            device: device.clone(),
+            slots: RefCell::new(slots),
+            recording: RefCell::default(),
+            max_zones: config.max_zones,
+            statistics: config.statistics,
+            last: None,
+        })
+    }
+
+    /// Reads the timings of the frame that last used `frame`'s slot and emits them, then
+    /// records resetting the slot's queries into `command_buffer`. It has to be submitted before
+    /// the zones of the frame execute.
+    ///
+    /// Call it after [`FrameContext::begin_frame`](super::sync::FrameContext::begin_frame), which
+    /// waits for the slot.
+    /// # Errors
+    /// If the device is lost.
+    /// # Panics
+    /// If a zone is still open.
+    pub fn begin_frame(
+        &mut self,
+        frame: &Frame<'_>,
+        command_buffer: &mut CommandBuffer,
+    ) -> Result<Option<&FrameTimings>, VkError> {
+        let recording = self.recording.get_mut();
+        assert_eq!(recording.depth, 0, "A zone is still open");
+        *recording = Recording {
+            slot: Some(frame.index()),
+            ..Recording::default()
+        };
+        let period = self.device.physical_device().limits().timestamp_period;
+        let slot = &mut self.slots.get_mut()[frame.index()];
+        let timings = read_slot(slot, period)?;
+        slot.zones.clear();
+        slot.frame = frame.number();
+        slot.timestamps
+            .reset(command_buffer, 0..slot.timestamps.count());
+        command_buffer.keep_alive(slot.timestamps.clone());
+        if let Some(statistics) = &slot.statistics {
+            statistics.reset(command_buffer, 0..statistics.count());
+            command_buffer.keep_alive(statistics.clone());
+        }
+        match timings {
+            Some(timings) => {
+                timings.emit();
+                self.last = Some(timings);
+                Ok(self.last.as_ref())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The latest timings read by [`Self::begin_frame`].
+    pub fn last_frame(&self) -> Option<&FrameTimings> {
+        self.last.as_ref()
+    }
+
+    /// Times the commands recorded through the returned zone, which also labels them, see
+    /// [`CommandBuffer::label`].
+    ///
+    /// Zones past [`ProfilerConfig::max_zones`], before [`Self::begin_frame`], or on queues
+    /// without timestamps are only labeled.
+    pub fn zone<'a>(
+        &'a self,
+        command_buffer: &'a mut CommandBuffer,
+        name: impl Into<String>,
+    ) -> GpuZone<'a> {
+        self.begin_zone(command_buffer, name.into(), false)
+    }
+
+    /// Like [`Self::zone`], and collects [`ProfilerConfig::statistics`]. Pipeline statistics
+    /// cannot be nested, and have to begin and end in the same subpass.
+    /// # Panics
+    /// If no statistics were configured, or another zone collecting them is open.
+    pub fn zone_with_statistics<'a>(
+        &'a self,
+        command_buffer: &'a mut CommandBuffer,
+        name: impl Into<String>,
+    ) -> GpuZone<'a> {
+        assert!(
+            !self.statistics.is_empty(),
+            "The profiler collects no statistics"
+        );
+        self.begin_zone(command_buffer, name.into(), true)
+    }
+
+    fn begin_zone<'a>(
+        &'a self,
+        command_buffer: &'a mut CommandBuffer,
+        name: String,
+        statistics: bool,
+    ) -> GpuZone<'a> {
+        command_buffer.begin_label(&name, [0.0; 4]);
+        let span = tracing::trace_span!(target: "gpu", "record_gpu_zone", name = %name).entered();
+        let mut recording = self.recording.borrow_mut();
+        let valid_bits = self.device.physical_device().queue_families()
+            [command_buffer.family_index() as usize]
+            .timestamp_valid_bits;
+        let mut slots = self.slots.borrow_mut();
+        let query = match recording.slot {
+            Some(slot) if valid_bits > 0 => {
+                let slot = &mut slots[slot];
+                let index = slot.zones.len() as u32;
+                if index < self.max_zones {
+                    Some((slot, index))
+                } else {
+                    if !recording.overflowed {
+                        tracing::warn!(
+                            target: "gpu",
+                            "More than {} GPU zones in frame {}, the rest are not timed",
+                            self.max_zones,
+                            slot.frame
+                        );
+                    }
+                    recording.overflowed = true;
+                    None
+                }
+            }
+            _ => None,
+        };
+        let query = query.map(|(slot, index)| {
+            unsafe {
+                self.device.raw().cmd_write_timestamp(
+                    command_buffer.raw(),
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    slot.timestamps.raw(),
+                    index * 2,
+                )
+            };
+            let statistics = statistics.then(|| {
+                assert!(
+                    !recording.statistics_active,
+                    "Zones collecting statistics cannot be nested"
+                );
+                recording.statistics_active = true;
+                let pool = slot.statistics.clone().unwrap();
+                unsafe {
+                    self.device.raw().cmd_begin_query(
+                        command_buffer.raw(),
+                        pool.raw(),
+                        index,
+                        vk::QueryControlFlags::empty(),
+                    )
+                };
+                pool
+            });
+            slot.zones.push(Zone {
+                name,
+                depth: recording.depth,
+                valid_bits,
+                statistics: statistics.is_some().then_some(index),
+            });
+            ZoneQuery {
+                timestamps: slot.timestamps.clone(),
+                statistics,
+                index,
+            }
+        });
+        recording.depth += 1;
+        GpuZone {
+            profiler: self,
+            command_buffer,
+            query,
+            _span: span,
+        }
+    }
+}
+
+/// Converts the queries of `slot`, `None` if it has no zones or their results are missing.
+fn read_slot(slot: &Slot, period: f32) -> Result<Option<FrameTimings>, VkError> {
+    if slot.zones.is_empty() {
+        return Ok(None);
+    }
+    let count = slot.zones.len() as u32;
+    let Some(timestamps) = slot.timestamps.results(0..count * 2)? else {
+        tracing::warn!(target: "gpu", "GPU timings of frame {} are not ready", slot.frame);
+        return Ok(None);
+    };
+    // Zones without statistics leave their queries unused, which never become available.
+    let mut statistics = vec![vec![]; slot.zones.len()];
+    if let Some(pool) = &slot.statistics {
+        let flags = statistic_flags(pool.statistics);
+        for (zone, statistics) in slot.zones.iter().zip(&mut statistics) {
+            if let Some(query) = zone.statistics {
+                if let Some(values) = pool.results(query..query + 1)? {
+                    *statistics = flags.iter().copied().zip(values).collect();
+                }
+            }
+        }
+    }
+    Ok(Some(convert(
+        slot.frame,
+        &slot.zones,
+        &timestamps,
+        statistics,
+        period,
+    )))
+}
+
+/// Turns the timestamps of `zones`, two for each, into timings.
+fn convert(
+    frame: u64,
+    zones: &[Zone],
+    timestamps: &[u64],
+    statistics: Vec<Vec<(vk::QueryPipelineStatisticFlags, u64)>>,
+    period: f32,
+) -> FrameTimings {
+    let frame_start = zones
+        .iter()
+        .enumerate()
+        .map(|(index, zone)| timestamps[index * 2] & mask(zone.valid_bits))
+        .min()
+        .unwrap_or(0);
+    FrameTimings {
+        frame,
+        zones: zones
+            .iter()
+            .zip(statistics)
+            .enumerate()
+            .map(|(index, (zone, statistics))| {
+                let begin = timestamps[index * 2];
+                let end = timestamps[index * 2 + 1];
+                ZoneTiming {
+                    name: zone.name.clone(),
+                    depth: zone.depth,
+                    start: ticks_to_duration(frame_start, begin, zone.valid_bits, period),
+                    duration: ticks_to_duration(begin, end, zone.valid_bits, period),
+                    statistics,
+                }
+            })
+            .collect(),
+    }
+}
+
+fn mask(valid_bits: u32) -> u64 {
+    if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << valid_bits) - 1
+    }
+}
+
+/// The time between two timestamps of a counter with `valid_bits` bits, which may have wrapped.
+fn ticks_to_duration(begin: u64, end: u64, valid_bits: u32, period: f32) -> Duration {
+    let ticks = end.wrapping_sub(begin) & mask(valid_bits);
+    Duration::from_nanos((ticks as f64 * f64::from(period)) as u64)
+}
+
+/// The single flags of `flags`, in the order queries return their values.
+fn statistic_flags(flags: vk::QueryPipelineStatisticFlags) -> Vec<vk::QueryPipelineStatisticFlags> {
+    (0..32)
+        .map(|bit| vk::QueryPipelineStatisticFlags::from_raw(1 << bit))
+        .filter(|x| flags.contains(*x))
+        .collect()
+}
+
+struct ZoneQuery {
+    timestamps: Arc<QueryPool>,
+    statistics: Option<Arc<QueryPool>>,
+    index: u32,
+}
+
+/// A timed region of a [`CommandBuffer`], ended when dropped. See [`GpuProfiler::zone`].
+#[must_use = "The zone ends when it is dropped"]
+pub struct GpuZone<'a> {
+    profiler: &'a GpuProfiler,
+    command_buffer: &'a mut CommandBuffer,
+    query: Option<ZoneQuery>,
+    _span: tracing::span::EnteredSpan,
+}
+
+impl std::fmt::Debug for GpuZone<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuZone")
+            .field("command_buffer", &self.command_buffer.raw())
+            .field("query", &self.query.as_ref().map(|x| x.index))
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::ops::Deref for GpuZone<'_> {
+    type Target = CommandBuffer;
+    fn deref(&self) -> &CommandBuffer {
+        self.command_buffer
+    }
+}
+impl std::ops::DerefMut for GpuZone<'_> {
+    fn deref_mut(&mut self) -> &mut CommandBuffer {
+        self.command_buffer
+    }
+}
+
+impl Drop for GpuZone<'_> {
+    fn drop(&mut self) {
+        let device = self.profiler.device.raw();
+        if let Some(query) = self.query.take() {
+            if let Some(statistics) = &query.statistics {
+                unsafe {
+                    device.cmd_end_query(self.command_buffer.raw(), statistics.raw(), query.index)
+                };
+                self.profiler.recording.borrow_mut().statistics_active = false;
+                self.command_buffer.keep_alive(statistics.clone());
+            }
+            unsafe {
+                device.cmd_write_timestamp(
+                    self.command_buffer.raw(),
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query.timestamps.raw(),
+                    query.index * 2 + 1,
+                )
+            };
+            self.command_buffer.keep_alive(query.timestamps);
+        }
+        self.profiler.recording.borrow_mut().depth -= 1;
+        self.command_buffer.end_label();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{CommandPools, QueueSubmit};
+    use crate::selection::{DeviceRequirements, Limit};
+    use crate::sync::{FrameContext, TimelineSemaphore};
+
+    #[test]
+    fn conversion() {
+        // A 36 bit counter that wrapped in the second zone.
+        let wrap = 1u64 << 36;
+        assert_eq!(
+            ticks_to_duration(wrap - 10, 30, 36, 2.0),
+            Duration::from_nanos(80)
+        );
+        assert_eq!(ticks_to_duration(5, 5, 64, 1.0), Duration::ZERO);
+        assert_eq!(
+            statistic_flags(
+                vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS
+                    | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES
+            ),
+            [
+                vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES,
+                vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS
+            ]
+        );
+
+        let zone = |name: &str, depth, statistics| Zone {
+            name: name.to_string(),
+            depth,
+            valid_bits: 64,
+            statistics,
+        };
+        let zones = [zone("frame", 0, Some(0)), zone("shadows", 1, None)];
+        let statistics = vec![
+            vec![(
+                vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS,
+                64,
+            )],
+            vec![],
+        ];
+        let timings = convert(7, &zones, &[100, 400, 150, 250], statistics, 0.5);
+        assert_eq!(timings.frame, 7);
+        assert_eq!(timings.zones[0].start, Duration::ZERO);
+        assert_eq!(timings.zones[0].duration, Duration::from_nanos(150));
+        assert_eq!(timings.zones[0].statistics.len(), 1);
+        assert_eq!(timings.zones[1].depth, 1);
+        assert_eq!(timings.zones[1].start, Duration::from_nanos(25));
+        assert_eq!(timings.zones[1].duration, Duration::from_nanos(50));
+        timings.emit();
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn zones_arrive_after_frames_in_flight() {
+        let (device, queue) = crate::test_support::device(
+            "profiling test",
+            DeviceRequirements::default()
+                .require_feature(TimelineSemaphore::FEATURE)
+                .require_limit(Limit::Custom {
+                    name: "timestampComputeAndGraphics",
+                    check: |x| x.timestamp_compute_and_graphics == vk::TRUE,
+                }),
+            vk::QueueFlags::COMPUTE,
+        );
+        assert!(matches!(
+            GpuProfiler::new(
+                &device,
+                2,
+                ProfilerConfig::default().with_common_statistics()
+            ),
+            Err(InitError::FeatureNotEnabled(_)) | Ok(_)
+        ));
+        let mut frames = FrameContext::new(&device, 2).unwrap();
+        let mut profiler = GpuProfiler::new(&device, 2, ProfilerConfig::default()).unwrap();
+        let pools = CommandPools::new(&device);
+        for number in 1..=3 {
+            let mut frame = frames.begin_frame().unwrap();
+            let mut command_buffer = pools
+                .current(queue.family_index())
+                .unwrap()
+                .primary()
+                .unwrap();
+            let timings = profiler
+                .begin_frame(&frame, &mut command_buffer)
+                .unwrap()
+                .cloned();
+            // The first frame is read when its slot is used again.
+            assert_eq!(timings.map(|x| x.frame), (number == 3).then_some(1));
+            {
+                let mut outer = profiler.zone(&mut command_buffer, "outer");
+                let inner = profiler.zone(&mut outer, "inner");
+                assert_eq!(inner.open_labels(), 2);
+            }
+            frame
+                .submit(
+                    &queue,
+                    QueueSubmit::new(&device).command_buffer(command_buffer),
+                )
+                .unwrap();
+        }
+        let timings = profiler.last_frame().unwrap();
+        let names = timings
+            .zones
+            .iter()
+            .map(|x| (x.name.as_str(), x.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("outer", 0), ("inner", 1)]);
+        assert!(timings.zones[1].duration <= timings.zones[0].duration);
+        frames.wait_idle().unwrap();
+    }
+}
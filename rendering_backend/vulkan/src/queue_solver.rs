@@ -0,0 +1,345 @@
+//! Assigns requested queues to queue families.
+//!
+//! Kept free of any Vulkan handles so it can be tested against synthetic
+//! `QueueFamilyProperties` tables.
+use ash::vk;
+
+/// A group of queues that has to come from a single family.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueueRequest {
+    pub flags: vk::QueueFlags,
+    /// Families that can serve this request, see [`candidate_families`].
+    pub candidates: Vec<u32>,
+    /// One entry per requested queue.
+    pub priorities: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueueAssignment {
+    pub family: u32,
+    /// Index within the family of each requested queue, in the order of the priorities.
+    pub queue_indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueueLayout {
+    /// Queues to create, one entry per used family with the priority of each of its queues.
+    /// This maps directly onto `DeviceQueueCreateInfo`.
+    pub families: Vec<(u32, Vec<f32>)>,
+    /// One entry per request, in the order of the requests.
+    pub assignments: Vec<QueueAssignment>,
+    /// Whether some queues had to be shared by several requests, because the families do not
+    /// have enough queues. Submissions to shared queues have to be synchronized by the caller.
+    pub shared: bool,
+}
+
+/// Families whose capabilities contain `flags`, that have queues, and that can present if
+/// `present_support` is given.
+pub(crate) fn candidate_families(
+    families: &[vk::QueueFamilyProperties],
+    flags: vk::QueueFlags,
+    present_support: Option<&[bool]>,
+) -> Vec<u32> {
+    families
+        .iter()
+        .enumerate()
+        .filter(|(index, family)| {
+            family.queue_count > 0
+                && family.queue_flags.contains(flags)
+                && present_support.is_none_or(|x| x.get(*index).copied().unwrap_or(false))
+        })
+        .map(|(index, _)| index as u32)
+        .collect()
+}
+
+/// Assigns every request to a family.
+///
+/// Each request gets its own queues if the families have enough of them. Among those
+/// assignments families with the fewest capabilities beyond the requested ones are preferred,
+/// so a transfer request lands on a dedicated transfer family when there is one. If there are
+/// not enough queues, requests share the queues of their preferred family and each shared
+/// queue gets the highest priority requested for it.
+/// # Errors
+/// Returns the index of a request without candidate families.
+pub(crate) fn solve(
+    families: &[vk::QueueFamilyProperties],
+    requests: &[QueueRequest],
+) -> Result<QueueLayout, usize> {
+    if let Some(index) = requests.iter().position(|x| x.candidates.is_empty()) {
+        return Err(index);
+    }
+    let preferences = requests
+        .iter()
+        .map(|request| {
+            let mut candidates = request.candidates.clone();
+            candidates.sort_by_key(|x| {
+                let extra = families[*x as usize].queue_flags.as_raw() & !request.flags.as_raw();
+                (extra.count_ones(), *x)
+            });
+            candidates
+        })
+        .collect::<Vec<_>>();
+
+    // Most constrained requests first.
+    let mut order = (0..requests.len()).collect::<Vec<_>>();
+    order.sort_by_key(|x| preferences[*x].len());
+
+    let mut remaining = families.iter().map(|x| x.queue_count).collect::<Vec<_>>();
+    let mut choice = vec![0; requests.len()];
+    let shared = !assign_exclusive(&order, requests, &preferences, &mut remaining, &mut choice);
+    if shared {
+        for (index, preference) in preferences.iter().enumerate() {
+            choice[index] = preference[0];
+        }
+    }
+
+    let mut used = vec![0u32; families.len()];
+    let mut priorities: Vec<Vec<f32>> = vec![vec![]; families.len()];
+    let assignments = requests
+        .iter()
+        .zip(&choice)
+        .map(|(request, &family)| {
+            let queue_count = families[family as usize].queue_count;
+            let queue_indices = queue_priorities(request)
+                .map(|priority| {
+                    let index = used[family as usize] % queue_count;
+                    used[family as usize] += 1;
+                    let family_priorities = &mut priorities[family as usize];
+                    match family_priorities.get_mut(index as usize) {
+                        Some(x) => *x = x.max(priority),
+                        None => family_priorities.push(priority),
+                    }
+                    index
+                })
+                .collect();
+            QueueAssignment {
+                family,
+                queue_indices,
+            }
+        })
+        .collect();
+
+    Ok(QueueLayout {
+        families: priorities
+            .into_iter()
+            .enumerate()
+            .filter(|(_, x)| !x.is_empty())
+            .map(|(family, x)| (family as u32, x))
+            .collect(),
+        assignments,
+        shared,
+    })
+}
+
+/// A request without priorities asks for a single queue.
+fn queue_priorities(request: &QueueRequest) -> impl Iterator<Item = f32> + '_ {
+    let default: &[f32] = &[0.0];
+    let priorities = if request.priorities.is_empty() {
+        default
+    } else {
+        &request.priorities
+    };
+    priorities.iter().map(|x| x.clamp(0.0, 1.0))
+}
+
+fn queue_count(request: &QueueRequest) -> u32 {
+    request.priorities.len().max(1) as u32
+}
+
+/// Backtracking search for an assignment where no queue is shared.
+fn assign_exclusive(
+    order: &[usize],
+    requests: &[QueueRequest],
+    preferences: &[Vec<u32>],
+    remaining: &mut [u32],
+    choice: &mut [u32],
+) -> bool {
+    let Some((&request, rest)) = order.split_first() else {
+        return true;
+    };
+    let count = queue_count(&requests[request]);
+    for &family in &preferences[request] {
+        if remaining[family as usize] < count {
+            continue;
+        }
+        remaining[family as usize] -= count;
+        choice[request] = family;
+        if assign_exclusive(rest, requests, preferences, remaining, choice) {
+            return true;
+        }
+        remaining[family as usize] += count;
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn family(flags: vk::QueueFlags, queue_count: u32) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags: flags,
+            queue_count,
+            ..Default::default()
+        }
+    }
+
+    const GCT: vk::QueueFlags = vk::QueueFlags::from_raw(
+        vk::QueueFlags::GRAPHICS.as_raw()
+            | vk::QueueFlags::COMPUTE.as_raw()
+            | vk::QueueFlags::TRANSFER.as_raw(),
+    );
+    const CT: vk::QueueFlags = vk::QueueFlags::from_raw(
+        vk::QueueFlags::COMPUTE.as_raw() | vk::QueueFlags::TRANSFER.as_raw(),
+    );
+
+    /// Roughly what discrete desktop GPUs expose.
+    fn discrete() -> Vec<vk::QueueFamilyProperties> {
+        vec![
+            family(GCT, 16),
+            family(vk::QueueFlags::TRANSFER, 2),
+            family(CT, 8),
+        ]
+    }
+
+    fn request(
+        families: &[vk::QueueFamilyProperties],
+        flags: vk::QueueFlags,
+        priorities: &[f32],
+    ) -> QueueRequest {
+        QueueRequest {
+            flags,
+            candidates: candidate_families(families, flags, None),
+            priorities: priorities.to_vec(),
+        }
+    }
+
+    #[test]
+    fn prefers_dedicated_families() {
+        let families = discrete();
+        let requests = [
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::TRANSFER, &[0.5]),
+            request(&families, vk::QueueFlags::COMPUTE, &[0.5, 0.25]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        let chosen = layout
+            .assignments
+            .iter()
+            .map(|x| x.family)
+            .collect::<Vec<_>>();
+        assert_eq!(chosen, vec![0, 1, 2]);
+        assert_eq!(
+            layout.families,
+            vec![(0, vec![1.0]), (1, vec![0.5]), (2, vec![0.5, 0.25])]
+        );
+        assert!(!layout.shared);
+    }
+
+    #[test]
+    fn groups_requests_per_family() {
+        let families = vec![family(GCT, 4)];
+        let requests = [
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::COMPUTE, &[0.5, 0.75]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        // A single create info for the family, with the priorities of every request.
+        assert_eq!(layout.families, vec![(0, vec![1.0, 0.5, 0.75])]);
+        assert_eq!(layout.assignments[0].queue_indices, vec![0]);
+        assert_eq!(layout.assignments[1].queue_indices, vec![1, 2]);
+        assert!(!layout.shared);
+    }
+
+    #[test]
+    fn respects_queue_counts() {
+        // The graphics family only has one queue, so the second graphics request has to go to
+        // the other family even though it has more capabilities than needed.
+        let families = vec![family(vk::QueueFlags::GRAPHICS, 1), family(GCT, 2)];
+        let requests = [
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::COMPUTE, &[0.5]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        let chosen = layout
+            .assignments
+            .iter()
+            .map(|x| (x.family, x.queue_indices.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(chosen, vec![(0, vec![0]), (1, vec![0]), (1, vec![1])]);
+        assert!(!layout.shared);
+    }
+
+    #[test]
+    fn backtracks_when_greedy_choice_fails() {
+        let gc = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE;
+        let families = vec![family(gc, 2), family(GCT, 1)];
+        // The first request prefers the first family, but then the second one does not fit
+        // anywhere.
+        let requests = [
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0, 1.0]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        assert_eq!(layout.assignments[0].family, 1);
+        assert_eq!(layout.assignments[1].family, 0);
+        assert!(!layout.shared);
+    }
+
+    #[test]
+    fn shares_queues_when_there_are_too_few() {
+        // Typical for integrated GPUs.
+        let families = vec![family(GCT, 1)];
+        let requests = [
+            request(&families, vk::QueueFlags::GRAPHICS, &[0.5]),
+            request(&families, vk::QueueFlags::COMPUTE, &[1.0]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        assert!(layout.shared);
+        assert_eq!(layout.families, vec![(0, vec![1.0])]);
+        assert_eq!(layout.assignments[0].queue_indices, vec![0]);
+        assert_eq!(layout.assignments[1].queue_indices, vec![0]);
+
+        // Every request still gets its preferred family.
+        let families = vec![family(GCT, 1), family(CT, 1)];
+        let requests = [
+            request(&families, vk::QueueFlags::COMPUTE, &[1.0]),
+            request(&families, vk::QueueFlags::GRAPHICS, &[1.0]),
+            request(&families, vk::QueueFlags::TRANSFER, &[1.0]),
+        ];
+        let layout = solve(&families, &requests).unwrap();
+        assert!(layout.shared);
+        let chosen = layout
+            .assignments
+            .iter()
+            .map(|x| x.family)
+            .collect::<Vec<_>>();
+        assert_eq!(chosen, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn present_support() {
+        let families = discrete();
+        let present = [false, false, true];
+        let candidates = candidate_families(&families, vk::QueueFlags::COMPUTE, Some(&present));
+        assert_eq!(candidates, vec![2]);
+        let candidates = candidate_families(&families, vk::QueueFlags::GRAPHICS, Some(&present));
+        assert!(candidates.is_empty());
+        let requests = [QueueRequest {
+            flags: vk::QueueFlags::GRAPHICS,
+            candidates,
+            priorities: vec![1.0],
+        }];
+        assert_eq!(solve(&families, &requests), Err(0));
+    }
+
+    #[test]
+    fn defaults() {
+        let families = vec![family(GCT, 1)];
+        let requests = [request(&families, vk::QueueFlags::empty(), &[])];
+        let layout = solve(&families, &requests).unwrap();
+        assert_eq!(layout.families, vec![(0, vec![0.0])]);
+        assert_eq!(layout.assignments[0].queue_indices, vec![0]);
+    }
+}
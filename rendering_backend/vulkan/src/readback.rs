@@ -0,0 +1,459 @@
+//! Reading rendered images back to the host.
+//!
+//! [`read_rgba8`] copies an image to host memory and converts it to 8 bit RGBA, which can be
+//! saved as a PNG. Rendering tests compare the result against a golden image:
+//! ```ignore
+//! let target = OffscreenTarget::new(&allocator, &desc)?;
+//! // ... render into target.targets()[0] on `queue` ...
+//! let image = target.read_rgba8(0, &queue)?;
+//! readback::assert_golden(&image, "tests/golden/triangle.png", 2);
+//! ```
+//! Missing golden images are written instead of compared when `HORIZON_UPDATE_GOLDEN` is set.
+use crate::buffer::{Buffer, BufferDescription};
+use crate::command::CommandPool;
+use crate::device::Queue;
+use crate::error::ReadbackError;
+use crate::image::Image;
+use crate::memory::MemoryUsage;
+use crate::offscreen::OffscreenTarget;
+use ash::vk;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Setting it makes [`compare_golden`] write missing golden images.
+pub const UPDATE_GOLDEN_VAR: &str = "HORIZON_UPDATE_GOLDEN";
+
+/// An 8 bit RGBA image in host memory, rows from top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height * 4` bytes.
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// # Panics
+    /// If `pixels` does not hold `width * height` pixels.
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize * 4);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// # Panics
+    /// If the pixel is out of bounds.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        assert!(x < self.width && y < self.height);
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[offset..offset + 4].try_into().unwrap()
+    }
+
+    /// Writes the image as an RGBA PNG.
+    /// # Errors
+    /// If the file cannot be created or encoding fails.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), ReadbackError> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Reads a PNG, converting gray, palette and 16 bit images to 8 bit RGBA.
+    /// # Errors
+    /// If the file cannot be opened or decoded.
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Self, ReadbackError> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        data.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => data,
+            png::ColorType::Rgb => data
+                .chunks_exact(3)
+                .flat_map(|x| [x[0], x[1], x[2], u8::MAX])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => data
+                .chunks_exact(2)
+                .flat_map(|x| [x[0], x[0], x[0], x[1]])
+                .collect(),
+            png::ColorType::Grayscale => data.iter().flat_map(|&x| [x, x, x, u8::MAX]).collect(),
+            other => return Err(ReadbackError::UnsupportedPng(other)),
+        };
+        Ok(Self::new(info.width, info.height, pixels))
+    }
+}
+
+/// Copies mip level 0 and array layer 0 of `image` to the host and converts it to RGBA8.
+/// The copy is submitted to `queue` after the work already on it, and waited on.
+///
+/// The image is left in `TRANSFER_SRC_OPTIMAL`.
+/// # Errors
+/// If the format cannot be converted, or the copy fails.
+pub fn read_rgba8(queue: &Queue, image: &Arc<Image>) -> Result<RgbaImage, ReadbackError> {
+    let format = image.format();
+    let texel_size = texel_size(format).ok_or(ReadbackError::UnsupportedFormat(format))?;
+    let extent = image.extent();
+    let staging = Arc::new(Buffer::<u8>::new(
+        image.allocator(),
+        extent.width as usize * extent.height as usize * texel_size,
+        BufferDescription::new(MemoryUsage::Readback).transfer_dst(),
+    )?);
+
+    let pool = CommandPool::new(image.allocator().device(), queue.family_index())?;
+    let mut command_buffer = pool.primary()?;
+    command_buffer.memory_barrier(
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::AccessFlags::MEMORY_WRITE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_READ,
+    );
+    command_buffer.copy_image_to_buffer(
+        image,
+        &staging,
+        &[vk::BufferImageCopy {
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D { depth: 1, ..extent },
+            ..Default::default()
+        }],
+    );
+    command_buffer.memory_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::PipelineStageFlags::HOST,
+        vk::AccessFlags::HOST_READ,
+    );
+    command_buffer.submit(queue)?.wait()?;
+
+    let data = staging.read(0..staging.len())?;
+    let pixels = to_rgba8(format, &data).ok_or(ReadbackError::UnsupportedFormat(format))?;
+    Ok(RgbaImage::new(extent.width, extent.height, pixels))
+}
+
+impl OffscreenTarget {
+    /// Reads back image `index`, see [`read_rgba8`].
+    /// # Errors
+    /// If the format cannot be converted, or the copy fails.
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn read_rgba8(&self, index: usize, queue: &Queue) -> Result<RgbaImage, ReadbackError> {
+        read_rgba8(queue, &self.targets()[index])
+    }
+}
+
+/// Bytes per texel of the formats [`to_rgba8`] converts.
+fn texel_size(format: vk::Format) -> Option<usize> {
+    Some(match format {
+        vk::Format::R8_UNORM => 1,
+        vk::Format::R8G8_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => 4,
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    })
+}
+
+/// Converts tightly packed texels to RGBA8. sRGB values are kept encoded, float channels are
+/// clamped to `0..=1`. Missing channels are 0, missing alpha is opaque.
+fn to_rgba8(format: vk::Format, data: &[u8]) -> Option<Vec<u8>> {
+    let texels = data.chunks_exact(texel_size(format)?);
+    Some(match format {
+        vk::Format::R8_UNORM => texels.flat_map(|x| [x[0], 0, 0, u8::MAX]).collect(),
+        vk::Format::R8G8_UNORM => texels.flat_map(|x| [x[0], x[1], 0, u8::MAX]).collect(),
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => data.to_vec(),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+            texels.flat_map(|x| [x[2], x[1], x[0], x[3]]).collect()
+        }
+        vk::Format::R16G16B16A16_SFLOAT => texels
+            .flat_map(|x| {
+                std::array::from_fn::<_, 4, _>(|c| {
+                    unorm8(f16_to_f32(u16::from_le_bytes([x[2 * c], x[2 * c + 1]])))
+                })
+            })
+            .collect(),
+        vk::Format::R32G32B32A32_SFLOAT => texels
+            .flat_map(|x| {
+                std::array::from_fn::<_, 4, _>(|c| {
+                    unorm8(f32::from_le_bytes(x[4 * c..4 * c + 4].try_into().unwrap()))
+                })
+            })
+            .collect(),
+        _ => return None,
+    })
+}
+
+fn unorm8(value: f32) -> u8 {
+    // NaN clamps to 0.
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Compares `actual` against the PNG at `golden`, allowing every channel to differ by
+/// `tolerance`.
+///
+/// On a mismatch `actual` is written next to the golden image as `<name>.actual.png`, together
+/// with `<name>.diff.png` that marks differing pixels red over a dimmed copy of the golden image.
+/// # Errors
+/// If the golden image is missing and [`UPDATE_GOLDEN_VAR`] is not set, if the sizes or pixels
+/// differ, or if an image cannot be read or written.
+pub fn compare_golden(
+    actual: &RgbaImage,
+    golden: impl AsRef<Path>,
+    tolerance: u8,
+) -> Result<(), ReadbackError> {
+    let golden = golden.as_ref();
+    if !golden.exists() {
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_none() {
+            return Err(ReadbackError::MissingGolden(golden.to_owned()));
+        }
+        tracing::info!("Writing golden image {golden:?}");
+        if let Some(parent) = golden.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        return actual.save_png(golden);
+    }
+
+    let expected = RgbaImage::load_png(golden)?;
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        actual.save_png(sibling(golden, "actual"))?;
+        return Err(ReadbackError::SizeMismatch {
+            expected: (expected.width, expected.height),
+            actual: (actual.width, actual.height),
+        });
+    }
+
+    let mut mismatched = 0;
+    let diff = expected
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+        .flat_map(|(e, a)| {
+            if e.iter().zip(a).any(|(e, a)| e.abs_diff(*a) > tolerance) {
+                mismatched += 1;
+                [u8::MAX, 0, 0, u8::MAX]
+            } else {
+                let luma = (u32::from(e[0]) * 2 + u32::from(e[1]) * 5 + u32::from(e[2])) / 8;
+                let dimmed = (luma / 3) as u8;
+                [dimmed, dimmed, dimmed, u8::MAX]
+            }
+        })
+        .collect();
+    if mismatched == 0 {
+        return Ok(());
+    }
+
+    let diff_path = sibling(golden, "diff");
+    actual.save_png(sibling(golden, "actual"))?;
+    RgbaImage::new(actual.width, actual.height, diff).save_png(&diff_path)?;
+    Err(ReadbackError::GoldenMismatch {
+        mismatched,
+        total: actual.pixels.len() / 4,
+        tolerance,
+        diff: diff_path,
+    })
+}
+
+/// [`compare_golden`] for tests.
+/// # Panics
+/// If the comparison fails.
+#[track_caller]
+pub fn assert_golden(actual: &RgbaImage, golden: impl AsRef<Path>, tolerance: u8) {
+    if let Err(err) = compare_golden(actual, golden, tolerance) {
+        panic!("{err}");
+    }
+}
+
+/// `dir/name.png` -> `dir/name.<suffix>.png`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}.{suffix}.png"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(
+            to_rgba8(vk::Format::B8G8R8A8_UNORM, &[1, 2, 3, 4]),
+            Some(vec![3, 2, 1, 4])
+        );
+        assert_eq!(
+            to_rgba8(vk::Format::R8G8_UNORM, &[7, 9]),
+            Some(vec![7, 9, 0, 255])
+        );
+        // 1.0, 0.5, 0.0, -2.0
+        let half = [0x3c00u16, 0x3800, 0x0000, 0xc000]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to_rgba8(vk::Format::R16G16B16A16_SFLOAT, &half),
+            Some(vec![255, 128, 0, 0])
+        );
+        let float = [0.25f32, 2.0, f32::NAN, 1.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to_rgba8(vk::Format::R32G32B32A32_SFLOAT, &float),
+            Some(vec![64, 255, 0, 255])
+        );
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert_eq!(to_rgba8(vk::Format::D32_SFLOAT, &[0; 4]), None);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("horizon-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [(x * 16) as u8, (y * 16) as u8, 0, 255]))
+            .collect();
+        RgbaImage::new(width, height, pixels)
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let dir = temp_dir("png");
+        let image = gradient(5, 3);
+        image.save_png(dir.join("gradient.png")).unwrap();
+        assert_eq!(
+            RgbaImage::load_png(dir.join("gradient.png")).unwrap(),
+            image
+        );
+        assert_eq!(image.pixel(4, 2), [64, 32, 0, 255]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn golden_comparison() {
+        let dir = temp_dir("golden");
+        let golden = dir.join("gradient.png");
+        let image = gradient(4, 4);
+        assert!(matches!(
+            compare_golden(&image, &golden, 0),
+            Err(ReadbackError::MissingGolden(_))
+        ));
+        image.save_png(&golden).unwrap();
+        compare_golden(&image, &golden, 0).unwrap();
+
+        let mut changed = image.clone();
+        changed.pixels[0] += 2;
+        compare_golden(&changed, &golden, 2).unwrap();
+        changed.pixels[4] += 3;
+        match compare_golden(&changed, &golden, 2) {
+            Err(ReadbackError::GoldenMismatch {
+                mismatched, diff, ..
+            }) => {
+                assert_eq!(mismatched, 1);
+                let diff = RgbaImage::load_png(diff).unwrap();
+                assert_eq!(diff.pixel(1, 0), [255, 0, 0, 255]);
+                assert_ne!(diff.pixel(0, 0), [255, 0, 0, 255]);
+                assert_eq!(
+                    RgbaImage::load_png(dir.join("gradient.actual.png")).unwrap(),
+                    changed
+                );
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(matches!(
+            compare_golden(&gradient(2, 4), &golden, 2),
+            Err(ReadbackError::SizeMismatch { .. })
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn read_offscreen_target() {
+        use crate::image::{ImageDescription, ImageDimensions};
+        use crate::selection::DeviceRequirements;
+        use crate::sync::TimelineSemaphore;
+
+        let (device, queue) = crate::test_support::device(
+            "readback test",
+            DeviceRequirements::default().require_feature(TimelineSemaphore::FEATURE),
+            vk::QueueFlags::GRAPHICS,
+        );
+        let allocator = crate::memory::Allocator::new(&device, Default::default());
+
+        let image = Image::new(
+            &allocator,
+            &ImageDescription::new(ImageDimensions::d2(2, 2), vk::Format::B8G8R8A8_UNORM)
+                .transfer_dst()
+                .transfer_src(),
+        )
+        .unwrap();
+        let bgra = [
+            0u8, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 255, 10, 20, 30, 40,
+        ];
+        let upload = Arc::new(
+            Buffer::from_slice(
+                &allocator,
+                &queue,
+                &bgra,
+                BufferDescription::new(MemoryUsage::Upload).transfer_src(),
+            )
+            .unwrap(),
+        );
+        let pool = CommandPool::new(&device, queue.family_index()).unwrap();
+        let mut command_buffer = pool.primary().unwrap();
+        command_buffer.copy_buffer_to_image(
+            &upload,
+            &image,
+            &[vk::BufferImageCopy {
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_extent: image.extent(),
+                ..Default::default()
+            }],
+        );
+        command_buffer.submit(&queue).unwrap().wait().unwrap();
+
+        let rgba = read_rgba8(&queue, &image).unwrap();
+        assert_eq!(rgba.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(rgba.pixel(1, 0), [0, 255, 0, 255]);
+        assert_eq!(rgba.pixel(0, 1), [0, 0, 255, 255]);
+        assert_eq!(rgba.pixel(1, 1), [30, 20, 10, 40]);
+    }
+}
@@ -0,0 +1,195 @@
+//! Samplers, created once per description and shared through a [`SamplerCache`].
+use super::device::Device;
+use super::error::ImageError;
+use super::handle::{own, Owned};
+use ash::vk;
+use spirv_reflect::requirements::VulkanFeature;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How a sampler filters and addresses, the key of the [`SamplerCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerDescription {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// U, V and W.
+    pub address_mode: [vk::SamplerAddressMode; 3],
+    /// Clamped to the device limit. Ignored if `samplerAnisotropy` is not enabled.
+    pub max_anisotropy: Option<u32>,
+    /// For shadow samplers.
+    pub compare_op: Option<vk::CompareOp>,
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerDescription {
+    fn default() -> Self {
+        Self::linear()
+    }
+}
+
+impl SamplerDescription {
+    /// Trilinear filtering, repeating.
+    pub fn linear() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: [vk::SamplerAddressMode::REPEAT; 3],
+            max_anisotropy: None,
+            compare_op: None,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        }
+    }
+    /// No filtering, repeating.
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Self::linear()
+        }
+    }
+    #[must_use]
+    pub fn address_mode(mut self, mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode = [mode; 3];
+        self
+    }
+    /// Ignored unless the `samplerAnisotropy` feature was requested when creating the device.
+    #[must_use]
+    pub fn anisotropy(mut self, max: u32) -> Self {
+        self.max_anisotropy = Some(max);
+        self
+    }
+    #[must_use]
+    pub fn compare(mut self, op: vk::CompareOp) -> Self {
+        self.compare_op = Some(op);
+        self
+    }
+
+    fn create_info(
+        &self,
+        anisotropy_enabled: bool,
+        max_anisotropy: f32,
+    ) -> vk::SamplerCreateInfo<'static> {
+        let anisotropy = self
+            .max_anisotropy
+            .filter(|_| anisotropy_enabled)
+            .map(|x| (x as f32).min(max_anisotropy));
+        vk::SamplerCreateInfo::default()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode[0])
+            .address_mode_v(self.address_mode[1])
+            .address_mode_w(self.address_mode[2])
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE)
+            .border_color(self.border_color)
+    }
+}
+
+/// Creates samplers on first use and destroys them when dropped.
+pub struct SamplerCache {
+    device: Arc<Device>,
+    samplers: Mutex<HashMap<SamplerDescription, Arc<Owned<vk::Sampler>>>>,
+}
+
+impl std::fmt::Debug for SamplerCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SamplerCache")
+            .field("samplers", &self.samplers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SamplerCache {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: device.clone(),
+            samplers: Mutex::default(),
+        }
+    }
+
+    /// The sampler for `description`, valid as long as the cache is. It is named after
+    /// `description` for validation messages and graphics debuggers.
+    /// # Errors
+    /// If the sampler cannot be created.
+    pub fn get(&self, description: &SamplerDescription) -> Result<vk::Sampler, ImageError> {
+        Ok(**self.get_shared(description)?)
+    }
+
+    /// [`Self::get`], valid as long as the returned reference is, e.g. for
+    /// [`BindlessTable::add_sampler`](crate::bindless::BindlessTable::add_sampler).
+    /// # Errors
+    /// If the sampler cannot be created.
+    pub fn get_shared(
+        &self,
+        description: &SamplerDescription,
+    ) -> Result<Arc<Owned<vk::Sampler>>, ImageError> {
+        let mut samplers = self.samplers.lock().unwrap();
+        if let Some(sampler) = samplers.get(description) {
+            return Ok(sampler.clone());
+        }
+        let anisotropy_enabled = self
+            .device
+            .enabled_features()
+            .get(&VulkanFeature::Core("samplerAnisotropy"))
+            .unwrap_or(false);
+        let max_anisotropy = self
+            .device
+            .physical_device()
+            .limits()
+            .max_sampler_anisotropy;
+        let create_info = description.create_info(anisotropy_enabled, max_anisotropy);
+        let sampler = unsafe {
+            self.device
+                .raw()
+                .create_sampler(&create_info, self.device.allocation_callbacks())
+        }?;
+        self.device
+            .set_debug_name(sampler, &format!("{description:?}"));
+        let sampler = Arc::new(own(&self.device, sampler));
+        samplers.insert(*description, sampler.clone());
+        Ok(sampler)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn anisotropy() {
+        let description = SamplerDescription::linear().anisotropy(16);
+        let info = description.create_info(true, 8.0);
+        assert_eq!(info.anisotropy_enable, vk::TRUE);
+        assert_eq!(info.max_anisotropy, 8.0);
+        let info = description.create_info(false, 8.0);
+        assert_eq!(info.anisotropy_enable, vk::FALSE);
+
+        let shadow = SamplerDescription::nearest()
+            .address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .compare(vk::CompareOp::LESS);
+        let info = shadow.create_info(true, 8.0);
+        assert_eq!(info.compare_enable, vk::TRUE);
+        assert_eq!(info.anisotropy_enable, vk::FALSE);
+        assert_eq!(info.address_mode_w, vk::SamplerAddressMode::CLAMP_TO_BORDER);
+
+        let mut keys = std::collections::HashSet::new();
+        assert!(keys.insert(shadow));
+        assert!(!keys.insert(shadow));
+        assert!(keys.insert(SamplerDescription::default()));
+    }
+}
@@ -0,0 +1,601 @@
+//! Picking a physical device.
+//!
+//! [`DeviceRequirements`] declares what the application needs, each entry either required or
+//! preferred. Evaluating it against the [`PhysicalDevice`]s of an instance gives a
+//! [`SelectionReport`], which ranks the devices and records why each one was accepted or
+//! rejected.
+use super::error;
+use super::features::DeviceFeatures;
+use super::instance::Instance;
+use super::types::ExtensionName;
+use ash::vk;
+use spirv_reflect::requirements::VulkanFeature;
+use std::fmt;
+use std::sync::Arc;
+
+/// How much a requirement matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Importance {
+    /// Devices missing it are rejected.
+    Required,
+    /// Devices having it get the given score added.
+    Preferred(u32),
+}
+
+/// A physical device with everything queried that is needed to choose between devices.
+#[derive(Clone)]
+pub struct PhysicalDevice {
+    raw: vk::PhysicalDevice,
+    instance: Arc<Instance>,
+    properties: vk::PhysicalDeviceProperties,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue_families: Vec<vk::QueueFamilyProperties>,
+    features: DeviceFeatures,
+}
+
+impl fmt::Debug for PhysicalDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PhysicalDevice")
+            .field("raw", &self.raw)
+            .field("name", &self.name())
+            .field("device_type", &self.properties.device_type)
+            .field("queue_families", &self.queue_families)
+            .field("features", &self.features)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PhysicalDevice {
+    /// Every physical device of `instance`.
+    /// # Errors
+    /// Fails if the devices cannot be enumerated or queried.
+    pub fn enumerate(instance: &Arc<Instance>) -> Result<Vec<Self>, error::InitError> {
+        unsafe { instance.raw.enumerate_physical_devices() }
+            .map_err(|_| error::InitError::EnumeratePhysicalDevicesFailed)?
+            .into_iter()
+            .map(|raw| Self::new(instance, raw))
+            .collect()
+    }
+
+    fn new(instance: &Arc<Instance>, raw: vk::PhysicalDevice) -> Result<Self, error::InitError> {
+        Ok(Self {
+            raw,
+            instance: instance.clone(),
+            properties: instance.get_physical_device_properties(&raw),
+            memory_properties: unsafe { instance.raw.get_physical_device_memory_properties(raw) },
+            queue_families: instance.get_physical_device_queue_family_properties(&raw),
+            features: DeviceFeatures::query(instance, raw)?,
+        })
+    }
+
+    pub fn raw(&self) -> vk::PhysicalDevice {
+        self.raw
+    }
+    pub fn instance(&self) -> &Arc<Instance> {
+        &self.instance
+    }
+    pub fn name(&self) -> String {
+        self.properties
+            .device_name_as_c_str()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+    pub fn device_type(&self) -> vk::PhysicalDeviceType {
+        self.properties.device_type
+    }
+    pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.properties
+    }
+    pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.properties.limits
+    }
+    /// Limits of update after bind descriptors, see [`crate::bindless`].
+    pub fn descriptor_indexing_properties(
+        &self,
+    ) -> vk::PhysicalDeviceDescriptorIndexingProperties<'static> {
+        let mut indexing = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut indexing);
+        unsafe {
+            self.instance
+                .raw
+                .get_physical_device_properties2(self.raw, &mut properties)
+        };
+        indexing.p_next = std::ptr::null_mut();
+        indexing
+    }
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+    pub fn queue_families(&self) -> &[vk::QueueFamilyProperties] {
+        &self.queue_families
+    }
+    /// Everything the device supports, see [`DeviceFeatures::get`].
+    pub fn features(&self) -> &DeviceFeatures {
+        &self.features
+    }
+    pub fn format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        unsafe {
+            self.instance
+                .raw
+                .get_physical_device_format_properties(self.raw, format)
+        }
+    }
+    /// The limits of images with the given parameters, `None` if the combination is not
+    /// supported at all.
+    pub fn image_format_properties(
+        &self,
+        format: vk::Format,
+        ty: vk::ImageType,
+        tiling: vk::ImageTiling,
+        usage: vk::ImageUsageFlags,
+        flags: vk::ImageCreateFlags,
+    ) -> Option<vk::ImageFormatProperties> {
+        unsafe {
+            self.instance
+                .raw
+                .get_physical_device_image_format_properties(
+                    self.raw, format, ty, tiling, usage, flags,
+                )
+        }
+        .ok()
+    }
+    pub fn supports_format(&self, requirement: &FormatRequirement) -> bool {
+        let properties = self.format_properties(requirement.format);
+        let supported = match requirement.tiling {
+            FormatTiling::Linear => properties.linear_tiling_features,
+            FormatTiling::Optimal => properties.optimal_tiling_features,
+            FormatTiling::Buffer => properties.buffer_features,
+        };
+        supported.contains(requirement.features)
+    }
+}
+
+/// A lower bound on one of the [`vk::PhysicalDeviceLimits`].
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    MaxImageDimension2D(u32),
+    MaxPushConstantsSize(u32),
+    MaxBoundDescriptorSets(u32),
+    MaxPerStageResources(u32),
+    MaxUniformBufferRange(u32),
+    MaxStorageBufferRange(u32),
+    MaxComputeSharedMemorySize(u32),
+    MaxComputeWorkGroupInvocations(u32),
+    MaxColorAttachments(u32),
+    MaxSamplerAnisotropy(f32),
+    /// All of these sample counts are supported for color attachments.
+    FramebufferColorSampleCounts(vk::SampleCountFlags),
+    TimestampComputeAndGraphics,
+    /// Anything else, `name` is used in reports.
+    Custom {
+        name: &'static str,
+        check: fn(&vk::PhysicalDeviceLimits) -> bool,
+    },
+}
+
+impl Limit {
+    pub fn is_satisfied(&self, limits: &vk::PhysicalDeviceLimits) -> bool {
+        match *self {
+            Self::MaxImageDimension2D(x) => limits.max_image_dimension2_d >= x,
+            Self::MaxPushConstantsSize(x) => limits.max_push_constants_size >= x,
+            Self::MaxBoundDescriptorSets(x) => limits.max_bound_descriptor_sets >= x,
+            Self::MaxPerStageResources(x) => limits.max_per_stage_resources >= x,
+            Self::MaxUniformBufferRange(x) => limits.max_uniform_buffer_range >= x,
+            Self::MaxStorageBufferRange(x) => limits.max_storage_buffer_range >= x,
+            Self::MaxComputeSharedMemorySize(x) => limits.max_compute_shared_memory_size >= x,
+            Self::MaxComputeWorkGroupInvocations(x) => {
+                limits.max_compute_work_group_invocations >= x
+            }
+            Self::MaxColorAttachments(x) => limits.max_color_attachments >= x,
+            Self::MaxSamplerAnisotropy(x) => limits.max_sampler_anisotropy >= x,
+            Self::FramebufferColorSampleCounts(x) => {
+                limits.framebuffer_color_sample_counts.contains(x)
+            }
+            Self::TimestampComputeAndGraphics => limits.timestamp_compute_and_graphics == vk::TRUE,
+            Self::Custom { check, .. } => check(limits),
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxImageDimension2D(x) => write!(f, "maxImageDimension2D >= {x}"),
+            Self::MaxPushConstantsSize(x) => write!(f, "maxPushConstantsSize >= {x}"),
+            Self::MaxBoundDescriptorSets(x) => write!(f, "maxBoundDescriptorSets >= {x}"),
+            Self::MaxPerStageResources(x) => write!(f, "maxPerStageResources >= {x}"),
+            Self::MaxUniformBufferRange(x) => write!(f, "maxUniformBufferRange >= {x}"),
+            Self::MaxStorageBufferRange(x) => write!(f, "maxStorageBufferRange >= {x}"),
+            Self::MaxComputeSharedMemorySize(x) => {
+                write!(f, "maxComputeSharedMemorySize >= {x}")
+            }
+            Self::MaxComputeWorkGroupInvocations(x) => {
+                write!(f, "maxComputeWorkGroupInvocations >= {x}")
+            }
+            Self::MaxColorAttachments(x) => write!(f, "maxColorAttachments >= {x}"),
+            Self::MaxSamplerAnisotropy(x) => write!(f, "maxSamplerAnisotropy >= {x}"),
+            Self::FramebufferColorSampleCounts(x) => {
+                write!(f, "framebufferColorSampleCounts contains {x:?}")
+            }
+            Self::TimestampComputeAndGraphics => f.write_str("timestampComputeAndGraphics"),
+            Self::Custom { name, .. } => f.write_str(name),
+        }
+    }
+}
+
+/// Which features of [`vk::FormatProperties`] a [`FormatRequirement`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatTiling {
+    Linear,
+    Optimal,
+    Buffer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatRequirement {
+    pub format: vk::Format,
+    pub tiling: FormatTiling,
+    pub features: vk::FormatFeatureFlags,
+}
+
+impl FormatRequirement {
+    pub fn optimal(format: vk::Format, features: vk::FormatFeatureFlags) -> Self {
+        Self {
+            format,
+            tiling: FormatTiling::Optimal,
+            features,
+        }
+    }
+}
+
+/// Something a device is checked for.
+#[derive(Debug, Clone)]
+pub enum Check {
+    ApiVersion(u32),
+    Feature(VulkanFeature),
+    // Boxed, the name is much larger than the other variants.
+    Extension(Box<ExtensionName>),
+    Limit(Limit),
+    Format(FormatRequirement),
+    /// A queue family with all of these capabilities.
+    Queue(vk::QueueFlags),
+    /// A queue description of [`DeviceCreationInfo`](crate::DeviceCreationInfo) can be served,
+    /// including presenting if it supports a swapchain.
+    QueueDescription(super::QueueDescription),
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ApiVersion(x) => write!(
+                f,
+                "Vulkan {}.{}",
+                vk::api_version_major(*x),
+                vk::api_version_minor(*x)
+            ),
+            Self::Feature(x) => x.fmt(f),
+            Self::Extension(x) => write!(f, "{}", x.to_string_lossy()),
+            Self::Limit(x) => x.fmt(f),
+            Self::Format(x) => write!(f, "{:?} {:?} with {:?}", x.format, x.tiling, x.features),
+            Self::Queue(x) => write!(f, "queue family with {x:?}"),
+            Self::QueueDescription(x) => {
+                write!(f, "queue with {:?}", x.flags)?;
+                if x.supports.is_some() {
+                    f.write_str(" that can present")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Declarative description of the device an application needs.
+#[derive(Debug, Clone)]
+pub struct DeviceRequirements {
+    pub checks: Vec<(Check, Importance)>,
+    /// Score added to the devices of each type.
+    pub device_types: Vec<(vk::PhysicalDeviceType, u32)>,
+}
+
+impl Default for DeviceRequirements {
+    /// No requirements, and discrete GPUs are preferred over integrated, virtual and CPU ones.
+    fn default() -> Self {
+        Self {
+            checks: vec![],
+            device_types: vec![
+                (vk::PhysicalDeviceType::DISCRETE_GPU, 4),
+                (vk::PhysicalDeviceType::INTEGRATED_GPU, 3),
+                (vk::PhysicalDeviceType::VIRTUAL_GPU, 2),
+                (vk::PhysicalDeviceType::CPU, 1),
+            ],
+        }
+    }
+}
+
+impl DeviceRequirements {
+    pub fn with(mut self, check: Check, importance: Importance) -> Self {
+        self.checks.push((check, importance));
+        self
+    }
+    /// Use `vk::make_api_version`.
+    pub fn require_api_version(self, version: u32) -> Self {
+        self.with(Check::ApiVersion(version), Importance::Required)
+    }
+    pub fn require_feature(self, feature: VulkanFeature) -> Self {
+        self.with(Check::Feature(feature), Importance::Required)
+    }
+    pub fn prefer_feature(self, feature: VulkanFeature, score: u32) -> Self {
+        self.with(Check::Feature(feature), Importance::Preferred(score))
+    }
+    pub fn require_extension(self, extension: ExtensionName) -> Self {
+        self.with(Check::Extension(Box::new(extension)), Importance::Required)
+    }
+    pub fn prefer_extension(self, extension: ExtensionName, score: u32) -> Self {
+        self.with(
+            Check::Extension(Box::new(extension)),
+            Importance::Preferred(score),
+        )
+    }
+    pub fn require_limit(self, limit: Limit) -> Self {
+        self.with(Check::Limit(limit), Importance::Required)
+    }
+    pub fn prefer_limit(self, limit: Limit, score: u32) -> Self {
+        self.with(Check::Limit(limit), Importance::Preferred(score))
+    }
+    pub fn require_format(self, format: FormatRequirement) -> Self {
+        self.with(Check::Format(format), Importance::Required)
+    }
+    pub fn prefer_format(self, format: FormatRequirement, score: u32) -> Self {
+        self.with(Check::Format(format), Importance::Preferred(score))
+    }
+    pub fn require_queue(self, flags: vk::QueueFlags) -> Self {
+        self.with(Check::Queue(flags), Importance::Required)
+    }
+    pub fn prefer_queue(self, flags: vk::QueueFlags, score: u32) -> Self {
+        self.with(Check::Queue(flags), Importance::Preferred(score))
+    }
+    pub fn with_device_type_score(
+        mut self,
+        device_type: vk::PhysicalDeviceType,
+        score: u32,
+    ) -> Self {
+        self.device_types.retain(|(x, _)| *x != device_type);
+        self.device_types.push((device_type, score));
+        self
+    }
+
+    pub fn evaluate(&self, device: PhysicalDevice) -> DeviceEvaluation {
+        let type_score = self
+            .device_types
+            .iter()
+            .find(|(x, _)| *x == device.device_type())
+            .map_or(0, |(_, score)| *score);
+        let outcomes = self
+            .checks
+            .iter()
+            .map(|(check, importance)| Outcome {
+                satisfied: is_satisfied(&device, check),
+                check: check.clone(),
+                importance: *importance,
+            })
+            .collect();
+        DeviceEvaluation {
+            device,
+            type_score,
+            outcomes,
+        }
+    }
+
+    pub fn select(&self, devices: impl IntoIterator<Item = PhysicalDevice>) -> SelectionReport {
+        SelectionReport::new(devices.into_iter().map(|x| self.evaluate(x)).collect())
+    }
+}
+
+fn is_satisfied(device: &PhysicalDevice, check: &Check) -> bool {
+    let features = device.features();
+    match check {
+        Check::ApiVersion(x) => features.api_version >= *x,
+        Check::Feature(x) => match features.get(x) {
+            Some(x) => x,
+            None => {
+                tracing::warn!("Could not check whether {} is supported", x);
+                false
+            }
+        },
+        Check::Extension(x) => features.extensions.iter().any(|ext| ext.name == **x),
+        Check::Limit(x) => x.is_satisfied(device.limits()),
+        Check::Format(x) => device.supports_format(x),
+        Check::Queue(flags) => {
+            !super::queue_solver::candidate_families(device.queue_families(), *flags, None)
+                .is_empty()
+        }
+        // Needs the surfaces, checked by `Device::new`.
+        Check::QueueDescription(_) => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub check: Check,
+    pub importance: Importance,
+    pub satisfied: bool,
+}
+
+/// How a device fared against [`DeviceRequirements`].
+#[derive(Debug, Clone)]
+pub struct DeviceEvaluation {
+    pub device: PhysicalDevice,
+    pub type_score: u32,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl DeviceEvaluation {
+    pub fn is_accepted(&self) -> bool {
+        self.unmet_required().next().is_none()
+    }
+    /// `None` if the device is rejected.
+    pub fn score(&self) -> Option<u32> {
+        self.is_accepted().then(|| {
+            self.outcomes
+                .iter()
+                .filter(|x| x.satisfied)
+                .filter_map(|x| match x.importance {
+                    Importance::Preferred(score) => Some(score),
+                    Importance::Required => None,
+                })
+                .fold(self.type_score, u32::saturating_add)
+        })
+    }
+    pub fn unmet_required(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes
+            .iter()
+            .filter(|x| !x.satisfied && x.importance == Importance::Required)
+    }
+    pub fn unmet_preferred(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes
+            .iter()
+            .filter(|x| !x.satisfied && x.importance != Importance::Required)
+    }
+    /// The checks the device passes.
+    pub(crate) fn satisfied(&self) -> impl Iterator<Item = &Check> {
+        self.outcomes
+            .iter()
+            .filter(|x| x.satisfied)
+            .map(|x| &x.check)
+    }
+    pub(crate) fn record(&mut self, check: Check, importance: Importance, satisfied: bool) {
+        self.outcomes.push(Outcome {
+            check,
+            importance,
+            satisfied,
+        });
+    }
+}
+
+fn write_checks<'a>(
+    f: &mut fmt::Formatter<'_>,
+    outcomes: impl Iterator<Item = &'a Outcome>,
+) -> fmt::Result {
+    for (i, outcome) in outcomes.enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", outcome.check)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for DeviceEvaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:?}): ",
+            self.device.name(),
+            self.device.device_type()
+        )?;
+        match self.score() {
+            Some(score) => write!(f, "accepted with score {score}")?,
+            None => {
+                f.write_str("rejected, missing ")?;
+                write_checks(f, self.unmet_required())?;
+            }
+        }
+        if self.unmet_preferred().next().is_some() {
+            f.write_str("; without preferred ")?;
+            write_checks(f, self.unmet_preferred())?;
+        }
+        Ok(())
+    }
+}
+
+/// Every evaluated device, the accepted ones first from the highest score.
+#[derive(Debug, Clone, Default)]
+pub struct SelectionReport {
+    pub evaluations: Vec<DeviceEvaluation>,
+}
+
+impl SelectionReport {
+    pub fn new(mut evaluations: Vec<DeviceEvaluation>) -> Self {
+        // Stable, so ties keep the order of the driver.
+        evaluations.sort_by_key(|x| std::cmp::Reverse(x.score().map_or(0, |x| u64::from(x) + 1)));
+        Self { evaluations }
+    }
+    pub fn best(&self) -> Option<&DeviceEvaluation> {
+        self.evaluations.first().filter(|x| x.is_accepted())
+    }
+    pub fn accepted(&self) -> impl Iterator<Item = &DeviceEvaluation> {
+        self.evaluations.iter().filter(|x| x.is_accepted())
+    }
+    pub fn rejected(&self) -> impl Iterator<Item = &DeviceEvaluation> {
+        self.evaluations.iter().filter(|x| !x.is_accepted())
+    }
+}
+
+impl fmt::Display for SelectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.evaluations.is_empty() {
+            return f.write_str("no physical devices");
+        }
+        for (i, evaluation) in self.evaluations.iter().enumerate() {
+            if i != 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}. {evaluation}", i + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits = vk::PhysicalDeviceLimits {
+            max_push_constants_size: 128,
+            framebuffer_color_sample_counts: vk::SampleCountFlags::TYPE_1
+                | vk::SampleCountFlags::TYPE_4,
+            ..Default::default()
+        };
+        assert!(Limit::MaxPushConstantsSize(128).is_satisfied(&limits));
+        assert!(!Limit::MaxPushConstantsSize(256).is_satisfied(&limits));
+        assert!(
+            Limit::FramebufferColorSampleCounts(vk::SampleCountFlags::TYPE_4).is_satisfied(&limits)
+        );
+        assert!(!Limit::FramebufferColorSampleCounts(
+            vk::SampleCountFlags::TYPE_4 | vk::SampleCountFlags::TYPE_8
+        )
+        .is_satisfied(&limits));
+        let custom = Limit::Custom {
+            name: "maxViewports >= 16",
+            check: |x| x.max_viewports >= 16,
+        };
+        assert!(!custom.is_satisfied(&limits));
+        assert_eq!(custom.to_string(), "maxViewports >= 16");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn select_from_instance() {
+        let instance = crate::test_support::instance(crate::InstanceCreateInfo::compute_usage(
+            "device selection test",
+        ));
+        let devices = PhysicalDevice::enumerate(&instance).unwrap();
+        assert!(!devices.is_empty(), "No physical devices");
+
+        let requirements = DeviceRequirements::default()
+            .require_api_version(vk::API_VERSION_1_0)
+            .require_queue(vk::QueueFlags::COMPUTE)
+            .prefer_feature(VulkanFeature::Core("shaderInt64"), 10);
+        let report = requirements.select(devices.clone());
+        assert_eq!(report.evaluations.len(), devices.len());
+        let best = report.best().expect("Every device has a compute queue");
+        assert!(report.accepted().all(|x| x.score() <= best.score()));
+
+        let impossible = requirements.require_limit(Limit::MaxPushConstantsSize(u32::MAX));
+        let report = impossible.select(devices);
+        assert!(report.best().is_none());
+        assert!(report.to_string().contains("maxPushConstantsSize"));
+    }
+}
@@ -0,0 +1,433 @@
+//! Shader modules, reflected when they are created.
+//!
+//! The words come from `shader!`'s `load_words`. Reflection finds the stage, the entry point,
+//! the descriptor sets, the push constants and the vertex inputs, which pipelines derive their
+//! layouts from.
+use super::device::Device;
+use super::error::PipelineError;
+use super::handle::{own, Owned};
+use ash::vk;
+use spirv_reflect::requirements::ShaderRequirements;
+use spirv_reflect::rspirv::dr::Operand;
+use spirv_reflect::spirv::{ExecutionModel, StorageClass};
+use spirv_reflect::types::Type;
+use spirv_reflect::{DescriptorInfo, PushConstantInfo, Reflection};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::sync::Arc;
+
+/// A vertex shader input, read from a vertex buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    /// Size in bytes.
+    pub size: u32,
+    /// Alignment in `#[repr(C)]` structs.
+    pub alignment: u32,
+}
+
+/// What a shader module declares.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: CString,
+    pub descriptor_sets: BTreeMap<u32, BTreeMap<u32, DescriptorInfo>>,
+    pub push_constants: Option<PushConstantInfo>,
+    /// Empty unless this is a vertex shader. In declaration order, like the fields of the
+    /// `Input` struct `shader!` generates.
+    pub vertex_attributes: Vec<VertexAttribute>,
+    /// The workgroup size, only for compute shaders.
+    pub local_size: Option<[u32; 3]>,
+    pub requirements: ShaderRequirements,
+}
+
+impl ShaderReflection {
+    /// Reflects the first entry point of `words`.
+    /// # Errors
+    /// If the module is invalid, has no entry point, or its stage or vertex inputs are not
+    /// supported.
+    pub fn new(words: &[u32]) -> Result<Self, PipelineError> {
+        let reflection = Reflection::new_from_spirv_words(words)?;
+        let entry = reflection
+            .0
+            .entry_points
+            .first()
+            .ok_or(PipelineError::InvalidDescription(
+                "shader has no entry point",
+            ))?;
+        let (model, name) = match (entry.operands.first(), entry.operands.get(2)) {
+            (Some(Operand::ExecutionModel(model)), Some(Operand::LiteralString(name))) => {
+                (*model, name.clone())
+            }
+            _ => return Err(PipelineError::InvalidDescription("malformed entry point")),
+        };
+        let stage = stage(model)?;
+        let entry_point = CString::new(name)
+            .map_err(|_| PipelineError::InvalidDescription("entry point name contains nul"))?;
+        let vertex_attributes = if stage == vk::ShaderStageFlags::VERTEX {
+            vertex_attributes(&reflection)?
+        } else {
+            vec![]
+        };
+        let local_size = if stage == vk::ShaderStageFlags::COMPUTE {
+            let (x, y, z) = reflection.get_compute_group_size()?;
+            Some([x, y, z])
+        } else {
+            None
+        };
+        Ok(Self {
+            stage,
+            entry_point,
+            descriptor_sets: reflection.get_descriptor_sets()?,
+            push_constants: reflection.get_push_constant_range()?,
+            vertex_attributes,
+            local_size,
+            requirements: reflection.get_vulkan_requirements()?,
+        })
+    }
+}
+
+fn stage(model: ExecutionModel) -> Result<vk::ShaderStageFlags, PipelineError> {
+    Ok(match model {
+        ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+        ExecutionModel::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        ExecutionModel::TessellationEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        ExecutionModel::Geometry => vk::ShaderStageFlags::GEOMETRY,
+        ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+        ExecutionModel::TaskEXT => vk::ShaderStageFlags::TASK_EXT,
+        ExecutionModel::MeshEXT => vk::ShaderStageFlags::MESH_EXT,
+        model => return Err(PipelineError::UnsupportedStage(model)),
+    })
+}
+
+fn vertex_attributes(reflection: &Reflection) -> Result<Vec<VertexAttribute>, PipelineError> {
+    let decorations = reflection.get_decorations();
+    let types = reflection.get_types()?;
+    let mut attributes = vec![];
+    for id in reflection.get_all_variables_with_storage_class(StorageClass::Input)? {
+        let Some(location) = decorations
+            .get(&id)
+            .filter(|x| x.builtin.is_none())
+            .and_then(|x| x.location)
+        else {
+            continue;
+        };
+        let type_id = reflection.get_type_of_variable(id)?;
+        let ty = types
+            .get(&type_id)
+            .ok_or(spirv_reflect::ReflectError::UnresolvedTypeId(type_id))?;
+        // A matrix takes up a location per column.
+        let (columns, column) = match ty.as_ref() {
+            Type::Mat(mat) => (u32::from(u16::from(mat.size)), mat.inner_type.as_ref()),
+            ty => (1, ty),
+        };
+        let (format, size, alignment) =
+            attribute_format(column).ok_or(PipelineError::UnsupportedVertexInput { location })?;
+        attributes.extend((0..columns).map(|i| VertexAttribute {
+            location: location + i,
+            format,
+            size,
+            alignment,
+        }));
+    }
+    Ok(attributes)
+}
+
+/// The format, size and alignment of a scalar or vector input.
+fn attribute_format(ty: &Type) -> Option<(vk::Format, u32, u32)> {
+    let (scalar, len) = match ty {
+        Type::Vector(vector) => (vector.inner_type.as_ref(), u16::from(vector.size)),
+        ty => (ty, 1),
+    };
+    use vk::Format as F;
+    let formats = match scalar {
+        Type::Float(x) if x.bits == 32 => [
+            F::R32_SFLOAT,
+            F::R32G32_SFLOAT,
+            F::R32G32B32_SFLOAT,
+            F::R32G32B32A32_SFLOAT,
+        ],
+        Type::Float(x) if x.bits == 64 => [
+            F::R64_SFLOAT,
+            F::R64G64_SFLOAT,
+            F::R64G64B64_SFLOAT,
+            F::R64G64B64A64_SFLOAT,
+        ],
+        Type::Int(x) if x.bits == 32 && x.issigned => [
+            F::R32_SINT,
+            F::R32G32_SINT,
+            F::R32G32B32_SINT,
+            F::R32G32B32A32_SINT,
+        ],
+        Type::Int(x) if x.bits == 32 => [
+            F::R32_UINT,
+            F::R32G32_UINT,
+            F::R32G32B32_UINT,
+            F::R32G32B32A32_UINT,
+        ],
+        _ => return None,
+    };
+    let bytes = match scalar {
+        Type::Float(x) => u32::from(x.bits / 8),
+        Type::Int(x) => u32::from(x.bits / 8),
+        _ => unreachable!(),
+    };
+    Some((formats[usize::from(len) - 1], bytes * u32::from(len), bytes))
+}
+
+/// A shader module with its reflection.
+pub struct ShaderModule {
+    raw: Owned<vk::ShaderModule>,
+    reflection: ShaderReflection,
+}
+
+impl std::fmt::Debug for ShaderModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShaderModule")
+            .field("raw", &*self.raw)
+            .field("reflection", &self.reflection)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShaderModule {
+    /// `name` is shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If the module cannot be reflected, needs something the device does not have enabled, or
+    /// cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        words: &[u32],
+        name: Option<&str>,
+    ) -> Result<Arc<Self>, PipelineError> {
+        let reflection = ShaderReflection::new(words)?;
+        let support = device.check_shader_requirements(&reflection.requirements);
+        if !support.is_supported() {
+            return Err(PipelineError::ShaderRequirementsNotMet(support));
+        }
+        let raw = unsafe {
+            device.raw().create_shader_module(
+                &vk::ShaderModuleCreateInfo::default().code(words),
+                device.allocation_callbacks(),
+            )
+        }?;
+        let module = Self {
+            raw: own(device, raw),
+            reflection,
+        };
+        if let Some(name) = name {
+            module.set_debug_name(name);
+        }
+        Ok(Arc::new(module))
+    }
+
+    pub fn raw(&self) -> vk::ShaderModule {
+        *self.raw
+    }
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+    }
+    pub fn stage(&self) -> vk::ShaderStageFlags {
+        self.reflection.stage
+    }
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+    pub(crate) fn stage_info(&self) -> vk::PipelineShaderStageCreateInfo<'_> {
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(self.reflection.stage)
+            .module(*self.raw)
+            .name(&self.reflection.entry_point)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use spirv_reflect::rspirv::binary::Assemble;
+    use spirv_reflect::rspirv::dr::Builder;
+    use spirv_reflect::spirv;
+
+    /// A vertex shader with `vec3` at location 0, `mat2` at 1 and `gl_VertexIndex`, and a
+    /// uniform buffer at set 1, binding 2.
+    pub(crate) fn vertex_shader() -> Vec<u32> {
+        let mut b = Builder::new();
+        b.set_version(1, 0);
+        b.capability(spirv::Capability::Shader);
+        b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+        let void = b.type_void();
+        let function_type = b.type_function(void, vec![]);
+        let float = b.type_float(32);
+        let int = b.type_int(32, 1);
+        let vec2 = b.type_vector(float, 2);
+        let vec3 = b.type_vector(float, 3);
+        let mat2 = b.type_matrix(vec2, 2);
+        let block = b.type_struct(vec![vec3]);
+        b.decorate(block, spirv::Decoration::Block, vec![]);
+        b.member_decorate(
+            block,
+            0,
+            spirv::Decoration::Offset,
+            vec![Operand::LiteralBit32(0)],
+        );
+
+        let input = |b: &mut Builder, ty, decoration, value| {
+            let pointer = b.type_pointer(None, StorageClass::Input, ty);
+            let variable = b.variable(pointer, None, StorageClass::Input, None);
+            b.decorate(variable, decoration, vec![value]);
+            variable
+        };
+        let position = input(
+            &mut b,
+            vec3,
+            spirv::Decoration::Location,
+            Operand::LiteralBit32(0),
+        );
+        let transform = input(
+            &mut b,
+            mat2,
+            spirv::Decoration::Location,
+            Operand::LiteralBit32(1),
+        );
+        let index = input(
+            &mut b,
+            int,
+            spirv::Decoration::BuiltIn,
+            Operand::BuiltIn(spirv::BuiltIn::VertexIndex),
+        );
+        let uniform_pointer = b.type_pointer(None, StorageClass::Uniform, block);
+        let uniform = b.variable(uniform_pointer, None, StorageClass::Uniform, None);
+        b.decorate(
+            uniform,
+            spirv::Decoration::DescriptorSet,
+            vec![Operand::LiteralBit32(1)],
+        );
+        b.decorate(
+            uniform,
+            spirv::Decoration::Binding,
+            vec![Operand::LiteralBit32(2)],
+        );
+
+        let main = b
+            .begin_function(void, None, spirv::FunctionControl::NONE, function_type)
+            .unwrap();
+        b.begin_block(None).unwrap();
+        b.ret().unwrap();
+        b.end_function().unwrap();
+        b.entry_point(
+            spirv::ExecutionModel::Vertex,
+            main,
+            "main",
+            vec![position, transform, index],
+        );
+        b.module().assemble()
+    }
+
+    /// A compute shader with a workgroup of 64 doubling the `uint`s of the storage buffer at
+    /// set 0, binding 0, one per invocation.
+    pub(crate) fn compute_shader() -> Vec<u32> {
+        let mut b = Builder::new();
+        b.set_version(1, 3);
+        b.capability(spirv::Capability::Shader);
+        b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+        let void = b.type_void();
+        let function_type = b.type_function(void, vec![]);
+        let uint = b.type_int(32, 0);
+        let uvec3 = b.type_vector(uint, 3);
+        let input_pointer = b.type_pointer(None, StorageClass::Input, uvec3);
+        let invocation = b.variable(input_pointer, None, StorageClass::Input, None);
+        b.decorate(
+            invocation,
+            spirv::Decoration::BuiltIn,
+            vec![Operand::BuiltIn(spirv::BuiltIn::GlobalInvocationId)],
+        );
+        let array = b.type_runtime_array(uint);
+        b.decorate(
+            array,
+            spirv::Decoration::ArrayStride,
+            vec![Operand::LiteralBit32(4)],
+        );
+        let block = b.type_struct(vec![array]);
+        b.decorate(block, spirv::Decoration::Block, vec![]);
+        b.member_decorate(
+            block,
+            0,
+            spirv::Decoration::Offset,
+            vec![Operand::LiteralBit32(0)],
+        );
+        let block_pointer = b.type_pointer(None, StorageClass::StorageBuffer, block);
+        let buffer = b.variable(block_pointer, None, StorageClass::StorageBuffer, None);
+        b.decorate(
+            buffer,
+            spirv::Decoration::DescriptorSet,
+            vec![Operand::LiteralBit32(0)],
+        );
+        b.decorate(
+            buffer,
+            spirv::Decoration::Binding,
+            vec![Operand::LiteralBit32(0)],
+        );
+        let element_pointer = b.type_pointer(None, StorageClass::StorageBuffer, uint);
+        let zero = b.constant_bit32(uint, 0);
+        let two = b.constant_bit32(uint, 2);
+
+        let main = b
+            .begin_function(void, None, spirv::FunctionControl::NONE, function_type)
+            .unwrap();
+        b.begin_block(None).unwrap();
+        let id = b.load(uvec3, None, invocation, None, vec![]).unwrap();
+        let x = b.composite_extract(uint, None, id, vec![0]).unwrap();
+        let element = b
+            .access_chain(element_pointer, None, buffer, vec![zero, x])
+            .unwrap();
+        let value = b.load(uint, None, element, None, vec![]).unwrap();
+        let doubled = b.i_mul(uint, None, value, two).unwrap();
+        b.store(element, doubled, None, vec![]).unwrap();
+        b.ret().unwrap();
+        b.end_function().unwrap();
+        b.entry_point(
+            spirv::ExecutionModel::GLCompute,
+            main,
+            "main",
+            vec![invocation],
+        );
+        b.execution_mode(main, spirv::ExecutionMode::LocalSize, vec![64, 1, 1]);
+        b.module().assemble()
+    }
+
+    #[test]
+    fn reflect_vertex_shader() {
+        let reflection = ShaderReflection::new(&vertex_shader()).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.entry_point.to_str().unwrap(), "main");
+        let attributes = reflection
+            .vertex_attributes
+            .iter()
+            .map(|x| (x.location, x.format, x.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            [
+                (0, vk::Format::R32G32B32_SFLOAT, 12),
+                (1, vk::Format::R32G32_SFLOAT, 8),
+                (2, vk::Format::R32G32_SFLOAT, 8),
+            ]
+        );
+        let binding = &reflection.descriptor_sets[&1][&2];
+        assert_eq!(binding.ty, spirv_reflect::DescriptorType::UniformBuffer);
+        assert!(reflection.push_constants.is_none());
+    }
+
+    #[test]
+    fn reflect_compute_shader() {
+        let reflection = ShaderReflection::new(&compute_shader()).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(reflection.local_size, Some([64, 1, 1]));
+        assert!(reflection.vertex_attributes.is_empty());
+        let binding = &reflection.descriptor_sets[&0][&0];
+        assert_eq!(binding.ty, spirv_reflect::DescriptorType::StorageBuffer);
+    }
+}
@@ -0,0 +1,511 @@
+//! Presenting to a [`Surface`].
+//!
+//! The [`SwapChainPolicy`] of a [`SwapChainDescription`] picks the format, present mode and
+//! image count among what the surface supports. A [`SwapChain`] recreates itself when the
+//! surface changes, [`SwapChain::generation`] tells when anything built from its images has to
+//! be rebuilt.
+use super::device::{Device, Queue};
+use super::error::SwapChainError;
+use super::handle::{own, Owned};
+use super::image::ImageViewDescription;
+use super::instance::Surface;
+use super::sync::Semaphore;
+use ash::vk;
+use std::sync::Arc;
+
+/// Preferences for the swapchain, the first supported entry of every list is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapChainPolicy {
+    /// FIFO is used if none of these are supported, it is always available.
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    /// The first format of the surface is used if none of these are supported.
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    /// Clamped to what the surface allows. One more than the minimum if `None`, so the
+    /// application does not wait for the presentation engine.
+    pub image_count: Option<u32>,
+    pub usage: vk::ImageUsageFlags,
+}
+
+impl Default for SwapChainPolicy {
+    fn default() -> Self {
+        Self::vsync()
+    }
+}
+
+impl SwapChainPolicy {
+    /// FIFO presentation to sRGB images.
+    pub fn vsync() -> Self {
+        Self {
+            present_modes: vec![vk::PresentModeKHR::FIFO],
+            formats: vec![
+                vk::SurfaceFormatKHR {
+                    format: vk::Format::B8G8R8A8_SRGB,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                },
+                vk::SurfaceFormatKHR {
+                    format: vk::Format::R8G8B8A8_SRGB,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                },
+            ],
+            image_count: None,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        }
+    }
+    /// Mailbox or immediate presentation if possible, with a spare image for mailbox.
+    pub fn low_latency() -> Self {
+        Self {
+            present_modes: vec![
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::FIFO,
+            ],
+            image_count: Some(3),
+            ..Self::vsync()
+        }
+    }
+
+    /// Resolves the policy for a surface.
+    /// # Errors
+    /// If the surface cannot be queried, has no formats, does not support the usage, or has
+    /// no area.
+    pub fn choose(
+        &self,
+        surface: &Surface,
+        physical_device: vk::PhysicalDevice,
+        window_extent: vk::Extent2D,
+    ) -> Result<SwapChainSupport, SwapChainError> {
+        let loader = &surface.surface_loader;
+        let formats =
+            unsafe { loader.get_physical_device_surface_formats(physical_device, surface.raw()) }?;
+        let present_modes = unsafe {
+            loader.get_physical_device_surface_present_modes(physical_device, surface.raw())
+        }?;
+        let capabilities = unsafe {
+            loader.get_physical_device_surface_capabilities(physical_device, surface.raw())
+        }?;
+
+        let format = choose_format(&formats, &self.formats).ok_or(SwapChainError::NoFormat)?;
+        if !capabilities.supported_usage_flags.contains(self.usage) {
+            return Err(SwapChainError::UsageNotSupported(self.usage));
+        }
+        let extent = choose_extent(&capabilities, window_extent);
+        if extent.width == 0 || extent.height == 0 {
+            return Err(SwapChainError::ZeroExtent);
+        }
+        Ok(SwapChainSupport {
+            capabilities,
+            extent,
+            format,
+            present_mode: choose_present_mode(&present_modes, &self.present_modes),
+            image_count: choose_image_count(&capabilities, self.image_count),
+        })
+    }
+}
+
+/// A [`SwapChainPolicy`] resolved against a surface.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapChainSupport {
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+    pub extent: vk::Extent2D,
+    pub format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
+    pub image_count: u32,
+}
+
+fn choose_format(
+    available: &[vk::SurfaceFormatKHR],
+    preferred: &[vk::SurfaceFormatKHR],
+) -> Option<vk::SurfaceFormatKHR> {
+    preferred
+        .iter()
+        .find(|x| available.contains(x))
+        .or(available.first())
+        .copied()
+}
+
+fn choose_present_mode(
+    available: &[vk::PresentModeKHR],
+    preferred: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    preferred
+        .iter()
+        .find(|x| available.contains(x))
+        .copied()
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+fn choose_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, requested: Option<u32>) -> u32 {
+    let max = match capabilities.max_image_count {
+        0 => u32::MAX,
+        max => max,
+    };
+    requested
+        .unwrap_or(capabilities.min_image_count + 1)
+        .clamp(capabilities.min_image_count, max)
+}
+
+/// The surface decides the extent, unless it leaves it to the window.
+fn choose_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window: vk::Extent2D) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+    vk::Extent2D {
+        width: window.width.clamp(
+            capabilities.min_image_extent.width,
+            capabilities.max_image_extent.width,
+        ),
+        height: window.height.clamp(
+            capabilities.min_image_extent.height,
+            capabilities.max_image_extent.height,
+        ),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapChainDescription {
+    pub surface: Arc<Surface>,
+    pub policy: SwapChainPolicy,
+}
+
+pub struct SwapChain {
+    device: Arc<Device>,
+    /// Views of `images`, destroyed before the swapchain.
+    views: Vec<Owned<vk::ImageView>>,
+    /// Declared before `description`, so it is destroyed before the surface.
+    inner: Option<Owned<vk::SwapchainKHR>>,
+    loader: ash::khr::swapchain::Device,
+    description: SwapChainDescription,
+    /// Families that access the images, shared concurrently if there are several.
+    queue_families: Vec<u32>,
+    window_extent: vk::Extent2D,
+    out_of_date: bool,
+    generation: u64,
+    present_mode: vk::PresentModeKHR,
+    pub(crate) images: Vec<vk::Image>,
+    pub(crate) image_views: Vec<vk::ImageView>,
+    /// One per image, see [`Self::render_finished`].
+    render_finished: Vec<Arc<Semaphore>>,
+    pub(crate) extent: vk::Extent2D,
+    pub(crate) format: vk::SurfaceFormatKHR,
+}
+
+impl std::fmt::Debug for SwapChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwapChain")
+            .field("inner", &self.raw())
+            .field("surface", &self.description.surface)
+            .field("queue_families", &self.queue_families)
+            .field("generation", &self.generation)
+            .field("present_mode", &self.present_mode)
+            .field("images", &self.images)
+            .field("image_views", &self.image_views)
+            .field("extent", &self.extent)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl SwapChain {
+    /// `queues` are the queues that render to and present the images.
+    /// # Errors
+    /// See [`SwapChainPolicy::choose`], or if the swapchain cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        description: &SwapChainDescription,
+        window_extent: vk::Extent2D,
+        queues: &[&Queue],
+    ) -> Result<Self, SwapChainError> {
+        let mut queue_families = queues.iter().map(|x| x.family_index()).collect::<Vec<_>>();
+        queue_families.sort_unstable();
+        queue_families.dedup();
+        let loader = ash::khr::swapchain::Device::new(
+            &device.physical_device().instance().raw,
+            device.raw(),
+        );
+        let mut res = Self {
+            device: device.clone(),
+            views: vec![],
+            inner: None,
+            loader,
+            description: description.clone(),
+            queue_families,
+            window_extent,
+            out_of_date: true,
+            generation: 0,
+            present_mode: vk::PresentModeKHR::FIFO,
+            images: vec![],
+            image_views: vec![],
+            render_finished: vec![],
+            extent: vk::Extent2D::default(),
+            format: vk::SurfaceFormatKHR::default(),
+        };
+        res.recreate()?;
+        Ok(res)
+    }
+
+    pub fn raw(&self) -> vk::SwapchainKHR {
+        self.inner.as_deref().copied().unwrap_or_default()
+    }
+    pub fn surface(&self) -> &Arc<Surface> {
+        &self.description.surface
+    }
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+    /// Increases every time the images are recreated.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    /// To be signaled by the last submission rendering to image `index`, and waited on when
+    /// presenting it. There is one per image rather than per frame in flight: presenting does
+    /// not tell when it is done waiting, only acquiring the same image again does.
+    pub fn render_finished(&self, index: u32) -> &Arc<Semaphore> {
+        &self.render_finished[index as usize]
+    }
+
+    /// Whether the swapchain will be recreated on the next acquire.
+    pub fn is_out_of_date(&self) -> bool {
+        self.out_of_date
+    }
+
+    /// The window changed size, the swapchain is recreated on the next acquire.
+    pub fn resize(&mut self, window_extent: vk::Extent2D) {
+        self.window_extent = window_extent;
+        self.out_of_date = true;
+    }
+
+    /// Acquires the next image, recreating the swapchain first if it is out of date.
+    /// `semaphore` and `fence` are signaled once the image can be used, either may be null.
+    /// Returns the index of the image.
+    /// # Errors
+    /// [`SwapChainError::ZeroExtent`] while the window is minimized, in that case skip the
+    /// frame. Timeouts are returned as [`vk::Result::TIMEOUT`] or [`vk::Result::NOT_READY`].
+    pub fn acquire_next_image(
+        &mut self,
+        semaphore: vk::Semaphore,
+        fence: vk::Fence,
+        timeout: u64,
+    ) -> Result<u32, SwapChainError> {
+        if self.out_of_date {
+            self.recreate()?;
+        }
+        let acquire = |swapchain: &Self| {
+            swapchain.device.check(unsafe {
+                swapchain
+                    .loader
+                    .acquire_next_image(swapchain.raw(), timeout, semaphore, fence)
+            })
+        };
+        let (index, suboptimal) = match acquire(self) {
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                // Nothing was signaled, so the same semaphore and fence can be used again.
+                self.recreate()?;
+                acquire(self)?
+            }
+            res => res?,
+        };
+        // The image is still usable, recreate after presenting it.
+        self.out_of_date |= suboptimal;
+        Ok(index)
+    }
+
+    /// Presents image `index` on `queue` after `wait_semaphores`. An out of date or suboptimal
+    /// swapchain is recreated on the next acquire.
+    /// # Errors
+    /// If presenting fails for any other reason.
+    pub fn present(
+        &mut self,
+        queue: &Queue,
+        index: u32,
+        wait_semaphores: &[vk::Semaphore],
+    ) -> Result<(), SwapChainError> {
+        let swapchains = [self.raw()];
+        let indices = [index];
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&indices);
+        let presented = unsafe { self.loader.queue_present(*queue.lock(), &present_info) };
+        match self.device.check(presented) {
+            Ok(false) => Ok(()),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Creates the images again for the current surface, waits for the device to be idle.
+    /// # Errors
+    /// See [`SwapChainPolicy::choose`], or if the swapchain cannot be created.
+    pub fn recreate(&mut self) -> Result<(), SwapChainError> {
+        let support = self.description.policy.choose(
+            &self.description.surface,
+            self.device.physical_device().raw(),
+            self.window_extent,
+        )?;
+        let (sharing_mode, queue_family_indices) = if self.queue_families.len() > 1 {
+            (vk::SharingMode::CONCURRENT, self.queue_families.as_slice())
+        } else {
+            (vk::SharingMode::EXCLUSIVE, &[][..])
+        };
+        let composite_alpha = [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|x| support.capabilities.supported_composite_alpha.contains(*x))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+        let create_info = vk::SwapchainCreateInfoKHR::default()
+            .surface(self.description.surface.raw())
+            .min_image_count(support.image_count)
+            .image_format(support.format.format)
+            .image_color_space(support.format.color_space)
+            .image_extent(support.extent)
+            .image_array_layers(1)
+            .image_usage(self.description.policy.usage)
+            .image_sharing_mode(sharing_mode)
+            .queue_family_indices(queue_family_indices)
+            .pre_transform(support.capabilities.current_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(support.present_mode)
+            .clipped(true)
+            .old_swapchain(self.raw());
+
+        // The old images may still be in use.
+        self.device.wait_idle()?;
+        let swapchain = unsafe {
+            self.loader
+                .create_swapchain(&create_info, self.device.allocation_callbacks())
+        }?;
+        self.destroy();
+        self.inner = Some(own(&self.device, swapchain));
+        self.images = unsafe { self.loader.get_swapchain_images(swapchain) }?;
+        let device = self.device.raw();
+        for image in &self.images {
+            let create_info =
+                ImageViewDescription::new(vk::ImageViewType::TYPE_2D, support.format.format)
+                    .create_info(*image);
+            let view = unsafe {
+                device.create_image_view(&create_info, self.device.allocation_callbacks())
+            }?;
+            self.image_views.push(view);
+            self.views.push(own(&self.device, view));
+        }
+        self.render_finished.truncate(self.images.len());
+        while self.render_finished.len() < self.images.len() {
+            let name = format!("render finished {}", self.render_finished.len());
+            self.render_finished
+                .push(Arc::new(Semaphore::new(&self.device, Some(&name))?));
+        }
+        self.extent = support.extent;
+        self.format = support.format;
+        self.present_mode = support.present_mode;
+        self.out_of_date = false;
+        self.generation += 1;
+        tracing::debug!(
+            "Swapchain created with {} {:?} images of {:?}, {:?}",
+            self.images.len(),
+            self.format.format,
+            self.extent,
+            self.present_mode
+        );
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.image_views.clear();
+        self.views.clear();
+        self.images.clear();
+        self.inner = None;
+    }
+}
+
+impl Drop for SwapChain {
+    fn drop(&mut self) {
+        // Nothing can be done about a lost device here. The images may still be in use until
+        // then, they are destroyed with the fields afterwards.
+        let _ = self.device.wait_idle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn policy() {
+        let srgb = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+        let unorm = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+        let policy = SwapChainPolicy::vsync();
+        assert_eq!(choose_format(&[unorm, srgb], &policy.formats), Some(srgb));
+        assert_eq!(choose_format(&[unorm], &policy.formats), Some(unorm));
+        assert_eq!(choose_format(&[], &policy.formats), None);
+
+        let policy = SwapChainPolicy::low_latency();
+        let modes = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::IMMEDIATE];
+        assert_eq!(
+            choose_present_mode(&modes, &policy.present_modes),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+        assert_eq!(
+            choose_present_mode(&modes, &[vk::PresentModeKHR::MAILBOX]),
+            vk::PresentModeKHR::FIFO
+        );
+
+        let capabilities = vk::SurfaceCapabilitiesKHR {
+            min_image_count: 2,
+            max_image_count: 3,
+            current_extent: vk::Extent2D {
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            min_image_extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            max_image_extent: vk::Extent2D {
+                width: 1024,
+                height: 1024,
+            },
+            ..Default::default()
+        };
+        assert_eq!(choose_image_count(&capabilities, None), 3);
+        assert_eq!(choose_image_count(&capabilities, Some(8)), 3);
+        assert_eq!(choose_image_count(&capabilities, Some(1)), 2);
+        let unbounded = vk::SurfaceCapabilitiesKHR {
+            max_image_count: 0,
+            ..capabilities
+        };
+        assert_eq!(choose_image_count(&unbounded, Some(8)), 8);
+
+        let window = vk::Extent2D {
+            width: 1920,
+            height: 600,
+        };
+        assert_eq!(
+            choose_extent(&capabilities, window),
+            vk::Extent2D {
+                width: 1024,
+                height: 600
+            }
+        );
+        let fixed = vk::SurfaceCapabilitiesKHR {
+            current_extent: vk::Extent2D {
+                width: 800,
+                height: 600,
+            },
+            ..capabilities
+        };
+        assert_eq!(choose_extent(&fixed, window), fixed.current_extent);
+    }
+}
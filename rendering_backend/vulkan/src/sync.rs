@@ -0,0 +1,475 @@
+//! Fences, semaphores, and a [`FrameContext`] pacing frames in flight.
+//!
+//! A frame context owns a timeline semaphore that every frame's submissions signal. Beginning a
+//! frame waits until the frame that last used the same slot is done on the device, then drops
+//! everything that frame deferred and recycles its descriptor sets. A frame looks like:
+//!
+//! ```ignore
+//! let mut frame = frames.begin_frame()?;
+//! let index = swapchain.acquire_next_image(frame.image_available().raw(), vk::Fence::null(), u64::MAX)?;
+//! // Record `command_buffer`...
+//! let submit = QueueSubmit::new(&device)
+//!     .command_buffer(command_buffer)
+//!     .wait(frame.image_available(), vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+//!     .signal(swapchain.render_finished(index));
+//! frame.submit(&queue, submit)?;
+//! swapchain.present(&queue, index, &[swapchain.render_finished(index).raw()])?;
+//! ```
+use super::command::{QueueSubmit, Submission};
+use super::descriptor::DescriptorAllocator;
+use super::device::{Device, Queue};
+use super::error::{InitError, VkError};
+use super::handle::{own, Owned};
+use ash::vk;
+use spirv_reflect::requirements::VulkanFeature;
+use std::any::Any;
+use std::sync::Arc;
+
+/// Signaled by the device, waited on by the host.
+pub struct Fence {
+    raw: Owned<vk::Fence>,
+}
+
+impl std::fmt::Debug for Fence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fence")
+            .field("raw", &*self.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Fence {
+    /// # Errors
+    /// If the fence cannot be created.
+    pub fn new(device: &Arc<Device>, signaled: bool) -> Result<Self, VkError> {
+        let flags = if signaled {
+            vk::FenceCreateFlags::SIGNALED
+        } else {
+            vk::FenceCreateFlags::empty()
+        };
+        let raw = unsafe {
+            device.raw().create_fence(
+                &vk::FenceCreateInfo::default().flags(flags),
+                device.allocation_callbacks(),
+            )
+        }?;
+        Ok(Self {
+            raw: own(device, raw),
+        })
+    }
+    pub fn raw(&self) -> vk::Fence {
+        *self.raw
+    }
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+    }
+
+    /// Whether the fence was signaled within `timeout` nanoseconds.
+    /// # Errors
+    /// If the device is lost.
+    pub fn wait(&self, timeout: u64) -> Result<bool, VkError> {
+        let device = &self.raw.owner;
+        match device.check(unsafe { device.raw().wait_for_fences(&[*self.raw], true, timeout) }) {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+    /// # Errors
+    /// If the device is lost.
+    pub fn is_signaled(&self) -> Result<bool, VkError> {
+        let device = &self.raw.owner;
+        Ok(device.check(unsafe { device.raw().get_fence_status(*self.raw) })?)
+    }
+    /// # Errors
+    /// If the device is out of memory.
+    pub fn reset(&self) -> Result<(), VkError> {
+        unsafe { self.raw.owner.raw().reset_fences(&[*self.raw]) }?;
+        Ok(())
+    }
+}
+
+/// A binary semaphore, ordering work between queues and the swapchain.
+pub struct Semaphore {
+    raw: Owned<vk::Semaphore>,
+}
+
+impl std::fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("raw", &*self.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Semaphore {
+    /// `name` is shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If the semaphore cannot be created.
+    pub fn new(device: &Arc<Device>, name: Option<&str>) -> Result<Self, VkError> {
+        let raw = unsafe {
+            device.raw().create_semaphore(
+                &vk::SemaphoreCreateInfo::default(),
+                device.allocation_callbacks(),
+            )
+        }?;
+        let semaphore = Self {
+            raw: own(device, raw),
+        };
+        if let Some(name) = name {
+            semaphore.set_debug_name(name);
+        }
+        Ok(semaphore)
+    }
+    pub fn raw(&self) -> vk::Semaphore {
+        *self.raw
+    }
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+    }
+}
+
+/// A semaphore with a counter that only increases, waitable from both the host and the device.
+pub struct TimelineSemaphore {
+    raw: Owned<vk::Semaphore>,
+}
+
+impl std::fmt::Debug for TimelineSemaphore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimelineSemaphore")
+            .field("raw", &*self.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TimelineSemaphore {
+    pub const FEATURE: VulkanFeature = VulkanFeature::Vulkan12("timelineSemaphore");
+
+    /// `name` is shown by validation messages and graphics debuggers.
+    /// # Errors
+    /// If [`Self::FEATURE`] is not enabled on `device`, or the semaphore cannot be created.
+    pub fn new(
+        device: &Arc<Device>,
+        initial_value: u64,
+        name: Option<&str>,
+    ) -> Result<Self, InitError> {
+        if !device
+            .enabled_features()
+            .get(&Self::FEATURE)
+            .unwrap_or(false)
+        {
+            return Err(InitError::FeatureNotEnabled(Self::FEATURE));
+        }
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let raw = unsafe {
+            device.raw().create_semaphore(
+                &vk::SemaphoreCreateInfo::default().push_next(&mut type_info),
+                device.allocation_callbacks(),
+            )
+        }?;
+        let semaphore = Self {
+            raw: own(device, raw),
+        };
+        if let Some(name) = name {
+            semaphore.set_debug_name(name);
+        }
+        Ok(semaphore)
+    }
+    pub fn raw(&self) -> vk::Semaphore {
+        *self.raw
+    }
+    /// See [`Device::set_debug_name`].
+    pub fn set_debug_name(&self, name: &str) {
+        self.raw.owner.set_debug_name(*self.raw, name);
+    }
+
+    /// The current value of the counter.
+    /// # Errors
+    /// If the device is lost.
+    pub fn value(&self) -> Result<u64, VkError> {
+        let device = &self.raw.owner;
+        Ok(device.check(unsafe { device.raw().get_semaphore_counter_value(*self.raw) })?)
+    }
+    /// Whether the counter reached `value` within `timeout` nanoseconds.
+    /// # Errors
+    /// If the device is lost.
+    pub fn wait(&self, value: u64, timeout: u64) -> Result<bool, VkError> {
+        let semaphores = [*self.raw];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        let device = &self.raw.owner;
+        match device.check(unsafe { device.raw().wait_semaphores(&wait_info, timeout) }) {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+    /// Sets the counter from the host. `value` has to be greater than the current one, and than
+    /// any pending signal.
+    /// # Errors
+    /// If the device is out of memory.
+    pub fn signal(&self, value: u64) -> Result<(), VkError> {
+        let signal_info = vk::SemaphoreSignalInfo::default()
+            .semaphore(*self.raw)
+            .value(value);
+        unsafe { self.raw.owner.raw().signal_semaphore(&signal_info) }?;
+        Ok(())
+    }
+}
+
+struct FrameData {
+    image_available: Arc<Semaphore>,
+    /// Timeline value signaled by the last submission of this frame.
+    value: u64,
+    submissions: Vec<Submission>,
+    deferred: Vec<Box<dyn Any>>,
+    descriptors: DescriptorAllocator,
+}
+
+impl FrameData {
+    /// Only once the device is done with the frame.
+    fn clear(&mut self) -> Result<(), VkError> {
+        self.submissions.clear();
+        self.deferred.clear();
+        unsafe { self.descriptors.reset() }
+    }
+}
+
+/// Paces `frames_in_flight` frames with a timeline semaphore.
+///
+/// Each frame slot has its own image available semaphore, destruction queue and descriptor
+/// allocator. The semaphores signaled for presenting belong to the images, see
+/// [`SwapChain::render_finished`](crate::SwapChain::render_finished).
+pub struct FrameContext {
+    device: Arc<Device>,
+    timeline: Arc<TimelineSemaphore>,
+    frames: Vec<FrameData>,
+    /// Number of frames begun.
+    frame_number: u64,
+    /// The last value a submission signals on the timeline.
+    last_value: u64,
+}
+
+impl std::fmt::Debug for FrameContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameContext")
+            .field("timeline", &self.timeline)
+            .field("frames_in_flight", &self.frames.len())
+            .field("frame_number", &self.frame_number)
+            .field("last_value", &self.last_value)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FrameContext {
+    /// # Errors
+    /// If [`TimelineSemaphore::FEATURE`] is not enabled, or creating the semaphores fails.
+    /// # Panics
+    /// If `frames_in_flight` is zero.
+    pub fn new(device: &Arc<Device>, frames_in_flight: usize) -> Result<Self, InitError> {
+        assert!(
+            frames_in_flight > 0,
+            "At least one frame has to be in flight"
+        );
+        let timeline = Arc::new(TimelineSemaphore::new(device, 0, Some("frame timeline"))?);
+        let frames = (0..frames_in_flight)
+            .map(|index| {
+                let name = format!("image available {index}");
+                Ok(FrameData {
+                    image_available: Arc::new(Semaphore::new(device, Some(&name))?),
+                    value: 0,
+                    submissions: vec![],
+                    deferred: vec![],
+                    descriptors: DescriptorAllocator::new(device),
+                })
+            })
+            .collect::<Result<_, VkError>>()?;
+        Ok(Self {
+            device: device.clone(),
+            timeline,
+            frames,
+            frame_number: 0,
+            last_value: 0,
+        })
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+    /// Number of frames begun so far.
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+    pub fn timeline(&self) -> &Arc<TimelineSemaphore> {
+        &self.timeline
+    }
+
+    fn current(&self) -> usize {
+        (self.frame_number.max(1) - 1) as usize % self.frames.len()
+    }
+
+    /// Waits until the device is done with the frame that last used the next slot, then drops
+    /// what it deferred and recycles its descriptor sets.
+    /// # Errors
+    /// If the device is lost.
+    pub fn begin_frame(&mut self) -> Result<Frame<'_>, VkError> {
+        self.frame_number += 1;
+        let index = self.current();
+        let frame = &mut self.frames[index];
+        self.timeline.wait(frame.value, u64::MAX)?;
+        frame.clear()?;
+        Ok(Frame { context: self })
+    }
+
+    /// Drops `resource` once the device is done with the latest frame.
+    pub fn defer_destroy<R: 'static>(&mut self, resource: R) {
+        let index = self.current();
+        self.frames[index].deferred.push(Box::new(resource));
+    }
+
+    /// Waits for every frame and empties all destruction queues.
+    /// # Errors
+    /// If the device is lost.
+    pub fn wait_idle(&mut self) -> Result<(), VkError> {
+        self.timeline.wait(self.last_value, u64::MAX)?;
+        self.frames.iter_mut().try_for_each(FrameData::clear)
+    }
+}
+
+impl Drop for FrameContext {
+    fn drop(&mut self) {
+        // Nothing is executing anymore if the device is lost.
+        let _ = self.wait_idle();
+    }
+}
+
+/// The frame being recorded, see [`FrameContext::begin_frame`].
+pub struct Frame<'a> {
+    context: &'a mut FrameContext,
+}
+
+impl std::fmt::Debug for Frame<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Frame")
+            .field("index", &self.index())
+            .field("number", &self.number())
+            .finish()
+    }
+}
+
+impl Frame<'_> {
+    fn data(&self) -> &FrameData {
+        &self.context.frames[self.index()]
+    }
+
+    /// The slot of the frame, below [`FrameContext::frames_in_flight`].
+    pub fn index(&self) -> usize {
+        self.context.current()
+    }
+    /// Starts at 1 and increases every frame.
+    pub fn number(&self) -> u64 {
+        self.context.frame_number
+    }
+    /// To be signaled when acquiring the swapchain image.
+    pub fn image_available(&self) -> &Arc<Semaphore> {
+        &self.data().image_available
+    }
+    /// Drops `resource` once the device is done with this frame.
+    pub fn defer_destroy<R: 'static>(&mut self, resource: R) {
+        self.context.defer_destroy(resource);
+    }
+    /// Descriptor sets valid until this slot is used again.
+    pub fn descriptors(&self) -> &DescriptorAllocator {
+        &self.data().descriptors
+    }
+
+    /// Submits `submit` to `queue` as part of this frame; the next use of this frame's slot waits
+    /// for it.
+    /// # Errors
+    /// If submitting fails.
+    pub fn submit(&mut self, queue: &Queue, submit: QueueSubmit) -> Result<(), VkError> {
+        let value = self.context.last_value + 1;
+        let submission = submit
+            .signal_timeline(&self.context.timeline, value)
+            .submit(queue)?;
+        self.context.last_value = value;
+        let index = self.index();
+        let frame = &mut self.context.frames[index];
+        frame.value = value;
+        frame.submissions.push(submission);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::CommandPool;
+    use crate::selection::DeviceRequirements;
+
+    #[test]
+    #[cfg_attr(not(feature = "device-tests"), ignore = "needs a Vulkan device")]
+    fn frames_in_flight() {
+        let (device, queue) = crate::test_support::device(
+            "sync test",
+            DeviceRequirements::default().require_feature(TimelineSemaphore::FEATURE),
+            vk::QueueFlags::TRANSFER,
+        );
+
+        let fence = Fence::new(&device, true).unwrap();
+        assert!(fence.is_signaled().unwrap());
+        fence.reset().unwrap();
+        assert!(!fence.wait(0).unwrap());
+
+        let timeline = TimelineSemaphore::new(&device, 1, None).unwrap();
+        timeline.signal(3).unwrap();
+        assert_eq!(timeline.value().unwrap(), 3);
+        assert!(timeline.wait(2, 0).unwrap());
+        assert!(!timeline.wait(4, 0).unwrap());
+
+        let pool = CommandPool::new(&device, queue.family_index()).unwrap();
+        let mut frames = FrameContext::new(&device, 2).unwrap();
+        let resource = Arc::new(());
+        for number in 1..=4 {
+            let mut frame = frames.begin_frame().unwrap();
+            assert_eq!(frame.number(), number);
+            assert_eq!(frame.index(), (number as usize - 1) % 2);
+            if number == 1 {
+                frame.defer_destroy(resource.clone());
+            }
+            let submit = QueueSubmit::new(&device).command_buffer(pool.primary().unwrap());
+            frame.submit(&queue, submit).unwrap();
+            // Dropped when the first slot is reused.
+            assert_eq!(Arc::strong_count(&resource), if number < 3 { 2 } else { 1 });
+        }
+        frames.wait_idle().unwrap();
+        assert_eq!(frames.timeline().value().unwrap(), 4);
+
+        // Pretend the device got lost, the hooks run exactly once.
+        let calls = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let hook = |calls: &Arc<std::sync::atomic::AtomicU32>| {
+            let calls = calls.clone();
+            move |_: &crate::Device| {
+                calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        };
+        device.on_device_lost(hook(&calls));
+        assert!(!device.is_lost());
+        let lost = device.check::<()>(Err(vk::Result::ERROR_DEVICE_LOST));
+        assert_eq!(
+            lost.map_err(|x| VkError::from(x).runtime()),
+            Err(crate::error::RuntimeError::DeviceLost)
+        );
+        let _ = device.check::<()>(Err(vk::Result::ERROR_DEVICE_LOST));
+        assert!(device.is_lost());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+        device.on_device_lost(hook(&calls));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+}
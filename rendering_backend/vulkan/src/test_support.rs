@@ -0,0 +1,48 @@
+//! Setup shared by the tests that need a Vulkan implementation.
+//!
+//! Those tests are ignored unless the `device-tests` feature is enabled, so a machine without a
+//! device reports them as ignored instead of passed. With the feature, as in CI, they fail when
+//! there is no device: `cargo test -p horizon --features device-tests`.
+use crate::error::InitError;
+use crate::selection::DeviceRequirements;
+use crate::{Device, DeviceCreation, DeviceCreationInfo, Instance, InstanceCreateInfo, Queue};
+use ash::vk;
+use std::sync::Arc;
+
+/// # Panics
+/// If the instance cannot be created.
+pub fn instance(info: InstanceCreateInfo) -> Arc<Instance> {
+    Instance::new_dynamic(info).unwrap_or_else(|err| panic!("No Vulkan instance: {err}"))
+}
+
+/// # Panics
+/// If no device meets the requirements of `info`, or it cannot be created.
+pub fn create(instance: &Arc<Instance>, info: DeviceCreationInfo) -> DeviceCreation {
+    match Device::new(instance, info) {
+        Ok(created) => created,
+        Err(InitError::SuitablePhysicalDeviceNotFound(report)) => {
+            panic!("No suitable device:\n{report}")
+        }
+        Err(err) => panic!("{err}"),
+    }
+}
+
+/// A device meeting `requirements` with one queue supporting `flags`, on an instance for
+/// [`InstanceCreateInfo::compute_usage`].
+/// # Panics
+/// See [`instance`] and [`create`].
+pub fn device(
+    name: &str,
+    requirements: DeviceRequirements,
+    flags: vk::QueueFlags,
+) -> (Arc<Device>, Arc<Queue>) {
+    let instance = instance(InstanceCreateInfo::compute_usage(name));
+    let mut info = DeviceCreationInfo::default().requirements(requirements);
+    let queue = info.request_queue(crate::QueueDescription {
+        flags,
+        ..crate::QueueDescription::from_count(1)
+    });
+    let created = create(&instance, info);
+    let queue = created.queue(queue).clone();
+    (created.device, queue)
+}
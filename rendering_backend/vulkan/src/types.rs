@@ -2,13 +2,7 @@
 use super::raw::*;
 use std::ffi::CStr;
 #[derive(
-    Debug,
-    derive_more::From,
-    derive_more::Into,
-    derive_more::AsRef,
-    derive_more::AsMut,
-    PartialEq,
-    Clone,
+    derive_more::From, derive_more::Into, derive_more::AsRef, derive_more::AsMut, PartialEq, Clone,
 )]
 pub struct ExtensionName([i8; ExtensionName::MAX_SIZE]);
 impl ExtensionName {
@@ -21,6 +15,15 @@ impl ExtensionName {
     }
 }
 
+// The raw array is unreadable in logs.
+impl std::fmt::Debug for ExtensionName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ExtensionName")
+            .field(&self.to_str())
+            .finish()
+    }
+}
+
 impl From<&CStr> for ExtensionName {
     fn from(value: &CStr) -> Self {
         Self(from_cstr_to_array(value))
@@ -109,3 +112,201 @@ impl<T: ash::vk::Handle> std::fmt::Debug for HandleWrapper<T> {
         write!(f, "Handle: {:?}", <T as ash::vk::Handle>::TYPE)
     }
 }
+
+/// A buffer device address pointing at a `T`, the Rust side of a `PhysicalStorageBuffer` pointer.
+///
+/// The shader macro uses this for `buffer_reference` members, so the generated structs keep track
+/// of what the address points to. It has the same layout as a raw `u64` address.
+#[repr(transparent)]
+pub struct DeviceAddress<T> {
+    raw: ash::vk::DeviceAddress,
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> DeviceAddress<T> {
+    pub const NULL: Self = Self::from_raw(0);
+
+    pub const fn from_raw(raw: ash::vk::DeviceAddress) -> Self {
+        Self {
+            raw,
+            _marker: std::marker::PhantomData,
+        }
+    }
+    pub const fn raw(self) -> ash::vk::DeviceAddress {
+        self.raw
+    }
+    pub const fn is_null(self) -> bool {
+        self.raw == 0
+    }
+    /// Address of the `count`th `T` after this one, as if this pointed into an array.
+    pub const fn add(self, count: u64) -> Self {
+        Self::from_raw(self.raw + count * std::mem::size_of::<T>() as u64)
+    }
+    /// Reinterpret the pointee. The layout of `U` is not checked.
+    pub const fn cast<U>(self) -> DeviceAddress<U> {
+        DeviceAddress::from_raw(self.raw)
+    }
+}
+
+// Manual impls, derives would put bounds on `T`.
+impl<T> Clone for DeviceAddress<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for DeviceAddress<T> {}
+impl<T> PartialEq for DeviceAddress<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+impl<T> Eq for DeviceAddress<T> {}
+impl<T> std::hash::Hash for DeviceAddress<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.raw.hash(state);
+    }
+}
+impl<T> Default for DeviceAddress<T> {
+    fn default() -> Self {
+        Self::NULL
+    }
+}
+impl<T> std::fmt::Debug for DeviceAddress<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "DeviceAddress<{}>({:#x})",
+            std::any::type_name::<T>(),
+            self.raw
+        )
+    }
+}
+
+/// Plain data, copied to and from device memory byte for byte.
+///
+/// The structs generated by the shader macro implement it, as do scalars, arrays, `nalgebra`
+/// vectors and matrices, [`DeviceAddress`] and the bindless handles.
+/// # Safety
+/// Every bit pattern has to be a valid value, and the type must not have padding bytes, which
+/// are uninitialized. `#[repr(C)]` structs of `Pod` fields with explicit padding fields qualify,
+/// `bool`, enums, references and `NonZero*` types do not.
+pub unsafe trait Pod: Copy + Send + Sync + 'static {
+    /// A value of all zero bytes.
+    fn zeroed() -> Self {
+        // Every bit pattern is a valid value.
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {$(
+        unsafe impl Pod for $ty {}
+    )*};
+}
+
+impl_pod!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+impl_pod!(
+    ash::vk::DrawIndirectCommand,
+    ash::vk::DrawIndexedIndirectCommand,
+    ash::vk::DispatchIndirectCommand
+);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+// `ArrayStorage` is `#[repr(C)]` over `[[T; R]; C]`.
+unsafe impl<T: Pod + nalgebra::Scalar, const R: usize, const C: usize> Pod
+    for nalgebra::SMatrix<T, R, C>
+{
+}
+unsafe impl<T: 'static> Pod for DeviceAddress<T> {}
+
+/// A `T` followed by `PAD` bytes, an element of an array whose `ArrayStride` is larger than `T`,
+/// e.g. a `float[4]` in a std140 block.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Padded<T, const PAD: usize> {
+    pub value: T,
+    padding: [u8; PAD],
+}
+
+impl<T, const PAD: usize> Padded<T, PAD> {
+    /// # Panics
+    /// At compile time, if `PAD` is not a multiple of the alignment of `T`, since the padding
+    /// bytes Rust would add are uninitialized.
+    pub const fn new(value: T) -> Self {
+        const {
+            assert!(
+                std::mem::size_of::<Self>() == std::mem::size_of::<T>() + PAD,
+                "the padding has to be a multiple of the alignment"
+            );
+        };
+        Self {
+            value,
+            padding: [0; PAD],
+        }
+    }
+}
+
+impl<T, const PAD: usize> From<T> for Padded<T, PAD> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+// Values only come from `new`, which rejects implicit padding.
+unsafe impl<T: Pod, const PAD: usize> Pod for Padded<T, PAD> {}
+
+/// A column major matrix whose `MatrixStride` is larger than a column, e.g. a `mat3` in a std140
+/// or std430 block, where every column takes 16 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StridedMatrix<T: nalgebra::Scalar, const R: usize, const C: usize, const PAD: usize> {
+    columns: [Padded<nalgebra::SVector<T, R>, PAD>; C],
+}
+
+impl<T: nalgebra::Scalar + Copy, const R: usize, const C: usize, const PAD: usize>
+    StridedMatrix<T, R, C, PAD>
+{
+    pub fn new(matrix: nalgebra::SMatrix<T, R, C>) -> Self {
+        Self {
+            columns: std::array::from_fn(|x| Padded::new(matrix.column(x).into_owned())),
+        }
+    }
+    pub fn matrix(&self) -> nalgebra::SMatrix<T, R, C> {
+        nalgebra::SMatrix::from_fn(|row, column| self.columns[column].value[row])
+    }
+}
+
+impl<T: nalgebra::Scalar + Copy, const R: usize, const C: usize, const PAD: usize>
+    From<nalgebra::SMatrix<T, R, C>> for StridedMatrix<T, R, C, PAD>
+{
+    fn from(matrix: nalgebra::SMatrix<T, R, C>) -> Self {
+        Self::new(matrix)
+    }
+}
+
+unsafe impl<T: Pod + nalgebra::Scalar, const R: usize, const C: usize, const PAD: usize> Pod
+    for StridedMatrix<T, R, C, PAD>
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strided_layouts() {
+        // A std140 `float[4]`.
+        let array = [1.0f32, 2.0, 3.0, 4.0].map(Padded::<f32, 12>::new);
+        assert_eq!(std::mem::size_of_val(&array), 64);
+        assert_eq!(array[2].value, 3.0);
+
+        // A `mat3` with a `MatrixStride` of 16.
+        let matrix = nalgebra::Matrix3::from_fn(|row, column| (row * 3 + column) as f32);
+        let strided = StridedMatrix::<f32, 3, 3, 4>::new(matrix);
+        assert_eq!(std::mem::size_of_val(&strided), 48);
+        assert_eq!(strided.matrix(), matrix);
+        // The second column starts at byte 16.
+        let words: [f32; 12] = unsafe { std::mem::transmute(strided) };
+        assert_eq!(words[4..7], [1.0, 4.0, 7.0]);
+        assert_eq!(words[3], 0.0);
+    }
+}
@@ -24,6 +24,7 @@ use std::num::TryFromIntError;
 use std::rc::Rc;
 use thiserror::Error;
 
+pub mod requirements;
 pub mod types;
 pub use rspirv;
 pub use rspirv::spirv;
@@ -93,6 +94,8 @@ pub enum ReflectError {
     InvalidInnerType(Instruction),
     #[error("Length value of instruction does not fit in a u16 {0:?}")]
     LengthDoesNotFitIn16(Instruction),
+    #[error("Member {1} of {0:?} lacks an `Offset` decoration")]
+    MissingOffsetDecoration(Instruction, u32),
 
     #[error("Duplicate set declaration {0:?}")]
     DuplicateSetDeclaration(Instruction),
@@ -285,6 +288,9 @@ pub struct Decoration {
     pub location: Option<u32>,
     pub offset: Option<u32>,
     pub array_stride: Option<u32>,
+    /// Bytes between the columns of a matrix member, or its rows if it is `row_major`.
+    pub matrix_stride: Option<u32>,
+    pub row_major: bool,
     pub descriptor_set: Option<u32>,
     pub nonwritable: bool,
     pub nonreadable: bool,
@@ -299,6 +305,33 @@ pub struct PushConstantInfo {
     pub size: u32,
 }
 
+/// A single member of an explicitly laid out struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberLayout {
+    pub name: Option<String>,
+    /// Byte offset from the start of the struct.
+    pub offset: u32,
+    /// Size in bytes including the `ArrayStride` and `MatrixStride` padding, a runtime array
+    /// counts as 0.
+    pub size: u32,
+    /// `MatrixStride` of a matrix member, or of the matrices in an array member.
+    pub matrix_stride: Option<u32>,
+    pub row_major: bool,
+    pub type_id: TypeId,
+    pub ty: Rc<types::Type>,
+}
+
+/// The type behind a `PhysicalStorageBuffer` pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointeeLayout {
+    pub pointee: TypeId,
+    pub name: Option<String>,
+    /// Size in bytes, a trailing runtime array counts as 0.
+    pub size: u32,
+    /// Empty if the pointee is not a struct.
+    pub members: Vec<MemberLayout>,
+}
+
 impl Reflection {
     const IMAGE_SAMPLED: u32 = 1;
     const IMAGE_STORAGE: u32 = 2;
@@ -446,7 +479,7 @@ impl Reflection {
                 // TODO: What if storageclass of variable and type isn't the same?
                 let x1 = get_operand_at!(inst, Operand::StorageClass, 0)?;
                 let x2 = get_operand_at!(type_inst, Operand::StorageClass, 0)?;
-                debug_assert!(x1 == x2);
+                debug_assert_eq!(x1, x2);
 
                 get_operand_at!(type_inst, Operand::IdRef, 1)
             }
@@ -607,12 +640,171 @@ impl Reflection {
                         .ok_or(ReflectError::MissingResultId(inst.clone()))?;
                     types.insert(result_id, type_info);
                 }
+                spirv::Op::TypeForwardPointer => {
+                    // %ptr is used before it is declared, so resolve its pointee from the
+                    // `OpTypePointer` that follows.
+                    let pointer_id = get_operand_at!(inst, Operand::IdRef, 0)?;
+                    let pointer =
+                        find_instructions_assigning_to_id(&self.0.types_global_values, pointer_id)?;
+                    types.insert(pointer_id, Rc::new(Self::pointer_type(pointer)?));
+                }
+                spirv::Op::TypePointer => {
+                    // Logical pointers are resolved through `get_type_of_variable`, only
+                    // buffer device addresses are part of the type graph.
+                    let storage_class = get_operand_at!(inst, Operand::StorageClass, 0)?;
+                    if storage_class == StorageClass::PhysicalStorageBuffer {
+                        let result_id = inst
+                            .result_id
+                            .ok_or(ReflectError::MissingResultId(inst.clone()))?;
+                        if let std::collections::btree_map::Entry::Vacant(entry) =
+                            types.entry(result_id)
+                        {
+                            entry.insert(Rc::new(Self::pointer_type(inst)?));
+                        }
+                    }
+                }
                 _ => {}
             }
         }
         Ok(types)
     }
 
+    fn pointer_type(inst: &Instruction) -> Result<types::Type> {
+        if inst.class.opcode != spirv::Op::TypePointer {
+            return Err(ReflectError::UnhandledTypeInstruction(inst.clone()));
+        }
+        let storage_class = get_operand_at!(inst, Operand::StorageClass, 0)?;
+        let pointee = get_operand_at!(inst, Operand::IdRef, 1)?;
+        Ok(types::Type::Pointer(types::Pointer {
+            storage_class,
+            pointee,
+        }))
+    }
+
+    /// Returns the explicit layout of every member of the struct `struct_id`, as given by its
+    /// `Offset` decorations.
+    /// # Errors
+    /// Errors if `struct_id` is not a struct, or a member has no `Offset` decoration.
+    pub fn get_struct_layout(&self, struct_id: TypeId) -> Result<Vec<MemberLayout>> {
+        let types = self.get_types()?;
+        self.get_struct_layout_with_types(&types, struct_id)
+    }
+
+    fn get_struct_layout_with_types(
+        &self,
+        types: &BTreeMap<TypeId, Rc<types::Type>>,
+        struct_id: TypeId,
+    ) -> Result<Vec<MemberLayout>> {
+        let inst = find_instructions_assigning_to_id(&self.0.types_global_values, struct_id)?;
+        if inst.class.opcode != spirv::Op::TypeStruct {
+            return Err(ReflectError::InvalidInnerType(inst.clone()));
+        }
+        let debug_names = self.get_debug_names();
+        let member_decorations = self.get_member_decoration();
+        let member_decorations = member_decorations.get(&struct_id);
+        inst.operands
+            .iter()
+            .enumerate()
+            .map(|(member, op)| -> Result<MemberLayout> {
+                let Operand::IdRef(type_id) = op else {
+                    return Err(ReflectError::InvalidInnerType(inst.clone()));
+                };
+                let member = u32::try_from(member)?;
+                let offset = member_decorations
+                    .and_then(|x| x.get(&member))
+                    .and_then(|x| x.offset)
+                    .ok_or_else(|| ReflectError::MissingOffsetDecoration(inst.clone(), member))?;
+                let ty = types
+                    .get(type_id)
+                    .ok_or(ReflectError::UnresolvedTypeId(*type_id))?
+                    .clone();
+                let size = Self::calculate_member_size_bytes(&self.0, struct_id, member, *type_id)?;
+                let decoration = member_decorations.and_then(|x| x.get(&member));
+                Ok(MemberLayout {
+                    name: debug_names
+                        .get_member_name(struct_id, member)
+                        .map(|x| (*x).to_owned()),
+                    offset,
+                    size,
+                    matrix_stride: decoration.and_then(|x| x.matrix_stride),
+                    row_major: decoration.is_some_and(|x| x.row_major),
+                    type_id: *type_id,
+                    ty,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the size of the struct `struct_id` including its trailing padding, which is the
+    /// `ArrayStride` of the arrays and `PhysicalStorageBuffer` pointers it is used through. Without
+    /// those the size ends with the last member.
+    /// # Errors
+    /// Errors if `struct_id` cannot be resolved.
+    pub fn get_struct_size(&self, struct_id: TypeId) -> Result<u32> {
+        let decorations = self.get_decorations();
+        let stride = self
+            .0
+            .types_global_values
+            .iter()
+            .filter(|inst| match inst.class.opcode {
+                spirv::Op::TypeArray | spirv::Op::TypeRuntimeArray => {
+                    inst.operands.first() == Some(&Operand::IdRef(struct_id))
+                }
+                spirv::Op::TypePointer => {
+                    inst.operands.first()
+                        == Some(&Operand::StorageClass(StorageClass::PhysicalStorageBuffer))
+                        && inst.operands.get(1) == Some(&Operand::IdRef(struct_id))
+                }
+                _ => false,
+            })
+            .filter_map(|inst| decorations.get(&inst.result_id?)?.array_stride)
+            .max();
+        let inst = find_instructions_assigning_to_id(&self.0.types_global_values, struct_id)?;
+        let size = Self::calculate_variable_size_bytes(&self.0, inst)?;
+        Ok(stride.map_or(size, |stride| stride.max(size)))
+    }
+
+    /// Returns the layout of every type pointed to by a `PhysicalStorageBuffer` pointer, keyed by
+    /// the id of the pointer type.
+    ///
+    /// These are the types behind buffer device addresses, e.g. a
+    /// `layout(buffer_reference) buffer` block passed through a push constant.
+    /// # Errors
+    /// Errors if the type graph cannot be resolved, or a pointee has no explicit layout.
+    pub fn get_physical_storage_buffer_layouts(&self) -> Result<BTreeMap<TypeId, PointeeLayout>> {
+        let types = self.get_types()?;
+        let debug_names = self.get_debug_names();
+        types
+            .iter()
+            .filter_map(|(id, ty)| match ty.as_ref() {
+                types::Type::Pointer(ptr)
+                    if ptr.storage_class == StorageClass::PhysicalStorageBuffer =>
+                {
+                    Some((*id, ptr.pointee))
+                }
+                _ => None,
+            })
+            .map(|(pointer, pointee)| -> Result<(TypeId, PointeeLayout)> {
+                let inst = find_instructions_assigning_to_id(&self.0.types_global_values, pointee)?;
+                let members = if inst.class.opcode == spirv::Op::TypeStruct {
+                    self.get_struct_layout_with_types(&types, pointee)?
+                } else {
+                    vec![]
+                };
+                let size = Self::calculate_variable_size_bytes(&self.0, inst)?;
+                Ok((
+                    pointer,
+                    PointeeLayout {
+                        pointee,
+                        name: debug_names.get_name(pointee).map(|x| (*x).to_owned()),
+                        size,
+                        members,
+                    },
+                ))
+            })
+            .collect()
+    }
+
     pub fn get_decorations(&self) -> BTreeMap<Id, Decoration> {
         let mut res = BTreeMap::new();
         for inst in &self.0.annotations {
@@ -684,10 +876,17 @@ impl Reflection {
                     spirv::Decoration::Offset => {
                         dec.offset = Some(get_operand_at!(inst, Operand::LiteralBit32, 3).unwrap());
                     }
-                    spirv::Decoration::ArrayStride | spirv::Decoration::MatrixStride => {
+                    spirv::Decoration::ArrayStride => {
                         dec.array_stride =
                             Some(get_operand_at!(inst, Operand::LiteralBit32, 3).unwrap());
                     }
+                    spirv::Decoration::MatrixStride => {
+                        dec.matrix_stride =
+                            Some(get_operand_at!(inst, Operand::LiteralBit32, 3).unwrap());
+                    }
+                    spirv::Decoration::RowMajor => {
+                        dec.row_major = true;
+                    }
                     spirv::Decoration::DescriptorSet => {
                         dec.descriptor_set =
                             Some(get_operand_at!(inst, Operand::LiteralBit32, 3).unwrap());
@@ -1004,7 +1203,7 @@ impl Reflection {
                             if let Operand::LiteralBit32(i) = inst.operands[2] {
                                 match d {
                                     spirv::Decoration::DescriptorSet => {
-                                        if state.0.is_none() {
+                                        if state.0.is_some() {
                                             err = Err(ReflectError::DuplicateSetDeclaration(
                                                 (*inst).clone(),
                                             ));
@@ -1012,7 +1211,7 @@ impl Reflection {
                                         return (Some(i), state.1);
                                     }
                                     spirv::Decoration::Binding => {
-                                        if state.1.is_none() {
+                                        if state.1.is_some() {
                                             err = Err(ReflectError::DuplicateBindingSet(
                                                 (*inst).clone(),
                                             ));
@@ -1051,11 +1250,18 @@ impl Reflection {
                     name.clone_into(&mut descriptor_info.name);
                 }
 
-                let inserted = current_set.insert(binding, descriptor_info);
-
-                if inserted.is_none() {
-                    // TODO: create a better err for this
-                    err = Err(ReflectError::DuplicateBindingInSet());
+                match current_set.entry(binding) {
+                    std::collections::btree_map::Entry::Vacant(entry) => {
+                        entry.insert(descriptor_info);
+                    }
+                    // Aliases of the same descriptor, e.g. one buffer viewed as different blocks.
+                    std::collections::btree_map::Entry::Occupied(entry)
+                        if entry.get().ty == descriptor_info.ty
+                            && entry.get().binding_count == descriptor_info.binding_count => {}
+                    std::collections::btree_map::Entry::Occupied(_) => {
+                        // TODO: create a better err for this
+                        err = Err(ReflectError::DuplicateBindingInSet());
+                    }
                 }
             }
         }
@@ -1069,7 +1275,7 @@ impl Reflection {
         reflect: &Module,
         struct_instruction: &Instruction,
     ) -> Result<u32, ReflectError> {
-        debug_assert!(struct_instruction.class.opcode == spirv::Op::TypeStruct);
+        debug_assert_eq!(struct_instruction.class.opcode, spirv::Op::TypeStruct);
 
         // if there are less then two members there is no offset to use, early out
         if struct_instruction.operands.len() < 2 {
@@ -1095,6 +1301,76 @@ impl Reflection {
             .unwrap_or(0))
     }
 
+    /// The `value` of the decoration `decoration` on `id`, or on member `member` of `id`.
+    fn decoration_value(
+        reflect: &Module,
+        id: Id,
+        member: Option<u32>,
+        decoration: spirv::Decoration,
+    ) -> Result<Option<u32>> {
+        let (opcode, index) = match member {
+            Some(_) => (spirv::Op::MemberDecorate, 2),
+            None => (spirv::Op::Decorate, 1),
+        };
+        for inst in filter_annotations_with_id(&reflect.annotations, id)? {
+            if inst.class.opcode != opcode
+                || get_operand_at!(inst, Operand::Decoration, index)? != decoration
+            {
+                continue;
+            }
+            if let Some(member) = member {
+                if get_operand_at!(inst, Operand::LiteralBit32, 1)? != member {
+                    continue;
+                }
+            }
+            return Ok(Some(get_operand_at!(
+                inst,
+                Operand::LiteralBit32,
+                index + 1
+            )?));
+        }
+        Ok(None)
+    }
+
+    /// Size of member `member` of `struct_id`, whose type is `type_id`. Matrices take
+    /// `MatrixStride` bytes per column, or per row if they are `RowMajor`.
+    fn calculate_member_size_bytes(
+        reflect: &Module,
+        struct_id: Id,
+        member: u32,
+        type_id: TypeId,
+    ) -> Result<u32> {
+        let inst = find_instructions_assigning_to_id(&reflect.types_global_values, type_id)?;
+        let stride = Self::decoration_value(
+            reflect,
+            struct_id,
+            Some(member),
+            spirv::Decoration::MatrixStride,
+        )?;
+        match (inst.class.opcode, stride) {
+            (spirv::Op::TypeMatrix, Some(stride)) => {
+                let row_major = filter_annotations_with_id(&reflect.annotations, struct_id)?
+                    .iter()
+                    .any(|x| {
+                        x.class.opcode == spirv::Op::MemberDecorate
+                            && x.operands.get(1) == Some(&Operand::LiteralBit32(member))
+                            && x.operands.get(2)
+                                == Some(&Operand::Decoration(spirv::Decoration::RowMajor))
+                    });
+                let count = if row_major {
+                    let column = get_operand_at!(inst, Operand::IdRef, 0)?;
+                    let column =
+                        find_instructions_assigning_to_id(&reflect.types_global_values, column)?;
+                    get_operand_at!(column, Operand::LiteralBit32, 1)?
+                } else {
+                    get_operand_at!(inst, Operand::LiteralBit32, 1)?
+                };
+                Ok(stride * count)
+            }
+            _ => Self::calculate_variable_size_bytes(reflect, inst),
+        }
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn calculate_variable_size_bytes(
         reflect: &Module,
@@ -1106,7 +1382,7 @@ impl Reflection {
                 Ok(get_operand_at!(type_instruction, Operand::LiteralBit32, 0)? / 8)
             }
             spirv::Op::TypeVector | spirv::Op::TypeMatrix => {
-                debug_assert!(type_instruction.operands.len() == 2);
+                debug_assert_eq!(type_instruction.operands.len(), 2);
                 let type_id = get_operand_at!(type_instruction, Operand::IdRef, 0)?;
                 let var_type_instruction =
                     find_instructions_assigning_to_id(&reflect.types_global_values, type_id)?;
@@ -1118,7 +1394,7 @@ impl Reflection {
                 Ok(type_size_bytes * type_constant_count)
             }
             spirv::Op::TypeArray => {
-                debug_assert!(type_instruction.operands.len() == 2);
+                debug_assert_eq!(type_instruction.operands.len(), 2);
                 let type_id = get_operand_at!(type_instruction, Operand::IdRef, 0)?;
                 let var_type_instruction =
                     find_instructions_assigning_to_id(&reflect.types_global_values, type_id)?;
@@ -1132,8 +1408,15 @@ impl Reflection {
                 )?;
                 let type_constant_count =
                     get_operand_at!(constant_instruction, Operand::LiteralBit32, 0)?;
+                // Elements of explicitly laid out arrays are `ArrayStride` apart.
+                let stride = match type_instruction.result_id {
+                    Some(id) => {
+                        Self::decoration_value(reflect, id, None, spirv::Decoration::ArrayStride)?
+                    }
+                    None => None,
+                };
 
-                Ok(type_size_bytes * type_constant_count)
+                Ok(stride.unwrap_or(type_size_bytes) * type_constant_count)
             }
             spirv::Op::TypeStruct => {
                 if type_instruction.operands.is_empty() {
@@ -1142,12 +1425,20 @@ impl Reflection {
                     let byte_offset = Self::byte_offset_to_last_var(reflect, type_instruction)?;
                     let last_var_idx = type_instruction.operands.len() - 1;
                     let id_ref = get_operand_at!(type_instruction, Operand::IdRef, last_var_idx)?;
-                    let type_instruction =
-                        find_instructions_assigning_to_id(&reflect.types_global_values, id_ref)?;
+                    let struct_id = type_instruction
+                        .result_id
+                        .ok_or_else(|| ReflectError::MissingResultId(type_instruction.clone()))?;
                     Ok(byte_offset
-                        + Self::calculate_variable_size_bytes(reflect, type_instruction)?)
+                        + Self::calculate_member_size_bytes(
+                            reflect,
+                            struct_id,
+                            u32::try_from(last_var_idx)?,
+                            id_ref,
+                        )?)
                 }
             }
+            // Only allowed as the last member of a block, where it does not add to the size.
+            spirv::Op::TypeRuntimeArray => Ok(0),
             spirv::Op::TypePointer => {
                 let memory_model = reflect
                     .memory_model
@@ -1226,6 +1517,65 @@ impl Reflection {
         }))
     }
 
+    /// Returns the type id of the push constant block, if there is one.
+    /// # Errors
+    /// Errors if there are multiple push constant blocks, or the variable type cannot be resolved.
+    pub fn get_push_constant_type(&self) -> Result<Option<TypeId>> {
+        let push_constants =
+            self.get_all_variables_with_storage_class(StorageClass::PushConstant)?;
+        if push_constants.len() > 1 {
+            return Err(ReflectError::TooManyPushConstants);
+        }
+        push_constants
+            .first()
+            .map(|id| self.get_type_of_variable(*id))
+            .transpose()
+    }
+
+    /// Returns every `OpCapability` declared by the module.
+    /// # Errors
+    /// Errors if an `OpCapability` lacks its operand.
+    pub fn get_capabilities(&self) -> Result<Vec<spirv::Capability>> {
+        self.0
+            .capabilities
+            .iter()
+            .map(|inst| get_operand_at!(inst, Operand::Capability, 0))
+            .collect()
+    }
+
+    /// Returns the name of every `OpExtension` declared by the module.
+    /// # Errors
+    /// Errors if an `OpExtension` lacks its operand.
+    pub fn get_extensions(&self) -> Result<Vec<String>> {
+        self.0
+            .extensions
+            .iter()
+            .map(|inst| get_ref_operand_at!(inst, Operand::LiteralString, 0).cloned())
+            .collect()
+    }
+
+    /// Returns the execution model of every entry point.
+    /// # Errors
+    /// Errors if an `OpEntryPoint` lacks its operand.
+    pub fn get_execution_models(&self) -> Result<Vec<spirv::ExecutionModel>> {
+        self.0
+            .entry_points
+            .iter()
+            .map(|inst| get_operand_at!(inst, Operand::ExecutionModel, 0))
+            .collect()
+    }
+
+    /// Returns the Vulkan versions, extensions and features the module needs to run.
+    /// # Errors
+    /// Errors if the capability, extension or entry point declarations are malformed.
+    pub fn get_vulkan_requirements(&self) -> Result<requirements::ShaderRequirements> {
+        Ok(requirements::ShaderRequirements::from_declarations(
+            &self.get_capabilities()?,
+            &self.get_extensions()?,
+            &self.get_execution_models()?,
+        ))
+    }
+
     #[must_use]
     pub fn disassemble(&self) -> String {
         use rspirv::binary::Disassemble;
@@ -0,0 +1,611 @@
+//! Maps the capabilities, extensions and execution models declared by a SPIR-V module to the
+//! Vulkan versions, extensions and device features needed to run it.
+//!
+//! The mapping follows the "SPIR-V Environment" appendix of the Vulkan specification. Vulkan
+//! names are kept as plain strings (exactly as they are spelled in the specification), so this
+//! crate does not have to depend on any particular Vulkan binding.
+
+use rspirv::spirv::{Capability, ExecutionModel};
+use std::fmt;
+
+/// The structure a device feature bit lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VulkanFeature {
+    /// `VkPhysicalDeviceFeatures::<name>`
+    Core(&'static str),
+    /// `VkPhysicalDeviceVulkan11Features::<name>`
+    Vulkan11(&'static str),
+    /// `VkPhysicalDeviceVulkan12Features::<name>`
+    Vulkan12(&'static str),
+    /// `VkPhysicalDeviceVulkan13Features::<name>`
+    Vulkan13(&'static str),
+    /// A feature exposed by the feature structure of `extension`.
+    Extension {
+        extension: &'static str,
+        name: &'static str,
+    },
+}
+
+impl VulkanFeature {
+    /// Name of the feature bit, e.g. `shaderFloat16`.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Core(name)
+            | Self::Vulkan11(name)
+            | Self::Vulkan12(name)
+            | Self::Vulkan13(name)
+            | Self::Extension { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for VulkanFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Core(name) => write!(f, "VkPhysicalDeviceFeatures::{name}"),
+            Self::Vulkan11(name) => write!(f, "VkPhysicalDeviceVulkan11Features::{name}"),
+            Self::Vulkan12(name) => write!(f, "VkPhysicalDeviceVulkan12Features::{name}"),
+            Self::Vulkan13(name) => write!(f, "VkPhysicalDeviceVulkan13Features::{name}"),
+            Self::Extension { extension, name } => write!(f, "{extension}::{name}"),
+        }
+    }
+}
+
+/// A single thing a device has to provide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VulkanRequirement {
+    /// Minimum core API version, as `(major, minor)`.
+    Version(u32, u32),
+    /// A device extension, e.g. `VK_KHR_8bit_storage`.
+    Extension(&'static str),
+    /// A device feature bit. Requires the Vulkan version or extension that exposes it.
+    Feature(VulkanFeature),
+    /// A bit of `VkPhysicalDeviceSubgroupProperties::supportedOperations`, named without the
+    /// `VK_SUBGROUP_FEATURE_` prefix and `_BIT` suffix, e.g. `ARITHMETIC`. Requires Vulkan 1.1.
+    SubgroupOperation(&'static str),
+}
+
+impl fmt::Display for VulkanRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version(major, minor) => write!(f, "Vulkan {major}.{minor}"),
+            Self::Extension(name) => f.write_str(name),
+            Self::Feature(feature) => feature.fmt(f),
+            Self::SubgroupOperation(op) => write!(f, "VK_SUBGROUP_FEATURE_{op}_BIT"),
+        }
+    }
+}
+
+/// What declared a requirement.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RequirementSource {
+    Capability(Capability),
+    Extension(String),
+    ExecutionModel(ExecutionModel),
+}
+
+impl fmt::Display for RequirementSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Capability(cap) => write!(f, "OpCapability {cap:?}"),
+            Self::Extension(ext) => write!(f, "OpExtension \"{ext}\""),
+            Self::ExecutionModel(model) => write!(f, "OpEntryPoint {model:?}"),
+        }
+    }
+}
+
+/// A requirement of the module together with the ways a device can satisfy it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub source: RequirementSource,
+    /// Satisfying any one of these is enough. Empty if every Vulkan implementation supports it.
+    pub any_of: Vec<VulkanRequirement>,
+}
+
+impl Requirement {
+    /// Whether this is guaranteed by every Vulkan 1.0 implementation.
+    #[must_use]
+    pub const fn is_core(&self) -> bool {
+        self.any_of.is_empty()
+    }
+}
+
+/// Everything a module needs from the device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShaderRequirements {
+    pub requirements: Vec<Requirement>,
+    /// Declarations without a known Vulkan mapping. These cannot be checked ahead of time.
+    pub unknown: Vec<RequirementSource>,
+}
+
+impl ShaderRequirements {
+    /// Builds the requirements from the declarations of a module.
+    #[must_use]
+    pub fn from_declarations(
+        capabilities: &[Capability],
+        extensions: &[String],
+        execution_models: &[ExecutionModel],
+    ) -> Self {
+        let mut res = Self::default();
+        for &cap in capabilities {
+            res.push(
+                RequirementSource::Capability(cap),
+                capability_requirements(cap),
+            );
+        }
+        for ext in extensions {
+            res.push(
+                RequirementSource::Extension(ext.clone()),
+                extension_requirements(ext),
+            );
+        }
+        for &model in execution_models {
+            res.push(
+                RequirementSource::ExecutionModel(model),
+                Some(execution_model_requirements(model)),
+            );
+        }
+        res
+    }
+
+    fn push(&mut self, source: RequirementSource, any_of: Option<Vec<VulkanRequirement>>) {
+        if self.requirements.iter().any(|r| r.source == source) || self.unknown.contains(&source) {
+            return;
+        }
+        match any_of {
+            Some(any_of) => self.requirements.push(Requirement { source, any_of }),
+            None => self.unknown.push(source),
+        }
+    }
+
+    /// Adds the requirements of another module, e.g. to check a whole pipeline at once.
+    pub fn merge(&mut self, other: Self) {
+        for req in other.requirements {
+            self.push(req.source, Some(req.any_of));
+        }
+        for source in other.unknown {
+            self.push(source, None);
+        }
+    }
+
+    /// Requirements that are not guaranteed by Vulkan 1.0.
+    pub fn non_core(&self) -> impl Iterator<Item = &Requirement> {
+        self.requirements.iter().filter(|r| !r.is_core())
+    }
+}
+
+const fn core(name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Feature(VulkanFeature::Core(name))
+}
+const fn v11(name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Feature(VulkanFeature::Vulkan11(name))
+}
+const fn v12(name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Feature(VulkanFeature::Vulkan12(name))
+}
+const fn v13(name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Feature(VulkanFeature::Vulkan13(name))
+}
+const fn ext_feature(extension: &'static str, name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Feature(VulkanFeature::Extension { extension, name })
+}
+const fn ext(name: &'static str) -> VulkanRequirement {
+    VulkanRequirement::Extension(name)
+}
+const fn version(major: u32, minor: u32) -> VulkanRequirement {
+    VulkanRequirement::Version(major, minor)
+}
+const fn subgroup(op: &'static str) -> VulkanRequirement {
+    VulkanRequirement::SubgroupOperation(op)
+}
+
+/// Ways a device can support `cap`. `None` if the capability has no known Vulkan mapping.
+#[must_use]
+#[allow(clippy::too_many_lines)]
+pub fn capability_requirements(cap: Capability) -> Option<Vec<VulkanRequirement>> {
+    use Capability as C;
+    let any_of = match cap {
+        C::Matrix
+        | C::Shader
+        | C::InputAttachment
+        | C::Sampled1D
+        | C::Image1D
+        | C::SampledBuffer
+        | C::ImageBuffer
+        | C::ImageQuery
+        | C::DerivativeControl
+        | C::StorageImageExtendedFormats => vec![],
+        C::Geometry => vec![core("geometryShader")],
+        C::Tessellation => vec![core("tessellationShader")],
+        C::Float64 => vec![core("shaderFloat64")],
+        C::Int64 => vec![core("shaderInt64")],
+        C::Int16 => vec![core("shaderInt16")],
+        C::Int64Atomics => vec![
+            v12("shaderBufferInt64Atomics"),
+            v12("shaderSharedInt64Atomics"),
+            ext_feature("VK_KHR_shader_atomic_int64", "shaderBufferInt64Atomics"),
+            ext_feature("VK_KHR_shader_atomic_int64", "shaderSharedInt64Atomics"),
+        ],
+        C::TessellationPointSize | C::GeometryPointSize => {
+            vec![core("shaderTessellationAndGeometryPointSize")]
+        }
+        C::ImageGatherExtended => vec![core("shaderImageGatherExtended")],
+        C::StorageImageMultisample | C::ImageMSArray => {
+            vec![core("shaderStorageImageMultisample")]
+        }
+        C::UniformBufferArrayDynamicIndexing => {
+            vec![core("shaderUniformBufferArrayDynamicIndexing")]
+        }
+        C::SampledImageArrayDynamicIndexing => {
+            vec![core("shaderSampledImageArrayDynamicIndexing")]
+        }
+        C::StorageBufferArrayDynamicIndexing => {
+            vec![core("shaderStorageBufferArrayDynamicIndexing")]
+        }
+        C::StorageImageArrayDynamicIndexing => {
+            vec![core("shaderStorageImageArrayDynamicIndexing")]
+        }
+        C::ClipDistance => vec![core("shaderClipDistance")],
+        C::CullDistance => vec![core("shaderCullDistance")],
+        C::ImageCubeArray | C::SampledCubeArray => vec![core("imageCubeArray")],
+        C::SampleRateShading | C::InterpolationFunction => vec![core("sampleRateShading")],
+        C::SparseResidency => vec![core("shaderResourceResidency")],
+        C::MinLod => vec![core("shaderResourceMinLod")],
+        C::StorageImageReadWithoutFormat => vec![
+            core("shaderStorageImageReadWithoutFormat"),
+            version(1, 3),
+            ext("VK_KHR_format_feature_flags2"),
+        ],
+        C::StorageImageWriteWithoutFormat => vec![
+            core("shaderStorageImageWriteWithoutFormat"),
+            version(1, 3),
+            ext("VK_KHR_format_feature_flags2"),
+        ],
+        C::MultiViewport => vec![core("multiViewport")],
+        C::TransformFeedback => vec![ext_feature(
+            "VK_EXT_transform_feedback",
+            "transformFeedback",
+        )],
+        C::GeometryStreams => vec![ext_feature("VK_EXT_transform_feedback", "geometryStreams")],
+
+        C::DrawParameters => vec![
+            v11("shaderDrawParameters"),
+            ext("VK_KHR_shader_draw_parameters"),
+        ],
+        C::MultiView => vec![
+            v11("multiview"),
+            ext_feature("VK_KHR_multiview", "multiview"),
+        ],
+        C::DeviceGroup => vec![version(1, 1), ext("VK_KHR_device_group")],
+        C::VariablePointersStorageBuffer => vec![
+            v11("variablePointersStorageBuffer"),
+            ext_feature("VK_KHR_variable_pointers", "variablePointersStorageBuffer"),
+        ],
+        C::VariablePointers => vec![
+            v11("variablePointers"),
+            ext_feature("VK_KHR_variable_pointers", "variablePointers"),
+        ],
+        C::StorageBuffer16BitAccess => vec![
+            v11("storageBuffer16BitAccess"),
+            ext_feature("VK_KHR_16bit_storage", "storageBuffer16BitAccess"),
+        ],
+        C::UniformAndStorageBuffer16BitAccess => vec![
+            v11("uniformAndStorageBuffer16BitAccess"),
+            ext_feature("VK_KHR_16bit_storage", "uniformAndStorageBuffer16BitAccess"),
+        ],
+        C::StoragePushConstant16 => vec![
+            v11("storagePushConstant16"),
+            ext_feature("VK_KHR_16bit_storage", "storagePushConstant16"),
+        ],
+        C::StorageInputOutput16 => vec![
+            v11("storageInputOutput16"),
+            ext_feature("VK_KHR_16bit_storage", "storageInputOutput16"),
+        ],
+        C::GroupNonUniform => vec![subgroup("BASIC")],
+        C::GroupNonUniformVote => vec![subgroup("VOTE")],
+        C::GroupNonUniformArithmetic => vec![subgroup("ARITHMETIC")],
+        C::GroupNonUniformBallot => vec![subgroup("BALLOT")],
+        C::GroupNonUniformShuffle => vec![subgroup("SHUFFLE")],
+        C::GroupNonUniformShuffleRelative => vec![subgroup("SHUFFLE_RELATIVE")],
+        C::GroupNonUniformClustered => vec![subgroup("CLUSTERED")],
+        C::GroupNonUniformQuad => vec![subgroup("QUAD")],
+        C::SubgroupBallotKHR => vec![ext("VK_EXT_shader_subgroup_ballot")],
+        C::SubgroupVoteKHR => vec![ext("VK_EXT_shader_subgroup_vote")],
+
+        C::Float16 => vec![
+            v12("shaderFloat16"),
+            ext_feature("VK_KHR_shader_float16_int8", "shaderFloat16"),
+            ext("VK_AMD_gpu_shader_half_float"),
+        ],
+        C::Int8 => vec![
+            v12("shaderInt8"),
+            ext_feature("VK_KHR_shader_float16_int8", "shaderInt8"),
+        ],
+        C::StorageBuffer8BitAccess => vec![
+            v12("storageBuffer8BitAccess"),
+            ext_feature("VK_KHR_8bit_storage", "storageBuffer8BitAccess"),
+        ],
+        C::UniformAndStorageBuffer8BitAccess => vec![
+            v12("uniformAndStorageBuffer8BitAccess"),
+            ext_feature("VK_KHR_8bit_storage", "uniformAndStorageBuffer8BitAccess"),
+        ],
+        C::StoragePushConstant8 => vec![
+            v12("storagePushConstant8"),
+            ext_feature("VK_KHR_8bit_storage", "storagePushConstant8"),
+        ],
+        C::VulkanMemoryModel => vec![
+            v12("vulkanMemoryModel"),
+            ext_feature("VK_KHR_vulkan_memory_model", "vulkanMemoryModel"),
+        ],
+        C::VulkanMemoryModelDeviceScope => vec![
+            v12("vulkanMemoryModelDeviceScope"),
+            ext_feature("VK_KHR_vulkan_memory_model", "vulkanMemoryModelDeviceScope"),
+        ],
+        C::PhysicalStorageBufferAddresses => vec![
+            v12("bufferDeviceAddress"),
+            ext_feature("VK_KHR_buffer_device_address", "bufferDeviceAddress"),
+            ext_feature("VK_EXT_buffer_device_address", "bufferDeviceAddress"),
+        ],
+        C::ShaderNonUniform => vec![version(1, 2), ext("VK_EXT_descriptor_indexing")],
+        C::RuntimeDescriptorArray => vec![
+            v12("runtimeDescriptorArray"),
+            ext_feature("VK_EXT_descriptor_indexing", "runtimeDescriptorArray"),
+        ],
+        C::InputAttachmentArrayDynamicIndexing => {
+            indexing("shaderInputAttachmentArrayDynamicIndexing")
+        }
+        C::UniformTexelBufferArrayDynamicIndexing => {
+            indexing("shaderUniformTexelBufferArrayDynamicIndexing")
+        }
+        C::StorageTexelBufferArrayDynamicIndexing => {
+            indexing("shaderStorageTexelBufferArrayDynamicIndexing")
+        }
+        C::UniformBufferArrayNonUniformIndexing => {
+            indexing("shaderUniformBufferArrayNonUniformIndexing")
+        }
+        C::SampledImageArrayNonUniformIndexing => {
+            indexing("shaderSampledImageArrayNonUniformIndexing")
+        }
+        C::StorageBufferArrayNonUniformIndexing => {
+            indexing("shaderStorageBufferArrayNonUniformIndexing")
+        }
+        C::StorageImageArrayNonUniformIndexing => {
+            indexing("shaderStorageImageArrayNonUniformIndexing")
+        }
+        C::InputAttachmentArrayNonUniformIndexing => {
+            indexing("shaderInputAttachmentArrayNonUniformIndexing")
+        }
+        C::UniformTexelBufferArrayNonUniformIndexing => {
+            indexing("shaderUniformTexelBufferArrayNonUniformIndexing")
+        }
+        C::StorageTexelBufferArrayNonUniformIndexing => {
+            indexing("shaderStorageTexelBufferArrayNonUniformIndexing")
+        }
+        C::ShaderViewportIndexLayerEXT => vec![ext("VK_EXT_shader_viewport_index_layer")],
+        C::ShaderViewportIndex => vec![v12("shaderOutputViewportIndex")],
+        C::ShaderLayer => vec![v12("shaderOutputLayer")],
+        C::SampleMaskPostDepthCoverage => vec![ext("VK_EXT_post_depth_coverage")],
+        C::StencilExportEXT => vec![ext("VK_EXT_shader_stencil_export")],
+        C::ShaderClockKHR => vec![ext("VK_KHR_shader_clock")],
+        C::Int64ImageEXT => vec![ext_feature(
+            "VK_EXT_shader_image_atomic_int64",
+            "shaderImageInt64Atomics",
+        )],
+        C::AtomicFloat32AddEXT => vec![
+            ext_feature("VK_EXT_shader_atomic_float", "shaderBufferFloat32AtomicAdd"),
+            ext_feature("VK_EXT_shader_atomic_float", "shaderSharedFloat32AtomicAdd"),
+            ext_feature("VK_EXT_shader_atomic_float", "shaderImageFloat32AtomicAdd"),
+        ],
+        C::AtomicFloat64AddEXT => vec![
+            ext_feature("VK_EXT_shader_atomic_float", "shaderBufferFloat64AtomicAdd"),
+            ext_feature("VK_EXT_shader_atomic_float", "shaderSharedFloat64AtomicAdd"),
+        ],
+        C::WorkgroupMemoryExplicitLayoutKHR => vec![ext_feature(
+            "VK_KHR_workgroup_memory_explicit_layout",
+            "workgroupMemoryExplicitLayout",
+        )],
+        C::WorkgroupMemoryExplicitLayout8BitAccessKHR => vec![ext_feature(
+            "VK_KHR_workgroup_memory_explicit_layout",
+            "workgroupMemoryExplicitLayout8BitAccess",
+        )],
+        C::WorkgroupMemoryExplicitLayout16BitAccessKHR => vec![ext_feature(
+            "VK_KHR_workgroup_memory_explicit_layout",
+            "workgroupMemoryExplicitLayout16BitAccess",
+        )],
+        C::DemoteToHelperInvocation => vec![
+            v13("shaderDemoteToHelperInvocation"),
+            ext_feature(
+                "VK_EXT_shader_demote_to_helper_invocation",
+                "shaderDemoteToHelperInvocation",
+            ),
+        ],
+        C::DotProductInputAll
+        | C::DotProductInput4x8Bit
+        | C::DotProductInput4x8BitPacked
+        | C::DotProduct => vec![
+            v13("shaderIntegerDotProduct"),
+            ext_feature(
+                "VK_KHR_shader_integer_dot_product",
+                "shaderIntegerDotProduct",
+            ),
+        ],
+        C::FragmentShaderSampleInterlockEXT => vec![ext_feature(
+            "VK_EXT_fragment_shader_interlock",
+            "fragmentShaderSampleInterlock",
+        )],
+        C::FragmentShaderPixelInterlockEXT => vec![ext_feature(
+            "VK_EXT_fragment_shader_interlock",
+            "fragmentShaderPixelInterlock",
+        )],
+        C::FragmentShaderShadingRateInterlockEXT => vec![ext_feature(
+            "VK_EXT_fragment_shader_interlock",
+            "fragmentShaderShadingRateInterlock",
+        )],
+        C::FragmentShadingRateKHR => vec![
+            ext_feature(
+                "VK_KHR_fragment_shading_rate",
+                "pipelineFragmentShadingRate",
+            ),
+            ext_feature(
+                "VK_KHR_fragment_shading_rate",
+                "primitiveFragmentShadingRate",
+            ),
+            ext_feature(
+                "VK_KHR_fragment_shading_rate",
+                "attachmentFragmentShadingRate",
+            ),
+        ],
+        C::FragmentBarycentricKHR => vec![
+            ext_feature(
+                "VK_KHR_fragment_shader_barycentric",
+                "fragmentShaderBarycentric",
+            ),
+            ext_feature(
+                "VK_NV_fragment_shader_barycentric",
+                "fragmentShaderBarycentric",
+            ),
+        ],
+        C::FragmentDensityEXT => vec![ext_feature(
+            "VK_EXT_fragment_density_map",
+            "fragmentDensityMap",
+        )],
+        C::FragmentFullyCoveredEXT => vec![ext("VK_EXT_conservative_rasterization")],
+        C::MeshShadingEXT => vec![ext_feature("VK_EXT_mesh_shader", "meshShader")],
+        C::MeshShadingNV => vec![ext("VK_NV_mesh_shader")],
+        C::RayTracingKHR => vec![ext_feature(
+            "VK_KHR_ray_tracing_pipeline",
+            "rayTracingPipeline",
+        )],
+        C::RayTraversalPrimitiveCullingKHR => vec![ext_feature(
+            "VK_KHR_ray_tracing_pipeline",
+            "rayTraversalPrimitiveCulling",
+        )],
+        C::RayQueryKHR => vec![ext_feature("VK_KHR_ray_query", "rayQuery")],
+        C::RayTracingNV => vec![ext("VK_NV_ray_tracing")],
+        _ => return None,
+    };
+    Some(any_of)
+}
+
+fn indexing(name: &'static str) -> Vec<VulkanRequirement> {
+    vec![v12(name), ext_feature("VK_EXT_descriptor_indexing", name)]
+}
+
+/// Ways a device can support the SPIR-V extension `name`. `None` if the extension has no known
+/// Vulkan mapping.
+#[must_use]
+pub fn extension_requirements(name: &str) -> Option<Vec<VulkanRequirement>> {
+    let any_of = match name {
+        "SPV_KHR_shader_draw_parameters" => {
+            vec![version(1, 1), ext("VK_KHR_shader_draw_parameters")]
+        }
+        "SPV_KHR_16bit_storage" => vec![version(1, 1), ext("VK_KHR_16bit_storage")],
+        "SPV_KHR_storage_buffer_storage_class" => {
+            vec![version(1, 1), ext("VK_KHR_storage_buffer_storage_class")]
+        }
+        "SPV_KHR_variable_pointers" => vec![version(1, 1), ext("VK_KHR_variable_pointers")],
+        "SPV_KHR_multiview" => vec![version(1, 1), ext("VK_KHR_multiview")],
+        "SPV_KHR_device_group" => vec![version(1, 1), ext("VK_KHR_device_group")],
+        "SPV_KHR_8bit_storage" => vec![version(1, 2), ext("VK_KHR_8bit_storage")],
+        "SPV_KHR_float_controls" => vec![version(1, 2), ext("VK_KHR_shader_float_controls")],
+        "SPV_KHR_vulkan_memory_model" => {
+            vec![version(1, 2), ext("VK_KHR_vulkan_memory_model")]
+        }
+        "SPV_EXT_descriptor_indexing" => vec![version(1, 2), ext("VK_EXT_descriptor_indexing")],
+        "SPV_KHR_physical_storage_buffer" => {
+            vec![version(1, 2), ext("VK_KHR_buffer_device_address")]
+        }
+        "SPV_EXT_physical_storage_buffer" => vec![ext("VK_EXT_buffer_device_address")],
+        "SPV_EXT_shader_viewport_index_layer" => {
+            vec![version(1, 2), ext("VK_EXT_shader_viewport_index_layer")]
+        }
+        "SPV_KHR_shader_atomic_counter_ops" => vec![ext("VK_KHR_shader_atomic_counter_ops")],
+        "SPV_EXT_demote_to_helper_invocation" => vec![
+            version(1, 3),
+            ext("VK_EXT_shader_demote_to_helper_invocation"),
+        ],
+        "SPV_KHR_non_semantic_info" => vec![version(1, 3), ext("VK_KHR_shader_non_semantic_info")],
+        "SPV_KHR_terminate_invocation" => {
+            vec![version(1, 3), ext("VK_KHR_shader_terminate_invocation")]
+        }
+        "SPV_KHR_integer_dot_product" => {
+            vec![version(1, 3), ext("VK_KHR_shader_integer_dot_product")]
+        }
+        "SPV_KHR_shader_ballot" => vec![ext("VK_EXT_shader_subgroup_ballot")],
+        "SPV_KHR_subgroup_vote" => vec![ext("VK_EXT_shader_subgroup_vote")],
+        "SPV_KHR_post_depth_coverage" => vec![ext("VK_EXT_post_depth_coverage")],
+        "SPV_EXT_shader_stencil_export" => vec![ext("VK_EXT_shader_stencil_export")],
+        "SPV_KHR_shader_clock" => vec![ext("VK_KHR_shader_clock")],
+        "SPV_EXT_shader_atomic_float_add" => vec![ext("VK_EXT_shader_atomic_float")],
+        "SPV_EXT_shader_image_int64" => vec![ext("VK_EXT_shader_image_atomic_int64")],
+        "SPV_KHR_workgroup_memory_explicit_layout" => {
+            vec![ext("VK_KHR_workgroup_memory_explicit_layout")]
+        }
+        "SPV_EXT_fragment_shader_interlock" => vec![ext("VK_EXT_fragment_shader_interlock")],
+        "SPV_KHR_fragment_shading_rate" => vec![ext("VK_KHR_fragment_shading_rate")],
+        "SPV_KHR_fragment_shader_barycentric" => {
+            vec![ext("VK_KHR_fragment_shader_barycentric")]
+        }
+        "SPV_NV_fragment_shader_barycentric" => {
+            vec![ext("VK_NV_fragment_shader_barycentric")]
+        }
+        "SPV_EXT_fragment_invocation_density" => vec![ext("VK_EXT_fragment_density_map")],
+        "SPV_EXT_fragment_fully_covered" => vec![ext("VK_EXT_conservative_rasterization")],
+        "SPV_EXT_mesh_shader" => vec![ext("VK_EXT_mesh_shader")],
+        "SPV_NV_mesh_shader" => vec![ext("VK_NV_mesh_shader")],
+        "SPV_KHR_ray_tracing" => vec![ext("VK_KHR_ray_tracing_pipeline")],
+        "SPV_KHR_ray_query" => vec![ext("VK_KHR_ray_query")],
+        "SPV_NV_ray_tracing" => vec![ext("VK_NV_ray_tracing")],
+        "SPV_GOOGLE_decorate_string" => vec![ext("VK_GOOGLE_decorate_string")],
+        "SPV_GOOGLE_hlsl_functionality1" => vec![ext("VK_GOOGLE_hlsl_functionality1")],
+        "SPV_GOOGLE_user_type" => vec![ext("VK_GOOGLE_user_type")],
+        _ => return None,
+    };
+    Some(any_of)
+}
+
+/// Ways a device can support entry points of `model`.
+///
+/// Most stages are already covered by the capability they require, e.g. `Geometry`. Task
+/// shaders however share `MeshShadingEXT` with mesh shaders but are gated by their own feature.
+#[must_use]
+pub fn execution_model_requirements(model: ExecutionModel) -> Vec<VulkanRequirement> {
+    match model {
+        ExecutionModel::TaskEXT => vec![ext_feature("VK_EXT_mesh_shader", "taskShader")],
+        ExecutionModel::TaskNV => vec![ext("VK_NV_mesh_shader")],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mapping() {
+        let req = ShaderRequirements::from_declarations(
+            &[Capability::Shader, Capability::Float16, Capability::Float16],
+            &["SPV_KHR_8bit_storage".to_owned(), "SPV_FOO_bar".to_owned()],
+            &[ExecutionModel::Fragment],
+        );
+        // Duplicate declarations are reported once.
+        assert_eq!(req.requirements.len(), 4);
+        assert_eq!(
+            req.unknown,
+            vec![RequirementSource::Extension("SPV_FOO_bar".to_owned())]
+        );
+        let non_core: Vec<_> = req.non_core().map(|r| &r.source).collect();
+        assert_eq!(
+            non_core,
+            vec![
+                &RequirementSource::Capability(Capability::Float16),
+                &RequirementSource::Extension("SPV_KHR_8bit_storage".to_owned()),
+            ]
+        );
+        assert_eq!(
+            req.requirements[1].any_of[0].to_string(),
+            "VkPhysicalDeviceVulkan12Features::shaderFloat16"
+        );
+    }
+}
@@ -79,6 +79,7 @@ pub enum Type {
     Array(Array),
     RunTimeArray(RunTimeArray),
     Struct(Struct),
+    Pointer(Pointer),
 }
 
 impl std::fmt::Debug for Type {
@@ -91,6 +92,7 @@ impl std::fmt::Debug for Type {
             Self::Array(arg0) => arg0.fmt(f),
             Self::RunTimeArray(arg0) => arg0.fmt(f),
             Self::Struct(arg0) => arg0.fmt(f),
+            Self::Pointer(arg0) => arg0.fmt(f),
         }
     }
 }
@@ -105,6 +107,7 @@ impl MinSize for Type {
             Self::Array(x) => x.min_bits_size(),
             Self::RunTimeArray(_) => 0,
             Self::Struct(x) => x.min_bits_size(),
+            Self::Pointer(x) => x.min_bits_size(),
         }
     }
 }
@@ -119,6 +122,7 @@ impl Alignment for Type {
             Self::Array(x) => x.base_align(),
             Self::RunTimeArray(x) => x.base_align(),
             Self::Struct(x) => x.base_align(),
+            Self::Pointer(x) => x.base_align(),
         }
     }
 
@@ -131,6 +135,7 @@ impl Alignment for Type {
             Self::Array(x) => x.extended_align(),
             Self::RunTimeArray(x) => x.extended_align(),
             Self::Struct(x) => x.extended_align(),
+            Self::Pointer(x) => x.extended_align(),
         }
     }
 
@@ -143,6 +148,7 @@ impl Alignment for Type {
             Self::Array(x) => x.scalar_align(),
             Self::RunTimeArray(x) => x.scalar_align(),
             Self::Struct(x) => x.scalar_align(),
+            Self::Pointer(x) => x.scalar_align(),
         }
     }
 }
@@ -183,6 +189,16 @@ pub struct Struct {
     pub(super) base_alignment: u16,
     pub(super) block_decor: bool,
 }
+/// A pointer in the `PhysicalStorageBuffer` storage class, i.e. a buffer device address.
+///
+/// The pointee is stored as an id rather than an `Rc<Type>`, since a struct may refer to itself
+/// through an `OpTypeForwardPointer` (e.g. a linked list node).
+/// Resolve it through the map returned by [`crate::Reflection::get_types`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    pub storage_class: StorageClass,
+    pub pointee: u32,
+}
 
 impl MinSize for Int {
     fn min_bits_size(&self) -> u16 {
@@ -191,7 +207,7 @@ impl MinSize for Int {
 }
 impl Alignment for Int {
     fn scalar_align(&self) -> u16 {
-        let residue = u16::from(self.bits % BYTE_SIZE != 0);
+        let residue = u16::from(!self.bits.is_multiple_of(BYTE_SIZE));
         self.bits / BYTE_SIZE + residue
     }
     fn base_align(&self) -> u16 {
@@ -209,7 +225,7 @@ impl MinSize for Float {
 
 impl Alignment for Float {
     fn scalar_align(&self) -> u16 {
-        let residue = u16::from(self.bits % BYTE_SIZE != 0);
+        let residue = u16::from(!self.bits.is_multiple_of(BYTE_SIZE));
         self.bits / BYTE_SIZE + residue
     }
     fn base_align(&self) -> u16 {
@@ -220,6 +236,25 @@ impl Alignment for Float {
     }
 }
 
+impl MinSize for Pointer {
+    fn min_bits_size(&self) -> u16 {
+        // `PhysicalStorageBuffer64` is the only addressing model Vulkan allows.
+        64
+    }
+}
+
+impl Alignment for Pointer {
+    fn scalar_align(&self) -> u16 {
+        8
+    }
+    fn base_align(&self) -> u16 {
+        self.scalar_align()
+    }
+    fn extended_align(&self) -> u16 {
+        self.scalar_align()
+    }
+}
+
 impl MinSize for Vector {
     fn min_bits_size(&self) -> u16 {
         self.inner_type.min_bits_size()
@@ -468,7 +503,7 @@ fn is_vector_straddling_inside_struct_aligned(
             .then_some(())
             .ok_or(VectorStraddle::OccupiesTwo16ByteBlocks)
     } else {
-        (first_byte % 16 != 0)
+        (!first_byte.is_multiple_of(16))
             .then_some(())
             .ok_or(VectorStraddle::NotAlignedTo16ByteBlock)
     }